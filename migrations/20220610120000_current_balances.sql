@@ -0,0 +1,8 @@
+CREATE TABLE current_balances
+(
+    account_id       text           NOT NULL,
+    block_timestamp  numeric(20, 0) NOT NULL,
+    nonstaked_amount numeric(45, 0) NOT NULL,
+    staked_amount    numeric(45, 0) NOT NULL,
+    PRIMARY KEY (account_id)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE shard_mapping
+(
+    block_height         numeric(20, 0) NOT NULL,
+    block_timestamp      numeric(20, 0) NOT NULL,
+    shard_layout_version integer        NOT NULL,
+    shard_id             integer        NOT NULL,
+    parent_shard_id      integer        NOT NULL,
+    PRIMARY KEY (block_height, shard_id)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE balance_changes
+    ADD COLUMN index_in_block integer NOT NULL DEFAULT 0;
+
+CREATE INDEX balance_changes_block_order_idx ON balance_changes (block_timestamp, index_in_block);
@@ -0,0 +1,10 @@
+CREATE TABLE balance_imbalances
+(
+    block_height     numeric(20, 0) NOT NULL,
+    block_timestamp  numeric(20, 0) NOT NULL,
+    minted_amount    numeric(45, 0) NOT NULL,
+    burnt_amount     numeric(45, 0) NOT NULL,
+    delta_amount     numeric(45, 0) NOT NULL,
+    imbalance_amount numeric(46, 0) NOT NULL,
+    PRIMARY KEY (block_height)
+);
@@ -0,0 +1,16 @@
+CREATE TABLE ft_balance_changes
+(
+    block_timestamp     numeric(20, 0) NOT NULL,
+    receipt_id          text           NOT NULL,
+    contract_account_id text           NOT NULL,
+    affected_account_id text           NOT NULL,
+    involved_account_id text,
+    cause               text           NOT NULL,
+    delta_amount        numeric(45, 0) NOT NULL,
+    -- NULL if the contract did not answer ft_balance_of
+    absolute_amount     numeric(45, 0),
+    index_in_receipt    integer        NOT NULL,
+    PRIMARY KEY (receipt_id, index_in_receipt)
+);
+
+CREATE INDEX ft_balance_changes_affected_account_idx ON ft_balance_changes (affected_account_id, contract_account_id);
@@ -0,0 +1,19 @@
+-- Links the NEAR and the wNEAR sides of one wrap/unwrap.
+-- WRAP: NEAR leaves the account with near_transaction_hash, wNEAR is minted in ft_receipt_id.
+-- UNWRAP: wNEAR is burnt in ft_receipt_id, NEAR comes back with near_receipt_id
+CREATE TABLE wrap_near_events
+(
+    correlation_id        text           NOT NULL,
+    block_timestamp       numeric(20, 0) NOT NULL,
+    kind                  text           NOT NULL,
+    account_id            text           NOT NULL,
+    amount                numeric(45, 0) NOT NULL,
+    near_transaction_hash text,
+    near_receipt_id       text,
+    ft_receipt_id         text           NOT NULL,
+    PRIMARY KEY (correlation_id)
+);
+
+CREATE INDEX wrap_near_events_account_idx ON wrap_near_events (account_id);
+CREATE INDEX wrap_near_events_transaction_hash_idx ON wrap_near_events (near_transaction_hash);
+CREATE INDEX wrap_near_events_receipt_id_idx ON wrap_near_events (near_receipt_id);
@@ -0,0 +1,13 @@
+CREATE TABLE lockup_balances
+(
+    block_timestamp   numeric(20, 0) NOT NULL,
+    lockup_account_id text           NOT NULL,
+    owner_account_id  text           NOT NULL,
+    -- Still locked by the vesting/lockup schedule
+    locked_amount     numeric(45, 0) NOT NULL,
+    -- The owner may withdraw it
+    liquid_amount     numeric(45, 0) NOT NULL,
+    PRIMARY KEY (block_timestamp, lockup_account_id)
+);
+
+CREATE INDEX lockup_balances_owner_idx ON lockup_balances (owner_account_id);
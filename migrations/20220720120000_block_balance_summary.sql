@@ -0,0 +1,12 @@
+CREATE TABLE block_balance_summary
+(
+    block_height               numeric(20, 0) NOT NULL,
+    block_timestamp            numeric(20, 0) NOT NULL,
+    tokens_burnt_amount        numeric(45, 0) NOT NULL,
+    validators_reward_amount   numeric(45, 0) NOT NULL,
+    delta_staked_amount        numeric(45, 0) NOT NULL,
+    affected_accounts_count    integer        NOT NULL,
+    PRIMARY KEY (block_height)
+);
+
+CREATE INDEX block_balance_summary_timestamp_idx ON block_balance_summary (block_timestamp);
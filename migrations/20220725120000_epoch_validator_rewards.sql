@@ -0,0 +1,13 @@
+-- epoch_id is the epoch which starts with the reward, the reward is paid for the previous one
+CREATE TABLE epoch_validator_rewards
+(
+    epoch_id         text           NOT NULL,
+    block_height     numeric(20, 0) NOT NULL,
+    block_timestamp  numeric(20, 0) NOT NULL,
+    validator_id     text           NOT NULL,
+    reward_amount    numeric(45, 0) NOT NULL,
+    staked_amount    numeric(45, 0) NOT NULL,
+    PRIMARY KEY (epoch_id, validator_id)
+);
+
+CREATE INDEX epoch_validator_rewards_validator_idx ON epoch_validator_rewards (validator_id, block_height);
@@ -0,0 +1,2 @@
+ALTER TABLE block_balance_summary
+    ADD COLUMN treasury_reward_amount numeric(45, 0) NOT NULL DEFAULT 0;
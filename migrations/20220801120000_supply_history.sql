@@ -0,0 +1,11 @@
+CREATE TABLE supply_history
+(
+    block_height     numeric(20, 0) NOT NULL,
+    block_timestamp  numeric(20, 0) NOT NULL,
+    minted_amount    numeric(45, 0) NOT NULL,
+    burnt_amount     numeric(45, 0) NOT NULL,
+    total_supply     numeric(45, 0) NOT NULL,
+    PRIMARY KEY (block_height)
+);
+
+CREATE INDEX supply_history_timestamp_idx ON supply_history (block_timestamp);
@@ -0,0 +1,23 @@
+CREATE TABLE top_accounts
+(
+    account_id       text           NOT NULL,
+    rank             integer        NOT NULL,
+    total_amount     numeric(45, 0) NOT NULL,
+    block_height     numeric(20, 0) NOT NULL,
+    PRIMARY KEY (account_id)
+);
+
+CREATE INDEX top_accounts_rank_idx ON top_accounts (rank);
+
+-- Written when the rank changes. NULL rank means the account dropped out of the top
+CREATE TABLE top_accounts_history
+(
+    block_height     numeric(20, 0) NOT NULL,
+    block_timestamp  numeric(20, 0) NOT NULL,
+    account_id       text           NOT NULL,
+    rank             integer,
+    total_amount     numeric(45, 0),
+    PRIMARY KEY (block_height, account_id)
+);
+
+CREATE INDEX top_accounts_history_account_idx ON top_accounts_history (account_id, block_height);
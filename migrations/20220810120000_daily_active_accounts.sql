@@ -0,0 +1,10 @@
+-- HyperLogLog sketch of the accounts with balance changes during the day (UTC).
+-- Distinct accounts of the block are in block_balance_summary.affected_accounts_count
+CREATE TABLE daily_active_accounts
+(
+    day                     date           NOT NULL,
+    last_block_height       numeric(20, 0) NOT NULL,
+    approx_accounts_count   bigint         NOT NULL,
+    hll_registers           bytea          NOT NULL,
+    PRIMARY KEY (day)
+);
@@ -0,0 +1,15 @@
+-- Accounts whose liquid outflow during the bucket is far above their trailing history
+CREATE TABLE anomalies
+(
+    block_height     numeric(20, 0)   NOT NULL,
+    block_timestamp  numeric(20, 0)   NOT NULL,
+    account_id       text             NOT NULL,
+    outflow_amount   numeric(45, 0)   NOT NULL,
+    trailing_mean    double precision NOT NULL,
+    trailing_stddev  double precision NOT NULL,
+    z_score          double precision NOT NULL,
+    PRIMARY KEY (block_height, account_id)
+);
+
+CREATE INDEX anomalies_account_idx ON anomalies (account_id, block_height);
+CREATE INDEX anomalies_timestamp_idx ON anomalies (block_timestamp);
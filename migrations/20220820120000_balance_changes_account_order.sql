@@ -0,0 +1,3 @@
+-- Pagination of the account history in the API goes in the primary key order
+CREATE INDEX balance_changes_account_order_idx
+    ON balance_changes (affected_account_id, block_timestamp, shard_id, index_in_chunk);
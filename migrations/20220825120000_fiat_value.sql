@@ -0,0 +1,2 @@
+ALTER TABLE balance_changes
+    ADD COLUMN fiat_value_usd numeric(38, 6);
@@ -0,0 +1,38 @@
+-- Layout of account_changes from near-indexer-for-explorer, filled with --compat-schema explorer.
+-- The existing table of the explorer database is reused as is
+DO
+$$
+    BEGIN
+        CREATE TYPE state_change_reason_kind AS ENUM (
+            'TRANSACTION_PROCESSING',
+            'ACTION_RECEIPT_PROCESSING_STARTED',
+            'ACTION_RECEIPT_GAS_REWARD',
+            'RECEIPT_PROCESSING',
+            'POSTPONED_RECEIPT',
+            'UPDATED_DELAYED_RECEIPTS',
+            'VALIDATOR_ACCOUNTS_UPDATE',
+            'MIGRATION',
+            'RESHARDING'
+            );
+    EXCEPTION
+        WHEN duplicate_object THEN NULL;
+    END
+$$;
+
+CREATE TABLE IF NOT EXISTS account_changes
+(
+    id                                 bigserial                NOT NULL PRIMARY KEY,
+    affected_account_id                text                     NOT NULL,
+    changed_in_block_timestamp         numeric(20, 0)           NOT NULL,
+    changed_in_block_hash              text                     NOT NULL,
+    caused_by_transaction_hash         text,
+    caused_by_receipt_id               text,
+    update_reason                      state_change_reason_kind NOT NULL,
+    affected_account_nonstaked_balance numeric(45, 0)           NOT NULL,
+    affected_account_staked_balance    numeric(45, 0)           NOT NULL,
+    affected_account_storage_usage     numeric(20, 0)           NOT NULL,
+    index_in_block                     integer                  NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS account_changes_changed_in_block_hash_idx ON account_changes (changed_in_block_hash);
+CREATE INDEX IF NOT EXISTS account_changes_affected_account_id_idx ON account_changes (affected_account_id);
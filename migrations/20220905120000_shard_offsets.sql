@@ -0,0 +1,7 @@
+-- With --shards, each indexer instance stores only its shards and continues from its own offsets
+CREATE TABLE shard_offsets
+(
+    shard_id     integer        NOT NULL,
+    block_height numeric(20, 0) NOT NULL,
+    PRIMARY KEY (shard_id)
+);
@@ -0,0 +1,13 @@
+-- --finality optimistic stores the rows before the block is final, the reconciler marks them FINAL
+ALTER TABLE balance_changes
+    ADD COLUMN finality text NOT NULL DEFAULT 'FINAL';
+
+CREATE TABLE optimistic_blocks
+(
+    block_hash      text           NOT NULL,
+    block_height    numeric(20, 0) NOT NULL,
+    block_timestamp numeric(20, 0) NOT NULL,
+    PRIMARY KEY (block_hash)
+);
+
+CREATE INDEX optimistic_blocks_height_idx ON optimistic_blocks (block_height);
@@ -0,0 +1,17 @@
+-- Account ids from the NEP-141 event logs (`EVENT_JSON:` written by the token contract) which are not
+-- valid account ids. The runtime does not validate the logs, e.g. `"new_owner_id": "Bob.NEAR"` is accepted.
+-- They are kept aside instead of stopping indexing, see https://nomicon.io/Standards/Tokens/FungibleToken/Event
+CREATE TABLE quarantined_ft_event_values
+(
+    block_height        numeric(20, 0) NOT NULL,
+    block_timestamp     numeric(20, 0) NOT NULL,
+    shard_id            integer        NOT NULL,
+    receipt_id          text           NOT NULL,
+    contract_account_id text           NOT NULL,
+    field               text           NOT NULL,
+    value               text           NOT NULL,
+    error               text           NOT NULL,
+    PRIMARY KEY (receipt_id, field, value)
+);
+
+CREATE INDEX quarantined_ft_event_values_height_idx ON quarantined_ft_event_values (block_height);
@@ -0,0 +1,20 @@
+-- Fee breakdown of each transaction and receipt outcome, one of transaction_hash and receipt_id is set.
+-- contract_reward_amount is the sum of CONTRACT_REWARD rows of the receipt in balance_changes,
+-- refund_amount is the deposit of the refund receipt (predecessor `system`)
+CREATE TABLE fee_details
+(
+    block_height           numeric(20, 0) NOT NULL,
+    block_timestamp        numeric(20, 0) NOT NULL,
+    shard_id               integer        NOT NULL,
+    transaction_hash       text,
+    receipt_id             text,
+    executor_account_id    text           NOT NULL,
+    gas_price              numeric(45, 0) NOT NULL,
+    gas_burnt              numeric(20, 0) NOT NULL,
+    tokens_burnt           numeric(45, 0) NOT NULL,
+    contract_reward_amount numeric(45, 0) NOT NULL,
+    refund_amount          numeric(45, 0) NOT NULL
+);
+
+CREATE UNIQUE INDEX fee_details_outcome_idx ON fee_details (coalesce(receipt_id, transaction_hash));
+CREATE INDEX fee_details_height_idx ON fee_details (block_height);
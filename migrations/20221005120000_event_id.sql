@@ -0,0 +1,35 @@
+-- Hex sha256 of `block_hash:cause_id:cause:affected_account_id:position`, see set_event_id
+-- in src/models/balance_changes.rs. The old rows take the block hash from blocks,
+-- the position is counted in the order of the rows in the block
+ALTER TABLE balance_changes
+    ADD COLUMN event_id text;
+
+UPDATE balance_changes
+SET event_id = encode(sha256(convert_to(ids.key, 'UTF8')), 'hex')
+FROM (SELECT balance_changes.block_timestamp,
+             balance_changes.shard_id,
+             balance_changes.index_in_chunk,
+             blocks.block_hash || ':' || coalesce(transaction_hash, receipt_id, '') || ':' || cause || ':' ||
+             affected_account_id || ':' ||
+             (row_number() OVER (PARTITION BY balance_changes.block_timestamp,
+                 coalesce(transaction_hash, receipt_id, ''), cause, affected_account_id
+                 ORDER BY shard_id, index_in_chunk) - 1) AS key
+      FROM balance_changes
+               JOIN blocks ON blocks.block_timestamp = balance_changes.block_timestamp) AS ids
+WHERE balance_changes.block_timestamp = ids.block_timestamp
+  AND balance_changes.shard_id = ids.shard_id
+  AND balance_changes.index_in_chunk = ids.index_in_chunk;
+
+ALTER TABLE balance_changes
+    ALTER COLUMN event_id SET NOT NULL;
+
+-- The positional key changes when the rows of the block are computed again (e.g. with another
+-- --only-accounts), event_id does not. block_timestamp is in the key for the partitions and Timescale
+ALTER TABLE balance_changes
+    DROP CONSTRAINT balance_changes_pkey;
+
+ALTER TABLE balance_changes
+    ADD PRIMARY KEY (event_id, block_timestamp);
+
+-- The order of the rows, the pagination of the API goes by it
+CREATE INDEX balance_changes_position_idx ON balance_changes (block_timestamp, shard_id, index_in_chunk);
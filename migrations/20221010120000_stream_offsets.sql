@@ -0,0 +1,7 @@
+-- The last block stored from the stream, moved in the same transaction as the rows of the block
+CREATE TABLE stream_offsets
+(
+    source       text           NOT NULL,
+    block_height numeric(20, 0) NOT NULL,
+    PRIMARY KEY (source)
+);
@@ -0,0 +1,12 @@
+-- Settings of the database. schema_version is the version of the latest applied migration,
+-- the binary refuses to run against another version, see src/db_adapters/schema_version.rs.
+-- The next migrations set it to their own version in their last statement
+CREATE TABLE meta
+(
+    key   text NOT NULL,
+    value text NOT NULL,
+    PRIMARY KEY (key)
+);
+
+INSERT INTO meta (key, value)
+VALUES ('schema_version', '20221015120000');
@@ -0,0 +1,8 @@
+-- The transaction the receipt descends from, see link_transactions in src/db_adapters/balance_changes.rs.
+-- The old rows keep NULL
+ALTER TABLE balance_changes
+    ADD COLUMN parent_transaction_hash text;
+
+UPDATE meta
+SET value = '20221020120000'
+WHERE key = 'schema_version';
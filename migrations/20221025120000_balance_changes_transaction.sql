@@ -0,0 +1,8 @@
+-- All the rows of one transaction for GET /transactions/{hash}/changes:
+-- the transaction rows have transaction_hash, the receipt rows have parent_transaction_hash
+CREATE INDEX balance_changes_transaction_idx
+    ON balance_changes (coalesce(parent_transaction_hash, transaction_hash));
+
+UPDATE meta
+SET value = '20221025120000'
+WHERE key = 'schema_version';
@@ -0,0 +1,8 @@
+-- The index of the action in the receipt for the rows of one action, see --split-actions.
+-- NULL for the rows of the whole receipt, including all the old ones
+ALTER TABLE balance_changes
+    ADD COLUMN index_in_receipt integer;
+
+UPDATE meta
+SET value = '20221030120000'
+WHERE key = 'schema_version';
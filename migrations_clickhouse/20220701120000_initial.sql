@@ -0,0 +1,20 @@
+CREATE TABLE balance_changes
+(
+    block_timestamp           UInt64,
+    receipt_id                Nullable(String),
+    transaction_hash          Nullable(String),
+    affected_account_id       String,
+    involved_account_id       Nullable(String),
+    direction                 LowCardinality(String),
+    cause                     LowCardinality(String),
+    status                    LowCardinality(String),
+    delta_nonstaked_amount    Int128,
+    absolute_nonstaked_amount UInt128,
+    delta_staked_amount       Int128,
+    absolute_staked_amount    UInt128,
+    shard_id                  Int32,
+    index_in_chunk            Int32,
+    index_in_block            Int32
+) ENGINE = ReplacingMergeTree
+      PARTITION BY toYYYYMM(toDateTime(intDiv(block_timestamp, 1000000000)))
+      ORDER BY (block_timestamp, shard_id, index_in_chunk);
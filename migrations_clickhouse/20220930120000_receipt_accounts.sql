@@ -0,0 +1,3 @@
+ALTER TABLE balance_changes
+    ADD COLUMN predecessor_account_id Nullable(String),
+    ADD COLUMN receiver_account_id Nullable(String);
@@ -0,0 +1,3 @@
+ALTER TABLE balance_changes
+    ADD COLUMN event_id String DEFAULT lower(hex(SHA256(concat(toString(block_timestamp), ':',
+        toString(shard_id), ':', toString(index_in_chunk), ':', affected_account_id))));
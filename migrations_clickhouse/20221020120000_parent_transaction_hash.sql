@@ -0,0 +1,2 @@
+ALTER TABLE balance_changes
+    ADD COLUMN parent_transaction_hash Nullable(String);
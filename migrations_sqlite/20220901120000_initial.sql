@@ -0,0 +1,39 @@
+-- Amounts do not fit SQLite integers, they are decimal strings. Timestamps are nanoseconds
+CREATE TABLE IF NOT EXISTS balance_changes
+(
+    block_timestamp           INTEGER NOT NULL,
+    receipt_id                TEXT,
+    transaction_hash          TEXT,
+    affected_account_id       TEXT    NOT NULL,
+    involved_account_id       TEXT,
+    direction                 TEXT    NOT NULL,
+    cause                     TEXT    NOT NULL,
+    status                    TEXT    NOT NULL,
+    delta_nonstaked_amount    TEXT    NOT NULL,
+    absolute_nonstaked_amount TEXT    NOT NULL,
+    delta_staked_amount       TEXT    NOT NULL,
+    absolute_staked_amount    TEXT    NOT NULL,
+    shard_id                  INTEGER NOT NULL,
+    index_in_chunk            INTEGER NOT NULL,
+    index_in_block            INTEGER NOT NULL,
+    fiat_value_usd            TEXT,
+    PRIMARY KEY (block_timestamp, shard_id, index_in_chunk)
+);
+
+CREATE INDEX IF NOT EXISTS balance_changes_account_order_idx
+    ON balance_changes (affected_account_id, block_timestamp, shard_id, index_in_chunk);
+
+CREATE TABLE IF NOT EXISTS current_balances
+(
+    account_id       TEXT    NOT NULL PRIMARY KEY,
+    block_timestamp  INTEGER NOT NULL,
+    nonstaked_amount TEXT    NOT NULL,
+    staked_amount    TEXT    NOT NULL
+);
+
+-- The stored blocks, to continue after the restart
+CREATE TABLE IF NOT EXISTS blocks
+(
+    block_height    INTEGER NOT NULL PRIMARY KEY,
+    block_timestamp INTEGER NOT NULL
+);
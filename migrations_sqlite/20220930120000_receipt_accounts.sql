@@ -0,0 +1,4 @@
+ALTER TABLE balance_changes
+    ADD COLUMN predecessor_account_id TEXT;
+ALTER TABLE balance_changes
+    ADD COLUMN receiver_account_id TEXT;
@@ -0,0 +1,3 @@
+-- SQLite has no sha256, the old rows keep the empty id
+ALTER TABLE balance_changes
+    ADD COLUMN event_id TEXT NOT NULL DEFAULT '';
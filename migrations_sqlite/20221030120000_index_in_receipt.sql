@@ -0,0 +1,2 @@
+ALTER TABLE balance_changes
+    ADD COLUMN index_in_receipt INTEGER;
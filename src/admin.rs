@@ -0,0 +1,54 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+
+static PAUSED: once_cell::sync::Lazy<tokio::sync::watch::Sender<bool>> =
+    once_cell::sync::Lazy::new(|| tokio::sync::watch::channel(false).0);
+
+// Computing and inserting stop at the block boundary while paused, so the database can be
+// maintained without the restart. The block in flight is finished, the caches stay warm
+pub(crate) async fn wait_while_paused() {
+    let mut paused = PAUSED.subscribe();
+    if *paused.borrow() {
+        tracing::info!(target: crate::INDEXER, "Indexing is paused");
+        // The sender is static, it is never dropped
+        let _ = paused.wait_for(|paused| !*paused).await;
+        tracing::info!(target: crate::INDEXER, "Indexing is resumed");
+    }
+}
+
+fn set_paused(paused: bool) {
+    PAUSED.send_replace(paused);
+    crate::metrics::PAUSED.set(paused as i64);
+}
+
+async fn serve_admin(request: Request<Body>) -> anyhow::Result<Response<Body>> {
+    match (request.method(), request.uri().path()) {
+        (&Method::POST, "/pause") => set_paused(true),
+        (&Method::POST, "/resume") => set_paused(false),
+        (&Method::GET, "/status") => {}
+        _ => {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())?)
+        }
+    }
+    let status = serde_json::json!({
+        "paused": *PAUSED.borrow(),
+        "indexed_height": crate::metrics::INDEXED_HEIGHT.get(),
+    });
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(status.to_string()))?)
+}
+
+// `POST /pause`, `POST /resume` and `GET /status` at `http://127.0.0.1:<port>`.
+// Local only, there is no authentication
+pub(crate) async fn init_server(port: u16) -> anyhow::Result<()> {
+    let address = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    tracing::info!(target: crate::INDEXER, "Starting admin server on {}", address);
+
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, anyhow::Error>(service_fn(serve_admin)) });
+    Server::bind(&address).serve(make_service).await?;
+    Ok(())
+}
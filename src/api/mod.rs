@@ -0,0 +1,95 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::json;
+
+pub(crate) mod queries;
+pub(crate) mod rest;
+mod schema;
+
+// Read-only API over the indexed data:
+// `POST /graphql` with `{"query": "...", "variables": {...}}`, see src/api/schema.rs,
+// and `GET /accounts/...`, `GET /transactions/...` REST endpoints, see src/api/rest.rs
+pub(crate) struct Api {
+    pool: sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+    schema: schema::Schema,
+}
+
+pub(crate) async fn serve(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    port: u16,
+) -> anyhow::Result<()> {
+    let api = std::sync::Arc::new(Api {
+        pool: pool.clone(),
+        json_rpc_client: json_rpc_client.clone(),
+        schema: schema::build(pool.clone(), json_rpc_client.clone()),
+    });
+    let address = std::net::SocketAddr::from(([0, 0, 0, 0], port));
+    tracing::info!(target: crate::INDEXER, "Starting API on http://{}", address);
+
+    let make_service = make_service_fn(move |_| {
+        let api = api.clone();
+        async move {
+            Ok::<_, anyhow::Error>(service_fn(move |request| {
+                let api = api.clone();
+                async move { api.handle(request).await }
+            }))
+        }
+    });
+    Server::bind(&address).serve(make_service).await?;
+    Ok(())
+}
+
+// `balance-at` subcommand, prints the balance as JSON
+pub(crate) async fn print_balance_at(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    account_id: &str,
+    timestamp: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<()> {
+    let block_timestamp = timestamp
+        .timestamp_nanos_opt()
+        .filter(|nanos| *nanos >= 0)
+        .ok_or_else(|| anyhow::anyhow!("Timestamp {} is out of range", timestamp))?;
+    let balance =
+        queries::balance_at_timestamp(pool, json_rpc_client, account_id, block_timestamp as u64)
+            .await?;
+    let output = match balance {
+        Some((balance, source)) => json!({
+            "account_id": account_id,
+            "block_timestamp": balance.block_timestamp.to_string(),
+            "nonstaked_amount": balance.nonstaked_amount.to_string(),
+            "staked_amount": balance.staked_amount.to_string(),
+            "source": source.as_str(),
+        }),
+        None => anyhow::bail!("Account {} did not exist at {}", account_id, timestamp),
+    };
+    println!("{}", output);
+    Ok(())
+}
+
+impl Api {
+    async fn handle(&self, request: Request<Body>) -> anyhow::Result<Response<Body>> {
+        match (request.method(), request.uri().path()) {
+            (&Method::POST, "/graphql") => self.handle_graphql(request).await,
+            (&Method::GET, path) if path.starts_with("/accounts/") => {
+                rest::handle(self, &request).await
+            }
+            _ => Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not found"))?),
+        }
+    }
+
+    async fn handle_graphql(&self, request: Request<Body>) -> anyhow::Result<Response<Body>> {
+        let body = hyper::body::to_bytes(request.into_body()).await?;
+        let response = match serde_json::from_slice::<async_graphql::Request>(&body) {
+            Ok(request) => serde_json::to_value(self.schema.execute(request).await)?,
+            Err(err) => json!({ "errors": [{ "message": format!("Invalid request: {}", err) }] }),
+        };
+        Ok(Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&response)?))?)
+    }
+}
@@ -0,0 +1,320 @@
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use num_traits::{ToPrimitive, Zero};
+
+use crate::models::balance_changes::BalanceChange;
+
+pub(crate) const DEFAULT_PAGE_SIZE: i64 = 100;
+pub(crate) const MAX_PAGE_SIZE: i64 = 1000;
+// yoctoNEAR per byte, the same since genesis
+const STORAGE_AMOUNT_PER_BYTE: u128 = 10_000_000_000_000_000_000;
+
+// The rows of one account go in the primary key order.
+// The cursor is `<block_timestamp>-<shard_id>-<index_in_chunk>` of the last row of the page
+pub(crate) struct ChangesFilter {
+    pub account_id: String,
+    pub cause: Option<String>,
+    // Inclusive bounds of block_timestamp
+    pub from_timestamp: Option<BigDecimal>,
+    pub to_timestamp: Option<BigDecimal>,
+    pub after: Option<String>,
+    pub limit: i64,
+}
+
+pub(crate) enum BalanceAt {
+    Latest,
+    BlockHeight(u64),
+    BlockTimestamp(BigDecimal),
+}
+
+pub(crate) struct Balance {
+    pub block_timestamp: BigDecimal,
+    pub nonstaked_amount: BigDecimal,
+    pub staked_amount: BigDecimal,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum BalanceSource {
+    Indexed,
+    Rpc,
+}
+
+impl BalanceSource {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            BalanceSource::Indexed => "indexed",
+            BalanceSource::Rpc => "rpc",
+        }
+    }
+}
+
+pub(crate) struct SpendableBalance {
+    // Both the balance and the storage usage are at this block
+    pub block_height: u64,
+    pub balance: Balance,
+    pub storage_usage: u64,
+    // The part of nonstaked_amount which pays for the storage
+    pub storage_locked_amount: BigDecimal,
+    pub spendable_amount: BigDecimal,
+}
+
+pub(crate) fn validate_limit(limit: Option<i64>) -> anyhow::Result<i64> {
+    match limit {
+        None => Ok(DEFAULT_PAGE_SIZE),
+        Some(limit) if (1..=MAX_PAGE_SIZE).contains(&limit) => Ok(limit),
+        Some(_) => anyhow::bail!("Page size should be between 1 and {}", MAX_PAGE_SIZE),
+    }
+}
+
+// Returns the page and whether there are more rows after it
+pub(crate) async fn account_changes(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    filter: &ChangesFilter,
+) -> anyhow::Result<(Vec<BalanceChange>, bool)> {
+    let (after_timestamp, after_shard_id, after_index) = match &filter.after {
+        Some(cursor) => parse_cursor(cursor)?,
+        None => (BigDecimal::from(-1), -1, -1),
+    };
+    let mut changes: Vec<BalanceChange> = sqlx::query_as(&format!(
+        "SELECT {} FROM {} \
+        WHERE affected_account_id = $1 \
+            AND ($2::text IS NULL OR cause = $2) \
+            AND ($3::numeric IS NULL OR block_timestamp >= $3::bigint) \
+            AND ($4::numeric IS NULL OR block_timestamp <= $4::bigint) \
+            AND (block_timestamp, shard_id, index_in_chunk) > ($5::bigint, $6, $7) \
+        ORDER BY block_timestamp, shard_id, index_in_chunk \
+        LIMIT $8",
+        crate::models::balance_changes::SELECT_COLUMNS,
+        crate::db_adapters::table("balance_changes")
+    ))
+    .bind(&filter.account_id)
+    .bind(&filter.cause)
+    .bind(&filter.from_timestamp)
+    .bind(&filter.to_timestamp)
+    .bind(&after_timestamp)
+    .bind(after_shard_id)
+    .bind(after_index)
+    .bind(filter.limit + 1)
+    .fetch_all(pool)
+    .await?;
+    let has_next_page = changes.len() as i64 > filter.limit;
+    changes.truncate(filter.limit as usize);
+    Ok((changes, has_next_page))
+}
+
+// The rows of the transaction and of the receipts descending from it, in the order of the chain.
+// The receipts executed after the restart of the indexer have no parent_transaction_hash
+pub(crate) async fn transaction_changes(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    transaction_hash: &str,
+) -> anyhow::Result<Vec<BalanceChange>> {
+    Ok(sqlx::query_as(&format!(
+        "SELECT {} FROM {} \
+        WHERE coalesce(parent_transaction_hash, transaction_hash) = $1 \
+        ORDER BY block_timestamp, shard_id, index_in_chunk",
+        crate::models::balance_changes::SELECT_COLUMNS,
+        crate::db_adapters::table("balance_changes")
+    ))
+    .bind(transaction_hash)
+    .fetch_all(pool)
+    .await?)
+}
+
+// None if the account did not exist at that moment
+pub(crate) async fn balance(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    account_id: &str,
+    at: BalanceAt,
+) -> anyhow::Result<Option<Balance>> {
+    let block_timestamp = match at {
+        BalanceAt::Latest => None,
+        BalanceAt::BlockHeight(height) => Some(BigDecimal::from(
+            crate::db_adapters::genesis::get_block_timestamp(json_rpc_client, height).await?,
+        )),
+        BalanceAt::BlockTimestamp(timestamp) => Some(timestamp),
+    };
+    let row: Option<(BigDecimal, BigDecimal, BigDecimal)> = match block_timestamp {
+        Some(block_timestamp) => {
+            sqlx::query_as(&format!(
+            "SELECT block_timestamp::numeric, absolute_nonstaked_amount, absolute_staked_amount \
+                FROM {} \
+                WHERE affected_account_id = $1 AND block_timestamp <= $2::bigint \
+                ORDER BY block_timestamp DESC, shard_id DESC, index_in_chunk DESC \
+                LIMIT 1",
+            crate::db_adapters::table("balance_changes")
+        ))
+            .bind(account_id)
+            .bind(block_timestamp)
+            .fetch_optional(pool)
+            .await?
+        }
+        None => {
+            sqlx::query_as(&format!(
+                "SELECT block_timestamp, nonstaked_amount, staked_amount \
+                FROM {} WHERE account_id = $1",
+                crate::db_adapters::table("current_balances")
+            ))
+            .bind(account_id)
+            .fetch_optional(pool)
+            .await?
+        }
+    };
+    Ok(row.map(
+        |(block_timestamp, nonstaked_amount, staked_amount)| Balance {
+            block_timestamp,
+            nonstaked_amount,
+            staked_amount,
+        },
+    ))
+}
+
+// The balance after the latest change at or before the timestamp (nanoseconds).
+// The account may have no rows, e.g. it has not changed since the indexing has started:
+// then RPC is asked at the latest stored block at or before the timestamp.
+// None if the account did not exist at that moment
+pub(crate) async fn balance_at_timestamp(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    account_id: &str,
+    block_timestamp: u64,
+) -> anyhow::Result<Option<(Balance, BalanceSource)>> {
+    let at = BalanceAt::BlockTimestamp(BigDecimal::from(block_timestamp));
+    if let Some(balance) = balance(pool, json_rpc_client, account_id, at).await? {
+        return Ok(Some((balance, BalanceSource::Indexed)));
+    }
+
+    let block: Option<(BigDecimal, BigDecimal)> = sqlx::query_as(&format!(
+        "SELECT block_height, block_timestamp FROM {} \
+            WHERE block_timestamp <= $1 ORDER BY block_timestamp DESC LIMIT 1",
+        crate::db_adapters::table("block_balance_summary")
+    ))
+    .bind(BigDecimal::from(block_timestamp))
+    .fetch_optional(pool)
+    .await?;
+    let (block_height, block_timestamp) = block
+        .ok_or_else(|| anyhow::anyhow!("No blocks are indexed at or before {}", block_timestamp))?;
+    let block_height = block_height
+        .to_u64()
+        .ok_or_else(|| anyhow::anyhow!("Invalid block_height {}", block_height))?;
+    let response = json_rpc_client
+        .call(near_jsonrpc_client::methods::query::RpcQueryRequest {
+            block_reference: near_primitives::types::BlockReference::BlockId(
+                near_primitives::types::BlockId::Height(block_height),
+            ),
+            request: near_primitives::views::QueryRequest::ViewAccount {
+                account_id: account_id.parse()?,
+            },
+        })
+        .await;
+    let account = match response {
+        Ok(response) => match response.kind {
+            near_jsonrpc_primitives::types::query::QueryResponseKind::ViewAccount(account) => {
+                account
+            }
+            _ => anyhow::bail!("Unexpected response kind for ViewAccount of {}", account_id),
+        },
+        Err(err) => match err.handler_error() {
+            Some(near_jsonrpc_primitives::types::query::RpcQueryError::UnknownAccount {
+                ..
+            }) => return Ok(None),
+            _ => return Err(err.into()),
+        },
+    };
+    Ok(Some((
+        Balance {
+            block_timestamp,
+            nonstaked_amount: crate::models::to_decimal(account.amount),
+            staked_amount: crate::models::to_decimal(account.locked),
+        },
+        BalanceSource::Rpc,
+    )))
+}
+
+// What the account can transfer: the storage is paid by the staked amount first, the rest of it
+// is locked in the nonstaked amount. The stake being unstaked stays in staked_amount until
+// the end of the epoch, so it is never spendable here.
+// The unstakes from a staking pool are excluded: the tokens unstaked there are the balance of the pool
+// until they are withdrawn, they are not in the balance of the account.
+// The indexed balance and the storage usage from RPC are both taken at `block_height`, by default
+// the latest indexed block. None if the account did not exist
+pub(crate) async fn spendable_balance(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    account_id: &str,
+    at: BalanceAt,
+) -> anyhow::Result<Option<SpendableBalance>> {
+    let latest: Option<(BigDecimal, BigDecimal)> = sqlx::query_as(&format!(
+        "SELECT block_height, block_timestamp FROM {} \
+            ORDER BY block_timestamp DESC LIMIT 1",
+        crate::db_adapters::table("block_balance_summary")
+    ))
+    .fetch_optional(pool)
+    .await?;
+    let (latest_height, latest_timestamp) =
+        latest.ok_or_else(|| anyhow::anyhow!("No blocks are indexed yet"))?;
+    let latest_height = latest_height
+        .to_u64()
+        .ok_or_else(|| anyhow::anyhow!("Invalid block_height {}", latest_height))?;
+    let (block_height, at) = match at {
+        BalanceAt::Latest => (latest_height, BalanceAt::BlockTimestamp(latest_timestamp)),
+        BalanceAt::BlockHeight(height) if height > latest_height => anyhow::bail!(
+            "Block {} is not indexed yet, the latest indexed block is {}",
+            height,
+            latest_height
+        ),
+        BalanceAt::BlockHeight(height) => (height, BalanceAt::BlockHeight(height)),
+        BalanceAt::BlockTimestamp(_) => {
+            anyhow::bail!("Spendable balance is computed at block_height or at the latest block")
+        }
+    };
+    let balance = match balance(pool, json_rpc_client, account_id, at).await? {
+        Some(balance) => balance,
+        None => return Ok(None),
+    };
+    let response = json_rpc_client
+        .call(near_jsonrpc_client::methods::query::RpcQueryRequest {
+            block_reference: near_primitives::types::BlockReference::BlockId(
+                near_primitives::types::BlockId::Height(block_height),
+            ),
+            request: near_primitives::views::QueryRequest::ViewAccount {
+                account_id: account_id.parse()?,
+            },
+        })
+        .await?;
+    let storage_usage = match response.kind {
+        near_jsonrpc_primitives::types::query::QueryResponseKind::ViewAccount(account) => {
+            account.storage_usage
+        }
+        _ => anyhow::bail!("Unexpected response kind for ViewAccount of {}", account_id),
+    };
+
+    let storage_amount = crate::models::to_decimal(storage_usage as u128 * STORAGE_AMOUNT_PER_BYTE);
+    let storage_locked_amount = (storage_amount - &balance.staked_amount).max(BigDecimal::zero());
+    let spendable_amount =
+        (&balance.nonstaked_amount - &storage_locked_amount).max(BigDecimal::zero());
+    Ok(Some(SpendableBalance {
+        block_height,
+        balance,
+        storage_usage,
+        storage_locked_amount,
+        spendable_amount,
+    }))
+}
+
+pub(crate) fn cursor(change: &BalanceChange) -> String {
+    format!(
+        "{}-{}-{}",
+        change.block_timestamp, change.shard_id, change.index_in_chunk
+    )
+}
+
+fn parse_cursor(cursor: &str) -> anyhow::Result<(BigDecimal, i32, i32)> {
+    let invalid = || anyhow::anyhow!("Invalid cursor {}", cursor);
+    let mut parts = cursor.splitn(3, '-');
+    let block_timestamp = BigDecimal::from_str(parts.next().ok_or_else(invalid)?)?;
+    let shard_id = parts.next().ok_or_else(invalid)?.parse()?;
+    let index_in_chunk = parts.next().ok_or_else(invalid)?.parse()?;
+    Ok((block_timestamp, shard_id, index_in_chunk))
+}
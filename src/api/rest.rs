@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use hyper::{Body, Request, Response, StatusCode};
+
+use crate::api::queries::{self, BalanceAt, ChangesFilter};
+use crate::models::balance_changes::BalanceChange;
+
+// GET /accounts/{id}/changes?from=&to=&cause=&after=&limit=
+//     `from` and `to` are inclusive block timestamps in nanoseconds, `after` is the cursor of the previous page
+// GET /accounts/{id}/balance?block_height=  or  ?block_timestamp=
+//     the latest balance without the parameters
+// GET /accounts/{id}/spendable?block_height=
+//     the balance minus the storage locked part at one block, the latest indexed one without the parameter,
+//     see queries::spendable_balance
+// GET /transactions/{hash}/changes
+//     all the rows of the transaction: its own ones and the ones of the receipts it has caused
+//
+// JSON by default, CSV with `Accept: text/csv`. The next page cursor is in `next_cursor`
+// of the JSON and in the `X-Next-Cursor` header
+pub(crate) async fn handle(
+    api: &crate::api::Api,
+    request: &Request<Body>,
+) -> anyhow::Result<Response<Body>> {
+    let is_csv = request
+        .headers()
+        .get(hyper::header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map_or(false, |accept| accept.contains("text/csv"));
+    let params: HashMap<String, String> = request
+        .uri()
+        .query()
+        .map(|query| {
+            reqwest::Url::parse(&format!("http://localhost/?{}", query))
+                .map(|url| url.query_pairs().into_owned().collect())
+                .unwrap_or_default()
+        })
+        .unwrap_or_default();
+    let segments: Vec<&str> = request.uri().path().trim_matches('/').split('/').collect();
+
+    let result = match segments.as_slice() {
+        ["accounts", account_id, "changes"] => changes(api, account_id, &params, is_csv).await,
+        ["accounts", account_id, "balance"] => balance(api, account_id, &params, is_csv).await,
+        ["accounts", account_id, "spendable"] => spendable(api, account_id, &params, is_csv).await,
+        ["transactions", transaction_hash, "changes"] => {
+            transaction_changes(api, transaction_hash, is_csv).await
+        }
+        _ => return error(StatusCode::NOT_FOUND, "Not found"),
+    };
+    match result {
+        Ok(response) => Ok(response),
+        Err(err) => error(StatusCode::BAD_REQUEST, &format!("{:#}", err)),
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ChangesResponse {
+    changes: Vec<BalanceChange>,
+    next_cursor: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct BalanceResponse<'a> {
+    account_id: &'a str,
+    block_timestamp: BigDecimal,
+    nonstaked_amount: BigDecimal,
+    staked_amount: BigDecimal,
+}
+
+#[derive(serde::Serialize)]
+struct SpendableResponse<'a> {
+    account_id: &'a str,
+    block_height: u64,
+    block_timestamp: BigDecimal,
+    nonstaked_amount: BigDecimal,
+    staked_amount: BigDecimal,
+    storage_usage: u64,
+    storage_locked_amount: BigDecimal,
+    spendable_amount: BigDecimal,
+}
+
+async fn changes(
+    api: &crate::api::Api,
+    account_id: &str,
+    params: &HashMap<String, String>,
+    is_csv: bool,
+) -> anyhow::Result<Response<Body>> {
+    let timestamp = |name: &str| {
+        params
+            .get(name)
+            .map(|value| BigDecimal::from_str(value))
+            .transpose()
+            .map_err(|_| anyhow::anyhow!("`{}` should be a block timestamp in nanoseconds", name))
+    };
+    let (changes, has_next_page) = queries::account_changes(
+        &api.pool,
+        &ChangesFilter {
+            account_id: account_id.to_string(),
+            cause: params.get("cause").cloned(),
+            from_timestamp: timestamp("from")?,
+            to_timestamp: timestamp("to")?,
+            after: params.get("after").cloned(),
+            limit: queries::validate_limit(
+                params.get("limit").map(|limit| limit.parse()).transpose()?,
+            )?,
+        },
+    )
+    .await?;
+    let next_cursor = changes
+        .last()
+        .filter(|_| has_next_page)
+        .map(queries::cursor);
+
+    let mut response = Response::builder();
+    if let Some(next_cursor) = &next_cursor {
+        response = response.header("X-Next-Cursor", next_cursor);
+    }
+    if is_csv {
+        Ok(response
+            .header("Content-Type", "text/csv")
+            .body(Body::from(changes_csv(&changes)))?)
+    } else {
+        Ok(response
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&ChangesResponse {
+                changes,
+                next_cursor,
+            })?))?)
+    }
+}
+
+async fn transaction_changes(
+    api: &crate::api::Api,
+    transaction_hash: &str,
+    is_csv: bool,
+) -> anyhow::Result<Response<Body>> {
+    let changes = queries::transaction_changes(&api.pool, transaction_hash).await?;
+    if is_csv {
+        Ok(Response::builder()
+            .header("Content-Type", "text/csv")
+            .body(Body::from(changes_csv(&changes)))?)
+    } else {
+        Ok(Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&ChangesResponse {
+                changes,
+                next_cursor: None,
+            })?))?)
+    }
+}
+
+fn changes_csv(changes: &[BalanceChange]) -> String {
+    let mut csv = String::from(
+        "block_timestamp,receipt_id,transaction_hash,affected_account_id,involved_account_id,\
+        direction,cause,status,delta_nonstaked_amount,absolute_nonstaked_amount,\
+        delta_staked_amount,absolute_staked_amount,shard_id,index_in_chunk,index_in_block,event_id,\
+        parent_transaction_hash,index_in_receipt\n",
+    );
+    for change in changes {
+        csv_row(
+            &mut csv,
+            &[
+                &change.block_timestamp.to_string(),
+                change.receipt_id.as_deref().unwrap_or_default(),
+                change.transaction_hash.as_deref().unwrap_or_default(),
+                &change.affected_account_id,
+                change.involved_account_id.as_deref().unwrap_or_default(),
+                &change.direction,
+                &change.cause,
+                &change.status,
+                &change.delta_nonstaked_amount.to_string(),
+                &change.absolute_nonstaked_amount.to_string(),
+                &change.delta_staked_amount.to_string(),
+                &change.absolute_staked_amount.to_string(),
+                &change.shard_id.to_string(),
+                &change.index_in_chunk.to_string(),
+                &change.index_in_block.to_string(),
+                &change.event_id,
+                change
+                    .parent_transaction_hash
+                    .as_deref()
+                    .unwrap_or_default(),
+                &change
+                    .index_in_receipt
+                    .map(|index| index.to_string())
+                    .unwrap_or_default(),
+            ],
+        );
+    }
+    csv
+}
+
+async fn balance(
+    api: &crate::api::Api,
+    account_id: &str,
+    params: &HashMap<String, String>,
+    is_csv: bool,
+) -> anyhow::Result<Response<Body>> {
+    let at = match (params.get("block_height"), params.get("block_timestamp")) {
+        (Some(_), Some(_)) => {
+            anyhow::bail!("Only one of block_height and block_timestamp may be given")
+        }
+        (Some(height), None) => BalanceAt::BlockHeight(height.parse()?),
+        (None, Some(timestamp)) => BalanceAt::BlockTimestamp(BigDecimal::from_str(timestamp)?),
+        (None, None) => BalanceAt::Latest,
+    };
+    let balance = match queries::balance(&api.pool, &api.json_rpc_client, account_id, at).await? {
+        Some(balance) => balance,
+        None => {
+            return error(
+                StatusCode::NOT_FOUND,
+                "Account did not exist at that moment",
+            )
+        }
+    };
+
+    if is_csv {
+        let mut csv = String::from("account_id,block_timestamp,nonstaked_amount,staked_amount\n");
+        csv_row(
+            &mut csv,
+            &[
+                account_id,
+                &balance.block_timestamp.to_string(),
+                &balance.nonstaked_amount.to_string(),
+                &balance.staked_amount.to_string(),
+            ],
+        );
+        Ok(Response::builder()
+            .header("Content-Type", "text/csv")
+            .body(Body::from(csv))?)
+    } else {
+        Ok(Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&BalanceResponse {
+                account_id,
+                block_timestamp: balance.block_timestamp,
+                nonstaked_amount: balance.nonstaked_amount,
+                staked_amount: balance.staked_amount,
+            })?))?)
+    }
+}
+
+async fn spendable(
+    api: &crate::api::Api,
+    account_id: &str,
+    params: &HashMap<String, String>,
+    is_csv: bool,
+) -> anyhow::Result<Response<Body>> {
+    let at = match params.get("block_height") {
+        Some(height) => BalanceAt::BlockHeight(height.parse()?),
+        None => BalanceAt::Latest,
+    };
+    let spendable =
+        match queries::spendable_balance(&api.pool, &api.json_rpc_client, account_id, at).await? {
+            Some(spendable) => spendable,
+            None => {
+                return error(
+                    StatusCode::NOT_FOUND,
+                    "Account did not exist at that moment",
+                )
+            }
+        };
+
+    if is_csv {
+        let mut csv = String::from(
+            "account_id,block_height,block_timestamp,nonstaked_amount,staked_amount,storage_usage,\
+            storage_locked_amount,spendable_amount\n",
+        );
+        csv_row(
+            &mut csv,
+            &[
+                account_id,
+                &spendable.block_height.to_string(),
+                &spendable.balance.block_timestamp.to_string(),
+                &spendable.balance.nonstaked_amount.to_string(),
+                &spendable.balance.staked_amount.to_string(),
+                &spendable.storage_usage.to_string(),
+                &spendable.storage_locked_amount.to_string(),
+                &spendable.spendable_amount.to_string(),
+            ],
+        );
+        Ok(Response::builder()
+            .header("Content-Type", "text/csv")
+            .body(Body::from(csv))?)
+    } else {
+        Ok(Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&SpendableResponse {
+                account_id,
+                block_height: spendable.block_height,
+                block_timestamp: spendable.balance.block_timestamp,
+                nonstaked_amount: spendable.balance.nonstaked_amount,
+                staked_amount: spendable.balance.staked_amount,
+                storage_usage: spendable.storage_usage,
+                storage_locked_amount: spendable.storage_locked_amount,
+                spendable_amount: spendable.spendable_amount,
+            })?))?)
+    }
+}
+
+pub(crate) fn csv_row(csv: &mut String, fields: &[&str]) {
+    let escaped: Vec<String> = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect();
+    csv.push_str(&escaped.join(","));
+    csv.push('\n');
+}
+
+fn error(status: StatusCode, message: &str) -> anyhow::Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            serde_json::json!({ "error": message }).to_string(),
+        ))?)
+}
@@ -0,0 +1,245 @@
+use std::str::FromStr;
+
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, SimpleObject};
+use bigdecimal::BigDecimal;
+
+use crate::api::queries::{self, BalanceAt, ChangesFilter};
+use crate::models::balance_changes::BalanceChange;
+
+// The amounts and timestamps are strings, they do not fit into the JSON numbers
+pub(crate) type Schema = async_graphql::Schema<Query, EmptyMutation, EmptySubscription>;
+
+pub(crate) fn build(
+    pool: sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+) -> Schema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(pool)
+        .data(json_rpc_client)
+        .finish()
+}
+
+pub(crate) struct Query;
+
+#[Object]
+impl Query {
+    /// Changes of the account in the order they were applied, 100 by default, 1000 at most
+    async fn balance_changes(
+        &self,
+        ctx: &Context<'_>,
+        account_id: String,
+        cause: Option<String>,
+        first: Option<i64>,
+        after: Option<String>,
+    ) -> async_graphql::Result<BalanceChangesPage> {
+        let (changes, has_next_page) = queries::account_changes(
+            ctx.data::<sqlx::Pool<sqlx::Postgres>>()?,
+            &ChangesFilter {
+                account_id,
+                cause,
+                from_timestamp: None,
+                to_timestamp: None,
+                after,
+                limit: queries::validate_limit(first).map_err(error)?,
+            },
+        )
+        .await
+        .map_err(error)?;
+        Ok(BalanceChangesPage {
+            page_info: PageInfo {
+                end_cursor: changes.last().map(queries::cursor),
+                has_next_page,
+            },
+            nodes: changes.into_iter().map(BalanceChangeNode).collect(),
+        })
+    }
+
+    /// Latest balance if neither blockHeight nor blockTimestamp is given
+    async fn balance(
+        &self,
+        ctx: &Context<'_>,
+        account_id: String,
+        block_height: Option<u64>,
+        block_timestamp: Option<String>,
+    ) -> async_graphql::Result<Option<Balance>> {
+        let at = match (block_height, block_timestamp) {
+            (Some(_), Some(_)) => {
+                return Err("Only one of blockHeight and blockTimestamp may be given".into())
+            }
+            (Some(height), None) => BalanceAt::BlockHeight(height),
+            (None, Some(timestamp)) => BalanceAt::BlockTimestamp(BigDecimal::from_str(&timestamp)?),
+            (None, None) => BalanceAt::Latest,
+        };
+        let balance = queries::balance(
+            ctx.data::<sqlx::Pool<sqlx::Postgres>>()?,
+            ctx.data::<near_jsonrpc_client::JsonRpcClient>()?,
+            &account_id,
+            at,
+        )
+        .await
+        .map_err(error)?;
+        Ok(balance.map(|balance| Balance {
+            account_id,
+            block_timestamp: balance.block_timestamp.to_string(),
+            nonstaked_amount: balance.nonstaked_amount.to_string(),
+            staked_amount: balance.staked_amount.to_string(),
+        }))
+    }
+}
+
+#[derive(SimpleObject)]
+pub(crate) struct BalanceChangesPage {
+    nodes: Vec<BalanceChangeNode>,
+    page_info: PageInfo,
+}
+
+#[derive(SimpleObject)]
+pub(crate) struct PageInfo {
+    end_cursor: Option<String>,
+    has_next_page: bool,
+}
+
+#[derive(SimpleObject)]
+pub(crate) struct Balance {
+    account_id: String,
+    block_timestamp: String,
+    nonstaked_amount: String,
+    staked_amount: String,
+}
+
+pub(crate) struct BalanceChangeNode(BalanceChange);
+
+#[Object(name = "BalanceChange")]
+impl BalanceChangeNode {
+    /// Pass it as `after` to get the next page
+    async fn cursor(&self) -> String {
+        queries::cursor(&self.0)
+    }
+
+    async fn block_timestamp(&self) -> String {
+        self.0.block_timestamp.to_string()
+    }
+
+    async fn receipt_id(&self) -> Option<&str> {
+        self.0.receipt_id.as_deref()
+    }
+
+    async fn transaction_hash(&self) -> Option<&str> {
+        self.0.transaction_hash.as_deref()
+    }
+
+    async fn affected_account_id(&self) -> &str {
+        &self.0.affected_account_id
+    }
+
+    async fn involved_account_id(&self) -> Option<&str> {
+        self.0.involved_account_id.as_deref()
+    }
+
+    async fn direction(&self) -> &str {
+        &self.0.direction
+    }
+
+    async fn cause(&self) -> &str {
+        &self.0.cause
+    }
+
+    async fn status(&self) -> &str {
+        &self.0.status
+    }
+
+    async fn delta_nonstaked_amount(&self) -> String {
+        self.0.delta_nonstaked_amount.to_string()
+    }
+
+    async fn absolute_nonstaked_amount(&self) -> String {
+        self.0.absolute_nonstaked_amount.to_string()
+    }
+
+    async fn delta_staked_amount(&self) -> String {
+        self.0.delta_staked_amount.to_string()
+    }
+
+    async fn absolute_staked_amount(&self) -> String {
+        self.0.absolute_staked_amount.to_string()
+    }
+
+    async fn shard_id(&self) -> i32 {
+        self.0.shard_id
+    }
+
+    async fn index_in_chunk(&self) -> i32 {
+        self.0.index_in_chunk
+    }
+
+    async fn index_in_block(&self) -> i32 {
+        self.0.index_in_block
+    }
+
+    async fn gas_burnt(&self) -> Option<String> {
+        self.0.gas_burnt.as_ref().map(ToString::to_string)
+    }
+
+    async fn predecessor_account_id(&self) -> Option<&str> {
+        self.0.predecessor_account_id.as_deref()
+    }
+
+    async fn receiver_account_id(&self) -> Option<&str> {
+        self.0.receiver_account_id.as_deref()
+    }
+
+    async fn event_id(&self) -> &str {
+        &self.0.event_id
+    }
+
+    async fn parent_transaction_hash(&self) -> Option<&str> {
+        self.0.parent_transaction_hash.as_deref()
+    }
+
+    async fn index_in_receipt(&self) -> Option<i32> {
+        self.0.index_in_receipt
+    }
+}
+
+// The whole chain of the causes, async-graphql takes only the top one
+fn error(err: anyhow::Error) -> async_graphql::Error {
+    async_graphql::Error::new(format!("{:#}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The queries which fail the validation never reach the database
+    fn schema() -> Schema {
+        Schema::build(Query, EmptyMutation, EmptySubscription).finish()
+    }
+
+    #[test]
+    fn exposes_the_documented_fields() {
+        let sdl = schema().sdl();
+        assert!(sdl.contains(
+            "balanceChanges(accountId: String!, cause: String, first: Int, after: String): BalanceChangesPage!"
+        ));
+        assert!(sdl.contains(
+            "balance(accountId: String!, blockHeight: Int, blockTimestamp: String): Balance"
+        ));
+        assert!(sdl.contains("type BalanceChange {"));
+        assert!(sdl.contains("endCursor: String"));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_queries() {
+        for query in [
+            "mutation { delete }",
+            "{ missing }",
+            "{ balance { stakedAmount } }",
+            "{ balance(accountId: 1) { stakedAmount } }",
+            "{ balanceChanges(accountId: \"alice.near\") { nodes } }",
+            "{ balance(accountId: \"alice.near\") { stakedAmount }",
+        ] {
+            let response = schema().execute(query).await;
+            assert!(!response.errors.is_empty(), "{} should fail", query);
+        }
+    }
+}
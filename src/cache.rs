@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use cached::{Cached, SizedCache, TimedSizedCache};
+
+// SizedCache which remembers what the current block has changed.
+// The block may fail in the middle, after some accounts already got their new balances:
+// `rollback` puts back the values and the missing marks they had before the block, so the retry
+// computes the same deltas with the same lookups. The entries evicted meanwhile are just queried from RPC again.
+// The journals nest: `commit` of the inner one hands its changes to the outer one
+pub struct JournaledCache<K, V> {
+    cache: SizedCache<K, V>,
+    // The states before each open journal, the innermost one is the last
+    journals: Vec<HashMap<K, Before<V>>>,
+    // The keys known to have no value, e.g. the accounts which do not exist. They outlive
+    // the eviction from `cache` until the TTL, and are forgotten when the value is set
+    missing: Option<TimedSizedCache<K, ()>>,
+}
+
+struct Before<V> {
+    // None if the key was not cached
+    value: Option<V>,
+    missing: bool,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> JournaledCache<K, V> {
+    pub(crate) fn with_size(size: usize) -> Self {
+        Self {
+            cache: SizedCache::with_size(size),
+            journals: vec![],
+            missing: None,
+        }
+    }
+
+    // 0 does not remember the missing keys
+    pub(crate) fn with_missing_ttl(mut self, size: usize, ttl_secs: u64) -> Self {
+        self.missing =
+            (ttl_secs > 0).then(|| TimedSizedCache::with_size_and_lifespan(size, ttl_secs));
+        self
+    }
+
+    pub(crate) fn is_missing(&mut self, key: &K) -> bool {
+        match self.missing.as_mut() {
+            Some(missing) => missing.cache_get(key).is_some(),
+            None => false,
+        }
+    }
+
+    pub(crate) fn set_missing(&mut self, key: K) {
+        if self.missing.is_some() {
+            self.record(&key);
+        }
+        if let Some(missing) = self.missing.as_mut() {
+            missing.cache_set(key, ());
+        }
+    }
+
+    pub(crate) fn cache_get(&mut self, key: &K) -> Option<&V> {
+        self.cache.cache_get(key)
+    }
+
+    pub(crate) fn cache_set(&mut self, key: K, value: V) -> Option<V> {
+        self.record(&key);
+        self.forget_missing(&key);
+        self.cache.cache_set(key, value)
+    }
+
+    pub(crate) fn cache_remove(&mut self, key: &K) -> Option<V> {
+        self.record(key);
+        self.forget_missing(key);
+        self.cache.cache_remove(key)
+    }
+
+    pub(crate) fn cache_clear(&mut self) {
+        self.cache.cache_clear();
+        if let Some(missing) = self.missing.as_mut() {
+            missing.cache_clear();
+        }
+        self.journals.iter_mut().for_each(HashMap::clear);
+    }
+
+    pub(crate) fn begin(&mut self) {
+        self.journals.push(HashMap::new());
+    }
+
+    pub(crate) fn commit(&mut self) {
+        let journal = self.journals.pop().unwrap_or_default();
+        // The outer journal keeps the states before it, the inner ones are newer
+        if let Some(outer) = self.journals.last_mut() {
+            for (key, before) in journal {
+                outer.entry(key).or_insert(before);
+            }
+        }
+    }
+
+    // Returns the number of restored keys
+    pub(crate) fn rollback(&mut self) -> usize {
+        let journal = self.journals.pop().unwrap_or_default();
+        let restored = journal.len();
+        // SizedCache evicts on a full cache even when the key is there, so the keys added by the block
+        // are removed before the changed ones are set back
+        let (changed, added): (Vec<_>, Vec<_>) = journal
+            .into_iter()
+            .partition(|(_, before)| before.value.is_some());
+        for (key, before) in added.into_iter().chain(changed) {
+            match before.value {
+                Some(value) => {
+                    self.cache.cache_set(key.clone(), value);
+                }
+                None => {
+                    self.cache.cache_remove(&key);
+                }
+            }
+            if let Some(missing) = self.missing.as_mut() {
+                match before.missing {
+                    true => {
+                        missing.cache_set(key, ());
+                    }
+                    false => {
+                        missing.cache_remove(&key);
+                    }
+                }
+            }
+        }
+        restored
+    }
+
+    fn forget_missing(&mut self, key: &K) {
+        if let Some(missing) = self.missing.as_mut() {
+            missing.cache_remove(key);
+        }
+    }
+
+    fn record(&mut self, key: &K) {
+        let is_recorded = match self.journals.last() {
+            Some(journal) => journal.contains_key(key),
+            None => return,
+        };
+        if !is_recorded {
+            let before = Before {
+                value: self.cache.cache_get(key).cloned(),
+                missing: self.is_missing(key),
+            };
+            if let Some(journal) = self.journals.last_mut() {
+                journal.insert(key.clone(), before);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JournaledCache;
+
+    fn cache() -> JournaledCache<&'static str, u64> {
+        JournaledCache::with_size(3).with_missing_ttl(10, 60)
+    }
+
+    #[test]
+    fn rollback_restores_values_and_missing_keys() {
+        let mut cache = cache();
+        cache.cache_set("alice.near", 1);
+        cache.set_missing("deleted.near");
+        cache.begin();
+        cache.cache_set("alice.near", 2);
+        cache.cache_set("bob.near", 3);
+        cache.cache_set("deleted.near", 4);
+        cache.set_missing("unknown.near");
+        assert!(!cache.is_missing(&"deleted.near"));
+        assert_eq!(cache.rollback(), 4);
+
+        assert_eq!(cache.cache_get(&"alice.near"), Some(&1));
+        assert_eq!(cache.cache_get(&"bob.near"), None);
+        assert_eq!(cache.cache_get(&"deleted.near"), None);
+        assert!(cache.is_missing(&"deleted.near"));
+        assert!(!cache.is_missing(&"unknown.near"));
+    }
+
+    #[test]
+    fn commit_keeps_the_changes() {
+        let mut cache = cache();
+        cache.begin();
+        cache.cache_set("alice.near", 1);
+        cache.set_missing("unknown.near");
+        cache.commit();
+        assert_eq!(cache.rollback(), 0);
+        assert_eq!(cache.cache_get(&"alice.near"), Some(&1));
+        assert!(cache.is_missing(&"unknown.near"));
+    }
+
+    #[test]
+    fn nested_rollback_restores_the_inner_changes_only() {
+        let mut cache = cache();
+        cache.begin();
+        cache.cache_set("alice.near", 1);
+        cache.begin();
+        cache.cache_set("alice.near", 2);
+        cache.set_missing("unknown.near");
+        cache.rollback();
+        assert_eq!(cache.cache_get(&"alice.near"), Some(&1));
+        assert!(!cache.is_missing(&"unknown.near"));
+        cache.rollback();
+        assert_eq!(cache.cache_get(&"alice.near"), None);
+    }
+
+    #[test]
+    fn nested_commit_is_rolled_back_with_the_outer_journal() {
+        let mut cache = cache();
+        cache.cache_set("alice.near", 1);
+        cache.begin();
+        cache.cache_set("bob.near", 2);
+        cache.begin();
+        cache.cache_set("alice.near", 3);
+        cache.set_missing("bob.near");
+        cache.commit();
+        assert_eq!(cache.cache_get(&"alice.near"), Some(&3));
+        assert_eq!(cache.rollback(), 2);
+        assert_eq!(cache.cache_get(&"alice.near"), Some(&1));
+        assert_eq!(cache.cache_get(&"bob.near"), None);
+        assert!(!cache.is_missing(&"bob.near"));
+    }
+
+    #[test]
+    fn rollback_after_eviction() {
+        let mut cache = cache();
+        cache.cache_set("a.near", 1);
+        cache.cache_set("b.near", 2);
+        cache.cache_set("c.near", 3);
+        cache.begin();
+        cache.cache_set("c.near", 4);
+        // Evicts the least recently used a.near, it was not changed by the block
+        cache.cache_set("d.near", 5);
+        assert_eq!(cache.cache_get(&"a.near"), None);
+        assert_eq!(cache.rollback(), 2);
+        assert_eq!(cache.cache_get(&"a.near"), None);
+        assert_eq!(cache.cache_get(&"b.near"), Some(&2));
+        assert_eq!(cache.cache_get(&"c.near"), Some(&3));
+        assert_eq!(cache.cache_get(&"d.near"), None);
+    }
+
+    #[test]
+    fn rollback_restores_the_evicted_changed_key() {
+        let mut cache = cache();
+        cache.cache_set("a.near", 1);
+        cache.begin();
+        cache.cache_set("a.near", 2);
+        cache.cache_set("b.near", 3);
+        cache.cache_set("c.near", 4);
+        // a.near is the least recently used one
+        cache.cache_set("d.near", 5);
+        assert_eq!(cache.cache_get(&"a.near"), None);
+        cache.rollback();
+        assert_eq!(cache.cache_get(&"a.near"), Some(&1));
+        assert_eq!(cache.cache_get(&"d.near"), None);
+    }
+}
@@ -0,0 +1,85 @@
+// Answers "is it keeping up?" without psql: the latest final block from RPC is compared
+// with the latest stored one. The alert fires once when the lag goes above the threshold,
+// and again only after the indexer has caught up
+pub(crate) fn spawn_lag_monitor(
+    json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+    interval: std::time::Duration,
+    alert_lag_blocks: Option<u64>,
+) {
+    tokio::spawn(async move {
+        let mut is_alerted = false;
+        loop {
+            match final_height(&json_rpc_client).await {
+                Ok(chain_head_height) => {
+                    crate::metrics::CHAIN_HEAD_HEIGHT.set(chain_head_height as i64);
+                    let indexed_height = crate::metrics::INDEXED_HEIGHT.get();
+                    // Nothing is stored yet after the start
+                    if indexed_height > 0 {
+                        let lag = chain_head_height.saturating_sub(indexed_height as u64);
+                        crate::metrics::LAG_BLOCKS.set(lag as i64);
+                        check_lag(
+                            lag,
+                            indexed_height as u64,
+                            alert_lag_blocks,
+                            &mut is_alerted,
+                        );
+                    }
+                }
+                Err(err) => tracing::warn!(
+                    target: crate::INDEXER,
+                    "Failed to get the chain head from RPC: {}",
+                    err
+                ),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+async fn final_height(json_rpc_client: &near_jsonrpc_client::JsonRpcClient) -> anyhow::Result<u64> {
+    Ok(json_rpc_client
+        .call(near_jsonrpc_client::methods::block::RpcBlockRequest {
+            block_reference: near_primitives::types::BlockReference::Finality(
+                near_primitives::types::Finality::Final,
+            ),
+        })
+        .await?
+        .header
+        .height)
+}
+
+fn check_lag(lag: u64, indexed_height: u64, alert_lag_blocks: Option<u64>, is_alerted: &mut bool) {
+    let alert_lag_blocks = match alert_lag_blocks {
+        Some(alert_lag_blocks) => alert_lag_blocks,
+        None => return,
+    };
+    if lag <= alert_lag_blocks {
+        if *is_alerted {
+            tracing::info!(
+                target: crate::INDEXER,
+                "Indexer has caught up, {} blocks behind the chain head",
+                lag
+            );
+        }
+        *is_alerted = false;
+        return;
+    }
+    if *is_alerted {
+        return;
+    }
+    *is_alerted = true;
+    let message = format!(
+        "Indexer is {} blocks behind the chain head, the threshold is {}",
+        lag, alert_lag_blocks
+    );
+    tracing::warn!(target: crate::INDEXER, "{}", message);
+    crate::error_reporting::spawn_report(
+        crate::error_reporting::Level::Warning,
+        "indexer_lag",
+        message,
+        vec![
+            ("block_height", indexed_height.to_string()),
+            ("lag_blocks", lag.to_string()),
+        ],
+    );
+}
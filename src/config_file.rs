@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+
+use clap::CommandFactory;
+
+const CONFIG_ENV: &str = "INDEXER_CONFIG";
+const NETWORK_ENV: &str = "INDEXER_NETWORK";
+// `INDEXER_INSERT_BATCH_SIZE=500` is `--insert-batch-size 500`
+const ENV_PREFIX: &str = "INDEXER_";
+const SKIPPED_ARGS: [&str; 4] = ["config", "network", "help", "version"];
+// What a `[networks.<name>]` section may set, the rest is shared by the networks of the process
+const NETWORK_OPTIONS: [&str; 23] = [
+    "chain-id",
+    "source",
+    "s3-bucket-name",
+    "s3-region-name",
+    "near-archival-rpc-url",
+    "genesis-block-height",
+    "start-block-height",
+    "stop-block-height",
+    "database",
+    "secondary-database",
+    "ha-lock-id",
+    "db-schema",
+    "table-prefix",
+    "protocol-treasury-account",
+    "wrap-near-contract",
+    "lockup-suffix",
+    "shared-balance-cache-prefix",
+    "disk-buffer-path",
+    "parquet-output",
+    "archive-output",
+    "kafka-topic",
+    "nats-subject",
+    "redis-channel",
+];
+
+// `--config config.toml` (or INDEXER_CONFIG) has the same options as the command line,
+// the keys are the long names: `database = "postgres://..."`, `insert-batch-size = 500`,
+// `shards = [0, 2]`, `pg-notify = true`. `snake_case` keys work too.
+// The precedence is: command line, the own env variable of the option (e.g. DATABASE_URL),
+// `INDEXER_<OPTION>`, the config file, the default.
+// The values are turned into the command line args, so clap validates them as usual.
+// The `[networks.<name>]` sections override the keys above them for that network,
+// each of them gets its own args, see src/networks.rs
+pub(crate) struct ConfigArgs {
+    pub network: Option<String>,
+    pub args: Vec<OsString>,
+    // Long name -> where the value is injected from
+    sources: HashMap<String, String>,
+}
+
+// One item without the networks in the config, or with --network
+pub(crate) fn load_args() -> anyhow::Result<Vec<ConfigArgs>> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let mut file = match arg_value(&args, "config").or_else(|| std::env::var(CONFIG_ENV).ok()) {
+        Some(path) => read_config(std::path::Path::new(&path))?,
+        None => toml::value::Table::new(),
+    };
+    let networks = match file.remove("networks") {
+        Some(toml::Value::Table(networks)) => networks,
+        Some(_) => anyhow::bail!("`networks` in the config should be `[networks.<name>]` sections"),
+        None => return Ok(vec![with_file(args, file, None)?]),
+    };
+    let selected = arg_value(&args, "network").or_else(|| std::env::var(NETWORK_ENV).ok());
+
+    let mut configs = vec![];
+    for (name, section) in networks {
+        if selected
+            .as_ref()
+            .map_or(false, |selected| *selected != name)
+        {
+            continue;
+        }
+        let section = match section {
+            toml::Value::Table(section) => section,
+            _ => anyhow::bail!("`networks.{}` in the config should be a section", name),
+        };
+        let mut network_file = file.clone();
+        for (key, value) in section {
+            let long = key.replace('_', "-");
+            if !NETWORK_OPTIONS.contains(&long.as_str()) {
+                anyhow::bail!(
+                    "`{}` is shared by the networks and cannot be set in `[networks.{}]`, only {}",
+                    key,
+                    name,
+                    NETWORK_OPTIONS.join(", ")
+                );
+            }
+            network_file.remove(&long.replace('-', "_"));
+            network_file.insert(long, value);
+        }
+        configs.push(with_file(args.clone(), network_file, Some(name))?);
+    }
+    if configs.is_empty() {
+        anyhow::bail!(
+            "No `[networks.{}]` in the config",
+            selected.unwrap_or_default()
+        );
+    }
+    Ok(configs)
+}
+
+fn with_file(
+    mut args: Vec<OsString>,
+    mut file: toml::value::Table,
+    network: Option<String>,
+) -> anyhow::Result<ConfigArgs> {
+    let mut injected = vec![];
+    let mut sources = HashMap::new();
+    for arg in crate::configs::Opts::command().get_arguments() {
+        let long = match arg.get_long() {
+            Some(long) if !SKIPPED_ARGS.contains(&long) => long,
+            _ => continue,
+        };
+        let file_value = file
+            .remove(long)
+            .or_else(|| file.remove(&long.replace('-', "_")));
+        let own_env = arg
+            .get_env()
+            .and_then(std::env::var_os)
+            .filter(|value| !value.is_empty());
+        if is_given(&args, long) || own_env.is_some() {
+            continue;
+        }
+        let env_name = format!("{}{}", ENV_PREFIX, long.replace('-', "_").to_uppercase());
+        let (values, source) = match (std::env::var(&env_name), file_value) {
+            (Ok(value), _) => (vec![toml::Value::String(value)], env_name),
+            (Err(_), Some(toml::Value::Array(values))) => (values, "config file".to_string()),
+            (Err(_), Some(value)) => (vec![value], "config file".to_string()),
+            (Err(_), None) => continue,
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Table(_) | toml::Value::Array(_) => {
+                    anyhow::bail!(
+                        "`{}` in the config should be a value or a list of values",
+                        long
+                    )
+                }
+                value => value.to_string(),
+            };
+            if matches!(arg.get_action(), clap::ArgAction::SetTrue) {
+                match value.as_str() {
+                    "true" | "1" => injected.push(OsString::from(format!("--{}", long))),
+                    "false" | "0" => {}
+                    _ => anyhow::bail!("`{}` should be true or false, got {}", long, value),
+                }
+            } else {
+                injected.push(OsString::from(format!("--{}={}", long, value)));
+            }
+        }
+        sources.insert(long.to_string(), source);
+    }
+    if let Some(key) = file.keys().next() {
+        anyhow::bail!("Unknown option `{}` in the config file", key);
+    }
+
+    // Before the subcommand, the top-level options are not accepted after it
+    args.splice(1..1, injected);
+    Ok(ConfigArgs {
+        network,
+        args,
+        sources,
+    })
+}
+
+// The value of the option before clap parses the args
+fn arg_value(args: &[OsString], long: &str) -> Option<String> {
+    let flag = format!("--{}", long);
+    let mut args = args.iter().filter_map(|arg| arg.to_str());
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next().map(Into::into);
+        }
+        if let Some(value) = arg
+            .strip_prefix(&flag)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(value.into());
+        }
+    }
+    None
+}
+
+fn read_config(path: &std::path::Path) -> anyhow::Result<toml::value::Table> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("Failed to read {}: {}", path.display(), err))?;
+    toml::from_str(&content)
+        .map_err(|err| anyhow::anyhow!("Failed to parse {}: {}", path.display(), err))
+}
+
+fn is_given(args: &[OsString], long: &str) -> bool {
+    let flag = format!("--{}", long);
+    args.iter().filter_map(|arg| arg.to_str()).any(|arg| {
+        arg == flag
+            || arg
+                .strip_prefix(&flag)
+                .map_or(false, |rest| rest.starts_with('='))
+    })
+}
+
+// `print-config`: the effective options in the config file format, with where each one comes from.
+// The secrets hidden in --help are hidden here too. The networks are printed one after another
+pub(crate) fn print_config(configs: &[ConfigArgs]) -> anyhow::Result<()> {
+    for config_args in configs {
+        if let Some(network) = &config_args.network {
+            println!("# [networks.{}]", network);
+        }
+        print_options(config_args)?;
+    }
+    Ok(())
+}
+
+fn print_options(config_args: &ConfigArgs) -> anyhow::Result<()> {
+    let command = crate::configs::Opts::command();
+    let matches = command.clone().try_get_matches_from(&config_args.args)?;
+    for arg in command.get_arguments() {
+        let long = match arg.get_long() {
+            Some(long) if !SKIPPED_ARGS.contains(&long) => long,
+            _ => continue,
+        };
+        let id = arg.get_id();
+        let source = match (config_args.sources.get(long), matches.value_source(id)) {
+            (Some(source), _) => source.as_str(),
+            (None, Some(clap::ValueSource::CommandLine)) => "command line",
+            (None, Some(clap::ValueSource::EnvVariable)) => "env",
+            (None, Some(clap::ValueSource::DefaultValue)) => "default",
+            (None, _) => continue,
+        };
+        let value = if matches!(arg.get_action(), clap::ArgAction::SetTrue) {
+            match matches.get_one::<bool>(id) {
+                Some(true) => toml::Value::Boolean(true),
+                _ => continue,
+            }
+        } else {
+            let mut values: Vec<toml::Value> = match matches.get_raw(id) {
+                Some(values) => values
+                    .map(|value| toml::Value::String(value.to_string_lossy().into_owned()))
+                    .collect(),
+                None => continue,
+            };
+            if arg.is_hide_env_values_set() {
+                toml::Value::String("<hidden>".to_string())
+            } else if values.len() == 1 {
+                values.remove(0)
+            } else {
+                toml::Value::Array(values)
+            }
+        };
+        println!("{} = {} # {}", long, value, source);
+    }
+    Ok(())
+}
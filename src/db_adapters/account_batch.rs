@@ -0,0 +1,248 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::StreamExt;
+use near_jsonrpc_client::methods::EXPERIMENTAL_changes::RpcStateChangesInBlockByTypeRequest;
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use near_lake_framework::near_indexer_primitives;
+
+// Archival RPC handles the parallel queries much better than the sequential ones
+const CONCURRENCY: usize = 20;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Catching up with the cold cache, almost every account of the block costs a ViewAccount call
+// made in the middle of computing. Instead, the previous balances of all the cold accounts
+// are loaded to the cache before computing the block:
+// the ones published by the other instances come from the shared balance cache,
+// the accounts changed by the previous block come from one EXPERIMENTAL_changes call at it,
+// the rest are queried concurrently
+pub(crate) fn configure_account_batching(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Accounts which balance may change in the block
+pub(crate) fn changed_account_ids(
+    shards: &[near_indexer_primitives::IndexerShard],
+) -> Vec<&near_indexer_primitives::types::AccountId> {
+    let mut account_ids: Vec<&near_indexer_primitives::types::AccountId> = shards
+        .iter()
+        .flat_map(|shard| &shard.state_changes)
+        .filter_map(|state_change| match &state_change.value {
+            near_indexer_primitives::views::StateChangeValueView::AccountUpdate {
+                account_id,
+                ..
+            }
+            | near_indexer_primitives::views::StateChangeValueView::AccountDeletion {
+                account_id,
+            } => Some(account_id),
+            _ => None,
+        })
+        .collect();
+    account_ids.sort();
+    account_ids.dedup();
+    account_ids
+}
+
+pub(crate) async fn load_previous_balances(
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    balances_cache: &crate::BalanceCache,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let block_hash = block_header.prev_hash;
+    let mut cold_account_ids = vec![];
+    {
+        let mut balances_cache_lock = balances_cache.lock().await;
+        for account_id in changed_account_ids(shards) {
+            // The missing ones are answered by the cache, see get_balance
+            if balances_cache_lock.cache_get(account_id).is_none()
+                && !balances_cache_lock.is_missing(account_id)
+            {
+                cold_account_ids.push(account_id.clone());
+            }
+        }
+    }
+    if cold_account_ids.is_empty() {
+        return;
+    }
+    let cold_count = cold_account_ids.len();
+
+    let mut loaded = HashSet::new();
+    if crate::db_adapters::shared_balance_cache().is_some() {
+        let block_height = block_header.height;
+        let shared_balances: Vec<_> = futures::stream::iter(cold_account_ids.clone())
+            .map(|account_id| async move {
+                let balance = crate::db_adapters::balance_changes::get_shared_balance(
+                    &account_id,
+                    block_height,
+                )
+                .await;
+                (account_id, balance)
+            })
+            .buffer_unordered(CONCURRENCY)
+            .collect()
+            .await;
+        let mut balances_cache_lock = balances_cache.lock().await;
+        for (account_id, balance) in shared_balances {
+            if let Some(balance) = balance {
+                balances_cache_lock.cache_set(account_id.clone(), balance);
+                loaded.insert(account_id);
+            }
+        }
+    }
+    let from_shared_cache = loaded.len();
+
+    // The cassette records ViewAccount only, the local nearcore state answers ViewAccount
+    if !crate::rpc_cassette::is_enabled() && !reads_nearcore_state() && loaded.len() < cold_count {
+        let account_ids: Vec<_> = cold_account_ids
+            .iter()
+            .filter(|account_id| !loaded.contains(*account_id))
+            .cloned()
+            .collect();
+        match changes_at_block(json_rpc_client, &block_hash, &account_ids).await {
+            Ok(balances) => {
+                let mut balances_cache_lock = balances_cache.lock().await;
+                for (account_id, balance) in balances {
+                    cache_previous_balance(&mut balances_cache_lock, account_id.clone(), balance);
+                    loaded.insert(account_id);
+                }
+            }
+            // Everything is queried with ViewAccount then
+            Err(err) => tracing::debug!(
+                target: crate::INDEXER,
+                "EXPERIMENTAL_changes failed at block {}: {}",
+                block_hash,
+                err
+            ),
+        }
+    }
+    let from_changes = loaded.len() - from_shared_cache;
+
+    let balances: Vec<_> = futures::stream::iter(
+        cold_account_ids
+            .into_iter()
+            .filter(|account_id| !loaded.contains(account_id)),
+    )
+    .map(|account_id| async move {
+        if let Some(balance) = crate::db_adapters::prefetch::take(&account_id, &block_hash).await {
+            return (account_id, Some(PreviousBalance::Exists(balance)));
+        }
+        let balance = match crate::db_adapters::balance_changes::get_account_view(
+            json_rpc_client,
+            &account_id,
+            &block_hash,
+        )
+        .await
+        {
+            Ok(account_view) => Some(PreviousBalance::Exists(crate::BalanceDetails {
+                non_staked: account_view.amount,
+                staked: account_view.locked,
+            })),
+            Err(err) => match err.handler_error() {
+                Some(RpcQueryError::UnknownAccount { .. }) => Some(PreviousBalance::Missing),
+                // Computing queries it again with the retries
+                _ => None,
+            },
+        };
+        (account_id, balance)
+    })
+    .buffer_unordered(CONCURRENCY)
+    .collect()
+    .await;
+    let mut balances_cache_lock = balances_cache.lock().await;
+    for (account_id, balance) in balances {
+        if let Some(balance) = balance {
+            cache_previous_balance(&mut balances_cache_lock, account_id, balance);
+        }
+    }
+    drop(balances_cache_lock);
+    tracing::debug!(
+        target: crate::INDEXER,
+        "Block {}: {} cold accounts, {} from the shared balance cache, {} loaded with EXPERIMENTAL_changes",
+        block_header.height,
+        cold_count,
+        from_shared_cache,
+        from_changes
+    );
+}
+
+enum PreviousBalance {
+    Exists(crate::BalanceDetails),
+    // Deleted or never created, the same as UNKNOWN_ACCOUNT in get_balance
+    Missing,
+}
+
+fn cache_previous_balance(
+    balances_cache: &mut crate::cache::JournaledCache<
+        near_indexer_primitives::types::AccountId,
+        crate::BalanceDetails,
+    >,
+    account_id: near_indexer_primitives::types::AccountId,
+    balance: PreviousBalance,
+) {
+    match balance {
+        PreviousBalance::Exists(balance) => {
+            balances_cache.cache_set(account_id, balance);
+        }
+        PreviousBalance::Missing => {
+            balances_cache.cache_set(account_id.clone(), crate::BalanceDetails::default());
+            balances_cache.set_missing(account_id);
+        }
+    }
+}
+
+#[cfg(feature = "nearcore-state")]
+fn reads_nearcore_state() -> bool {
+    crate::nearcore_state::is_enabled()
+}
+
+#[cfg(not(feature = "nearcore-state"))]
+fn reads_nearcore_state() -> bool {
+    false
+}
+
+// Balances after the block for the accounts it has changed
+async fn changes_at_block(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    block_hash: &near_indexer_primitives::CryptoHash,
+    account_ids: &[near_indexer_primitives::types::AccountId],
+) -> anyhow::Result<Vec<(near_indexer_primitives::types::AccountId, PreviousBalance)>> {
+    let request = RpcStateChangesInBlockByTypeRequest {
+        block_reference: near_primitives::types::BlockReference::BlockId(
+            near_primitives::types::BlockId::Hash(*block_hash),
+        ),
+        state_changes_request: near_primitives::views::StateChangesRequestView::AccountChanges {
+            account_ids: account_ids.to_vec(),
+        },
+    };
+    let response = json_rpc_client.call(request).await?;
+    // The account may change several times in the block, the last change is the state after it
+    let mut balances: Vec<(near_indexer_primitives::types::AccountId, PreviousBalance)> = vec![];
+    for change in response.changes {
+        let (account_id, balance) = match change.value {
+            near_indexer_primitives::views::StateChangeValueView::AccountUpdate {
+                account_id,
+                account,
+            } => (
+                account_id,
+                PreviousBalance::Exists(crate::BalanceDetails {
+                    non_staked: account.amount,
+                    staked: account.locked,
+                }),
+            ),
+            near_indexer_primitives::views::StateChangeValueView::AccountDeletion {
+                account_id,
+            } => (account_id, PreviousBalance::Missing),
+            _ => continue,
+        };
+        match balances.iter_mut().find(|(id, _)| *id == account_id) {
+            Some(entry) => entry.1 = balance,
+            None => balances.push((account_id, balance)),
+        }
+    }
+    Ok(balances)
+}
@@ -0,0 +1,114 @@
+// Restricts the balance changes to the configured accounts and account patterns.
+// The state changes of the other accounts are still read to check the consistency of the block,
+// but we do not query RPC for them and do not build the rows
+// The accounts are re-read on SIGHUP
+static ACCOUNT_FILTER: once_cell::sync::Lazy<std::sync::RwLock<AccountFilter>> =
+    once_cell::sync::Lazy::new(Default::default);
+
+#[derive(Debug, Default)]
+pub(crate) struct AccountFilter {
+    accounts: Option<std::collections::HashSet<String>>,
+    patterns: Vec<crate::configs::AccountPattern>,
+}
+
+impl AccountFilter {
+    pub(crate) fn from_opts(opts: &crate::configs::Opts) -> anyhow::Result<Self> {
+        let mut accounts: Option<std::collections::HashSet<String>> = None;
+        if let Some(only_accounts) = &opts.only_accounts {
+            accounts
+                .get_or_insert_with(Default::default)
+                .extend(only_accounts.iter().cloned());
+        }
+        if let Some(path) = &opts.accounts_file {
+            accounts
+                .get_or_insert_with(Default::default)
+                .extend(crate::configs::read_accounts_file(path)?);
+        }
+        Ok(Self {
+            accounts,
+            patterns: opts.account_patterns.clone(),
+        })
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.accounts.is_some() || !self.patterns.is_empty()
+    }
+
+    // The account is tracked if it is listed or matches any of the patterns
+    fn is_tracked(&self, account_id: &str) -> bool {
+        !self.is_enabled()
+            || self
+                .accounts
+                .as_ref()
+                .map(|accounts| accounts.contains(account_id))
+                .unwrap_or(false)
+            || self
+                .patterns
+                .iter()
+                .any(|pattern| pattern.matches(account_id))
+    }
+}
+
+pub(crate) fn configure_account_filter(filter: AccountFilter) {
+    if filter.is_enabled() {
+        tracing::info!(
+            target: crate::INDEXER,
+            "Balance changes are collected only for the configured accounts: {:?}",
+            filter
+        );
+    }
+    *ACCOUNT_FILTER
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = filter;
+}
+
+// Turning the filter on or off changes what is stored for the whole block, it needs the restart
+pub(crate) fn reload_account_filter(opts: &crate::configs::Opts) -> anyhow::Result<()> {
+    let filter = AccountFilter::from_opts(opts)?;
+    let mut current = ACCOUNT_FILTER
+        .write()
+        .unwrap_or_else(|err| err.into_inner());
+    if filter.is_enabled() != current.is_enabled() {
+        anyhow::bail!("Account filter can't be turned on or off without the restart");
+    }
+    *current = filter;
+    Ok(())
+}
+
+// Some rows are not stored, so the checks over the whole block do not work
+pub(crate) fn is_filtering() -> bool {
+    ACCOUNT_FILTER
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .is_enabled()
+}
+
+pub(crate) fn is_tracked(account_id: &str) -> bool {
+    ACCOUNT_FILTER
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .is_tracked(account_id)
+}
+
+// Both kinds are created by the first transfer to them, the account id cannot be registered
+pub(crate) fn is_implicit(account_id: &str) -> bool {
+    is_near_implicit(account_id) || is_eth_implicit(account_id)
+}
+
+// 64 hex chars of the ED25519 public key
+pub(crate) fn is_near_implicit(account_id: &str) -> bool {
+    account_id.len() == 64 && is_lowercase_hex(account_id)
+}
+
+// `0x` and 40 hex chars of the Ethereum address, since protocol version 70 (NEP-518).
+// The runtime deploys the wallet contract to them on creation
+pub(crate) fn is_eth_implicit(account_id: &str) -> bool {
+    account_id.strip_prefix("0x").map_or(false, |address| {
+        address.len() == 40 && is_lowercase_hex(address)
+    })
+}
+
+fn is_lowercase_hex(s: &str) -> bool {
+    s.chars()
+        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
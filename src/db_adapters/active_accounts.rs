@@ -0,0 +1,203 @@
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives;
+use sha2::Digest;
+use sqlx::Row;
+
+use crate::models::balance_changes::BalanceChange;
+
+// 2^14 registers give ~0.8% standard error
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+// Distinct accounts with balance changes per day, counted with HyperLogLog.
+// Merging the sketches takes the maximum of the registers, so the replayed blocks do not change it.
+// The sketch is written in the transaction of the block, and it is kept in memory only after the commit
+pub(crate) struct ActiveAccounts {
+    current_day: tokio::sync::Mutex<Option<DaySketch>>,
+}
+
+#[derive(Clone)]
+struct DaySketch {
+    day: chrono::NaiveDate,
+    registers: Vec<u8>,
+}
+
+// The sketch of the day with the accounts of the block
+pub(crate) struct SketchUpdate {
+    sketch: DaySketch,
+    block_height: u64,
+}
+
+impl ActiveAccounts {
+    pub(crate) fn new() -> Self {
+        Self {
+            current_day: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    // None if the block adds nothing to the sketch
+    pub(crate) async fn prepare(
+        &self,
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<Option<SketchUpdate>> {
+        let day = day_of(block_header.timestamp_nanosec)?;
+        let block_accounts: std::collections::HashSet<&str> = changes
+            .iter()
+            .map(|change| change.affected_account_id.as_str())
+            .collect();
+        crate::metrics::BLOCK_ACTIVE_ACCOUNTS.set(block_accounts.len() as i64);
+
+        let mut current_day = self.current_day.lock().await;
+        let mut sketch = match current_day.as_ref() {
+            Some(sketch) if sketch.day == day => sketch.clone(),
+            _ => current_day
+                .insert(DaySketch::load(pool, day).await?)
+                .clone(),
+        };
+        let mut is_changed = false;
+        for account_id in block_accounts {
+            is_changed |= sketch.add(account_id);
+        }
+        crate::metrics::DAILY_ACTIVE_ACCOUNTS.set(sketch.count() as i64);
+        Ok(is_changed.then(|| SketchUpdate {
+            sketch,
+            block_height: block_header.height,
+        }))
+    }
+
+    // After the block is committed
+    pub(crate) async fn apply(&self, update: SketchUpdate) {
+        *self.current_day.lock().await = Some(update.sketch);
+    }
+
+    // After the rollback the sketch of the day is loaded again
+    pub(crate) async fn forget(&self) {
+        *self.current_day.lock().await = None;
+    }
+}
+
+fn day_of(block_timestamp: u64) -> anyhow::Result<chrono::NaiveDate> {
+    Ok(
+        chrono::DateTime::from_timestamp((block_timestamp / 1_000_000_000) as i64, 0)
+            .ok_or_else(|| anyhow::anyhow!("Invalid block_timestamp {}", block_timestamp))?
+            .date_naive(),
+    )
+}
+
+pub(crate) async fn store_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    update: &SketchUpdate,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        &format!("INSERT INTO {} AS daily_active_accounts VALUES ($1::date, $2, $3, $4) \
+        ON CONFLICT (day) DO UPDATE SET \
+            last_block_height = GREATEST(daily_active_accounts.last_block_height, EXCLUDED.last_block_height), \
+            approx_accounts_count = EXCLUDED.approx_accounts_count, \
+            hll_registers = EXCLUDED.hll_registers", crate::db_adapters::table("daily_active_accounts")),
+    )
+    .bind(update.sketch.day.format("%Y-%m-%d").to_string())
+    .bind(BigDecimal::from(update.block_height))
+    .bind(update.sketch.count() as i64)
+    .bind(&update.sketch.registers)
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+// HyperLogLog cannot remove the accounts of the discarded block, so the sketch of its day
+// is rebuilt from the rows left in balance_changes. Forks happen at the head, the day is not pruned yet
+pub(crate) async fn rebuild_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block_timestamp: u64,
+) -> anyhow::Result<()> {
+    let day = day_of(block_timestamp)?;
+    let day_start = day.and_time(chrono::NaiveTime::MIN).and_utc().timestamp() * 1_000_000_000;
+    let accounts: Vec<(String,)> = sqlx::query_as(&format!(
+        "SELECT DISTINCT affected_account_id FROM {} \
+        WHERE block_timestamp >= $1::bigint AND block_timestamp < $2::bigint",
+        crate::db_adapters::table("balance_changes")
+    ))
+    .bind(day_start)
+    .bind(day_start + 24 * 60 * 60 * 1_000_000_000)
+    .fetch_all(&mut *transaction)
+    .await?;
+    let mut sketch = DaySketch {
+        day,
+        registers: vec![0; REGISTERS],
+    };
+    for (account_id,) in &accounts {
+        sketch.add(account_id);
+    }
+    sqlx::query(&format!(
+        "UPDATE {} SET approx_accounts_count = $2, hll_registers = $3 WHERE day = $1::date",
+        crate::db_adapters::table("daily_active_accounts")
+    ))
+    .bind(day.format("%Y-%m-%d").to_string())
+    .bind(sketch.count() as i64)
+    .bind(&sketch.registers)
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+impl DaySketch {
+    // Continues the sketch of the day after the restart
+    async fn load(
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        day: chrono::NaiveDate,
+    ) -> anyhow::Result<Self> {
+        let registers: Option<Vec<u8>> = sqlx::query(&format!(
+            "SELECT hll_registers FROM {} WHERE day = $1::date",
+            crate::db_adapters::table("daily_active_accounts")
+        ))
+        .bind(day.format("%Y-%m-%d").to_string())
+        .fetch_optional(pool)
+        .await?
+        .map(|row| row.get(0));
+        Ok(Self {
+            day,
+            registers: registers
+                .filter(|registers| registers.len() == REGISTERS)
+                .unwrap_or_else(|| vec![0; REGISTERS]),
+        })
+    }
+
+    // Returns true if the sketch has changed
+    fn add(&mut self, account_id: &str) -> bool {
+        // The hash should be stable between the releases, the sketch is stored
+        let digest = sha2::Sha256::digest(account_id.as_bytes());
+        let hash = u64::from_be_bytes(digest[..8].try_into().expect("Digest is 32 bytes"));
+        let index = (hash >> (64 - PRECISION)) as usize;
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        if self.registers[index] < rank {
+            self.registers[index] = rank;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-(*register as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        // Linear counting is more precise for the small cardinalities
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
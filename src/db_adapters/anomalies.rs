@@ -0,0 +1,254 @@
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, ToPrimitive};
+use cached::Cached;
+use near_lake_framework::near_indexer_primitives;
+use num_traits::Zero;
+use sqlx::Row;
+
+use crate::models::anomalies::Anomaly;
+use crate::models::balance_changes::BalanceChange;
+
+const YOCTO_PER_NEAR: f64 = 1e24;
+
+// Flags the accounts whose liquid outflow during the bucket (e.g. an hour) is more than
+// `z_score` standard deviations above the mean of their trailing buckets.
+// The history lives in memory, on startup it is seeded from the stored balance changes.
+// The anomalies are written in the transaction of the block, and the history is updated only after the commit.
+// The deviation is at least 1 NEAR, so the dormant account which is suddenly drained is flagged too
+pub(crate) struct AnomalyDetector {
+    bucket_nanos: u64,
+    window: usize,
+    z_score: f64,
+    min_outflow: BigDecimal,
+    accounts: tokio::sync::Mutex<cached::SizedCache<String, OutflowHistory>>,
+}
+
+#[derive(Clone, Default)]
+struct OutflowHistory {
+    // Outflows of the finished buckets, the latest is at the back
+    buckets: VecDeque<f64>,
+    current_bucket: u64,
+    current_outflow: BigDecimal,
+    last_block_timestamp: u64,
+    is_flagged: bool,
+}
+
+// The histories of the accounts with the outflow in the block, and the anomalies among them
+pub(crate) struct AnomalyUpdate {
+    histories: Vec<(String, OutflowHistory)>,
+    pub(crate) anomalies: Vec<Anomaly>,
+}
+
+impl AnomalyDetector {
+    pub(crate) async fn load(
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        bucket_secs: u64,
+        window: usize,
+        z_score: f64,
+        min_outflow_near: u64,
+    ) -> anyhow::Result<Self> {
+        let detector = Self {
+            bucket_nanos: bucket_secs.max(1) * 1_000_000_000,
+            window: window.max(2),
+            z_score,
+            min_outflow: BigDecimal::from(min_outflow_near)
+                * BigDecimal::from_str("1000000000000000000000000")?,
+            accounts: tokio::sync::Mutex::new(cached::SizedCache::with_size(100_000)),
+        };
+        detector.seed(pool).await?;
+        Ok(detector)
+    }
+
+    // After the rollback the histories may have the outflows of the discarded blocks
+    pub(crate) async fn reseed(&self, pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+        self.accounts.lock().await.cache_clear();
+        self.seed(pool).await
+    }
+
+    // Replays the outflows of the trailing window from balance_changes,
+    // so the restart does not wait for the whole window before it flags anything again
+    async fn seed(&self, pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+        let last_block_timestamp: Option<BigDecimal> = sqlx::query(&format!(
+            "SELECT MAX(block_timestamp)::numeric FROM {}",
+            crate::db_adapters::table("balance_changes")
+        ))
+        .fetch_one(pool)
+        .await?
+        .get(0);
+        let last_block_timestamp = match last_block_timestamp.and_then(|value| value.to_u64()) {
+            Some(timestamp) => timestamp,
+            None => return Ok(()),
+        };
+        let last_bucket = last_block_timestamp / self.bucket_nanos;
+        let window_start = last_bucket.saturating_sub(self.window as u64) * self.bucket_nanos;
+
+        let rows = sqlx::query(&format!(
+            "SELECT affected_account_id, div(block_timestamp::numeric, $1) AS bucket, \
+                -SUM(delta_nonstaked_amount) AS outflow \
+            FROM {} \
+            WHERE block_timestamp >= $2::bigint AND delta_nonstaked_amount < 0 \
+            GROUP BY affected_account_id, bucket \
+            ORDER BY bucket",
+            crate::db_adapters::table("balance_changes")
+        ))
+        .bind(BigDecimal::from(self.bucket_nanos))
+        .bind(window_start as i64)
+        .fetch_all(pool)
+        .await?;
+        let flagged: std::collections::HashSet<String> = sqlx::query(&format!(
+            "SELECT account_id FROM {} WHERE block_timestamp >= $1",
+            crate::db_adapters::table("anomalies")
+        ))
+        .bind(BigDecimal::from(last_bucket * self.bucket_nanos))
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
+
+        let mut histories: HashMap<String, OutflowHistory> = HashMap::new();
+        for row in rows {
+            let account_id: String = row.get(0);
+            let bucket: BigDecimal = row.get(1);
+            let outflow: BigDecimal = row.get(2);
+            let history = histories.entry(account_id).or_default();
+            self.roll(history, bucket.to_u64().unwrap_or_default());
+            history.current_outflow += outflow;
+        }
+        let mut accounts = self.accounts.lock().await;
+        for (account_id, mut history) in histories {
+            self.roll(&mut history, last_bucket);
+            history.last_block_timestamp = last_block_timestamp;
+            history.is_flagged = flagged.contains(&account_id);
+            accounts.cache_set(account_id, history);
+        }
+        tracing::info!(
+            target: crate::INDEXER,
+            "Anomaly detector is seeded with the outflows of {} accounts",
+            accounts.cache_size()
+        );
+        Ok(())
+    }
+
+    // Does not change the histories, see `apply`
+    pub(crate) async fn prepare(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> AnomalyUpdate {
+        let mut outflows: HashMap<&str, BigDecimal> = HashMap::new();
+        for change in changes {
+            if change.delta_nonstaked_amount < BigDecimal::zero() {
+                *outflows
+                    .entry(change.affected_account_id.as_str())
+                    .or_insert_with(BigDecimal::zero) -= &change.delta_nonstaked_amount;
+            }
+        }
+
+        let bucket = block_header.timestamp / self.bucket_nanos;
+        let mut accounts = self.accounts.lock().await;
+        let mut update = AnomalyUpdate {
+            histories: vec![],
+            anomalies: vec![],
+        };
+        for (account_id, outflow) in outflows {
+            let mut history = accounts
+                .cache_get(&account_id.to_string())
+                .cloned()
+                .unwrap_or_default();
+            // The block is stored again after the restart, it should not be counted twice
+            if history.last_block_timestamp >= block_header.timestamp {
+                continue;
+            }
+            history.last_block_timestamp = block_header.timestamp;
+            self.roll(&mut history, bucket);
+            history.current_outflow += outflow;
+            if let Some(anomaly) = self.check(&mut history, account_id, block_header) {
+                update.anomalies.push(anomaly);
+            }
+            update.histories.push((account_id.to_string(), history));
+        }
+        update
+    }
+
+    // After the block is committed
+    pub(crate) async fn apply(&self, update: AnomalyUpdate) {
+        for anomaly in &update.anomalies {
+            tracing::warn!(
+                target: crate::INDEXER,
+                "Anomalous outflow of {} at block_height {}: {} yoctoNEAR, z-score {:.1}",
+                anomaly.account_id,
+                anomaly.block_height,
+                anomaly.outflow_amount,
+                anomaly.z_score
+            );
+        }
+        let mut accounts = self.accounts.lock().await;
+        for (account_id, history) in update.histories {
+            accounts.cache_set(account_id, history);
+        }
+    }
+
+    // Finishes the buckets before the current one, the buckets without outflow count as zero
+    fn roll(&self, history: &mut OutflowHistory, bucket: u64) {
+        if history.current_bucket == bucket {
+            return;
+        }
+        if history.current_bucket != 0 {
+            history
+                .buckets
+                .push_back(history.current_outflow.to_f64().unwrap_or_default());
+            let empty_buckets = (bucket - history.current_bucket - 1).min(self.window as u64);
+            history
+                .buckets
+                .extend(std::iter::repeat(0.0).take(empty_buckets as usize));
+            while history.buckets.len() > self.window {
+                history.buckets.pop_front();
+            }
+        }
+        history.current_bucket = bucket;
+        history.current_outflow = BigDecimal::zero();
+        history.is_flagged = false;
+    }
+
+    // Flags the account once per bucket
+    fn check(
+        &self,
+        history: &mut OutflowHistory,
+        account_id: &str,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+    ) -> Option<Anomaly> {
+        // Not enough history to say what is normal for the account
+        if history.is_flagged
+            || history.buckets.len() < self.window
+            || history.current_outflow < self.min_outflow
+        {
+            return None;
+        }
+        let count = history.buckets.len() as f64;
+        let mean = history.buckets.iter().sum::<f64>() / count;
+        let variance = history
+            .buckets
+            .iter()
+            .map(|outflow| (outflow - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        let stddev = variance.sqrt().max(YOCTO_PER_NEAR);
+        let z_score = (history.current_outflow.to_f64()? - mean) / stddev;
+        if z_score < self.z_score {
+            return None;
+        }
+        history.is_flagged = true;
+        Some(Anomaly {
+            block_height: block_header.height.into(),
+            block_timestamp: block_header.timestamp.into(),
+            account_id: account_id.to_string(),
+            outflow_amount: history.current_outflow.clone(),
+            trailing_mean: mean,
+            trailing_stddev: stddev,
+            z_score,
+        })
+    }
+}
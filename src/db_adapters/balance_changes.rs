@@ -1,8 +1,13 @@
-use cached::Cached;
-use std::collections::HashMap;
-use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
+use crate::db_adapters::account_filter::is_tracked;
 use crate::models::balance_changes::BalanceChange;
+use crate::models::balance_imbalances::BalanceImbalance;
+use crate::models::block_balance_summary::BlockBalanceSummary;
+use crate::models::current_balances::CurrentBalance;
+use crate::models::epoch_validator_rewards::EpochValidatorReward;
+use crate::models::lockup_balances::LockupBalance;
 use crate::models::PrintEnum;
 use bigdecimal::BigDecimal;
 use futures::future::try_join_all;
@@ -10,46 +15,298 @@ use near_jsonrpc_client::errors::JsonRpcError;
 use near_jsonrpc_primitives::types::query::RpcQueryError;
 use near_lake_framework::near_indexer_primitives::{
     self,
-    views::{ExecutionStatusView, StateChangeCauseView},
+    views::{ActionView, ExecutionStatusView, ReceiptEnumView, StateChangeCauseView},
 };
 use num_traits::Zero;
 
+static RECEIPT_CACHE_SIZE: AtomicUsize = AtomicUsize::new(100_000);
+static SPLIT_ACTIONS: AtomicBool = AtomicBool::new(false);
+// 0 does not log the skipped state changes
+static LOG_SKIPPED_STATE_CHANGES_EVERY: AtomicU64 = AtomicU64::new(0);
+// Receipt id -> the delegated sender, for the receipts of the inner actions of the meta transactions.
+// They are usually executed in a later block than the delegate receipt which has created them.
+// The restart forgets them, such receipts are stored with RECEIPT cause
+static DELEGATED_RECEIPTS: once_cell::sync::Lazy<
+    std::sync::Mutex<
+        cached::SizedCache<
+            near_indexer_primitives::CryptoHash,
+            near_indexer_primitives::types::AccountId,
+        >,
+    >,
+> = once_cell::sync::Lazy::new(|| std::sync::Mutex::new(cached::SizedCache::with_size(10_000)));
+
 // https://explorer.near.org/transactions/FGSPpucGQBUTPscfjQRs7Poo4XyaXGawX6QriKbhT3sE#7nu7ZAK3T11erEgG8aWTRGmz9uTHGazoNMjJdVyG3piX
 
-// https://nomicon.io/RuntimeSpec/ApplyingChunk#processing-order
+// The rows computed from the block by the optional features of the storage,
+// they are written in the same transaction as the balance changes
+#[derive(Default)]
+pub(crate) struct DerivedRows {
+    pub imbalances: Vec<BalanceImbalance>,
+    pub lockup_balances: Vec<LockupBalance>,
+    pub active_accounts: Option<crate::db_adapters::active_accounts::SketchUpdate>,
+    pub anomalies: Option<crate::db_adapters::anomalies::AnomalyUpdate>,
+}
+
+// Stores the balance changes computed by `collect_balance_changes`.
+// false if the block is committed already
 pub(crate) async fn store_balance_changes(
     pool: &sqlx::Pool<sqlx::Postgres>,
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+    derived_rows: &DerivedRows,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<bool> {
+    if crate::db_adapters::resharding::is_resharding_block(shards) {
+        crate::db_adapters::resharding::store_shard_mapping(
+            pool,
+            shards,
+            block_header,
+            json_rpc_client,
+        )
+        .await?;
+    }
+
+    let stored_changes = crate::db_adapters::without_dust(changes);
+    // The summary is counted over all the changes, including the dropped ones
+    let summary = block_summary(shards, block_header, changes);
+    let epoch_rewards = epoch_validator_rewards(block_header, changes);
+    let mut transaction = pool.begin().await?;
+    // Other shards are stored by the other instances
+    let is_sharded = crate::db_adapters::shard_assignment::is_sharded();
+    if !is_sharded
+        && !crate::db_adapters::stream_offsets::commit_in_transaction(
+            &mut transaction,
+            block_header,
+        )
+        .await?
+    {
+        transaction.rollback().await?;
+        tracing::debug!(
+            target: crate::INDEXER,
+            "Block {} is committed already, skipping",
+            block_header.height
+        );
+        return Ok(false);
+    }
+    if crate::db_adapters::is_bulk_load() {
+        crate::models::copy_in_transaction(&mut transaction, &stored_changes).await?;
+    } else {
+        crate::models::insert_in_transaction(&mut transaction, &stored_changes).await?;
+    }
+    if !is_sharded {
+        crate::models::insert_in_transaction(&mut transaction, &[summary]).await?;
+        crate::models::insert_in_transaction(&mut transaction, &epoch_rewards).await?;
+    }
+    crate::models::insert_in_transaction(&mut transaction, &derived_rows.imbalances).await?;
+    crate::models::insert_in_transaction(&mut transaction, &derived_rows.lockup_balances).await?;
+    if let Some(anomalies) = &derived_rows.anomalies {
+        crate::models::insert_in_transaction(&mut transaction, &anomalies.anomalies).await?;
+    }
+    if let Some(active_accounts) = &derived_rows.active_accounts {
+        crate::db_adapters::active_accounts::store_in_transaction(
+            &mut transaction,
+            active_accounts,
+        )
+        .await?;
+    }
+    // The filtered changes miss the rewards of the other accounts
+    if !crate::db_adapters::account_filter::is_filtering() && !is_sharded {
+        crate::db_adapters::supply::store_supply(&mut transaction, shards, block_header, changes)
+            .await?;
+    }
+    crate::db_adapters::shard_assignment::store_offsets(&mut transaction, block_header).await?;
+    crate::db_adapters::finality::mark_optimistic(&mut transaction, block_header).await?;
+    crate::db_adapters::explorer_compat::store_in_transaction(
+        &mut transaction,
+        shards,
+        block_header,
+        &stored_changes,
+    )
+    .await?;
+    crate::db_adapters::fee_details::store_in_transaction(
+        &mut transaction,
+        shards,
+        block_header,
+        changes,
+    )
+    .await?;
+    crate::db_adapters::notify::notify_in_transaction(
+        &mut transaction,
+        block_header,
+        &stored_changes,
+    )
+    .await?;
+    // After the crash, the block is skipped together with its current balances
+    if crate::db_adapters::dust_updates_current_balances() {
+        store_current_balances(&mut transaction, changes, block_header).await?;
+    } else {
+        store_current_balances(&mut transaction, &stored_changes, block_header).await?;
+    }
+    transaction.commit().await?;
+    Ok(true)
+}
+
+// https://nomicon.io/RuntimeSpec/ApplyingChunk#processing-order
+pub(crate) async fn collect_balance_changes(
     shards: &[near_indexer_primitives::IndexerShard],
     block_header: &near_indexer_primitives::views::BlockHeaderView,
     balances_cache: &crate::BalanceCache,
+    postponed_receipts: &crate::PostponedReceipts,
+    receipt_transactions: &crate::ReceiptTransactions,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Vec<BalanceChange>> {
+    crate::db_adapters::account_batch::load_previous_balances(
+        shards,
+        block_header,
+        balances_cache,
+        json_rpc_client,
+    )
+    .await;
     let futures = shards.iter().map(|shard| {
-        store_changes_for_chunk(pool, shard, block_header, balances_cache, json_rpc_client)
+        collect_changes_for_chunk(
+            shard,
+            block_header,
+            balances_cache,
+            postponed_receipts,
+            receipt_transactions,
+            json_rpc_client,
+        )
     });
 
-    try_join_all(futures).await.map(|_| ())
+    // Shards are joined in the order of shard_id, so the rows get the total order inside the block
+    let mut changes: Vec<BalanceChange> =
+        try_join_all(futures).await?.into_iter().flatten().collect();
+    changes.iter_mut().enumerate().for_each(|(i, change)| {
+        change.index_in_block = crate::db_adapters::shard_assignment::index_in_block(
+            change.shard_id,
+            change.index_in_chunk,
+            i,
+        );
+    });
+    crate::models::balance_changes::set_event_ids(&mut changes, &block_header.hash.to_string());
+    crate::db_adapters::shard_assignment::forget_foreign_accounts(shards, &changes, balances_cache)
+        .await;
+    Ok(changes)
+}
+
+pub(crate) fn block_summary(
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+) -> BlockBalanceSummary {
+    let affected_accounts: HashSet<&str> = changes
+        .iter()
+        .map(|change| change.affected_account_id.as_str())
+        .collect();
+    BlockBalanceSummary {
+        block_height: block_header.height.into(),
+        block_timestamp: block_header.timestamp.into(),
+        tokens_burnt_amount: crate::db_adapters::invariant::tokens_burnt_amount(shards),
+        validators_reward_amount: crate::db_adapters::invariant::reward_amount(
+            changes,
+            crate::models::Cause::ValidatorsReward,
+        ),
+        delta_staked_amount: changes.iter().fold(BigDecimal::zero(), |delta, change| {
+            delta + &change.delta_staked_amount
+        }),
+        affected_accounts_count: affected_accounts.len() as i32,
+        treasury_reward_amount: crate::db_adapters::invariant::reward_amount(
+            changes,
+            crate::models::Cause::ProtocolTreasuryReward,
+        ),
+    }
+}
+
+// Validators get the reward in the first block of the epoch, it's the only block with VALIDATORS_REWARD rows.
+// The same row may also move the unstaked tokens to the liquid balance, so the reward is the sum of both deltas
+pub(crate) fn epoch_validator_rewards(
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+) -> Vec<EpochValidatorReward> {
+    let validators_reward = crate::models::Cause::ValidatorsReward.print();
+    let mut rewards: HashMap<&str, EpochValidatorReward> = HashMap::new();
+    for change in changes
+        .iter()
+        .filter(|change| change.cause == validators_reward)
+    {
+        let reward = rewards
+            .entry(&change.affected_account_id)
+            .or_insert_with(|| EpochValidatorReward {
+                epoch_id: block_header.epoch_id.to_string(),
+                block_height: block_header.height.into(),
+                block_timestamp: block_header.timestamp.into(),
+                validator_id: change.affected_account_id.clone(),
+                reward_amount: BigDecimal::zero(),
+                staked_amount: BigDecimal::zero(),
+            });
+        reward.reward_amount += &change.delta_nonstaked_amount + &change.delta_staked_amount;
+        reward.staked_amount = change.absolute_staked_amount.clone();
+    }
+    rewards.into_values().collect()
+}
+
+// The latest row of the account in the block has its balance after the block.
+// We do not take the balances from the cache: it may already have the balances from the next blocks
+pub(crate) async fn store_current_balances(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    changes: &[BalanceChange],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+) -> anyhow::Result<()> {
+    let mut latest_changes: HashMap<&str, &BalanceChange> = HashMap::new();
+    for change in changes {
+        latest_changes.insert(&change.affected_account_id, change);
+    }
+
+    let current_balances: Vec<CurrentBalance> = latest_changes
+        .into_iter()
+        .map(|(account_id, change)| CurrentBalance {
+            account_id: account_id.to_string(),
+            block_timestamp: block_header.timestamp.into(),
+            nonstaked_amount: change.absolute_nonstaked_amount.clone(),
+            staked_amount: change.absolute_staked_amount.clone(),
+        })
+        .collect();
+
+    crate::models::insert_in_transaction(transaction, &current_balances).await
 }
 
 #[derive(Debug, Default)]
 struct AccountChangesBalances {
+    pub initial_state: Vec<crate::AccountWithBalance>,
     pub validators: Vec<crate::AccountWithBalance>,
     pub transactions: HashMap<near_indexer_primitives::CryptoHash, crate::AccountWithBalance>,
     pub receipts: HashMap<near_indexer_primitives::CryptoHash, crate::AccountWithBalance>,
     pub rewards: HashMap<near_indexer_primitives::CryptoHash, crate::AccountWithBalance>,
+    // The causes this version does not know, stored only with --store-unknown-causes
+    pub unknown: Vec<crate::AccountWithBalance>,
+    // Receipts waiting for the data, they will be executed in one of the next blocks
+    pub postponed: Vec<near_indexer_primitives::CryptoHash>,
+    // Whatever the cause of the deletion, see forget_deleted_implicit_accounts
+    pub deleted_implicit: Vec<near_indexer_primitives::types::AccountId>,
 }
 
-async fn store_changes_for_chunk(
-    pool: &sqlx::Pool<sqlx::Postgres>,
+async fn collect_changes_for_chunk(
     shard: &near_indexer_primitives::IndexerShard,
     block_header: &near_indexer_primitives::views::BlockHeaderView,
     balances_cache: &crate::BalanceCache,
+    postponed_receipts: &crate::PostponedReceipts,
+    receipt_transactions: &crate::ReceiptTransactions,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Vec<BalanceChange>> {
     let mut changes: Vec<BalanceChange> = vec![];
     let mut changes_data =
         collect_data_from_balance_changes(&shard.state_changes, block_header.height)?;
-    // We should collect these 3 groups sequentially because they all share the same cache
+    // We should collect these groups sequentially because they all share the same cache
+    changes.extend(
+        store_initial_state_for_chunk(
+            &changes_data.initial_state,
+            block_header,
+            shard.shard_id,
+            balances_cache,
+        )
+        .await,
+    );
     changes.extend(
         store_validator_accounts_update_for_chunk(
             &changes_data.validators,
@@ -88,11 +345,192 @@ async fn store_changes_for_chunk(
         .await?,
     );
 
-    changes.iter_mut().enumerate().for_each(|(i, mut change)| {
+    // We don't know when the runtime applies them, so they go after everything else
+    changes.extend(
+        store_unknown_causes_for_chunk(
+            &changes_data.unknown,
+            block_header,
+            shard.shard_id,
+            balances_cache,
+            json_rpc_client,
+        )
+        .await?,
+    );
+
+    track_postponed_receipts(
+        &changes_data.postponed,
+        &shard.receipt_execution_outcomes,
+        block_header.height,
+        postponed_receipts,
+    )
+    .await;
+    forget_deleted_implicit_accounts(
+        &changes_data.deleted_implicit,
+        block_header.height,
+        balances_cache,
+    )
+    .await;
+    link_transactions(shard, &mut changes, receipt_transactions).await;
+
+    enumerate_in_apply_order(&mut changes);
+    Ok(changes)
+}
+
+// Stable sort keeps the order of the outcomes inside each group
+fn enumerate_in_apply_order(changes: &mut [BalanceChange]) {
+    changes.sort_by_key(apply_order);
+    changes.iter_mut().enumerate().for_each(|(i, change)| {
         change.index_in_chunk = i as i32;
     });
-    crate::models::chunked_insert(pool, &changes, 10).await?;
-    Ok(())
+}
+
+// The order the runtime applies the chunk in:
+// 1. initial state (genesis only)
+// 2. validator accounts update
+// 3. transactions are converted to receipts, the signers are charged
+// 4. receipts (local, delayed, incoming) with their gas rewards
+// https://nomicon.io/RuntimeSpec/ApplyingChunk#processing-order
+fn apply_order(change: &BalanceChange) -> u8 {
+    let initial_state = crate::models::Cause::InitialState.print();
+    let validators_reward = crate::models::Cause::ValidatorsReward.print();
+    let treasury_reward = crate::models::Cause::ProtocolTreasuryReward.print();
+    match change.cause.as_str() {
+        cause if cause == initial_state => 0,
+        cause if cause == validators_reward || cause == treasury_reward => 1,
+        // Meta transaction may be both transaction and receipt, only the transaction rows have the hash
+        _ if change.transaction_hash.is_some() => 2,
+        _ => 3,
+    }
+}
+
+// Postponed receipt is executed in the later block, when all the input data arrives.
+// The balance changes are stored at the execution time, with the prev_balance for the executing block
+async fn track_postponed_receipts(
+    postponed: &[near_indexer_primitives::CryptoHash],
+    outcomes_with_receipts: &[near_indexer_primitives::IndexerExecutionOutcomeWithReceipt],
+    block_height: u64,
+    postponed_receipts: &crate::PostponedReceipts,
+) {
+    let mut postponed_receipts_lock = postponed_receipts.lock().await;
+    for outcome_with_receipt in outcomes_with_receipts {
+        let receipt_id = &outcome_with_receipt.receipt.receipt_id;
+        if let Some(postponed_at) = postponed_receipts_lock.cache_remove(receipt_id) {
+            tracing::debug!(
+                target: crate::INDEXER,
+                "Postponed receipt {} from block_height {} is executed at block_height {}",
+                receipt_id.to_string(),
+                postponed_at,
+                block_height
+            );
+        }
+    }
+    for receipt_id in postponed {
+        postponed_receipts_lock.cache_set(*receipt_id, block_height);
+    }
+    drop(postponed_receipts_lock);
+}
+
+pub(crate) fn configure_receipt_cache_size(size: usize) {
+    RECEIPT_CACHE_SIZE.store(size.max(1), Ordering::Relaxed);
+}
+
+pub(crate) fn new_receipt_transactions() -> crate::ReceiptTransactions {
+    std::sync::Arc::new(tokio::sync::Mutex::new(
+        crate::cache::JournaledCache::with_size(RECEIPT_CACHE_SIZE.load(Ordering::Relaxed)),
+    ))
+}
+
+// The transaction is converted to one receipt, the receipts created by the execution (refunds too)
+// descend from the transaction of their parent. The receipts executed in the same chunk as their
+// transaction are the local ones, the rest come in the later blocks. The cache is bounded,
+// so the receipt executed long after its transaction, or after the restart, gets None
+async fn link_transactions(
+    shard: &near_indexer_primitives::IndexerShard,
+    changes: &mut [BalanceChange],
+    receipt_transactions: &crate::ReceiptTransactions,
+) {
+    let mut receipt_transactions_lock = receipt_transactions.lock().await;
+    for transaction in shard.chunk.iter().flat_map(|chunk| &chunk.transactions) {
+        if let Some(receipt_id) = transaction
+            .outcome
+            .execution_outcome
+            .outcome
+            .receipt_ids
+            .first()
+        {
+            receipt_transactions_lock.cache_set(*receipt_id, transaction.transaction.hash);
+        }
+    }
+    for change in changes.iter_mut() {
+        if change.transaction_hash.is_some() {
+            continue;
+        }
+        let receipt_id = change
+            .receipt_id
+            .as_ref()
+            .and_then(|receipt_id| receipt_id.parse().ok());
+        change.parent_transaction_hash = receipt_id
+            .and_then(|receipt_id| receipt_transactions_lock.cache_get(&receipt_id))
+            .map(ToString::to_string);
+        if change.parent_transaction_hash.is_none() && change.receipt_id.is_some() {
+            crate::metrics::RECEIPT_TRANSACTION_MISSES_TOTAL.inc();
+        }
+    }
+    for outcome_with_receipt in &shard.receipt_execution_outcomes {
+        let transaction_hash =
+            match receipt_transactions_lock.cache_get(&outcome_with_receipt.receipt.receipt_id) {
+                Some(transaction_hash) => *transaction_hash,
+                None => continue,
+            };
+        for receipt_id in &outcome_with_receipt.execution_outcome.outcome.receipt_ids {
+            receipt_transactions_lock.cache_set(*receipt_id, transaction_hash);
+        }
+    }
+}
+
+// The deleted implicit account is created again by the next transfer to it. The deletion may come
+// with the cause which does not build the rows, then the cache would keep the balance before it
+// and the re-creation would get the wrong delta. The next balance is queried from RPC instead
+async fn forget_deleted_implicit_accounts(
+    deleted_implicit: &[near_indexer_primitives::types::AccountId],
+    block_height: u64,
+    balances_cache: &crate::BalanceCache,
+) {
+    if deleted_implicit.is_empty() {
+        return;
+    }
+    let mut balances_cache_lock = balances_cache.lock().await;
+    for account_id in deleted_implicit {
+        tracing::debug!(
+            target: crate::INDEXER,
+            "Implicit account {} is deleted at block_height {}",
+            account_id,
+            block_height
+        );
+        balances_cache_lock.cache_remove(account_id);
+    }
+}
+
+// Only the transfer creates the implicit account. The missing accounts have zero balances here,
+// so the zero balance before the receipt is taken as no account
+fn creates_implicit_account(
+    outcome_with_receipt: &near_indexer_primitives::IndexerExecutionOutcomeWithReceipt,
+    prev_balance: &crate::BalanceDetails,
+    balance: &crate::BalanceDetails,
+) -> bool {
+    let has_transfer = match &outcome_with_receipt.receipt.receipt {
+        ReceiptEnumView::Action { actions, .. } => actions
+            .iter()
+            .any(|action| matches!(action, ActionView::Transfer { .. })),
+        ReceiptEnumView::Data { .. } => false,
+    };
+    has_transfer
+        && crate::db_adapters::account_filter::is_implicit(
+            outcome_with_receipt.receipt.receiver_id.as_str(),
+        )
+        && prev_balance.non_staked == 0
+        && prev_balance.staked == 0
+        && (balance.non_staked > 0 || balance.staked > 0)
 }
 
 fn collect_data_from_balance_changes(
@@ -100,11 +538,18 @@ fn collect_data_from_balance_changes(
     block_height: u64,
 ) -> anyhow::Result<AccountChangesBalances> {
     let mut result: AccountChangesBalances = Default::default();
+    let mut processing_started: HashSet<near_indexer_primitives::CryptoHash> = HashSet::new();
 
     for state_change_with_cause in state_changes {
         let near_indexer_primitives::views::StateChangeWithCauseView { cause, value } =
             state_change_with_cause;
 
+        if let StateChangeCauseView::PostponedReceipt { receipt_hash } = cause {
+            if !result.postponed.contains(receipt_hash) {
+                result.postponed.push(*receipt_hash);
+            }
+        }
+
         let account_details = match value {
             near_indexer_primitives::views::StateChangeValueView::AccountUpdate {
                 account_id,
@@ -118,25 +563,87 @@ fn collect_data_from_balance_changes(
             },
             near_indexer_primitives::views::StateChangeValueView::AccountDeletion {
                 account_id,
-            } => crate::AccountWithBalance {
-                account_id: account_id.clone(),
-                balance: crate::BalanceDetails {
-                    non_staked: 0,
-                    staked: 0,
-                },
-            },
+            } => {
+                if crate::db_adapters::account_filter::is_implicit(account_id) {
+                    result.deleted_implicit.push(account_id.clone());
+                }
+                crate::AccountWithBalance {
+                    account_id: account_id.clone(),
+                    balance: crate::BalanceDetails {
+                        non_staked: 0,
+                        staked: 0,
+                    },
+                }
+            }
             // other values do not provide balance changes
-            _ => continue,
+            _ => {
+                skipped_state_change(state_change_value_kind(value), cause, block_height);
+                continue;
+            }
         };
 
         match cause {
-            StateChangeCauseView::NotWritableToDisk
-            | StateChangeCauseView::InitialState
-            | StateChangeCauseView::ActionReceiptProcessingStarted { .. }
-            | StateChangeCauseView::UpdatedDelayedReceipts
-            | StateChangeCauseView::PostponedReceipt { .. }
-            | StateChangeCauseView::Resharding => {
-                anyhow::bail!("Unexpected state change cause met: {:#?}", cause);
+            StateChangeCauseView::NotWritableToDisk => {
+                // The runtime never commits these changes to the state, the balance stays the same
+                tracing::debug!(
+                    target: crate::INDEXER,
+                    "Skipping NotWritableToDisk state change for account {} at block_height {}",
+                    account_details.account_id,
+                    block_height
+                );
+                skipped_state_change(state_change_value_kind(value), cause, block_height);
+            }
+            StateChangeCauseView::PostponedReceipt { receipt_hash } => {
+                // Postponing the receipt does not move the tokens, the effects come with the execution
+                tracing::debug!(
+                    target: crate::INDEXER,
+                    "Skipping PostponedReceipt {} state change for account {} at block_height {}",
+                    receipt_hash.to_string(),
+                    account_details.account_id,
+                    block_height
+                );
+                skipped_state_change(state_change_value_kind(value), cause, block_height);
+            }
+            StateChangeCauseView::ActionReceiptProcessingStarted { receipt_hash } => {
+                // The data for the postponed receipt has arrived, the receipt is executed in this block.
+                // ReceiptProcessing change for the same receipt goes after it and has the final balance
+                processing_started.insert(*receipt_hash);
+                result.receipts.insert(*receipt_hash, account_details);
+            }
+            StateChangeCauseView::ReceiptProcessing { receipt_hash } => {
+                let prev_inserted_item = result
+                    .receipts
+                    .insert(*receipt_hash, account_details.clone());
+                if let Some(details) =
+                    prev_inserted_item.filter(|_| !processing_started.contains(receipt_hash))
+                {
+                    anyhow::bail!(
+                        "Duplicated balance changes for receipt {} at block_height {}. \
+                        One of them may be missed\n{:#?}\n{:#?}",
+                        receipt_hash,
+                        block_height,
+                        account_details,
+                        details
+                    );
+                }
+            }
+            StateChangeCauseView::UpdatedDelayedReceipts => {
+                // Congested shard puts the receipts to the delayed queue, it does not affect balances
+                tracing::debug!(
+                    target: crate::INDEXER,
+                    "Skipping UpdatedDelayedReceipts state change for account {} at block_height {}",
+                    account_details.account_id,
+                    block_height
+                );
+                skipped_state_change(state_change_value_kind(value), cause, block_height);
+            }
+            StateChangeCauseView::Resharding => {
+                // The account is moved to the new shard, the balance is the same.
+                // Shard mapping is stored separately
+                skipped_state_change(state_change_value_kind(value), cause, block_height);
+            }
+            StateChangeCauseView::InitialState => {
+                result.initial_state.push(account_details);
             }
             StateChangeCauseView::ValidatorAccountsUpdate => {
                 result.validators.push(account_details);
@@ -149,16 +656,17 @@ fn collect_data_from_balance_changes(
                     anyhow::bail!(
                         "Duplicated balance changes for transaction {} at block_height {}. \
                         One of them may be missed\n{:#?}\n{:#?}",
-                        tx_hash.to_string(),
+                        tx_hash,
                         block_height,
                         account_details,
                         details
                     );
                 }
             }
-            StateChangeCauseView::Migration {} => {
+            StateChangeCauseView::Migration => {
                 // We had this reason once, in block 44337060
                 // It does not affect balances, so we can skip it
+                skipped_state_change(state_change_value_kind(value), cause, block_height);
             }
             StateChangeCauseView::ActionReceiptGasReward { receipt_hash } => {
                 let prev_inserted_item = result
@@ -168,33 +676,158 @@ fn collect_data_from_balance_changes(
                     anyhow::bail!(
                         "Duplicated balance changes for receipt {} (reward), at block_height {}. \
                         One of them may be missed\n{:#?}\n{:#?}",
-                        receipt_hash.to_string(),
+                        receipt_hash,
                         block_height,
                         account_details,
                         details
                     );
                 }
             }
-            StateChangeCauseView::ReceiptProcessing { receipt_hash } => {
-                let prev_inserted_item = result
-                    .receipts
-                    .insert(*receipt_hash, account_details.clone());
-                if let Some(details) = prev_inserted_item {
-                    anyhow::bail!(
-                        "Duplicated balance changes for receipt {} at block_height {}. \
-                        One of them may be missed\n{:#?}\n{:#?}",
-                        receipt_hash.to_string(),
-                        block_height,
-                        account_details,
-                        details
-                    );
-                }
+            // The newer protocol versions add the causes, it compiles after the upgrade of near-primitives
+            #[allow(unreachable_patterns)]
+            _ => {
+                unknown_cause(cause, account_details, block_height, &mut result);
             }
         }
     }
     Ok(result)
 }
 
+pub(crate) fn configure_skipped_state_changes_log(every: Option<u64>) {
+    LOG_SKIPPED_STATE_CHANGES_EVERY.store(every.unwrap_or_default(), Ordering::Relaxed);
+}
+
+// Most of the skipped changes are the contract storage and the access keys. The new value kind
+// or the account change with the skipped cause shows up in the metric before anyone reads the logs
+fn skipped_state_change(value_kind: &str, cause: &StateChangeCauseView, block_height: u64) {
+    let counter = crate::metrics::SKIPPED_STATE_CHANGES_TOTAL
+        .with_label_values(&[value_kind, state_change_cause_kind(cause)]);
+    counter.inc();
+    let every = LOG_SKIPPED_STATE_CHANGES_EVERY.load(Ordering::Relaxed);
+    if every > 0 && (counter.get() - 1) % every == 0 {
+        tracing::info!(
+            target: crate::INDEXER,
+            "Skipped {} state change #{} with cause {:?} at block_height {}",
+            value_kind,
+            counter.get(),
+            cause,
+            block_height
+        );
+    }
+}
+
+// The names are the `type` of the RPC JSON
+fn state_change_value_kind(
+    value: &near_indexer_primitives::views::StateChangeValueView,
+) -> &'static str {
+    use near_indexer_primitives::views::StateChangeValueView;
+    match value {
+        StateChangeValueView::AccountUpdate { .. } => "account_update",
+        StateChangeValueView::AccountDeletion { .. } => "account_deletion",
+        StateChangeValueView::AccessKeyUpdate { .. } => "access_key_update",
+        StateChangeValueView::AccessKeyDeletion { .. } => "access_key_deletion",
+        StateChangeValueView::DataUpdate { .. } => "data_update",
+        StateChangeValueView::DataDeletion { .. } => "data_deletion",
+        StateChangeValueView::ContractCodeUpdate { .. } => "contract_code_update",
+        StateChangeValueView::ContractCodeDeletion { .. } => "contract_code_deletion",
+        // The newer protocol versions add the values, it compiles after the upgrade of near-primitives
+        #[allow(unreachable_patterns)]
+        _ => "unknown",
+    }
+}
+
+fn state_change_cause_kind(cause: &StateChangeCauseView) -> &'static str {
+    match cause {
+        StateChangeCauseView::NotWritableToDisk => "not_writable_to_disk",
+        StateChangeCauseView::InitialState => "initial_state",
+        StateChangeCauseView::TransactionProcessing { .. } => "transaction_processing",
+        StateChangeCauseView::ActionReceiptProcessingStarted { .. } => {
+            "action_receipt_processing_started"
+        }
+        StateChangeCauseView::ActionReceiptGasReward { .. } => "action_receipt_gas_reward",
+        StateChangeCauseView::ReceiptProcessing { .. } => "receipt_processing",
+        StateChangeCauseView::PostponedReceipt { .. } => "postponed_receipt",
+        StateChangeCauseView::UpdatedDelayedReceipts => "updated_delayed_receipts",
+        StateChangeCauseView::ValidatorAccountsUpdate => "validator_accounts_update",
+        StateChangeCauseView::Migration => "migration",
+        StateChangeCauseView::Resharding => "resharding",
+        #[allow(unreachable_patterns)]
+        _ => "unknown",
+    }
+}
+
+fn unknown_cause(
+    cause: &StateChangeCauseView,
+    account_details: crate::AccountWithBalance,
+    block_height: u64,
+    result: &mut AccountChangesBalances,
+) {
+    tracing::warn!(
+        target: crate::INDEXER,
+        "Unknown state change cause {:?} for account {} at block_height {}, balance {:?}",
+        cause,
+        account_details.account_id,
+        block_height,
+        account_details.balance
+    );
+    crate::metrics::UNKNOWN_STATE_CHANGE_CAUSES_TOTAL.inc();
+    // Without the row, the next change of the account gets this delta too
+    if crate::db_adapters::store_unknown_causes() {
+        result.unknown.push(account_details);
+    }
+}
+
+// The accounts did not exist before, so the previous balance is zero
+async fn store_initial_state_for_chunk(
+    initial_state_changes: &[crate::AccountWithBalance],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    shard_id: near_indexer_primitives::types::ShardId,
+    balances_cache: &crate::BalanceCache,
+) -> Vec<BalanceChange> {
+    let mut result: Vec<BalanceChange> = vec![];
+    for new_details in initial_state_changes
+        .iter()
+        .filter(|details| is_tracked(&details.account_id))
+    {
+        save_latest_balance(
+            new_details.account_id.clone(),
+            &new_details.balance,
+            balances_cache,
+        )
+        .await;
+
+        result.push(BalanceChange {
+            block_timestamp: block_header.timestamp.into(),
+            receipt_id: None,
+            transaction_hash: None,
+            affected_account_id: new_details.account_id.to_string(),
+            involved_account_id: None,
+            direction: crate::models::Direction::Inbound.print().to_string(),
+            cause: crate::models::Cause::InitialState.print().to_string(),
+            status: ExecutionStatusView::SuccessValue(vec![])
+                .print()
+                .to_string(),
+            delta_nonstaked_amount: crate::models::to_decimal(new_details.balance.non_staked),
+            absolute_nonstaked_amount: crate::models::to_decimal(new_details.balance.non_staked),
+            delta_staked_amount: crate::models::to_decimal(new_details.balance.staked),
+            absolute_staked_amount: crate::models::to_decimal(new_details.balance.staked),
+            shard_id: shard_id as i32,
+            // will enumerate later
+            index_in_chunk: 0,
+            index_in_block: 0,
+            event_id: String::new(),
+            parent_transaction_hash: None,
+            index_in_receipt: None,
+            fiat_value_usd: None,
+            gas_burnt: None,
+            predecessor_account_id: None,
+            receiver_account_id: None,
+        });
+    }
+
+    result
+}
+
 async fn store_validator_accounts_update_for_chunk(
     validator_changes: &[crate::AccountWithBalance],
     block_header: &near_indexer_primitives::views::BlockHeaderView,
@@ -203,10 +836,13 @@ async fn store_validator_accounts_update_for_chunk(
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
 ) -> anyhow::Result<Vec<BalanceChange>> {
     let mut result: Vec<BalanceChange> = vec![];
-    for new_details in validator_changes {
+    for new_details in validator_changes
+        .iter()
+        .filter(|details| is_tracked(&details.account_id))
+    {
         let prev_balance = get_balance_retriable(
             &new_details.account_id,
-            &block_header.prev_hash,
+            block_header,
             balances_cache,
             json_rpc_client,
         )
@@ -226,56 +862,150 @@ async fn store_validator_accounts_update_for_chunk(
             affected_account_id: new_details.account_id.to_string(),
             involved_account_id: None,
             direction: crate::models::Direction::Inbound.print().to_string(),
-            cause: crate::models::Cause::ValidatorsReward.print().to_string(),
-            status: ExecutionStatusView::SuccessValue("".to_string())
+            cause: if crate::db_adapters::is_protocol_treasury(&new_details.account_id) {
+                crate::models::Cause::ProtocolTreasuryReward
+            } else {
+                crate::models::Cause::ValidatorsReward
+            }
+            .print()
+            .to_string(),
+            status: ExecutionStatusView::SuccessValue(vec![])
                 .print()
                 .to_string(),
-            delta_nonstaked_amount: BigDecimal::from_str(&deltas.0.to_string()).unwrap(),
-            absolute_nonstaked_amount: BigDecimal::from_str(
-                &new_details.balance.non_staked.to_string(),
-            )
-            .unwrap(),
-            delta_staked_amount: BigDecimal::from_str(&deltas.1.to_string()).unwrap(),
-            absolute_staked_amount: BigDecimal::from_str(&new_details.balance.staked.to_string())
-                .unwrap(),
+            delta_nonstaked_amount: crate::models::to_decimal(deltas.0),
+            absolute_nonstaked_amount: crate::models::to_decimal(new_details.balance.non_staked),
+            delta_staked_amount: crate::models::to_decimal(deltas.1),
+            absolute_staked_amount: crate::models::to_decimal(new_details.balance.staked),
             shard_id: shard_id as i32,
             // will enumerate later
             index_in_chunk: 0,
+            index_in_block: 0,
+            event_id: String::new(),
+            parent_transaction_hash: None,
+            index_in_receipt: None,
+            fiat_value_usd: None,
+            gas_burnt: None,
+            predecessor_account_id: None,
+            receiver_account_id: None,
         });
     }
 
     Ok(result)
 }
 
-async fn store_transaction_execution_outcomes_for_chunk(
-    transactions: &[near_indexer_primitives::IndexerTransactionWithOutcome],
-    transaction_changes: &mut HashMap<
-        near_indexer_primitives::CryptoHash,
-        crate::AccountWithBalance,
-    >,
+async fn store_unknown_causes_for_chunk(
+    unknown_changes: &[crate::AccountWithBalance],
     block_header: &near_indexer_primitives::views::BlockHeaderView,
     shard_id: near_indexer_primitives::types::ShardId,
     balances_cache: &crate::BalanceCache,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
 ) -> anyhow::Result<Vec<BalanceChange>> {
     let mut result: Vec<BalanceChange> = vec![];
-
-    for transaction in transactions {
-        let affected_account_id = &transaction.transaction.signer_id;
-        let involved_account_id = match transaction.transaction.receiver_id.as_str() {
-            "system" => None,
-            _ => Some(&transaction.transaction.receiver_id),
-        };
-
+    for new_details in unknown_changes
+        .iter()
+        .filter(|details| is_tracked(&details.account_id))
+    {
         let prev_balance = get_balance_retriable(
-            affected_account_id,
-            &block_header.prev_hash,
+            &new_details.account_id,
+            block_header,
             balances_cache,
             json_rpc_client,
         )
         .await?;
+        let deltas = get_delta_balance(&new_details.balance, &prev_balance);
+        save_latest_balance(
+            new_details.account_id.clone(),
+            &new_details.balance,
+            balances_cache,
+        )
+        .await;
 
-        let details_after_transaction = transaction_changes
+        result.push(BalanceChange {
+            block_timestamp: block_header.timestamp.into(),
+            receipt_id: None,
+            transaction_hash: None,
+            affected_account_id: new_details.account_id.to_string(),
+            involved_account_id: None,
+            direction: if deltas.0 < 0 || deltas.1 < 0 {
+                crate::models::Direction::Outbound
+            } else {
+                crate::models::Direction::Inbound
+            }
+            .print()
+            .to_string(),
+            cause: crate::models::Cause::Unknown.print().to_string(),
+            status: ExecutionStatusView::SuccessValue(vec![])
+                .print()
+                .to_string(),
+            delta_nonstaked_amount: crate::models::to_decimal(deltas.0),
+            absolute_nonstaked_amount: crate::models::to_decimal(new_details.balance.non_staked),
+            delta_staked_amount: crate::models::to_decimal(deltas.1),
+            absolute_staked_amount: crate::models::to_decimal(new_details.balance.staked),
+            shard_id: shard_id as i32,
+            // will enumerate later
+            index_in_chunk: 0,
+            index_in_block: 0,
+            event_id: String::new(),
+            parent_transaction_hash: None,
+            index_in_receipt: None,
+            fiat_value_usd: None,
+            gas_burnt: None,
+            predecessor_account_id: None,
+            receiver_account_id: None,
+        });
+    }
+
+    Ok(result)
+}
+
+async fn store_transaction_execution_outcomes_for_chunk(
+    transactions: &[near_indexer_primitives::IndexerTransactionWithOutcome],
+    transaction_changes: &mut HashMap<
+        near_indexer_primitives::CryptoHash,
+        crate::AccountWithBalance,
+    >,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    shard_id: near_indexer_primitives::types::ShardId,
+    balances_cache: &crate::BalanceCache,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<Vec<BalanceChange>> {
+    let mut result: Vec<BalanceChange> = vec![];
+
+    for transaction in transactions {
+        let affected_account_id = &transaction.transaction.signer_id;
+        // Relayer signs the meta-transaction and pays for the gas and the deposits,
+        // the actions are the ones of the delegated sender
+        let delegate_action = find_delegate_action(&transaction.transaction.actions);
+        let involved_account_id = match (
+            delegate_action,
+            transaction.transaction.receiver_id.as_str(),
+        ) {
+            (Some(delegate_action), _) => Some(&delegate_action.sender_id),
+            (None, "system") => None,
+            (None, _) => Some(&transaction.transaction.receiver_id),
+        };
+        let cause = match delegate_action {
+            Some(_) => crate::models::Cause::MetaTransaction,
+            None => crate::models::Cause::Transaction,
+        };
+        let gas_burnt = Some(crate::models::to_decimal(
+            transaction.outcome.execution_outcome.outcome.gas_burnt,
+        ));
+        // The gas is burnt by the relayer only, the row of the delegated sender has none
+        let involved_gas_burnt = match delegate_action {
+            Some(_) => None,
+            None => gas_burnt.clone(),
+        };
+        // The fee row and the effects of the receipt are one action of the user
+        let converted_receipt_id = transaction
+            .outcome
+            .execution_outcome
+            .outcome
+            .receipt_ids
+            .first()
+            .map(ToString::to_string);
+
+        let details_after_transaction = transaction_changes
             .remove(&transaction.transaction.hash)
             .ok_or_else(|| {
             anyhow::anyhow!(
@@ -288,68 +1018,83 @@ async fn store_transaction_execution_outcomes_for_chunk(
             anyhow::bail!(
                 "Unexpected balance change info found for transaction {}.\nExpected account_id {},\nActual account_id {}",
                 &transaction.transaction.hash.to_string(),
-                affected_account_id.to_string(),
-                details_after_transaction.account_id.to_string()
+                affected_account_id,
+                details_after_transaction.account_id
             );
         }
 
-        let deltas = get_delta_balance(&details_after_transaction.balance, &prev_balance);
-        save_latest_balance(
-            affected_account_id.clone(),
-            &details_after_transaction.balance,
-            balances_cache,
-        )
-        .await;
-
-        result.push(BalanceChange {
-            block_timestamp: block_header.timestamp.into(),
-            receipt_id: None,
-            transaction_hash: Some(transaction.transaction.hash.to_string()),
-            affected_account_id: affected_account_id.to_string(),
-            involved_account_id: involved_account_id.map(|id| id.to_string()),
-            direction: crate::models::Direction::Outbound.print().to_string(),
-            cause: crate::models::Cause::Transaction.print().to_string(),
-            status: transaction
-                .outcome
-                .execution_outcome
-                .outcome
-                .status
-                .print()
-                .to_string(),
-            delta_nonstaked_amount: BigDecimal::from_str(&deltas.0.to_string()).unwrap(),
-            absolute_nonstaked_amount: BigDecimal::from_str(
-                &details_after_transaction.balance.non_staked.to_string(),
+        if is_tracked(affected_account_id) {
+            let prev_balance = get_balance_retriable(
+                affected_account_id,
+                block_header,
+                balances_cache,
+                json_rpc_client,
             )
-            .unwrap(),
-            delta_staked_amount: BigDecimal::from_str(&deltas.1.to_string()).unwrap(),
-            absolute_staked_amount: BigDecimal::from_str(
-                &details_after_transaction.balance.staked.to_string(),
+            .await?;
+            let deltas = get_delta_balance(&details_after_transaction.balance, &prev_balance);
+            save_latest_balance(
+                affected_account_id.clone(),
+                &details_after_transaction.balance,
+                balances_cache,
             )
-            .unwrap(),
-            shard_id: shard_id as i32,
-            // will enumerate later
-            index_in_chunk: 0,
-        });
+            .await;
+
+            result.push(BalanceChange {
+                block_timestamp: block_header.timestamp.into(),
+                receipt_id: converted_receipt_id.clone(),
+                transaction_hash: Some(transaction.transaction.hash.to_string()),
+                affected_account_id: affected_account_id.to_string(),
+                involved_account_id: involved_account_id.map(|id| id.to_string()),
+                direction: crate::models::Direction::Outbound.print().to_string(),
+                cause: cause.print().to_string(),
+                status: transaction
+                    .outcome
+                    .execution_outcome
+                    .outcome
+                    .status
+                    .print()
+                    .to_string(),
+                delta_nonstaked_amount: crate::models::to_decimal(deltas.0),
+                absolute_nonstaked_amount: crate::models::to_decimal(
+                    details_after_transaction.balance.non_staked,
+                ),
+                delta_staked_amount: crate::models::to_decimal(deltas.1),
+                absolute_staked_amount: crate::models::to_decimal(
+                    details_after_transaction.balance.staked,
+                ),
+                shard_id: shard_id as i32,
+                // will enumerate later
+                index_in_chunk: 0,
+                index_in_block: 0,
+                event_id: String::new(),
+                parent_transaction_hash: None,
+                index_in_receipt: None,
+                fiat_value_usd: None,
+                gas_burnt: gas_burnt.clone(),
+                predecessor_account_id: None,
+                receiver_account_id: None,
+            });
+        }
 
         // Adding the opposite entry to the DB, just to show that the second account_id was there too
-        if let Some(account_id) = involved_account_id {
+        if let Some(account_id) = involved_account_id.filter(|id| is_tracked(id)) {
             if account_id != affected_account_id {
                 // balance is not changing here, we just note the line here
                 let balance = get_balance_retriable(
                     account_id,
-                    &block_header.prev_hash,
+                    block_header,
                     balances_cache,
                     json_rpc_client,
                 )
                 .await?;
                 result.push(BalanceChange {
                     block_timestamp: block_header.timestamp.into(),
-                    receipt_id: None,
+                    receipt_id: converted_receipt_id.clone(),
                     transaction_hash: Some(transaction.transaction.hash.to_string()),
                     affected_account_id: account_id.to_string(),
                     involved_account_id: Some(affected_account_id.to_string()),
                     direction: crate::models::Direction::Inbound.print().to_string(),
-                    cause: crate::models::Cause::Transaction.print().to_string(),
+                    cause: cause.print().to_string(),
                     status: transaction
                         .outcome
                         .execution_outcome
@@ -358,16 +1103,20 @@ async fn store_transaction_execution_outcomes_for_chunk(
                         .print()
                         .to_string(),
                     delta_nonstaked_amount: BigDecimal::zero(),
-                    absolute_nonstaked_amount: BigDecimal::from_str(
-                        &balance.non_staked.to_string(),
-                    )
-                    .unwrap(),
+                    absolute_nonstaked_amount: crate::models::to_decimal(balance.non_staked),
                     delta_staked_amount: BigDecimal::zero(),
-                    absolute_staked_amount: BigDecimal::from_str(&balance.staked.to_string())
-                        .unwrap(),
+                    absolute_staked_amount: crate::models::to_decimal(balance.staked),
                     shard_id: shard_id as i32,
                     // will enumerate later
                     index_in_chunk: 0,
+                    index_in_block: 0,
+                    event_id: String::new(),
+                    parent_transaction_hash: None,
+                    index_in_receipt: None,
+                    fiat_value_usd: None,
+                    gas_burnt: involved_gas_burnt.clone(),
+                    predecessor_account_id: None,
+                    receiver_account_id: None,
                 });
             }
         }
@@ -404,69 +1153,124 @@ async fn store_receipt_execution_outcomes_for_chunk(
             "system" => None,
             _ => Some(&outcome_with_receipt.receipt.predecessor_id),
         };
+        let meta_transaction = meta_transaction_role(outcome_with_receipt);
+        let cause = match meta_transaction {
+            MetaTransactionRole::None => crate::models::Cause::Receipt,
+            MetaTransactionRole::Delegate | MetaTransactionRole::Inner(_) => {
+                crate::models::Cause::MetaTransaction
+            }
+        };
+        let involved_account_id = match &meta_transaction {
+            MetaTransactionRole::Inner(delegated_sender) => Some(delegated_sender),
+            _ => involved_account_id,
+        };
+        let gas_burnt = Some(crate::models::to_decimal(
+            outcome_with_receipt.execution_outcome.outcome.gas_burnt,
+        ));
+        // The relayer has prepaid the gas of the meta transaction, the delegated sender has burnt none:
+        // it is the receiver of the delegate receipt and the predecessor of the inner one
+        let (affected_gas_burnt, involved_gas_burnt) = match meta_transaction {
+            MetaTransactionRole::None => (gas_burnt.clone(), gas_burnt),
+            MetaTransactionRole::Delegate => (None, gas_burnt),
+            MetaTransactionRole::Inner(_) => (gas_burnt, None),
+        };
 
         if let Some(details_after_receipt) = receipt_changes.remove(receipt_id) {
             if details_after_receipt.account_id != *affected_account_id {
                 anyhow::bail!(
                 "Unexpected balance change info found for receipt {}.\nExpected account_id {},\nActual account_id {}",
-                receipt_id.to_string(),
-                affected_account_id.to_string(),
-                details_after_receipt.account_id.to_string()
+                receipt_id,
+                affected_account_id,
+                details_after_receipt.account_id
             );
             }
 
-            let prev_balance = get_balance_retriable(
-                affected_account_id,
-                &block_header.prev_hash,
-                balances_cache,
-                json_rpc_client,
-            )
-            .await?;
-
-            let deltas = get_delta_balance(&details_after_receipt.balance, &prev_balance);
-            save_latest_balance(
-                affected_account_id.clone(),
-                &details_after_receipt.balance,
-                balances_cache,
-            )
-            .await;
-
-            result.push(BalanceChange {
-                block_timestamp: block_header.timestamp.into(),
-                receipt_id: Some(receipt_id.to_string()),
-                transaction_hash: None,
-                affected_account_id: affected_account_id.to_string(),
-                involved_account_id: involved_account_id.map(|id| id.to_string()),
-                direction: crate::models::Direction::Inbound.print().to_string(),
-                cause: crate::models::Cause::Receipt.print().to_string(),
-                status: outcome_with_receipt
-                    .execution_outcome
-                    .outcome
-                    .status
-                    .print()
-                    .to_string(),
-                delta_nonstaked_amount: BigDecimal::from_str(&deltas.0.to_string()).unwrap(),
-                absolute_nonstaked_amount: BigDecimal::from_str(
-                    &details_after_receipt.balance.non_staked.to_string(),
+            if is_tracked(affected_account_id) {
+                let prev_balance = get_balance_retriable(
+                    affected_account_id,
+                    block_header,
+                    balances_cache,
+                    json_rpc_client,
                 )
-                .unwrap(),
-                delta_staked_amount: BigDecimal::from_str(&deltas.1.to_string()).unwrap(),
-                absolute_staked_amount: BigDecimal::from_str(
-                    &details_after_receipt.balance.staked.to_string(),
+                .await?;
+
+                let deltas = get_delta_balance(&details_after_receipt.balance, &prev_balance);
+                save_latest_balance(
+                    affected_account_id.clone(),
+                    &details_after_receipt.balance,
+                    balances_cache,
                 )
-                .unwrap(),
-                shard_id: shard_id as i32,
-                // will enumerate later
-                index_in_chunk: 0,
-            });
+                .await;
+                let affected_cause = if creates_implicit_account(
+                    outcome_with_receipt,
+                    &prev_balance,
+                    &details_after_receipt.balance,
+                ) {
+                    tracing::debug!(
+                        target: crate::INDEXER,
+                        "Implicit account {} is created by receipt {} at block_height {}",
+                        affected_account_id,
+                        receipt_id,
+                        block_header.height
+                    );
+                    crate::models::Cause::ImplicitAccountCreated
+                        .print()
+                        .to_string()
+                } else {
+                    cause.print().to_string()
+                };
+
+                let change = BalanceChange {
+                    block_timestamp: block_header.timestamp.into(),
+                    receipt_id: Some(receipt_id.to_string()),
+                    transaction_hash: None,
+                    affected_account_id: affected_account_id.to_string(),
+                    involved_account_id: involved_account_id.map(|id| id.to_string()),
+                    direction: crate::models::Direction::Inbound.print().to_string(),
+                    cause: affected_cause,
+                    status: outcome_with_receipt
+                        .execution_outcome
+                        .outcome
+                        .status
+                        .print()
+                        .to_string(),
+                    delta_nonstaked_amount: crate::models::to_decimal(deltas.0),
+                    absolute_nonstaked_amount: crate::models::to_decimal(
+                        details_after_receipt.balance.non_staked,
+                    ),
+                    delta_staked_amount: crate::models::to_decimal(deltas.1),
+                    absolute_staked_amount: crate::models::to_decimal(
+                        details_after_receipt.balance.staked,
+                    ),
+                    shard_id: shard_id as i32,
+                    // will enumerate later
+                    index_in_chunk: 0,
+                    index_in_block: 0,
+                    event_id: String::new(),
+                    parent_transaction_hash: None,
+                    index_in_receipt: None,
+                    fiat_value_usd: None,
+                    gas_burnt: affected_gas_burnt.clone(),
+                    predecessor_account_id: Some(
+                        outcome_with_receipt.receipt.predecessor_id.to_string(),
+                    ),
+                    receiver_account_id: Some(outcome_with_receipt.receipt.receiver_id.to_string()),
+                };
+                result.extend(split_by_actions(
+                    change,
+                    outcome_with_receipt,
+                    &prev_balance,
+                    deltas,
+                ));
+            }
 
             // Adding the opposite entry to the DB, just to show that the second account_id was there too
-            if let Some(account_id) = involved_account_id {
+            if let Some(account_id) = involved_account_id.filter(|id| is_tracked(id)) {
                 if account_id != affected_account_id {
                     // balance is not changing here, we just note the line here
                     let balance = get_balance_retriable(
                         account_id,
-                        &block_header.prev_hash,
+                        block_header,
                         balances_cache,
                         json_rpc_client,
                     )
@@ -478,7 +1282,7 @@ async fn store_receipt_execution_outcomes_for_chunk(
                         affected_account_id: account_id.to_string(),
                         involved_account_id: Some(affected_account_id.to_string()),
                         direction: crate::models::Direction::Outbound.print().to_string(),
-                        cause: crate::models::Cause::Receipt.print().to_string(),
+                        cause: cause.print().to_string(),
                         status: outcome_with_receipt
                             .execution_outcome
                             .outcome
@@ -486,75 +1290,55 @@ async fn store_receipt_execution_outcomes_for_chunk(
                             .print()
                             .to_string(),
                         delta_nonstaked_amount: BigDecimal::zero(),
-                        absolute_nonstaked_amount: BigDecimal::from_str(
-                            &balance.non_staked.to_string(),
-                        )
-                        .unwrap(),
+                        absolute_nonstaked_amount: crate::models::to_decimal(balance.non_staked),
                         delta_staked_amount: BigDecimal::zero(),
-                        absolute_staked_amount: BigDecimal::from_str(&balance.staked.to_string())
-                            .unwrap(),
+                        absolute_staked_amount: crate::models::to_decimal(balance.staked),
                         shard_id: shard_id as i32,
                         // will enumerate later
                         index_in_chunk: 0,
+                        index_in_block: 0,
+                        event_id: String::new(),
+                        parent_transaction_hash: None,
+                        index_in_receipt: None,
+                        fiat_value_usd: None,
+                        gas_burnt: involved_gas_burnt.clone(),
+                        predecessor_account_id: Some(
+                            outcome_with_receipt.receipt.predecessor_id.to_string(),
+                        ),
+                        receiver_account_id: Some(
+                            outcome_with_receipt.receipt.receiver_id.to_string(),
+                        ),
                     });
                 }
             }
         }
 
         // REWARDS
-        if let Some(details_after_reward) = reward_changes.remove(receipt_id) {
+        if let Some(details_after_reward) = reward_changes
+            .remove(receipt_id)
+            .filter(|details| is_tracked(&details.account_id))
+        {
             if details_after_reward.account_id != *affected_account_id {
                 anyhow::bail!(
                 "Unexpected balance change info found for receipt_id {} (reward).\nExpected account_id {},\nActual account_id {}",
-                receipt_id.to_string(),
-                affected_account_id.to_string(),
-                details_after_reward.account_id.to_string()
+                receipt_id,
+                affected_account_id,
+                details_after_reward.account_id
             );
             }
 
-            let prev_balance = get_balance_retriable(
-                affected_account_id,
-                &block_header.prev_hash,
-                balances_cache,
-                json_rpc_client,
-            )
-            .await?;
-            let deltas = get_delta_balance(&details_after_reward.balance, &prev_balance);
-            save_latest_balance(
-                affected_account_id.clone(),
-                &details_after_reward.balance,
-                balances_cache,
-            )
-            .await;
-
-            result.push(BalanceChange {
-                block_timestamp: block_header.timestamp.into(),
-                receipt_id: Some(receipt_id.to_string()),
-                transaction_hash: None,
-                affected_account_id: affected_account_id.to_string(),
-                involved_account_id: involved_account_id.map(|id| id.to_string()),
-                direction: crate::models::Direction::Inbound.print().to_string(),
-                cause: crate::models::Cause::ContractReward.print().to_string(),
-                status: outcome_with_receipt
-                    .execution_outcome
-                    .outcome
-                    .status
-                    .print()
-                    .to_string(),
-                delta_nonstaked_amount: BigDecimal::from_str(&deltas.0.to_string()).unwrap(),
-                absolute_nonstaked_amount: BigDecimal::from_str(
-                    &details_after_reward.balance.non_staked.to_string(),
-                )
-                .unwrap(),
-                delta_staked_amount: BigDecimal::from_str(&deltas.1.to_string()).unwrap(),
-                absolute_staked_amount: BigDecimal::from_str(
-                    &details_after_reward.balance.staked.to_string(),
+            result.push(
+                store_contract_reward(
+                    receipt_id,
+                    &details_after_reward,
+                    Some(outcome_with_receipt),
+                    block_header,
+                    shard_id,
+                    balances_cache,
+                    json_rpc_client,
                 )
-                .unwrap(),
-                shard_id: shard_id as i32,
-                // will enumerate later
-                index_in_chunk: 0,
-            });
+                .await?,
+            );
         }
     }
 
@@ -566,18 +1350,237 @@ async fn store_receipt_execution_outcomes_for_chunk(
             receipt_changes
         );
     }
-    if !reward_changes.is_empty() {
-        anyhow::bail!(
-            "{} reward changes for receipts were not applied, block_height {}\n{:#?}",
-            reward_changes.len(),
-            block_header.height,
-            reward_changes
+    // The gas reward is credited even if we did not meet the receipt outcome in this chunk.
+    // We still store it, otherwise the balance of the contract drifts
+    let mut orphan_rewards: Vec<_> = reward_changes
+        .drain()
+        .filter(|(_, details)| is_tracked(&details.account_id))
+        .collect();
+    orphan_rewards.sort_by_key(|(receipt_id, _)| receipt_id.to_string());
+    for (receipt_id, details_after_reward) in orphan_rewards {
+        tracing::warn!(
+            target: crate::INDEXER,
+            "Gas reward for receipt {} to account {} has no execution outcome, block_height {}",
+            receipt_id.to_string(),
+            details_after_reward.account_id.to_string(),
+            block_header.height
+        );
+        result.push(
+            store_contract_reward(
+                &receipt_id,
+                &details_after_reward,
+                None,
+                block_header,
+                shard_id,
+                balances_cache,
+                json_rpc_client,
+            )
+            .await?,
         );
     }
 
     Ok(result)
 }
 
+// Contract gets 30% of the gas burnt by the function call on it.
+// The outcome of the receipt is None if we did not meet it in this chunk
+async fn store_contract_reward(
+    receipt_id: &near_indexer_primitives::CryptoHash,
+    details_after_reward: &crate::AccountWithBalance,
+    outcome_with_receipt: Option<&near_indexer_primitives::IndexerExecutionOutcomeWithReceipt>,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    shard_id: near_indexer_primitives::types::ShardId,
+    balances_cache: &crate::BalanceCache,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<BalanceChange> {
+    let prev_balance = get_balance_retriable(
+        &details_after_reward.account_id,
+        block_header,
+        balances_cache,
+        json_rpc_client,
+    )
+    .await?;
+    let deltas = get_delta_balance(&details_after_reward.balance, &prev_balance);
+    save_latest_balance(
+        details_after_reward.account_id.clone(),
+        &details_after_reward.balance,
+        balances_cache,
+    )
+    .await;
+
+    let receipt = outcome_with_receipt.map(|outcome_with_receipt| &outcome_with_receipt.receipt);
+    let involved_account_id = receipt
+        .map(|receipt| &receipt.predecessor_id)
+        .filter(|account_id| account_id.as_str() != "system");
+    let status = match outcome_with_receipt {
+        Some(outcome_with_receipt) => &outcome_with_receipt.execution_outcome.outcome.status,
+        None => &ExecutionStatusView::SuccessValue(vec![]),
+    };
+
+    Ok(BalanceChange {
+        block_timestamp: block_header.timestamp.into(),
+        receipt_id: Some(receipt_id.to_string()),
+        transaction_hash: None,
+        affected_account_id: details_after_reward.account_id.to_string(),
+        involved_account_id: involved_account_id.map(|id| id.to_string()),
+        direction: crate::models::Direction::Inbound.print().to_string(),
+        cause: crate::models::Cause::ContractReward.print().to_string(),
+        status: status.print().to_string(),
+        delta_nonstaked_amount: crate::models::to_decimal(deltas.0),
+        absolute_nonstaked_amount: crate::models::to_decimal(
+            details_after_reward.balance.non_staked,
+        ),
+        delta_staked_amount: crate::models::to_decimal(deltas.1),
+        absolute_staked_amount: crate::models::to_decimal(details_after_reward.balance.staked),
+        shard_id: shard_id as i32,
+        // will enumerate later
+        index_in_chunk: 0,
+        index_in_block: 0,
+        event_id: String::new(),
+        parent_transaction_hash: None,
+        index_in_receipt: None,
+        fiat_value_usd: None,
+        gas_burnt: outcome_with_receipt.map(|outcome_with_receipt| {
+            crate::models::to_decimal(outcome_with_receipt.execution_outcome.outcome.gas_burnt)
+        }),
+        predecessor_account_id: receipt.map(|receipt| receipt.predecessor_id.to_string()),
+        receiver_account_id: receipt.map(|receipt| receipt.receiver_id.to_string()),
+    })
+}
+
+pub(crate) fn configure_split_actions(split_actions: bool) {
+    SPLIT_ACTIONS.store(split_actions, Ordering::Relaxed);
+}
+
+// The row of the receipt receiver as one row per action changing the balance. The deposits
+// of Transfer and FunctionCall and the stake increase are known from the actions, the rest
+// of the delta (the contract code, the storage, the DeleteAccount) goes to the last of them.
+// The failed receipt has changed nothing but the gas, the meta transaction spends
+// the deposits of the sender, and one such action is the whole delta: they keep the merged row
+fn split_by_actions(
+    change: BalanceChange,
+    outcome_with_receipt: &near_indexer_primitives::IndexerExecutionOutcomeWithReceipt,
+    prev_balance: &crate::BalanceDetails,
+    deltas: (i128, i128),
+) -> Vec<BalanceChange> {
+    let actions = match &outcome_with_receipt.receipt.receipt {
+        ReceiptEnumView::Action { actions, .. } => actions,
+        ReceiptEnumView::Data { .. } => return vec![change],
+    };
+    if !SPLIT_ACTIONS.load(Ordering::Relaxed)
+        || find_delegate_action(actions).is_some()
+        || matches!(
+            outcome_with_receipt.execution_outcome.outcome.status,
+            ExecutionStatusView::Failure(_)
+        )
+    {
+        return vec![change];
+    }
+
+    // (index in the receipt, nonstaked delta, staked delta)
+    let mut action_deltas: Vec<(usize, i128, i128)> = vec![];
+    let mut staked = prev_balance.staked;
+    for (index, action) in actions.iter().enumerate() {
+        let (nonstaked_delta, staked_delta) = match action {
+            ActionView::Transfer { deposit } | ActionView::FunctionCall { deposit, .. } => {
+                (*deposit as i128, 0)
+            }
+            // Unstaking happens at the end of the epoch, the increase is locked at once
+            ActionView::Stake { stake, .. } if *stake > staked => {
+                let locked = (*stake - staked) as i128;
+                staked = *stake;
+                (-locked, locked)
+            }
+            _ => continue,
+        };
+        if nonstaked_delta != 0 || staked_delta != 0 {
+            action_deltas.push((index, nonstaked_delta, staked_delta));
+        }
+    }
+    if action_deltas.len() < 2 {
+        return vec![change];
+    }
+    let (known_nonstaked, known_staked) = action_deltas.iter().fold(
+        (0, 0),
+        |(nonstaked, staked), (_, nonstaked_delta, staked_delta)| {
+            (nonstaked + nonstaked_delta, staked + staked_delta)
+        },
+    );
+    if let Some(last) = action_deltas.last_mut() {
+        last.1 += deltas.0 - known_nonstaked;
+        last.2 += deltas.1 - known_staked;
+    }
+
+    let mut absolute_nonstaked = prev_balance.non_staked as i128;
+    let mut absolute_staked = prev_balance.staked as i128;
+    action_deltas
+        .into_iter()
+        .map(|(index, nonstaked_delta, staked_delta)| {
+            absolute_nonstaked += nonstaked_delta;
+            absolute_staked += staked_delta;
+            BalanceChange {
+                delta_nonstaked_amount: crate::models::to_decimal(nonstaked_delta),
+                absolute_nonstaked_amount: crate::models::to_decimal(absolute_nonstaked),
+                delta_staked_amount: crate::models::to_decimal(staked_delta),
+                absolute_staked_amount: crate::models::to_decimal(absolute_staked),
+                index_in_receipt: Some(index as i32),
+                ..change.clone()
+            }
+        })
+        .collect()
+}
+
+enum MetaTransactionRole {
+    None,
+    // Relayer -> delegated sender, it unpacks the delegate action on the sender's shard
+    Delegate,
+    // Delegated sender -> receiver of the delegate action, with the actions of the sender
+    Inner(near_indexer_primitives::types::AccountId),
+}
+
+// The delegate receipt remembers the receipts it creates, so they are attributed to the delegated sender
+fn meta_transaction_role(
+    outcome_with_receipt: &near_indexer_primitives::IndexerExecutionOutcomeWithReceipt,
+) -> MetaTransactionRole {
+    let mut delegated_receipts = DELEGATED_RECEIPTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if let Some(delegated_sender) = cached::Cached::cache_remove(
+        &mut *delegated_receipts,
+        &outcome_with_receipt.receipt.receipt_id,
+    ) {
+        return MetaTransactionRole::Inner(delegated_sender);
+    }
+    let delegate_action = match &outcome_with_receipt.receipt.receipt {
+        ReceiptEnumView::Action { actions, .. } => find_delegate_action(actions),
+        ReceiptEnumView::Data { .. } => None,
+    };
+    match delegate_action {
+        Some(delegate_action) => {
+            for receipt_id in &outcome_with_receipt.execution_outcome.outcome.receipt_ids {
+                cached::Cached::cache_set(
+                    &mut *delegated_receipts,
+                    *receipt_id,
+                    delegate_action.sender_id.clone(),
+                );
+            }
+            MetaTransactionRole::Delegate
+        }
+        None => MetaTransactionRole::None,
+    }
+}
+
+fn find_delegate_action(
+    actions: &[ActionView],
+) -> Option<&near_primitives::delegate_action::DelegateAction> {
+    actions.iter().find_map(|action| match action {
+        ActionView::Delegate {
+            delegate_action, ..
+        } => Some(delegate_action),
+        _ => None,
+    })
+}
+
 fn get_delta_balance(
     new_balance: &crate::BalanceDetails,
     old_balance: &crate::BalanceDetails,
@@ -588,27 +1591,41 @@ fn get_delta_balance(
     )
 }
 
+// The balance before the block
 async fn get_balance_retriable(
     account_id: &near_indexer_primitives::types::AccountId,
-    block_hash: &near_indexer_primitives::CryptoHash,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
     balance_cache: &crate::BalanceCache,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
 ) -> anyhow::Result<crate::BalanceDetails> {
+    let block_hash = &block_header.prev_hash;
     let mut interval = crate::INTERVAL;
     let mut retry_attempt = 0usize;
 
     loop {
         if retry_attempt == crate::RETRY_COUNT {
-            anyhow::bail!(
+            let message = format!(
                 "Failed to perform query to RPC after {} attempts. Stop trying.\nAccount {}, block_hash {}",
                 crate::RETRY_COUNT,
-                account_id.to_string(),
-                block_hash.to_string()
+                account_id,
+                block_hash
             );
+            // The indexer stops with this error, so the report is sent before it
+            crate::error_reporting::report(
+                crate::error_reporting::Level::Error,
+                "rpc_exhausted",
+                message.clone(),
+                vec![
+                    ("account_id", account_id.to_string()),
+                    ("block_hash", block_hash.to_string()),
+                ],
+            )
+            .await;
+            anyhow::bail!(message);
         }
         retry_attempt += 1;
 
-        match get_balance(account_id, block_hash, balance_cache, json_rpc_client).await {
+        match get_balance(account_id, block_header, balance_cache, json_rpc_client).await {
             Ok(res) => return Ok(res),
             Err(err) => {
                 tracing::error!(
@@ -630,36 +1647,102 @@ async fn get_balance_retriable(
 
 async fn get_balance(
     account_id: &near_indexer_primitives::types::AccountId,
-    block_hash: &near_indexer_primitives::CryptoHash,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
     balance_cache: &crate::BalanceCache,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
 ) -> anyhow::Result<crate::BalanceDetails> {
-    let mut balances_cache_lock = balance_cache.lock().await;
-    let result = match balances_cache_lock.cache_get(account_id) {
-        None => {
-            let account_balance =
-                match get_account_view(json_rpc_client, account_id, block_hash).await {
-                    Ok(account_view) => Ok(crate::BalanceDetails {
-                        non_staked: account_view.amount,
-                        staked: account_view.locked,
-                    }),
-                    Err(err) => match err.handler_error() {
-                        Some(RpcQueryError::UnknownAccount { .. }) => Ok(crate::BalanceDetails {
-                            non_staked: 0,
-                            staked: 0,
-                        }),
-                        _ => Err(err.into()),
-                    },
-                };
-            if let Ok(balance) = account_balance {
-                balances_cache_lock.cache_set(account_id.clone(), balance);
-            }
-            account_balance
+    let block_hash = &block_header.prev_hash;
+    // The cache is locked only to check and to fill it, the lookups are awaited without it,
+    // so the other shards of the block are computed meanwhile
+    let is_missing = {
+        let mut balances_cache_lock = balance_cache.lock().await;
+        if let Some(balance) = balances_cache_lock.cache_get(account_id) {
+            return Ok(*balance);
         }
-        Some(balance) => Ok(*balance),
+        balances_cache_lock.is_missing(account_id)
     };
-    drop(balances_cache_lock);
-    result
+    if let Some(balance) = crate::db_adapters::prefetch::take(account_id, block_hash).await {
+        return Ok(cache_looked_up_balance(account_id, balance, balance_cache).await);
+    }
+    // It has not appeared since the last query, any change of the account forgets it
+    if is_missing {
+        crate::metrics::MISSING_ACCOUNT_HITS_TOTAL.inc();
+        return Ok(crate::BalanceDetails::default());
+    }
+    if let Some(balance) = get_shared_balance(account_id, block_header.height).await {
+        return Ok(cache_looked_up_balance(account_id, balance, balance_cache).await);
+    }
+
+    match get_account_view(json_rpc_client, account_id, block_hash).await {
+        Ok(account_view) => {
+            let balance = crate::BalanceDetails {
+                non_staked: account_view.amount,
+                staked: account_view.locked,
+            };
+            Ok(cache_looked_up_balance(account_id, balance, balance_cache).await)
+        }
+        Err(err) => match err.handler_error() {
+            Some(RpcQueryError::UnknownAccount { .. }) => {
+                let mut balances_cache_lock = balance_cache.lock().await;
+                if let Some(balance) = balances_cache_lock.cache_get(account_id) {
+                    return Ok(*balance);
+                }
+                balances_cache_lock.cache_set(account_id.clone(), crate::BalanceDetails::default());
+                balances_cache_lock.set_missing(account_id.clone());
+                Ok(crate::BalanceDetails::default())
+            }
+            _ => Err(err.into()),
+        },
+    }
+}
+
+// The balance set meanwhile by the other change of the account is the more recent one, it is kept
+async fn cache_looked_up_balance(
+    account_id: &near_indexer_primitives::types::AccountId,
+    balance: crate::BalanceDetails,
+    balance_cache: &crate::BalanceCache,
+) -> crate::BalanceDetails {
+    let mut balances_cache_lock = balance_cache.lock().await;
+    if let Some(balance) = balances_cache_lock.cache_get(account_id) {
+        return *balance;
+    }
+    balances_cache_lock.cache_set(account_id.clone(), balance);
+    balance
+}
+
+// Not found or failed lookups are answered by RPC
+pub(crate) async fn get_shared_balance(
+    account_id: &near_indexer_primitives::types::AccountId,
+    block_height: u64,
+) -> Option<crate::BalanceDetails> {
+    let shared_balance_cache = crate::db_adapters::shared_balance_cache()?;
+    match shared_balance_cache.get(account_id, block_height).await {
+        Ok(Some(balance)) => {
+            crate::metrics::SHARED_BALANCE_CACHE_TOTAL
+                .with_label_values(&["hit"])
+                .inc();
+            Some(balance)
+        }
+        Ok(None) => {
+            crate::metrics::SHARED_BALANCE_CACHE_TOTAL
+                .with_label_values(&["miss"])
+                .inc();
+            None
+        }
+        Err(err) => {
+            crate::metrics::SHARED_BALANCE_CACHE_TOTAL
+                .with_label_values(&["error"])
+                .inc();
+            tracing::warn!(
+                target: crate::INDEXER,
+                "Failed to get the balance of {} from the shared balance cache {}: {:#}",
+                account_id,
+                shared_balance_cache.name(),
+                err
+            );
+            None
+        }
+    }
 }
 
 async fn save_latest_balance(
@@ -678,11 +1761,19 @@ async fn save_latest_balance(
     drop(balances_cache_lock);
 }
 
-async fn get_account_view(
+pub(crate) async fn get_account_view(
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
     account_id: &near_indexer_primitives::types::AccountId,
     block_hash: &near_indexer_primitives::CryptoHash,
 ) -> Result<near_indexer_primitives::views::AccountView, JsonRpcError<RpcQueryError>> {
+    if crate::rpc_cassette::is_replaying() {
+        return crate::rpc_cassette::replay(account_id, block_hash);
+    }
+    #[cfg(feature = "nearcore-state")]
+    if let Some(account) = crate::nearcore_state::view_account(account_id, block_hash) {
+        crate::rpc_cassette::record(account_id, block_hash, 0, &account);
+        return account;
+    }
     let query = near_jsonrpc_client::methods::query::RpcQueryRequest {
         block_reference: near_primitives::types::BlockReference::BlockId(
             near_primitives::types::BlockId::Hash(*block_hash),
@@ -692,10 +1783,24 @@ async fn get_account_view(
         },
     };
 
-    let account_response = json_rpc_client.call(query).await?;
+    let account_response = match json_rpc_client.call(query).await {
+        Ok(account_response) => account_response,
+        Err(err) => {
+            let err = Err(err);
+            crate::rpc_cassette::record(account_id, block_hash, 0, &err);
+            return err;
+        }
+    };
     match account_response.kind {
         near_jsonrpc_primitives::types::query::QueryResponseKind::ViewAccount(account) => {
-            Ok(account)
+            let account = Ok(account);
+            crate::rpc_cassette::record(
+                account_id,
+                block_hash,
+                account_response.block_height,
+                &account,
+            );
+            account
         }
         _ => unreachable!(
             "Unreachable code! Asked for ViewAccount (block_hash {}, account_id {})\nReceived\n\
@@ -706,3 +1811,569 @@ async fn get_account_view(
         ),
     }
 }
+
+// Calls the view method of the contract with JSON args and parses the JSON result
+pub(crate) async fn call_view_function<T: serde::de::DeserializeOwned>(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    contract_account_id: &near_indexer_primitives::types::AccountId,
+    method_name: &str,
+    args: serde_json::Value,
+    block_hash: &near_indexer_primitives::CryptoHash,
+) -> anyhow::Result<T> {
+    let query = near_jsonrpc_client::methods::query::RpcQueryRequest {
+        block_reference: near_primitives::types::BlockReference::BlockId(
+            near_primitives::types::BlockId::Hash(*block_hash),
+        ),
+        request: near_primitives::views::QueryRequest::CallFunction {
+            account_id: contract_account_id.clone(),
+            method_name: method_name.to_string(),
+            args: serde_json::to_vec(&args)?.into(),
+        },
+    };
+    match json_rpc_client.call(query).await?.kind {
+        near_jsonrpc_primitives::types::query::QueryResponseKind::CallResult(result) => {
+            Ok(serde_json::from_slice(&result.result)?)
+        }
+        kind => anyhow::bail!("Unexpected response to {}: {:?}", method_name, kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bigdecimal::BigDecimal;
+    use near_lake_framework::near_indexer_primitives;
+    use num_traits::Zero;
+
+    use crate::models::balance_changes::BalanceChange;
+    use crate::models::PrintEnum;
+
+    fn change(
+        account_id: &str,
+        cause: crate::models::Cause,
+        transaction_hash: Option<&str>,
+    ) -> BalanceChange {
+        BalanceChange {
+            block_timestamp: BigDecimal::from(1_600_000_000_000_000_000u64),
+            receipt_id: None,
+            transaction_hash: transaction_hash.map(ToString::to_string),
+            affected_account_id: account_id.to_string(),
+            involved_account_id: None,
+            direction: crate::models::Direction::Inbound.print().to_string(),
+            cause: cause.print().to_string(),
+            status: "SUCCESS".to_string(),
+            delta_nonstaked_amount: BigDecimal::zero(),
+            absolute_nonstaked_amount: BigDecimal::zero(),
+            delta_staked_amount: BigDecimal::zero(),
+            absolute_staked_amount: BigDecimal::zero(),
+            shard_id: 0,
+            index_in_chunk: 0,
+            index_in_block: 0,
+            event_id: String::new(),
+            parent_transaction_hash: None,
+            index_in_receipt: None,
+            fiat_value_usd: None,
+            gas_burnt: None,
+            predecessor_account_id: None,
+            receiver_account_id: None,
+        }
+    }
+
+    #[test]
+    fn enumerates_in_apply_order() {
+        use crate::models::Cause;
+        let mut changes = vec![
+            change("contract.near", Cause::Receipt, None),
+            change("alice.near", Cause::Transaction, Some("tx1")),
+            change("unknown.near", Cause::Unknown, None),
+            change("validator.near", Cause::ValidatorsReward, None),
+            change("relayer.near", Cause::MetaTransaction, Some("tx2")),
+            change("treasury.near", Cause::ProtocolTreasuryReward, None),
+            change("bob.near", Cause::Receipt, None),
+            change("genesis.near", Cause::InitialState, None),
+        ];
+        super::enumerate_in_apply_order(&mut changes);
+        let order: Vec<(&str, i32)> = changes
+            .iter()
+            .map(|change| (change.affected_account_id.as_str(), change.index_in_chunk))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                ("genesis.near", 0),
+                ("validator.near", 1),
+                ("treasury.near", 2),
+                ("alice.near", 3),
+                ("relayer.near", 4),
+                ("contract.near", 5),
+                ("unknown.near", 6),
+                ("bob.near", 7),
+            ]
+        );
+    }
+
+    // The rows of the other accounts and the order of the shards do not change the ids
+    #[test]
+    fn event_ids_do_not_depend_on_other_rows() {
+        use crate::models::Cause;
+        let receipt = |account_id: &str| {
+            let mut change = change(account_id, Cause::Receipt, None);
+            change.receipt_id = Some("receipt".to_string());
+            change
+        };
+        let ids = |changes: &mut Vec<BalanceChange>| {
+            crate::models::balance_changes::set_event_ids(changes, "block");
+            changes
+                .iter()
+                .map(|change| (change.affected_account_id.clone(), change.event_id.clone()))
+                .collect::<Vec<_>>()
+        };
+        let all = ids(&mut vec![
+            change("alice.near", Cause::Transaction, Some("tx")),
+            receipt("bob.near"),
+            receipt("alice.near"),
+            receipt("alice.near"),
+        ]);
+        let only_alice = ids(&mut vec![
+            receipt("alice.near"),
+            change("alice.near", Cause::Transaction, Some("tx")),
+            receipt("alice.near"),
+        ]);
+        assert_eq!(
+            only_alice,
+            vec![all[2].clone(), all[0].clone(), all[3].clone()]
+        );
+        // The split rows of one receipt are told apart by the position
+        assert_ne!(all[2].1, all[3].1);
+    }
+
+    const HASH: &str = "11111111111111111111111111111111";
+    const PUBLIC_KEY: &str = "ed25519:11111111111111111111111111111111";
+
+    fn outcome_with_receipt(
+        actions: serde_json::Value,
+        status: serde_json::Value,
+    ) -> near_indexer_primitives::IndexerExecutionOutcomeWithReceipt {
+        serde_json::from_value(serde_json::json!({
+            "execution_outcome": {
+                "proof": [],
+                "block_hash": HASH,
+                "id": HASH,
+                "outcome": {
+                    "logs": [],
+                    "receipt_ids": [],
+                    "gas_burnt": 2428000000000u64,
+                    "tokens_burnt": "0",
+                    "executor_id": "contract.near",
+                    "status": status,
+                    "metadata": {"version": 1, "gas_profile": null},
+                },
+            },
+            "receipt": {
+                "predecessor_id": "alice.near",
+                "receiver_id": "contract.near",
+                "receipt_id": HASH,
+                "receipt": {"Action": {
+                    "signer_id": "alice.near",
+                    "signer_public_key": PUBLIC_KEY,
+                    "gas_price": "100000000",
+                    "output_data_receivers": [],
+                    "input_data_ids": [],
+                    "actions": actions,
+                }},
+            },
+        }))
+        .unwrap()
+    }
+
+    // (index_in_receipt, delta_nonstaked, absolute_nonstaked, delta_staked, absolute_staked)
+    fn split(
+        actions: serde_json::Value,
+        status: serde_json::Value,
+        prev_balance: (u128, u128),
+        deltas: (i128, i128),
+    ) -> Vec<(Option<i32>, i128, i128, i128, i128)> {
+        super::configure_split_actions(true);
+        let prev_balance = crate::BalanceDetails {
+            non_staked: prev_balance.0,
+            staked: prev_balance.1,
+        };
+        let mut merged = change("contract.near", crate::models::Cause::Receipt, None);
+        merged.delta_nonstaked_amount = crate::models::to_decimal(deltas.0);
+        merged.delta_staked_amount = crate::models::to_decimal(deltas.1);
+        let to_i128 = |value: &BigDecimal| value.to_string().parse::<i128>().unwrap();
+        super::split_by_actions(
+            merged,
+            &outcome_with_receipt(actions, status),
+            &prev_balance,
+            deltas,
+        )
+        .iter()
+        .map(|change| {
+            (
+                change.index_in_receipt,
+                to_i128(&change.delta_nonstaked_amount),
+                to_i128(&change.absolute_nonstaked_amount),
+                to_i128(&change.delta_staked_amount),
+                to_i128(&change.absolute_staked_amount),
+            )
+        })
+        .collect()
+    }
+
+    fn success() -> serde_json::Value {
+        serde_json::json!({"SuccessValue": ""})
+    }
+
+    fn function_call(deposit: u128) -> serde_json::Value {
+        serde_json::json!({"FunctionCall": {
+            "method_name": "deposit",
+            "args": "",
+            "gas": 30000000000000u64,
+            "deposit": deposit.to_string(),
+        }})
+    }
+
+    #[test]
+    fn splits_receipt_by_actions() {
+        let actions = serde_json::json!([{"Transfer": {"deposit": "10"}}, function_call(5)]);
+        assert_eq!(
+            split(actions, success(), (100, 0), (15, 0)),
+            vec![(Some(0), 10, 110, 0, 0), (Some(1), 5, 115, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn splits_stake_increase() {
+        let actions = serde_json::json!([
+            {"Transfer": {"deposit": "10"}},
+            {"Stake": {"stake": "30", "public_key": PUBLIC_KEY}},
+        ]);
+        assert_eq!(
+            split(actions, success(), (100, 20), (0, 10)),
+            vec![(Some(0), 10, 110, 0, 20), (Some(1), -10, 100, 10, 30)]
+        );
+    }
+
+    #[test]
+    fn keeps_single_action_merged() {
+        let actions = serde_json::json!([{"Transfer": {"deposit": "10"}}]);
+        let rows = split(actions, success(), (100, 0), (10, 0));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, None);
+        assert_eq!(rows[0].1, 10);
+    }
+
+    #[test]
+    fn keeps_failed_receipt_merged() {
+        let actions = serde_json::json!([{"Transfer": {"deposit": "10"}}, function_call(5)]);
+        let failure = serde_json::json!({"Failure": {"ActionError": {
+            "index": 1,
+            "kind": {"FunctionCallError": {"ExecutionError": "Smart contract panicked"}},
+        }}});
+        let rows = split(actions, failure, (100, 0), (0, 0));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, None);
+    }
+
+    // The storage and the code are not in the actions, the difference goes to the last row
+    #[test]
+    fn puts_remainder_on_last_action() {
+        let actions = serde_json::json!([
+            "CreateAccount",
+            {"Transfer": {"deposit": "10"}},
+            {"DeployContract": {"code": ""}},
+            function_call(5),
+        ]);
+        assert_eq!(
+            split(actions, success(), (100, 0), (12, 0)),
+            vec![(Some(1), 10, 110, 0, 0), (Some(3), 2, 112, 0, 0)]
+        );
+    }
+
+    const DELEGATE_RECEIPT: &str = "8UopbHH1AeJB4b2pYYtK1d1dDVwGut9yqKjTVfvzU3f9";
+    const INNER_RECEIPT: &str = "4V16oM2HQoi563d4twLFoJDx9Drer6zetLk6ABs2rAuU";
+    const SIGNATURE: &str = "ed25519:2EdVNHdQLv9Y6xvRNu2VC91akKKRR21M1jx7gNHUqSqQ1eApQFj2mK7SLYeJYRZ5qdct5UZVh7XCgK2EBCY5NuEb";
+
+    // sender.near asks relayer.near to transfer 1 yoctoNEAR to bob.near
+    fn signed_delegate_action() -> serde_json::Value {
+        serde_json::json!({"Delegate": {
+            "delegate_action": {
+                "sender_id": "sender.near",
+                "receiver_id": "bob.near",
+                "actions": [{"Transfer": {"deposit": "1"}}],
+                "nonce": 1,
+                "max_block_height": 1000,
+                "public_key": PUBLIC_KEY,
+            },
+            "signature": SIGNATURE,
+        }})
+    }
+
+    fn receipt(
+        receipt_id: &str,
+        predecessor_id: &str,
+        receiver_id: &str,
+        actions: serde_json::Value,
+        receipt_ids: Vec<&str>,
+    ) -> near_indexer_primitives::IndexerExecutionOutcomeWithReceipt {
+        serde_json::from_value(serde_json::json!({
+            "execution_outcome": {
+                "proof": [],
+                "block_hash": HASH,
+                "id": receipt_id,
+                "outcome": {
+                    "logs": [],
+                    "receipt_ids": receipt_ids,
+                    "gas_burnt": 2428000000000u64,
+                    "tokens_burnt": "0",
+                    "executor_id": receiver_id,
+                    "status": success(),
+                    "metadata": {"version": 1, "gas_profile": null},
+                },
+            },
+            "receipt": {
+                "predecessor_id": predecessor_id,
+                "receiver_id": receiver_id,
+                "receipt_id": receipt_id,
+                "receipt": {"Action": {
+                    "signer_id": "relayer.near",
+                    "signer_public_key": PUBLIC_KEY,
+                    "gas_price": "100000000",
+                    "output_data_receivers": [],
+                    "input_data_ids": [],
+                    "actions": actions,
+                }},
+            },
+        }))
+        .unwrap()
+    }
+
+    fn block_header() -> near_indexer_primitives::views::BlockHeaderView {
+        serde_json::from_value(serde_json::json!({
+            "height": 100,
+            "prev_height": 99,
+            "epoch_id": HASH,
+            "next_epoch_id": HASH,
+            "hash": HASH,
+            "prev_hash": HASH,
+            "prev_state_root": HASH,
+            "chunk_receipts_root": HASH,
+            "chunk_headers_root": HASH,
+            "chunk_tx_root": HASH,
+            "outcome_root": HASH,
+            "chunks_included": 1,
+            "challenges_root": HASH,
+            "timestamp": 1_600_000_000_000_000_000u64,
+            "timestamp_nanosec": "1600000000000000000",
+            "random_value": HASH,
+            "validator_proposals": [],
+            "chunk_mask": [true],
+            "gas_price": "100000000",
+            "block_ordinal": 100,
+            "rent_paid": "0",
+            "validator_reward": "0",
+            "total_supply": "1000000000000000000000000000000000",
+            "challenges_result": [],
+            "last_final_block": HASH,
+            "last_ds_final_block": HASH,
+            "next_bp_hash": HASH,
+            "block_merkle_root": HASH,
+            "epoch_sync_data_hash": null,
+            "approvals": [],
+            "signature": SIGNATURE,
+            "latest_protocol_version": 58,
+        }))
+        .unwrap()
+    }
+
+    // The balances are cached, RPC is never asked
+    fn balances_cache(accounts: &[(&str, u128)]) -> crate::BalanceCache {
+        let mut cache = crate::cache::JournaledCache::with_size(100);
+        for (account_id, non_staked) in accounts {
+            cache.cache_set(
+                account_id.parse().unwrap(),
+                crate::BalanceDetails {
+                    non_staked: *non_staked,
+                    staked: 0,
+                },
+            );
+        }
+        std::sync::Arc::new(tokio::sync::Mutex::new(cache))
+    }
+
+    fn account_with_balance(account_id: &str, non_staked: u128) -> crate::AccountWithBalance {
+        crate::AccountWithBalance {
+            account_id: account_id.parse().unwrap(),
+            balance: crate::BalanceDetails {
+                non_staked,
+                staked: 0,
+            },
+        }
+    }
+
+    // (affected, involved, cause, delta_nonstaked, has gas_burnt)
+    fn rows(changes: &[BalanceChange]) -> Vec<(&str, Option<&str>, &str, String, bool)> {
+        changes
+            .iter()
+            .map(|change| {
+                (
+                    change.affected_account_id.as_str(),
+                    change.involved_account_id.as_deref(),
+                    change.cause.as_str(),
+                    change.delta_nonstaked_amount.to_string(),
+                    change.gas_burnt.is_some(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn attributes_meta_transaction_to_delegated_sender() {
+        let json_rpc_client = near_jsonrpc_client::JsonRpcClient::connect("http://127.0.0.1:9");
+        let balances_cache =
+            balances_cache(&[("relayer.near", 100), ("sender.near", 50), ("bob.near", 10)]);
+        let outcomes = vec![
+            receipt(
+                DELEGATE_RECEIPT,
+                "relayer.near",
+                "sender.near",
+                serde_json::json!([signed_delegate_action()]),
+                vec![INNER_RECEIPT],
+            ),
+            receipt(
+                INNER_RECEIPT,
+                "sender.near",
+                "bob.near",
+                serde_json::json!([{"Transfer": {"deposit": "1"}}]),
+                vec![],
+            ),
+        ];
+        let mut receipt_changes = HashMap::from([
+            (
+                DELEGATE_RECEIPT.parse().unwrap(),
+                account_with_balance("sender.near", 50),
+            ),
+            (
+                INNER_RECEIPT.parse().unwrap(),
+                account_with_balance("bob.near", 11),
+            ),
+        ]);
+        let changes = super::store_receipt_execution_outcomes_for_chunk(
+            &outcomes,
+            &mut receipt_changes,
+            &mut HashMap::new(),
+            &block_header(),
+            0,
+            &balances_cache,
+            &json_rpc_client,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            rows(&changes),
+            vec![
+                // The delegate receipt: the sender does not pay for the gas
+                (
+                    "sender.near",
+                    Some("relayer.near"),
+                    "META_TRANSACTION",
+                    "0".to_string(),
+                    false
+                ),
+                (
+                    "relayer.near",
+                    Some("sender.near"),
+                    "META_TRANSACTION",
+                    "0".to_string(),
+                    true
+                ),
+                // The inner actions are the ones of the sender, the gas is prepaid by the relayer
+                (
+                    "bob.near",
+                    Some("sender.near"),
+                    "META_TRANSACTION",
+                    "1".to_string(),
+                    true
+                ),
+                (
+                    "sender.near",
+                    Some("bob.near"),
+                    "META_TRANSACTION",
+                    "0".to_string(),
+                    false
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn charges_meta_transaction_gas_to_relayer() {
+        let json_rpc_client = near_jsonrpc_client::JsonRpcClient::connect("http://127.0.0.1:9");
+        let balances_cache = balances_cache(&[("relayer.near", 100), ("sender.near", 50)]);
+        let transactions: Vec<near_indexer_primitives::IndexerTransactionWithOutcome> =
+            serde_json::from_value(serde_json::json!([{
+                "transaction": {
+                    "signer_id": "relayer.near",
+                    "public_key": PUBLIC_KEY,
+                    "nonce": 1,
+                    "receiver_id": "sender.near",
+                    "actions": [signed_delegate_action()],
+                    "signature": SIGNATURE,
+                    "hash": HASH,
+                },
+                "outcome": {
+                    "execution_outcome": {
+                        "proof": [],
+                        "block_hash": HASH,
+                        "id": HASH,
+                        "outcome": {
+                            "logs": [],
+                            "receipt_ids": [DELEGATE_RECEIPT],
+                            "gas_burnt": 2428000000000u64,
+                            "tokens_burnt": "90",
+                            "executor_id": "relayer.near",
+                            "status": {"SuccessReceiptId": DELEGATE_RECEIPT},
+                            "metadata": {"version": 1, "gas_profile": null},
+                        },
+                    },
+                    "receipt": null,
+                },
+            }]))
+            .unwrap();
+        let mut transaction_changes = HashMap::from([(
+            HASH.parse().unwrap(),
+            account_with_balance("relayer.near", 9),
+        )]);
+        let changes = super::store_transaction_execution_outcomes_for_chunk(
+            &transactions,
+            &mut transaction_changes,
+            &block_header(),
+            0,
+            &balances_cache,
+            &json_rpc_client,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            rows(&changes),
+            vec![
+                (
+                    "relayer.near",
+                    Some("sender.near"),
+                    "META_TRANSACTION",
+                    "-91".to_string(),
+                    true
+                ),
+                (
+                    "sender.near",
+                    Some("relayer.near"),
+                    "META_TRANSACTION",
+                    "0".to_string(),
+                    false
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::str::FromStr;
+
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use futures::TryStreamExt;
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+
+use crate::models::balance_changes::BalanceChange;
+
+// Walks the history of the account and checks that each row continues the previous one:
+// absolute = previous absolute + delta, for both nonstaked and staked amounts.
+// The first divergence is printed with the rows around it and the command fails.
+// The rows dropped by --min-delta-yocto or --skip-zero-delta show up as divergences too.
+// With `heal`, RPC is asked for the balance before the block of the diverging row: if it explains
+// the row, DRIFT_CORRECTION is stored at the previous block and the check goes on
+pub(crate) async fn check_account(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    account_id: &str,
+    heal: bool,
+) -> anyhow::Result<()> {
+    let query = format!(
+        "SELECT {} FROM {} WHERE affected_account_id = $1 \
+        ORDER BY block_timestamp, shard_id, index_in_chunk",
+        crate::models::balance_changes::SELECT_COLUMNS,
+        crate::db_adapters::table("balance_changes")
+    );
+    let mut rows = sqlx::query_as::<_, BalanceChange>(&query)
+        .bind(account_id)
+        .fetch(pool);
+
+    let mut previous: Option<BalanceChange> = None;
+    let mut count = 0;
+    let mut corrections = 0;
+    while let Some(change) = rows.try_next().await? {
+        count += 1;
+        if let Some(previous) = &previous {
+            let expected_nonstaked =
+                &previous.absolute_nonstaked_amount + &change.delta_nonstaked_amount;
+            let expected_staked = &previous.absolute_staked_amount + &change.delta_staked_amount;
+            if expected_nonstaked != change.absolute_nonstaked_amount
+                || expected_staked != change.absolute_staked_amount
+            {
+                // The correction ends with the balance the diverging row continues
+                if heal && heal_divergence(pool, json_rpc_client, previous, &change).await? {
+                    corrections += 1;
+                } else {
+                    drop(rows);
+                    print_divergence(
+                        pool,
+                        previous,
+                        &change,
+                        &expected_nonstaked,
+                        &expected_staked,
+                    )
+                    .await?;
+                    anyhow::bail!(
+                        "History of {} diverges at row {}, block_timestamp {}",
+                        account_id,
+                        count,
+                        change.block_timestamp
+                    );
+                }
+            }
+        }
+        previous = Some(change);
+    }
+    if count == 0 {
+        anyhow::bail!("Account {} has no balance changes", account_id);
+    }
+    println!(
+        "{}: {} rows checked, {} corrections stored, the running balance is consistent",
+        account_id, count, corrections
+    );
+    Ok(())
+}
+
+// false if RPC does not confirm the balance before the diverging row
+async fn heal_divergence(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    previous: &BalanceChange,
+    change: &BalanceChange,
+) -> anyhow::Result<bool> {
+    // The difference inside one block can't be placed between the rows
+    if previous.block_timestamp == change.block_timestamp {
+        return Ok(false);
+    }
+    let block_height = match block_height(pool, &change.block_timestamp).await? {
+        Some(block_height) => block_height,
+        None => return Ok(false),
+    };
+    let (previous_hash, previous_timestamp) =
+        crate::db_adapters::drift::previous_block(json_rpc_client, block_height).await?;
+    let account_id: near_primitives::types::AccountId = change.affected_account_id.parse()?;
+    let (rpc_nonstaked, rpc_staked) = match crate::db_adapters::balance_changes::get_account_view(
+        json_rpc_client,
+        &account_id,
+        &previous_hash,
+    )
+    .await
+    {
+        Ok(account_view) => (
+            BigDecimal::from_str(&account_view.amount.to_string())?,
+            BigDecimal::from_str(&account_view.locked.to_string())?,
+        ),
+        Err(err) => match err.handler_error() {
+            Some(RpcQueryError::UnknownAccount { .. }) => (BigDecimal::zero(), BigDecimal::zero()),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Failed to get account {} at block {} from RPC: {}",
+                    account_id,
+                    previous_hash,
+                    err
+                ))
+            }
+        },
+    };
+    if &rpc_nonstaked + &change.delta_nonstaked_amount != change.absolute_nonstaked_amount
+        || &rpc_staked + &change.delta_staked_amount != change.absolute_staked_amount
+    {
+        return Ok(false);
+    }
+    crate::db_adapters::drift::insert_correction(
+        pool,
+        &change.affected_account_id,
+        &previous_hash,
+        previous_timestamp,
+        (
+            &previous.absolute_nonstaked_amount,
+            &previous.absolute_staked_amount,
+        ),
+        (&rpc_nonstaked, &rpc_staked),
+    )
+    .await?;
+    Ok(true)
+}
+
+// The summary is not written by the sharded instances
+async fn block_height(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    block_timestamp: &BigDecimal,
+) -> anyhow::Result<Option<u64>> {
+    let block_height: Option<(BigDecimal,)> = sqlx::query_as(&format!(
+        "SELECT block_height FROM {} WHERE block_timestamp = $1",
+        crate::db_adapters::table("block_balance_summary")
+    ))
+    .bind(block_timestamp)
+    .fetch_optional(pool)
+    .await?;
+    Ok(block_height.and_then(|(block_height,)| block_height.to_u64()))
+}
+
+async fn print_divergence(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    previous: &BalanceChange,
+    change: &BalanceChange,
+    expected_nonstaked: &BigDecimal,
+    expected_staked: &BigDecimal,
+) -> anyhow::Result<()> {
+    println!("Previous row:");
+    print_row(pool, previous).await?;
+    println!("Diverging row:");
+    print_row(pool, change).await?;
+    println!(
+        "  expected nonstaked {} (diff {}), staked {} (diff {})",
+        expected_nonstaked,
+        &change.absolute_nonstaked_amount - expected_nonstaked,
+        expected_staked,
+        &change.absolute_staked_amount - expected_staked
+    );
+    Ok(())
+}
+
+async fn print_row(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    change: &BalanceChange,
+) -> anyhow::Result<()> {
+    let block_height = block_height(pool, &change.block_timestamp).await?;
+    println!(
+        "  block_height {}, block_timestamp {}, shard_id {}, index_in_chunk {}",
+        block_height.map_or_else(|| "unknown".to_string(), |height| height.to_string()),
+        change.block_timestamp,
+        change.shard_id,
+        change.index_in_chunk
+    );
+    println!(
+        "  cause {}, direction {}, status {}, involved {}, transaction {}, receipt {}",
+        change.cause,
+        change.direction,
+        change.status,
+        change.involved_account_id.as_deref().unwrap_or("-"),
+        change.transaction_hash.as_deref().unwrap_or("-"),
+        change.receipt_id.as_deref().unwrap_or("-")
+    );
+    println!(
+        "  delta nonstaked {}, staked {}; absolute nonstaked {}, staked {}",
+        change.delta_nonstaked_amount,
+        change.delta_staked_amount,
+        change.absolute_nonstaked_amount,
+        change.absolute_staked_amount
+    );
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use std::path::PathBuf;
+
+use near_lake_framework::near_indexer_primitives;
+use tokio::io::AsyncWriteExt;
+
+use crate::models::balance_changes::BalanceChange;
+
+#[derive(Debug, serde::Deserialize)]
+struct BufferedBlock {
+    streamer_message: near_indexer_primitives::StreamerMessage,
+    changes: Vec<BalanceChange>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct BufferedBlockRef<'a> {
+    streamer_message: &'a near_indexer_primitives::StreamerMessage,
+    changes: &'a [BalanceChange],
+}
+
+// Keeps the computed blocks in the local JSON lines file while the database is unavailable.
+// The blocks are stored to the database in the same order once it is back
+pub(crate) struct DiskBuffer {
+    path: PathBuf,
+    max_bytes: u64,
+    bytes: u64,
+    blocks: usize,
+}
+
+impl DiskBuffer {
+    // Picks up the blocks left from the previous run
+    pub(crate) async fn open(path: PathBuf, max_bytes: u64) -> anyhow::Result<Self> {
+        let (bytes, blocks) = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => (content.len() as u64, content.lines().count()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (0, 0),
+            Err(err) => anyhow::bail!("Failed to read {}: {}", path.display(), err),
+        };
+        let buffer = Self {
+            path,
+            max_bytes,
+            bytes,
+            blocks,
+        };
+        buffer.update_metrics();
+        Ok(buffer)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.blocks == 0
+    }
+
+    pub(crate) async fn push(
+        &mut self,
+        streamer_message: &near_indexer_primitives::StreamerMessage,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let block_height = streamer_message.block.header.height;
+        let mut line = serde_json::to_vec(&BufferedBlockRef {
+            streamer_message,
+            changes,
+        })?;
+        line.push(b'\n');
+        if self.bytes + line.len() as u64 > self.max_bytes {
+            anyhow::bail!(
+                "Disk buffer {} is full ({} blocks, {} bytes), can't buffer block_height {}",
+                self.path.display(),
+                self.blocks,
+                self.bytes,
+                block_height
+            );
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+        self.bytes += line.len() as u64;
+        self.blocks += 1;
+        self.update_metrics();
+        tracing::warn!(
+            target: crate::INDEXER,
+            "Block_height {} is buffered to {}, {} blocks are waiting for the database",
+            block_height,
+            self.path.display(),
+            self.blocks
+        );
+        Ok(())
+    }
+
+    // Stores the buffered blocks one by one and publishes each stored block to the sinks.
+    // Stops at the first failure of the storage and keeps the rest, check `is_empty` after it.
+    // The failure of the sink is returned as the error
+    pub(crate) async fn drain(
+        &mut self,
+        storage: &dyn crate::db_adapters::storage::Storage,
+        sinks: &[Box<dyn crate::sinks::Sink>],
+    ) -> anyhow::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let content = tokio::fs::read_to_string(&self.path).await?;
+        let mut lines = content.lines().peekable();
+        let mut result = Ok(());
+        while let Some(line) = lines.peek() {
+            let block: BufferedBlock = serde_json::from_str(line)?;
+            if let Err(err) = storage
+                .store_block(
+                    &block.streamer_message.shards,
+                    &block.streamer_message.block.header,
+                    &block.changes,
+                )
+                .await
+            {
+                tracing::warn!(
+                    target: crate::INDEXER,
+                    "Failed to store buffered block_height {}: {:#}",
+                    block.streamer_message.block.header.height,
+                    err
+                );
+                break;
+            }
+            lines.next();
+            if let Err(err) =
+                crate::sinks::publish(sinks, &block.streamer_message.block.header, &block.changes)
+                    .await
+            {
+                result = Err(err);
+                break;
+            }
+        }
+
+        let rest: String = lines.map(|line| line.to_string() + "\n").collect();
+        self.replace_content(&rest).await?;
+        tracing::info!(
+            target: crate::INDEXER,
+            "{} buffered blocks are stored to the database, {} are left",
+            self.blocks - rest.lines().count(),
+            rest.lines().count()
+        );
+        self.bytes = rest.len() as u64;
+        self.blocks = rest.lines().count();
+        self.update_metrics();
+        result
+    }
+
+    // The crash in the middle leaves either the old or the new content, never the truncated file
+    async fn replace_content(&self, content: &str) -> anyhow::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        file.write_all(content.as_bytes()).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        // The rename itself is durable only after the sync of the directory
+        let dir = self
+            .path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| std::path::Path::new("."));
+        tokio::fs::File::open(dir).await?.sync_all().await?;
+        Ok(())
+    }
+
+    fn update_metrics(&self) {
+        crate::metrics::DISK_BUFFER_BLOCKS.set(self.blocks as i64);
+        crate::metrics::DISK_BUFFER_BYTES.set(self.bytes as i64);
+    }
+}
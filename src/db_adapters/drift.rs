@@ -0,0 +1,123 @@
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives;
+use num_traits::Signed;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::models::current_balances::CurrentBalance;
+use crate::models::PrintEnum;
+
+// The corrections go after the rows of the real shards in the block
+const CORRECTION_SHARD_ID: i32 = i32::MAX;
+
+// Anchors the history of the account to the balance confirmed by RPC at the block:
+// DRIFT_CORRECTION row has the difference as the delta and the RPC balance as the absolute,
+// so the sums of the deltas match the chain again. The cause of the drift is still to be found,
+// the rows are easy to list with `cause = 'DRIFT_CORRECTION'`
+pub(crate) async fn insert_correction(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    account_id: &str,
+    block_hash: &near_indexer_primitives::CryptoHash,
+    block_timestamp: u64,
+    // The balances in our history and in RPC, (nonstaked, staked)
+    computed: (&BigDecimal, &BigDecimal),
+    rpc: (&BigDecimal, &BigDecimal),
+) -> anyhow::Result<BalanceChange> {
+    let block_timestamp = BigDecimal::from(block_timestamp);
+    let delta_nonstaked_amount = rpc.0 - computed.0;
+    let delta_staked_amount = rpc.1 - computed.1;
+
+    let mut transaction = pool.begin().await?;
+    // The previous corrections of the account in the block give the position for event_id
+    let (index_in_chunk, position): (Option<i32>, i64) = sqlx::query_as(&format!(
+        "SELECT max(index_in_chunk) + 1, count(*) FILTER (WHERE affected_account_id = $3) FROM {} \
+        WHERE block_timestamp = $1::bigint AND shard_id = $2",
+        crate::db_adapters::table("balance_changes")
+    ))
+    .bind(&block_timestamp)
+    .bind(CORRECTION_SHARD_ID)
+    .bind(account_id)
+    .fetch_one(&mut transaction)
+    .await?;
+    let index_in_chunk = index_in_chunk.unwrap_or_default();
+    let mut correction = BalanceChange {
+        block_timestamp: block_timestamp.clone(),
+        receipt_id: None,
+        transaction_hash: None,
+        affected_account_id: account_id.to_string(),
+        involved_account_id: None,
+        direction: if delta_nonstaked_amount.is_negative() || delta_staked_amount.is_negative() {
+            crate::models::Direction::Outbound
+        } else {
+            crate::models::Direction::Inbound
+        }
+        .print()
+        .to_string(),
+        cause: crate::models::Cause::DriftCorrection.print().to_string(),
+        status: near_indexer_primitives::views::ExecutionStatusView::SuccessValue(vec![])
+            .print()
+            .to_string(),
+        delta_nonstaked_amount,
+        absolute_nonstaked_amount: rpc.0.clone(),
+        delta_staked_amount,
+        absolute_staked_amount: rpc.1.clone(),
+        shard_id: CORRECTION_SHARD_ID,
+        index_in_chunk,
+        index_in_block: CORRECTION_SHARD_ID,
+        event_id: String::new(),
+        parent_transaction_hash: None,
+        index_in_receipt: None,
+        fiat_value_usd: None,
+        gas_burnt: None,
+        predecessor_account_id: None,
+        receiver_account_id: None,
+    };
+    correction.set_event_id(&block_hash.to_string(), position as usize);
+    crate::models::insert_in_transaction(&mut transaction, std::slice::from_ref(&correction))
+        .await?;
+    crate::models::insert_in_transaction(
+        &mut transaction,
+        &[CurrentBalance {
+            account_id: account_id.to_string(),
+            block_timestamp,
+            nonstaked_amount: rpc.0.clone(),
+            staked_amount: rpc.1.clone(),
+        }],
+    )
+    .await?;
+    transaction.commit().await?;
+
+    crate::metrics::DRIFT_CORRECTIONS_TOTAL.inc();
+    tracing::warn!(
+        target: crate::INDEXER,
+        "Stored DRIFT_CORRECTION for account {} at block_timestamp {}: nonstaked {} -> {}, staked {} -> {}",
+        account_id,
+        correction.block_timestamp,
+        computed.0,
+        rpc.0,
+        computed.1,
+        rpc.1
+    );
+    Ok(correction)
+}
+
+// The block before the given one, (hash, timestamp)
+pub(crate) async fn previous_block(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    block_height: u64,
+) -> anyhow::Result<(near_indexer_primitives::CryptoHash, u64)> {
+    let block = json_rpc_client
+        .call(near_jsonrpc_client::methods::block::RpcBlockRequest {
+            block_reference: near_primitives::types::BlockReference::BlockId(
+                near_primitives::types::BlockId::Height(block_height),
+            ),
+        })
+        .await?;
+    let previous = json_rpc_client
+        .call(near_jsonrpc_client::methods::block::RpcBlockRequest {
+            block_reference: near_primitives::types::BlockReference::BlockId(
+                near_primitives::types::BlockId::Hash(block.header.prev_hash),
+            ),
+        })
+        .await?;
+    Ok((previous.header.hash, previous.header.timestamp_nanosec))
+}
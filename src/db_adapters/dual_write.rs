@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use near_lake_framework::near_indexer_primitives;
+
+use crate::db_adapters::storage::Storage;
+use crate::models::balance_changes::BalanceChange;
+
+// Writes to the primary storage as usual and repeats the stored blocks to the secondary one,
+// so the new backend can catch up while the old one still serves the readers.
+// The secondary storage works in its own task: its errors are logged and retried until the block
+// is stored, the primary storage waits only when the queue between them is full.
+// After restart the indexer continues from the storage which is behind, the stores are idempotent
+pub(crate) struct DualStorage {
+    primary: Box<dyn Storage>,
+    secondary: Arc<dyn Storage>,
+    sender: tokio::sync::mpsc::Sender<SecondaryBlock>,
+    heights: Arc<Heights>,
+}
+
+struct SecondaryBlock {
+    shards: Vec<near_indexer_primitives::IndexerShard>,
+    block_header: near_indexer_primitives::views::BlockHeaderView,
+    changes: Vec<BalanceChange>,
+}
+
+#[derive(Default)]
+struct Heights {
+    primary: AtomicU64,
+    secondary: AtomicU64,
+}
+
+impl Heights {
+    fn update_lag(&self) {
+        let primary = self.primary.load(Ordering::Relaxed);
+        let secondary = self.secondary.load(Ordering::Relaxed);
+        crate::metrics::SECONDARY_LAG_BLOCKS.set(primary.saturating_sub(secondary) as i64);
+    }
+}
+
+impl DualStorage {
+    pub(crate) fn new(
+        primary: Box<dyn Storage>,
+        secondary: Box<dyn Storage>,
+        queue_size: usize,
+    ) -> Self {
+        let secondary: Arc<dyn Storage> = Arc::from(secondary);
+        let heights = Arc::new(Heights::default());
+        let (sender, receiver) = tokio::sync::mpsc::channel(queue_size);
+        crate::db_adapters::spawn(write_secondary(
+            secondary.clone(),
+            receiver,
+            heights.clone(),
+        ));
+        tracing::info!(
+            target: crate::INDEXER,
+            "Writing to {} and, in background, to {}",
+            primary.name(),
+            secondary.name()
+        );
+        Self {
+            primary,
+            secondary,
+            sender,
+            heights,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for DualStorage {
+    fn name(&self) -> &'static str {
+        self.primary.name()
+    }
+
+    // The secondary storage may have lost the queued blocks on exit.
+    // 0 means it does not know, e.g. ClickHouse, or it is empty: then it starts from the primary height
+    async fn start_after_interruption(&self) -> anyhow::Result<u64> {
+        let primary = self.primary.start_after_interruption().await?;
+        let secondary = self.secondary.start_after_interruption().await?;
+        Ok(match secondary {
+            0 => primary,
+            _ => primary.min(secondary),
+        })
+    }
+
+    async fn store_block(
+        &self,
+        shards: &[near_indexer_primitives::IndexerShard],
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        self.primary
+            .store_block(shards, block_header, changes)
+            .await?;
+        self.heights
+            .primary
+            .store(block_header.height, Ordering::Relaxed);
+        self.heights.update_lag();
+        // IndexerShard is not Clone
+        let shards = serde_json::from_value(serde_json::to_value(shards)?)?;
+        self.sender
+            .send(SecondaryBlock {
+                shards,
+                block_header: block_header.clone(),
+                changes: changes.to_vec(),
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("Secondary storage {} has stopped", self.secondary.name()))
+    }
+
+    // The blocks of the discarded fork may still wait in the queue to the secondary storage
+    async fn rollback_blocks(&self, blocks: &[crate::forks::TrackedBlock]) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "Rollback of {} blocks is not supported with the secondary storage {}",
+            blocks.len(),
+            self.secondary.name()
+        )
+    }
+
+    fn postgres_pool(&self) -> Option<&sqlx::Pool<sqlx::Postgres>> {
+        self.primary.postgres_pool()
+    }
+}
+
+async fn write_secondary(
+    secondary: Arc<dyn Storage>,
+    mut receiver: tokio::sync::mpsc::Receiver<SecondaryBlock>,
+    heights: Arc<Heights>,
+) {
+    while let Some(block) = receiver.recv().await {
+        let mut interval = crate::INTERVAL;
+        while let Err(err) = secondary
+            .store_block(&block.shards, &block.block_header, &block.changes)
+            .await
+        {
+            crate::metrics::SECONDARY_STORE_ERRORS_TOTAL.inc();
+            tracing::warn!(
+                target: crate::INDEXER,
+                "Failed to store block {} to the secondary storage {}: {:#}\n Retrying in {} milliseconds...",
+                block.block_header.height,
+                secondary.name(),
+                err,
+                interval.as_millis(),
+            );
+            tokio::time::sleep(interval).await;
+            if interval < crate::MAX_DELAY_TIME {
+                interval *= 2;
+            }
+        }
+        heights
+            .secondary
+            .store(block.block_header.height, Ordering::Relaxed);
+        crate::metrics::SECONDARY_INDEXED_HEIGHT.set(block.block_header.height as i64);
+        heights.update_lag();
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use near_lake_framework::near_indexer_primitives;
+use near_lake_framework::near_indexer_primitives::views::StateChangeValueView;
+
+use crate::models::account_changes::AccountChange;
+use crate::models::balance_changes::BalanceChange;
+
+static COMPAT_SCHEMA: once_cell::sync::OnceCell<crate::configs::CompatSchema> =
+    once_cell::sync::OnceCell::new();
+
+pub(crate) fn configure_compat_schema(compat_schema: Option<crate::configs::CompatSchema>) {
+    if let Some(compat_schema) = compat_schema {
+        // Configured once at the start
+        let _ = COMPAT_SCHEMA.set(compat_schema);
+    }
+}
+
+// Writes the balance changes of the block also to the legacy layout, in the same transaction.
+// Our rows are coarser than the explorer ones: the receipt processing steps are merged to
+// RECEIPT_PROCESSING, and the storage usage is the one the account has at the end of the block.
+// INITIAL_STATE rows are not written, the explorer keeps the genesis in the accounts table
+pub(crate) async fn store_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+) -> anyhow::Result<()> {
+    if COMPAT_SCHEMA.get().is_none() {
+        return Ok(());
+    }
+    let block_hash = block_header.hash.to_string();
+    let storage_usage = storage_usage(shards);
+    let account_changes: Vec<AccountChange> = changes
+        .iter()
+        .filter_map(|change| {
+            Some(AccountChange {
+                affected_account_id: change.affected_account_id.clone(),
+                changed_in_block_timestamp: change.block_timestamp.clone(),
+                changed_in_block_hash: block_hash.clone(),
+                caused_by_transaction_hash: change.transaction_hash.clone(),
+                // The transaction rows of near-indexer-for-explorer do not have the converted receipt
+                caused_by_receipt_id: match change.transaction_hash {
+                    Some(_) => None,
+                    None => change.receipt_id.clone(),
+                },
+                update_reason: update_reason(&change.cause)?.to_string(),
+                affected_account_nonstaked_balance: change.absolute_nonstaked_amount.clone(),
+                affected_account_staked_balance: change.absolute_staked_amount.clone(),
+                affected_account_storage_usage: storage_usage
+                    .get(change.affected_account_id.as_str())
+                    .copied()
+                    .unwrap_or_default()
+                    .into(),
+                index_in_block: change.index_in_block,
+            })
+        })
+        .collect();
+
+    // The table has no natural key, so the retried block replaces its rows
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE changed_in_block_hash = $1",
+        crate::db_adapters::table("account_changes")
+    ))
+    .bind(&block_hash)
+    .execute(&mut *transaction)
+    .await?;
+    crate::models::insert_in_transaction(transaction, &account_changes).await
+}
+
+pub(crate) async fn rollback_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block: &crate::forks::TrackedBlock,
+) -> anyhow::Result<()> {
+    if COMPAT_SCHEMA.get().is_none() {
+        return Ok(());
+    }
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE changed_in_block_hash = $1",
+        crate::db_adapters::table("account_changes")
+    ))
+    .bind(block.hash.to_string())
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+fn update_reason(cause: &str) -> Option<&'static str> {
+    match cause {
+        "TRANSACTION" | "META_TRANSACTION" => Some("TRANSACTION_PROCESSING"),
+        "RECEIPT" | "IMPLICIT_ACCOUNT_CREATED" => Some("RECEIPT_PROCESSING"),
+        "CONTRACT_REWARD" => Some("ACTION_RECEIPT_GAS_REWARD"),
+        "VALIDATORS_REWARD" | "PROTOCOL_TREASURY_REWARD" => Some("VALIDATOR_ACCOUNTS_UPDATE"),
+        _ => None,
+    }
+}
+
+// Deleted accounts have no storage
+fn storage_usage(shards: &[near_indexer_primitives::IndexerShard]) -> HashMap<&str, u64> {
+    let mut result = HashMap::new();
+    for state_change in shards.iter().flat_map(|shard| &shard.state_changes) {
+        match &state_change.value {
+            StateChangeValueView::AccountUpdate {
+                account_id,
+                account,
+            } => {
+                result.insert(account_id.as_str(), account.storage_usage);
+            }
+            StateChangeValueView::AccountDeletion { account_id } => {
+                result.insert(account_id.as_str(), 0);
+            }
+            _ => {}
+        }
+    }
+    result
+}
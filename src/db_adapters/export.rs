@@ -0,0 +1,147 @@
+use bigdecimal::BigDecimal;
+use futures::TryStreamExt;
+use tokio::io::AsyncWriteExt;
+
+use crate::configs::{ExportArgs, ExportFormat};
+use crate::models::balance_changes::BalanceChange;
+
+const CSV_HEADER: &str = "datetime,block_timestamp,direction,cause,status,counterparty,\
+    delta_nonstaked_amount,delta_staked_amount,balance_nonstaked_amount,balance_staked_amount,\
+    balance_total_amount,transaction_hash,receipt_id,link\n";
+
+// Account statement: the changes of the account in the order they were applied,
+// with the balance after each of them. The amounts are in yoctoNEAR.
+// The rows are streamed from the database, so the long statements do not sit in memory
+pub(crate) async fn export_statement(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    args: &ExportArgs,
+    explorer_url: &str,
+) -> anyhow::Result<()> {
+    let (from_timestamp, to_timestamp) = (
+        day_start_nanos(args.from),
+        day_start_nanos(args.to.succ_opt().unwrap_or(chrono::NaiveDate::MAX)),
+    );
+    let output: Box<dyn tokio::io::AsyncWrite + Unpin + Send> = match &args.output {
+        Some(path) => Box::new(tokio::fs::File::create(path).await?),
+        None => Box::new(tokio::io::stdout()),
+    };
+    let mut output = tokio::io::BufWriter::new(output);
+    if args.format == ExportFormat::Csv {
+        output.write_all(CSV_HEADER.as_bytes()).await?;
+    }
+
+    let query = format!(
+        "SELECT {} FROM {} \
+        WHERE affected_account_id = $1 AND block_timestamp >= $2::bigint AND block_timestamp < $3::bigint \
+        ORDER BY block_timestamp, shard_id, index_in_chunk",
+        crate::models::balance_changes::SELECT_COLUMNS,
+        crate::db_adapters::table("balance_changes")
+    );
+    let mut rows = sqlx::query_as::<_, BalanceChange>(&query)
+        .bind(&args.account)
+        .bind(&from_timestamp)
+        .bind(&to_timestamp)
+        .fetch(pool);
+    let mut count = 0;
+    while let Some(change) = rows.try_next().await? {
+        let line = match args.format {
+            ExportFormat::Csv => csv_line(&change, explorer_url),
+            ExportFormat::Jsonl => json_line(&change, explorer_url),
+        };
+        output.write_all(line.as_bytes()).await?;
+        count += 1;
+    }
+    output.flush().await?;
+    tracing::info!(
+        target: crate::INDEXER,
+        "Exported {} balance changes of {} from {} to {}",
+        count,
+        args.account,
+        args.from,
+        args.to
+    );
+    Ok(())
+}
+
+fn day_start_nanos(day: chrono::NaiveDate) -> BigDecimal {
+    let seconds = day.and_time(chrono::NaiveTime::MIN).and_utc().timestamp();
+    BigDecimal::from(seconds) * BigDecimal::from(1_000_000_000)
+}
+
+// Seconds are enough for the statement, the exact moment is in block_timestamp
+fn datetime(change: &BalanceChange) -> String {
+    let seconds = (&change.block_timestamp / BigDecimal::from(1_000_000_000))
+        .with_scale(0)
+        .to_string()
+        .parse()
+        .unwrap_or_default();
+    chrono::DateTime::from_timestamp(seconds, 0)
+        .map(|datetime| datetime.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .unwrap_or_default()
+}
+
+// Receipts do not have their own page: the page of their transaction if it is known, or the search
+fn link(change: &BalanceChange, explorer_url: &str) -> Option<String> {
+    let transaction_hash = change
+        .transaction_hash
+        .as_ref()
+        .or(change.parent_transaction_hash.as_ref());
+    match (transaction_hash, &change.receipt_id) {
+        (Some(transaction_hash), _) => Some(format!(
+            "{}/transactions/{}",
+            explorer_url, transaction_hash
+        )),
+        (None, Some(receipt_id)) => Some(format!("{}/?query={}", explorer_url, receipt_id)),
+        (None, None) => None,
+    }
+}
+
+fn csv_line(change: &BalanceChange, explorer_url: &str) -> String {
+    let mut line = String::new();
+    crate::api::rest::csv_row(
+        &mut line,
+        &[
+            &datetime(change),
+            &change.block_timestamp.to_string(),
+            &change.direction,
+            &change.cause,
+            &change.status,
+            change.involved_account_id.as_deref().unwrap_or_default(),
+            &change.delta_nonstaked_amount.to_string(),
+            &change.delta_staked_amount.to_string(),
+            &change.absolute_nonstaked_amount.to_string(),
+            &change.absolute_staked_amount.to_string(),
+            &(&change.absolute_nonstaked_amount + &change.absolute_staked_amount).to_string(),
+            change.transaction_hash.as_deref().unwrap_or_default(),
+            change.receipt_id.as_deref().unwrap_or_default(),
+            &link(change, explorer_url).unwrap_or_default(),
+        ],
+    );
+    line
+}
+
+fn json_line(change: &BalanceChange, explorer_url: &str) -> String {
+    let mut line = serde_json::json!({
+        "datetime": datetime(change),
+        "block_timestamp": change.block_timestamp.to_string(),
+        "direction": change.direction,
+        "cause": change.cause,
+        "status": change.status,
+        "counterparty": change.involved_account_id,
+        "delta_nonstaked_amount": change.delta_nonstaked_amount.to_string(),
+        "delta_staked_amount": change.delta_staked_amount.to_string(),
+        "balance_nonstaked_amount": change.absolute_nonstaked_amount.to_string(),
+        "balance_staked_amount": change.absolute_staked_amount.to_string(),
+        "balance_total_amount":
+            (&change.absolute_nonstaked_amount + &change.absolute_staked_amount).to_string(),
+        "transaction_hash": change.transaction_hash,
+        "receipt_id": change.receipt_id,
+        "link": link(change, explorer_url),
+        "event_id": change.event_id,
+        "parent_transaction_hash": change.parent_transaction_hash,
+        "index_in_receipt": change.index_in_receipt,
+    })
+    .to_string();
+    line.push('\n');
+    line
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives;
+use near_lake_framework::near_indexer_primitives::views::{ActionView, ReceiptEnumView};
+use num_traits::Zero;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::models::fee_details::FeeDetails;
+use crate::models::PrintEnum;
+
+static FEE_DETAILS: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn configure_fee_details(fee_details: bool) {
+    FEE_DETAILS.store(fee_details, Ordering::Relaxed);
+}
+
+// The fee of each transaction and receipt as the protocol has applied it: the gas price of the block,
+// the gas and tokens burnt, the part of the burnt tokens paid to the contract, and the refund.
+// The refund receipt comes from `system` in the later block, its row has only refund_amount.
+// The contract reward is taken from our CONTRACT_REWARD rows, so it is 0 for the filtered out accounts
+pub(crate) async fn store_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+) -> anyhow::Result<()> {
+    if !FEE_DETAILS.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let mut contract_rewards: HashMap<&str, BigDecimal> = HashMap::new();
+    for change in changes {
+        if change.cause != crate::models::Cause::ContractReward.print() {
+            continue;
+        }
+        if let Some(receipt_id) = &change.receipt_id {
+            *contract_rewards
+                .entry(receipt_id.as_str())
+                .or_insert_with(BigDecimal::zero) += &change.delta_nonstaked_amount;
+        }
+    }
+
+    let gas_price = crate::models::to_decimal(block_header.gas_price);
+    let mut fee_details = vec![];
+    for shard in shards {
+        let transactions = shard
+            .chunk
+            .iter()
+            .flat_map(|chunk| chunk.transactions.iter());
+        for transaction in transactions {
+            let outcome = &transaction.outcome.execution_outcome.outcome;
+            fee_details.push(FeeDetails {
+                block_height: block_header.height.into(),
+                block_timestamp: block_header.timestamp.into(),
+                shard_id: shard.shard_id as i32,
+                transaction_hash: Some(transaction.transaction.hash.to_string()),
+                receipt_id: None,
+                executor_account_id: outcome.executor_id.to_string(),
+                gas_price: gas_price.clone(),
+                gas_burnt: crate::models::to_decimal(outcome.gas_burnt),
+                tokens_burnt: crate::models::to_decimal(outcome.tokens_burnt),
+                contract_reward_amount: BigDecimal::zero(),
+                refund_amount: BigDecimal::zero(),
+            });
+        }
+        for outcome_with_receipt in &shard.receipt_execution_outcomes {
+            let receipt = &outcome_with_receipt.receipt;
+            let outcome = &outcome_with_receipt.execution_outcome.outcome;
+            let receipt_id = receipt.receipt_id.to_string();
+            let refund_amount: u128 = match &receipt.receipt {
+                ReceiptEnumView::Action { actions, .. }
+                    if receipt.predecessor_id.as_str() == "system" =>
+                {
+                    actions
+                        .iter()
+                        .map(|action| match action {
+                            ActionView::Transfer { deposit } => *deposit,
+                            _ => 0,
+                        })
+                        .sum()
+                }
+                _ => 0,
+            };
+            fee_details.push(FeeDetails {
+                block_height: block_header.height.into(),
+                block_timestamp: block_header.timestamp.into(),
+                shard_id: shard.shard_id as i32,
+                transaction_hash: None,
+                contract_reward_amount: contract_rewards
+                    .remove(receipt_id.as_str())
+                    .unwrap_or_else(BigDecimal::zero),
+                receipt_id: Some(receipt_id),
+                executor_account_id: outcome.executor_id.to_string(),
+                gas_price: gas_price.clone(),
+                gas_burnt: crate::models::to_decimal(outcome.gas_burnt),
+                tokens_burnt: crate::models::to_decimal(outcome.tokens_burnt),
+                refund_amount: crate::models::to_decimal(refund_amount),
+            });
+        }
+    }
+    crate::models::insert_in_transaction(transaction, &fee_details).await
+}
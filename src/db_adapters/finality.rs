@@ -0,0 +1,230 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use near_jsonrpc_client::methods::block::RpcBlockError;
+use near_lake_framework::near_indexer_primitives;
+
+const INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+static OPTIMISTIC: AtomicBool = AtomicBool::new(false);
+
+// NEAR Lake has only the final blocks, the optimistic ones come from the local lake directory
+// written by the node with the optimistic finality.
+// Their rows are stored with `finality = 'OPTIMISTIC'` and the block is remembered in `optimistic_blocks`.
+// The reconciler compares them with the final blocks from RPC: the same block becomes `FINAL`,
+// the block from the discarded fork is deleted, and the range from it to the final head
+// is computed again from NEAR Lake with the fresh balances cache
+pub(crate) fn configure_finality(finality: crate::configs::Finality) {
+    OPTIMISTIC.store(
+        finality == crate::configs::Finality::Optimistic,
+        Ordering::Relaxed,
+    );
+}
+
+pub(crate) async fn mark_optimistic(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+) -> anyhow::Result<()> {
+    if !OPTIMISTIC.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    sqlx::query(&format!(
+        "UPDATE {} SET finality = 'OPTIMISTIC' WHERE block_timestamp = $1::bigint",
+        crate::db_adapters::table("balance_changes")
+    ))
+    .bind(block_header.timestamp as i64)
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query(&format!(
+        "INSERT INTO {} VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        crate::db_adapters::table("optimistic_blocks")
+    ))
+    .bind(block_header.hash.to_string())
+    .bind(block_header.height as i64)
+    .bind(block_header.timestamp as i64)
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn rollback_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block: &crate::forks::TrackedBlock,
+) -> anyhow::Result<()> {
+    if !OPTIMISTIC.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE block_hash = $1",
+        crate::db_adapters::table("optimistic_blocks")
+    ))
+    .bind(block.hash.to_string())
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+pub(crate) fn spawn_reconciler(
+    opts: std::sync::Arc<crate::configs::Opts>,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+    balances_cache: crate::BalanceCache,
+    postponed_receipts: crate::PostponedReceipts,
+) {
+    crate::db_adapters::spawn(async move {
+        loop {
+            tokio::time::sleep(INTERVAL).await;
+            if let Err(err) = reconcile(
+                &opts,
+                &pool,
+                &json_rpc_client,
+                &balances_cache,
+                &postponed_receipts,
+            )
+            .await
+            {
+                tracing::warn!(
+                    target: crate::INDEXER,
+                    "Failed to reconcile optimistic blocks: {:#}",
+                    err
+                );
+                crate::error_reporting::spawn_report(
+                    crate::error_reporting::Level::Error,
+                    "reconciliation_failed",
+                    format!("Failed to reconcile optimistic blocks: {:#}", err),
+                    vec![],
+                );
+            }
+        }
+    });
+}
+
+async fn reconcile(
+    opts: &crate::configs::Opts,
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    balances_cache: &crate::BalanceCache,
+    postponed_receipts: &crate::PostponedReceipts,
+) -> anyhow::Result<()> {
+    let final_height = json_rpc_client
+        .call(near_jsonrpc_client::methods::block::RpcBlockRequest {
+            block_reference: near_primitives::types::BlockReference::Finality(
+                near_primitives::types::Finality::Final,
+            ),
+        })
+        .await?
+        .header
+        .height;
+    let blocks: Vec<(String, i64, i64)> = sqlx::query_as(&format!(
+        "SELECT block_hash, block_height::bigint, block_timestamp::bigint FROM {} \
+        WHERE block_height <= $1 ORDER BY block_height",
+        crate::db_adapters::table("optimistic_blocks")
+    ))
+    .bind(final_height as i64)
+    .fetch_all(pool)
+    .await?;
+
+    for (block_hash, block_height, block_timestamp) in blocks {
+        if final_block_hash(json_rpc_client, block_height as u64).await? == Some(block_hash.clone())
+        {
+            let mut transaction = pool.begin().await?;
+            sqlx::query(&format!(
+                "UPDATE {} SET finality = 'FINAL' \
+                WHERE block_timestamp = $1::bigint AND finality = 'OPTIMISTIC'",
+                crate::db_adapters::table("balance_changes")
+            ))
+            .bind(block_timestamp)
+            .execute(&mut transaction)
+            .await?;
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE block_hash = $1",
+                crate::db_adapters::table("optimistic_blocks")
+            ))
+            .bind(&block_hash)
+            .execute(&mut transaction)
+            .await?;
+            transaction.commit().await?;
+            continue;
+        }
+
+        // The later blocks were computed with the balances of the discarded fork
+        tracing::warn!(
+            target: crate::INDEXER,
+            "Block {} at block_height {} is not final, reindexing {}..={}",
+            block_hash,
+            block_height,
+            block_height,
+            final_height
+        );
+        crate::metrics::ORPHANED_OPTIMISTIC_BLOCKS_TOTAL.inc();
+        balances_cache.lock().await.cache_clear();
+        postponed_receipts.lock().await.cache_clear();
+        let mut transaction = pool.begin().await?;
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE finality = 'OPTIMISTIC' AND block_timestamp IN \
+            (SELECT block_timestamp FROM {} WHERE block_height BETWEEN $1 AND $2)",
+            crate::db_adapters::table("balance_changes"),
+            crate::db_adapters::table("optimistic_blocks")
+        ))
+        .bind(block_height)
+        .bind(final_height as i64)
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE (block_height, block_timestamp) IN \
+            (SELECT block_height, block_timestamp FROM {} WHERE block_height BETWEEN $1 AND $2)",
+            crate::db_adapters::table("block_balance_summary"),
+            crate::db_adapters::table("optimistic_blocks")
+        ))
+        .bind(block_height)
+        .bind(final_height as i64)
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE block_height BETWEEN $1 AND $2",
+            crate::db_adapters::table("optimistic_blocks")
+        ))
+        .bind(block_height)
+        .bind(final_height as i64)
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+
+        let (lake_handle, stream) =
+            near_lake_framework::streamer(opts.to_lake_config(block_height as u64)?);
+        let result = crate::db_adapters::reindex::reindex_range(
+            pool,
+            stream,
+            block_height as u64,
+            final_height,
+            1,
+            json_rpc_client,
+        )
+        .await;
+        lake_handle.abort();
+        return result;
+    }
+    Ok(())
+}
+
+// None if the height was skipped in the final chain
+async fn final_block_hash(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    block_height: u64,
+) -> anyhow::Result<Option<String>> {
+    let request = near_jsonrpc_client::methods::block::RpcBlockRequest {
+        block_reference: near_primitives::types::BlockReference::BlockId(
+            near_primitives::types::BlockId::Height(block_height),
+        ),
+    };
+    match json_rpc_client.call(request).await {
+        Ok(block) => Ok(Some(block.header.hash.to_string())),
+        Err(err) => match err.handler_error() {
+            Some(RpcBlockError::UnknownBlock { .. }) => Ok(None),
+            _ => Err(anyhow::anyhow!(
+                "Failed to get block {} from RPC: {}",
+                block_height,
+                err
+            )),
+        },
+    }
+}
@@ -0,0 +1,364 @@
+use cached::Cached;
+use std::str::FromStr;
+
+use near_lake_framework::near_indexer_primitives::{self, views::ExecutionStatusView};
+
+use crate::models::ft_balance_changes::FtBalanceChange;
+use crate::models::quarantined_ft_event_values::QuarantinedFtEventValue;
+use crate::models::PrintEnum;
+
+// (contract, account) -> FT balance after the latest processed change
+pub type FtBalanceCache = std::sync::Arc<
+    tokio::sync::Mutex<
+        cached::SizedCache<(String, String), near_indexer_primitives::types::Balance>,
+    >,
+>;
+
+// Fungible token balances are taken from the NEP-141 events in the logs.
+// The absolute amount is tracked in the cache, the missing ones are asked from the contract
+pub(crate) struct FtIndexer {
+    pool: sqlx::Pool<sqlx::Postgres>,
+    cache: FtBalanceCache,
+    wrap_near: crate::db_adapters::wrap_near::WrapNearTracker,
+    json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+}
+
+#[derive(serde::Deserialize)]
+struct EventLog {
+    standard: String,
+    event: String,
+    data: serde_json::Value,
+}
+
+#[derive(serde::Deserialize)]
+struct FtTransferData {
+    old_owner_id: String,
+    new_owner_id: String,
+    amount: String,
+}
+
+#[derive(serde::Deserialize)]
+struct FtMintBurnData {
+    owner_id: String,
+    amount: String,
+}
+
+// One side of the event: the balance of `account_id` goes up or down by `amount`
+struct FtDelta {
+    account_id: String,
+    involved_account_id: Option<String>,
+    cause: crate::models::FtCause,
+    amount: u128,
+    is_credit: bool,
+}
+
+impl FtIndexer {
+    pub(crate) fn new(
+        pool: sqlx::Pool<sqlx::Postgres>,
+        wrap_near_contract: String,
+        json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+    ) -> Self {
+        Self {
+            pool,
+            cache: std::sync::Arc::new(tokio::sync::Mutex::new(cached::SizedCache::with_size(
+                100_000,
+            ))),
+            wrap_near: crate::db_adapters::wrap_near::WrapNearTracker::new(wrap_near_contract),
+            json_rpc_client,
+        }
+    }
+
+    // Should be called for each block in order, the cache relies on it
+    pub(crate) async fn process_block(
+        &self,
+        streamer_message: &near_indexer_primitives::StreamerMessage,
+    ) -> anyhow::Result<()> {
+        let (changes, quarantined) = self.collect_ft_balance_changes(streamer_message).await?;
+        let wrap_near_events = self.wrap_near.collect_events(streamer_message).await?;
+        crate::models::chunked_insert(&self.pool, &changes, 10).await?;
+        crate::models::chunked_insert(&self.pool, &quarantined, 10).await?;
+        crate::models::chunked_insert(&self.pool, &wrap_near_events, 10).await
+    }
+
+    async fn collect_ft_balance_changes(
+        &self,
+        streamer_message: &near_indexer_primitives::StreamerMessage,
+    ) -> anyhow::Result<(Vec<FtBalanceChange>, Vec<QuarantinedFtEventValue>)> {
+        let block_header = &streamer_message.block.header;
+        let mut result = vec![];
+        let mut quarantined = vec![];
+        for (shard_id, outcome_with_receipt) in streamer_message.shards.iter().flat_map(|shard| {
+            shard
+                .receipt_execution_outcomes
+                .iter()
+                .map(move |outcome| (shard.shard_id, outcome))
+        }) {
+            let outcome = &outcome_with_receipt.execution_outcome.outcome;
+            // The state of the failed receipt is reverted, the events did not happen
+            if matches!(
+                outcome.status,
+                ExecutionStatusView::Failure(_) | ExecutionStatusView::Unknown
+            ) {
+                continue;
+            }
+            let contract_account_id = &outcome_with_receipt.receipt.receiver_id;
+            let receipt_id = &outcome_with_receipt.receipt.receipt_id;
+            let mut index_in_receipt = 0;
+            for log in &outcome.logs {
+                for delta in parse_ft_event(log) {
+                    // Account ids in the events are not validated by the runtime.
+                    // The malformed ones are kept aside, they can't have the balance anyway
+                    if let Some(value) = invalid_account_id(&delta, |field, value, error| {
+                        quarantine(
+                            block_header,
+                            shard_id,
+                            outcome_with_receipt,
+                            field,
+                            value,
+                            error,
+                        )
+                    }) {
+                        quarantined.push(value);
+                        continue;
+                    }
+                    let absolute_amount = self
+                        .apply_delta(contract_account_id, &delta, &block_header.prev_hash)
+                        .await;
+                    let amount = crate::models::to_decimal(delta.amount);
+                    result.push(FtBalanceChange {
+                        block_timestamp: block_header.timestamp.into(),
+                        receipt_id: receipt_id.to_string(),
+                        contract_account_id: contract_account_id.to_string(),
+                        affected_account_id: delta.account_id,
+                        involved_account_id: delta.involved_account_id,
+                        cause: delta.cause.print().to_string(),
+                        delta_amount: if delta.is_credit { amount } else { -amount },
+                        absolute_amount: absolute_amount.map(crate::models::to_decimal),
+                        index_in_receipt,
+                    });
+                    index_in_receipt += 1;
+                }
+            }
+        }
+        Ok((result, quarantined))
+    }
+
+    // Returns the balance after the change, None if we do not know the balance before it
+    async fn apply_delta(
+        &self,
+        contract_account_id: &near_indexer_primitives::types::AccountId,
+        delta: &FtDelta,
+        prev_block_hash: &near_indexer_primitives::CryptoHash,
+    ) -> Option<near_indexer_primitives::types::Balance> {
+        let key = (contract_account_id.to_string(), delta.account_id.clone());
+        let mut cache = self.cache.lock().await;
+        let prev_balance = match cache.cache_get(&key) {
+            Some(balance) => *balance,
+            None => {
+                match ft_balance_of(
+                    &self.json_rpc_client,
+                    contract_account_id,
+                    &delta.account_id,
+                    prev_block_hash,
+                )
+                .await
+                {
+                    Ok(balance) => balance,
+                    Err(err) => {
+                        tracing::warn!(
+                            target: crate::INDEXER,
+                            "Failed to get ft_balance_of {} from {}: {:#}",
+                            delta.account_id,
+                            contract_account_id,
+                            err
+                        );
+                        return None;
+                    }
+                }
+            }
+        };
+        let balance = if delta.is_credit {
+            prev_balance.checked_add(delta.amount)
+        } else {
+            prev_balance.checked_sub(delta.amount)
+        };
+        match balance {
+            Some(balance) => {
+                cache.cache_set(key, balance);
+            }
+            None => {
+                // The contract reported the balances inconsistently, ask it again next time
+                cache.cache_remove(&key);
+            }
+        }
+        balance
+    }
+}
+
+fn invalid_account_id(
+    delta: &FtDelta,
+    quarantine: impl Fn(&str, &str, String) -> QuarantinedFtEventValue,
+) -> Option<QuarantinedFtEventValue> {
+    let fields = [
+        ("account_id", Some(&delta.account_id)),
+        ("involved_account_id", delta.involved_account_id.as_ref()),
+    ];
+    fields.into_iter().find_map(|(field, value)| {
+        let value = value?;
+        let error = near_indexer_primitives::types::AccountId::from_str(value).err()?;
+        Some(quarantine(field, value, error.to_string()))
+    })
+}
+
+fn quarantine(
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    shard_id: near_indexer_primitives::types::ShardId,
+    outcome_with_receipt: &near_indexer_primitives::IndexerExecutionOutcomeWithReceipt,
+    field: &str,
+    value: &str,
+    error: String,
+) -> QuarantinedFtEventValue {
+    tracing::warn!(
+        target: crate::INDEXER,
+        "Quarantined {} {:?} from the FT event of contract {}, receipt {} at block_height {}, shard {}: {}",
+        field,
+        value,
+        outcome_with_receipt.receipt.receiver_id,
+        outcome_with_receipt.receipt.receipt_id,
+        block_header.height,
+        shard_id,
+        error
+    );
+    crate::metrics::QUARANTINED_FT_EVENT_VALUES_TOTAL.inc();
+    QuarantinedFtEventValue {
+        block_height: block_header.height.into(),
+        block_timestamp: block_header.timestamp.into(),
+        shard_id: shard_id as i32,
+        receipt_id: outcome_with_receipt.receipt.receipt_id.to_string(),
+        contract_account_id: outcome_with_receipt.receipt.receiver_id.to_string(),
+        field: field.to_string(),
+        value: value.to_string(),
+        error,
+    }
+}
+
+fn parse_ft_event(log: &str) -> Vec<FtDelta> {
+    let event: EventLog = match log
+        .strip_prefix("EVENT_JSON:")
+        .map(|json| serde_json::from_str(json.trim()))
+    {
+        Some(Ok(event)) => event,
+        // Not an event, or a malformed one
+        _ => return vec![],
+    };
+    if event.standard != "nep141" {
+        return vec![];
+    }
+    let parse_amount = |amount: &str| amount.parse::<u128>().ok();
+    match event.event.as_str() {
+        "ft_transfer" => serde_json::from_value::<Vec<FtTransferData>>(event.data)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|data| {
+                let amount = parse_amount(&data.amount)?;
+                Some([
+                    FtDelta {
+                        account_id: data.old_owner_id.clone(),
+                        involved_account_id: Some(data.new_owner_id.clone()),
+                        cause: crate::models::FtCause::Transfer,
+                        amount,
+                        is_credit: false,
+                    },
+                    FtDelta {
+                        account_id: data.new_owner_id,
+                        involved_account_id: Some(data.old_owner_id),
+                        cause: crate::models::FtCause::Transfer,
+                        amount,
+                        is_credit: true,
+                    },
+                ])
+            })
+            .flatten()
+            .collect(),
+        event_name @ ("ft_mint" | "ft_burn") => {
+            serde_json::from_value::<Vec<FtMintBurnData>>(event.data)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|data| {
+                    Some(FtDelta {
+                        account_id: data.owner_id,
+                        involved_account_id: None,
+                        cause: if event_name == "ft_mint" {
+                            crate::models::FtCause::Mint
+                        } else {
+                            crate::models::FtCause::Burn
+                        },
+                        amount: parse_amount(&data.amount)?,
+                        is_credit: event_name == "ft_mint",
+                    })
+                })
+                .collect()
+        }
+        _ => vec![],
+    }
+}
+
+async fn ft_balance_of(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    contract_account_id: &near_indexer_primitives::types::AccountId,
+    account_id: &str,
+    block_hash: &near_indexer_primitives::CryptoHash,
+) -> anyhow::Result<near_indexer_primitives::types::Balance> {
+    let balance: String = crate::db_adapters::balance_changes::call_view_function(
+        json_rpc_client,
+        contract_account_id,
+        "ft_balance_of",
+        serde_json::json!({ "account_id": account_id }),
+        block_hash,
+    )
+    .await?;
+    Ok(balance.parse()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quarantine_field(log: &str) -> Vec<Option<(String, String)>> {
+        parse_ft_event(log)
+            .iter()
+            .map(|delta| {
+                invalid_account_id(delta, |field, value, error| QuarantinedFtEventValue {
+                    block_height: 0.into(),
+                    block_timestamp: 0.into(),
+                    shard_id: 0,
+                    receipt_id: String::new(),
+                    contract_account_id: String::new(),
+                    field: field.to_string(),
+                    value: value.to_string(),
+                    error,
+                })
+                .map(|value| (value.field, value.value))
+            })
+            .collect()
+    }
+
+    // The contract writes any string to the log, the runtime does not check it
+    #[test]
+    fn quarantines_malformed_account_ids() {
+        let log = r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[{"old_owner_id":"alice.near","new_owner_id":"Bob.NEAR","amount":"100"}]}"#;
+        assert_eq!(
+            quarantine_field(log),
+            vec![
+                Some(("involved_account_id".to_string(), "Bob.NEAR".to_string())),
+                Some(("account_id".to_string(), "Bob.NEAR".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_valid_account_ids() {
+        let log = r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_mint","data":[{"owner_id":"alice.near","amount":"100"}]}"#;
+        assert_eq!(quarantine_field(log), vec![None]);
+    }
+}
@@ -0,0 +1,114 @@
+use near_lake_framework::near_indexer_primitives::views::ExecutionStatusView;
+use near_primitives::state_record::StateRecord;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::models::current_balances::CurrentBalance;
+use crate::models::PrintEnum;
+
+#[derive(Debug, serde::Deserialize)]
+struct Genesis {
+    genesis_height: u64,
+    records: Vec<StateRecord>,
+}
+
+// JSON RPC does not give genesis records, so we can take them only from genesis.json
+pub(crate) async fn import_genesis(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    genesis_file: &std::path::Path,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<()> {
+    let genesis_path = genesis_file.to_path_buf();
+    let genesis: Genesis = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&genesis_path)
+            .map_err(|err| anyhow::anyhow!("Failed to open {}: {}", genesis_path.display(), err))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|err| anyhow::anyhow!("Failed to parse {}: {}", genesis_path.display(), err))
+    })
+    .await??;
+
+    let (block_hash, block_timestamp) = get_block(json_rpc_client, genesis.genesis_height).await?;
+    let mut changes: Vec<BalanceChange> = vec![];
+    let mut current_balances: Vec<CurrentBalance> = vec![];
+
+    for record in genesis.records {
+        let (account_id, account) = match record {
+            StateRecord::Account {
+                account_id,
+                account,
+            } => (account_id, account),
+            // other records do not provide balances
+            _ => continue,
+        };
+        let non_staked = crate::models::to_decimal(account.amount());
+        let staked = crate::models::to_decimal(account.locked());
+
+        changes.push(BalanceChange {
+            block_timestamp: block_timestamp.into(),
+            receipt_id: None,
+            transaction_hash: None,
+            affected_account_id: account_id.to_string(),
+            involved_account_id: None,
+            direction: crate::models::Direction::Inbound.print().to_string(),
+            cause: crate::models::Cause::InitialState.print().to_string(),
+            status: ExecutionStatusView::SuccessValue(vec![])
+                .print()
+                .to_string(),
+            delta_nonstaked_amount: non_staked.clone(),
+            absolute_nonstaked_amount: non_staked.clone(),
+            delta_staked_amount: staked.clone(),
+            absolute_staked_amount: staked.clone(),
+            // Genesis state lives in one shard
+            shard_id: 0,
+            index_in_chunk: changes.len() as i32,
+            index_in_block: changes.len() as i32,
+            event_id: String::new(),
+            parent_transaction_hash: None,
+            index_in_receipt: None,
+            fiat_value_usd: None,
+            gas_burnt: None,
+            predecessor_account_id: None,
+            receiver_account_id: None,
+        });
+        current_balances.push(CurrentBalance {
+            account_id: account_id.to_string(),
+            block_timestamp: block_timestamp.into(),
+            nonstaked_amount: non_staked,
+            staked_amount: staked,
+        });
+    }
+    crate::models::balance_changes::set_event_ids(&mut changes, &block_hash.to_string());
+
+    tracing::info!(
+        target: crate::INDEXER,
+        "Importing {} genesis balances at block_height {}",
+        changes.len(),
+        genesis.genesis_height
+    );
+    crate::models::chunked_insert(pool, &changes, 10).await?;
+    crate::models::chunked_insert(pool, &current_balances, 10).await?;
+    Ok(())
+}
+
+pub(crate) async fn get_block_timestamp(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    block_height: u64,
+) -> anyhow::Result<u64> {
+    Ok(get_block(json_rpc_client, block_height).await?.1)
+}
+
+// The hash and the timestamp
+async fn get_block(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    block_height: u64,
+) -> anyhow::Result<(near_primitives::hash::CryptoHash, u64)> {
+    let request = near_jsonrpc_client::methods::block::RpcBlockRequest {
+        block_reference: near_primitives::types::BlockReference::BlockId(
+            near_primitives::types::BlockId::Height(block_height),
+        ),
+    };
+    let block = json_rpc_client
+        .call(request)
+        .await
+        .map_err(|err| anyhow::anyhow!("Failed to get block {} from RPC: {}", block_height, err))?;
+    Ok((block.header.hash, block.header.timestamp))
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+
+use crate::configs::ImportFormat;
+use crate::models::balance_changes::BalanceChange;
+use crate::models::current_balances::CurrentBalance;
+
+// Rebuilds balance_changes and current_balances from the files of --archive-output
+// or --parquet-output. The files are stored one by one in the order of the blocks,
+// each in its own transaction, the rows of each block replace the stored ones,
+// so the interrupted import can be started again.
+// The stream offset is moved to the last imported block, the indexer continues after it.
+// The block-level tables (summary, rewards, supply) are not exported and stay as they are
+pub(crate) async fn import(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    opts: &crate::configs::Opts,
+    args: &crate::configs::ImportArgs,
+) -> anyhow::Result<()> {
+    let store = crate::sinks::object_store::ObjectStore::new(opts, &args.from).await?;
+    let names = store.list().await?;
+    let files_of = |format: ImportFormat| -> Vec<(u64, u64, String)> {
+        let extension = match format {
+            ImportFormat::Jsonl => crate::sinks::archive::FILE_EXTENSION,
+            ImportFormat::Parquet => crate::sinks::parquet::FILE_EXTENSION,
+        };
+        names
+            .iter()
+            .filter_map(|name| {
+                let (first, last) = crate::sinks::object_store::block_range(name, extension)?;
+                Some((first, last, name.clone()))
+            })
+            .collect()
+    };
+    // Both sinks may write to the same location, the same blocks should not be imported twice
+    let format = match args.format {
+        Some(format) => format,
+        None => match (
+            files_of(ImportFormat::Jsonl).is_empty(),
+            files_of(ImportFormat::Parquet).is_empty(),
+        ) {
+            (false, false) => anyhow::bail!(
+                "Both JSONL and Parquet files are in {}, choose one with --format",
+                args.from
+            ),
+            (false, true) => ImportFormat::Jsonl,
+            _ => ImportFormat::Parquet,
+        },
+    };
+    let mut files: Vec<(u64, u64, String)> = files_of(format)
+        .into_iter()
+        .filter(|(first, last, _)| {
+            args.to_block_height.map_or(true, |to| *first <= to)
+                && args.from_block_height.map_or(true, |from| *last >= from)
+        })
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        anyhow::bail!("No {:?} files in {} for the range", format, args.from);
+    }
+
+    let started_at = std::time::Instant::now();
+    let mut rows_count = 0;
+    for (first, last, name) in &files {
+        let content = store.read(name).await?;
+        let mut rows = match format {
+            ImportFormat::Jsonl => crate::sinks::archive::decode(&content),
+            ImportFormat::Parquet => crate::sinks::parquet::decode(&content),
+        }
+        .map_err(|err| anyhow::anyhow!("Failed to decode {}: {}", name, err))?;
+        rows.retain(|(block_height, _)| {
+            args.from_block_height
+                .map_or(true, |from| *block_height >= from)
+                && args.to_block_height.map_or(true, |to| *block_height <= to)
+        });
+        // The order of the indexer, the latest row of the account gives its current balance
+        rows.sort_by_key(|(block_height, change)| (*block_height, change.index_in_block));
+        let mut blocks: Vec<(u64, Vec<BalanceChange>)> = vec![];
+        for (block_height, change) in rows {
+            match blocks.last_mut() {
+                Some((height, changes)) if *height == block_height => changes.push(change),
+                _ => blocks.push((block_height, vec![change])),
+            }
+        }
+
+        let mut transaction = pool.begin().await?;
+        let mut latest_changes: HashMap<&str, &BalanceChange> = HashMap::new();
+        for (_, changes) in &blocks {
+            let block_timestamp: &BigDecimal = &changes[0].block_timestamp;
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE block_timestamp = $1::bigint",
+                crate::db_adapters::table("balance_changes")
+            ))
+            .bind(block_timestamp)
+            .execute(&mut transaction)
+            .await?;
+            if crate::db_adapters::is_bulk_load() {
+                crate::models::copy_in_transaction(&mut transaction, changes).await?;
+            } else {
+                crate::models::insert_in_transaction(&mut transaction, changes).await?;
+            }
+            for change in changes {
+                latest_changes.insert(&change.affected_account_id, change);
+            }
+            rows_count += changes.len();
+        }
+        let current_balances: Vec<CurrentBalance> = latest_changes
+            .into_iter()
+            .map(|(account_id, change)| CurrentBalance {
+                account_id: account_id.to_string(),
+                block_timestamp: change.block_timestamp.clone(),
+                nonstaked_amount: change.absolute_nonstaked_amount.clone(),
+                staked_amount: change.absolute_staked_amount.clone(),
+            })
+            .collect();
+        crate::models::insert_in_transaction(&mut transaction, &current_balances).await?;
+        let imported_to = args.to_block_height.map_or(*last, |to| to.min(*last));
+        crate::db_adapters::stream_offsets::advance_in_transaction(&mut transaction, imported_to)
+            .await?;
+        transaction.commit().await?;
+        tracing::info!(
+            target: crate::INDEXER,
+            "Imported blocks {}..={} from {}, {} blocks with changes",
+            first,
+            last,
+            name,
+            blocks.len()
+        );
+    }
+    tracing::info!(
+        target: crate::INDEXER,
+        "Imported {} balance changes from {} files in {:.0?}",
+        rows_count,
+        files.len(),
+        started_at.elapsed()
+    );
+    Ok(())
+}
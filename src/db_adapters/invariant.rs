@@ -0,0 +1,302 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use bigdecimal::BigDecimal;
+use num_traits::Zero;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::models::balance_imbalances::BalanceImbalance;
+use crate::models::PrintEnum;
+use near_lake_framework::near_indexer_primitives::{self, views::ReceiptEnumView, CryptoHash};
+
+// The trees which are not finished in this number of blocks are dropped unchecked,
+// we may have missed one of their receipts
+const MAX_TREE_AGE_BLOCKS: u64 = 1000;
+
+// Tokens are minted only by validators and treasury rewards at the start of the epoch,
+// and burnt by the gas of transactions and receipts.
+// All the other changes move the tokens between the accounts, but not within one block:
+// the deposit of the receipt leaves the signer in one block and reaches the receiver in the next one,
+// or much later if the receipt is delayed.
+// So we check the whole tree of the receipts started by the transaction, at the block where its last receipt
+// is executed: the deltas of all its outcomes plus the tokens they burnt should sum up to zero.
+// The trees started before the indexer was launched are not checked
+#[derive(Default)]
+pub(crate) struct ReceiptTrees {
+    // By the hash of the transaction
+    trees: HashMap<CryptoHash, ReceiptTree>,
+    receipt_roots: HashMap<CryptoHash, CryptoHash>,
+}
+
+#[derive(Clone, Debug)]
+struct ReceiptTree {
+    // Negative while the deposits of the pending receipts are in flight
+    net_amount: BigDecimal,
+    pending_receipts: HashSet<CryptoHash>,
+    started_at_block_height: u64,
+}
+
+// The result of the check, applied to `ReceiptTrees` after the block is committed
+pub(crate) struct BlockCheck {
+    block_height: u64,
+    touched_trees: HashMap<CryptoHash, ReceiptTree>,
+    new_receipts: HashMap<CryptoHash, CryptoHash>,
+    executed_receipts: Vec<CryptoHash>,
+    broken_trees: Vec<(CryptoHash, BigDecimal)>,
+    pub(crate) imbalance: Option<BalanceImbalance>,
+}
+
+impl ReceiptTrees {
+    pub(crate) fn check_block(
+        &self,
+        shards: &[near_indexer_primitives::IndexerShard],
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> BlockCheck {
+        let (mut transaction_deltas, mut receipt_deltas) = outcome_deltas(changes);
+        let mut touched_trees: HashMap<CryptoHash, ReceiptTree> = HashMap::new();
+        let mut new_receipts: HashMap<CryptoHash, CryptoHash> = HashMap::new();
+        let mut executed_receipts: HashMap<CryptoHash, CryptoHash> = HashMap::new();
+
+        // The transactions go first, the local receipts are executed in the same chunk
+        for transaction in shards
+            .iter()
+            .filter_map(|shard| shard.chunk.as_ref())
+            .flat_map(|chunk| &chunk.transactions)
+        {
+            let root = transaction.transaction.hash;
+            let outcome = &transaction.outcome.execution_outcome.outcome;
+            let delta = transaction_deltas.remove(&root).unwrap_or_default();
+            for receipt_id in &outcome.receipt_ids {
+                new_receipts.insert(*receipt_id, root);
+            }
+            touched_trees.insert(
+                root,
+                ReceiptTree {
+                    net_amount: delta + crate::models::to_decimal(outcome.tokens_burnt),
+                    pending_receipts: outcome.receipt_ids.iter().copied().collect(),
+                    started_at_block_height: block_header.height,
+                },
+            );
+        }
+
+        for shard in shards {
+            // Data receipts are never executed, they are done once they are passed to the receiver
+            for receipt in shard.chunk.iter().flat_map(|chunk| &chunk.receipts) {
+                if !matches!(receipt.receipt, ReceiptEnumView::Data { .. }) {
+                    continue;
+                }
+                if let Some((root, tree)) =
+                    self.tree_of(&receipt.receipt_id, &new_receipts, &mut touched_trees)
+                {
+                    tree.pending_receipts.remove(&receipt.receipt_id);
+                    executed_receipts.insert(receipt.receipt_id, root);
+                }
+            }
+            for outcome_with_receipt in &shard.receipt_execution_outcomes {
+                let receipt_id = outcome_with_receipt.receipt.receipt_id;
+                let outcome = &outcome_with_receipt.execution_outcome.outcome;
+                let (root, tree) =
+                    match self.tree_of(&receipt_id, &new_receipts, &mut touched_trees) {
+                        Some(tree) => tree,
+                        None => continue,
+                    };
+                tree.pending_receipts.remove(&receipt_id);
+                tree.net_amount += receipt_deltas.remove(&receipt_id).unwrap_or_default()
+                    + crate::models::to_decimal(outcome.tokens_burnt);
+                for new_receipt_id in &outcome.receipt_ids {
+                    tree.pending_receipts.insert(*new_receipt_id);
+                    new_receipts.insert(*new_receipt_id, root);
+                }
+                executed_receipts.insert(receipt_id, root);
+            }
+        }
+        // The gas rewards stored without the outcome of their receipt
+        for (receipt_id, delta) in receipt_deltas {
+            if let Some(tree) = executed_receipts
+                .get(&receipt_id)
+                .and_then(|root| touched_trees.get_mut(root))
+            {
+                tree.net_amount += delta;
+            }
+        }
+
+        let mut broken_trees: Vec<_> = touched_trees
+            .iter()
+            .filter(|(_, tree)| tree.pending_receipts.is_empty() && !tree.net_amount.is_zero())
+            .map(|(root, tree)| (*root, tree.net_amount.clone()))
+            .collect();
+        broken_trees.sort_by_key(|(root, _)| root.to_string());
+        let imbalance = if broken_trees.is_empty() {
+            None
+        } else {
+            Some(BalanceImbalance {
+                block_height: block_header.height.into(),
+                block_timestamp: block_header.timestamp.into(),
+                minted_amount: minted_amount(changes),
+                burnt_amount: tokens_burnt_amount(shards),
+                delta_amount: delta_amount(changes),
+                imbalance_amount: broken_trees
+                    .iter()
+                    .fold(BigDecimal::zero(), |sum, (_, net_amount)| sum + net_amount),
+            })
+        };
+        BlockCheck {
+            block_height: block_header.height,
+            touched_trees,
+            new_receipts,
+            executed_receipts: executed_receipts.into_keys().collect(),
+            broken_trees,
+            imbalance,
+        }
+    }
+
+    // Should be called only for the committed blocks, in their order
+    pub(crate) fn apply(&mut self, check: BlockCheck) {
+        for (receipt_id, root) in check.new_receipts {
+            self.receipt_roots.insert(receipt_id, root);
+        }
+        for receipt_id in &check.executed_receipts {
+            self.receipt_roots.remove(receipt_id);
+        }
+        for (root, tree) in check.touched_trees {
+            if tree.pending_receipts.is_empty() {
+                self.trees.remove(&root);
+            } else {
+                self.trees.insert(root, tree);
+            }
+        }
+        let receipt_roots = &mut self.receipt_roots;
+        self.trees.retain(|root, tree| {
+            if tree.started_at_block_height + MAX_TREE_AGE_BLOCKS >= check.block_height {
+                return true;
+            }
+            tracing::debug!(
+                target: crate::INDEXER,
+                "Receipts of transaction {} are not finished in {} blocks, {} receipts pending, the tree is not checked",
+                root,
+                MAX_TREE_AGE_BLOCKS,
+                tree.pending_receipts.len()
+            );
+            for receipt_id in &tree.pending_receipts {
+                receipt_roots.remove(receipt_id);
+            }
+            false
+        });
+    }
+
+    // The trees of the discarded fork cannot be finished anymore
+    pub(crate) fn clear(&mut self) {
+        self.trees.clear();
+        self.receipt_roots.clear();
+    }
+
+    fn tree_of<'a>(
+        &self,
+        receipt_id: &CryptoHash,
+        new_receipts: &HashMap<CryptoHash, CryptoHash>,
+        touched_trees: &'a mut HashMap<CryptoHash, ReceiptTree>,
+    ) -> Option<(CryptoHash, &'a mut ReceiptTree)> {
+        let root = *new_receipts
+            .get(receipt_id)
+            .or_else(|| self.receipt_roots.get(receipt_id))?;
+        match touched_trees.entry(root) {
+            Entry::Occupied(entry) => Some((root, entry.into_mut())),
+            Entry::Vacant(entry) => Some((root, entry.insert(self.trees.get(&root)?.clone()))),
+        }
+    }
+}
+
+impl BlockCheck {
+    pub(crate) fn report(&self) {
+        let imbalance = match &self.imbalance {
+            Some(imbalance) => imbalance,
+            None => return,
+        };
+        for (root, net_amount) in &self.broken_trees {
+            tracing::warn!(
+                target: crate::INDEXER,
+                "Balance invariant is broken at block_height {}: receipts of transaction {} sum up to {}",
+                self.block_height,
+                root,
+                net_amount
+            );
+        }
+        crate::error_reporting::spawn_report(
+            crate::error_reporting::Level::Warning,
+            "invariant_broken",
+            format!(
+                "Balance invariant is broken: {} transactions finished with the imbalance {}",
+                self.broken_trees.len(),
+                imbalance.imbalance_amount
+            ),
+            vec![("block_height", self.block_height.to_string())],
+        );
+    }
+}
+
+// The sums of the deltas by the outcome which caused them.
+// The rows without the transaction and the receipt are rewards, genesis and unknown changes
+fn outcome_deltas(
+    changes: &[BalanceChange],
+) -> (
+    HashMap<CryptoHash, BigDecimal>,
+    HashMap<CryptoHash, BigDecimal>,
+) {
+    let mut transaction_deltas: HashMap<CryptoHash, BigDecimal> = HashMap::new();
+    let mut receipt_deltas: HashMap<CryptoHash, BigDecimal> = HashMap::new();
+    for change in changes {
+        let (deltas, id) = match (&change.transaction_hash, &change.receipt_id) {
+            (Some(transaction_hash), _) => (&mut transaction_deltas, transaction_hash),
+            (None, Some(receipt_id)) => (&mut receipt_deltas, receipt_id),
+            (None, None) => continue,
+        };
+        if let Ok(id) = id.parse::<CryptoHash>() {
+            *deltas.entry(id).or_default() +=
+                &change.delta_nonstaked_amount + &change.delta_staked_amount;
+        }
+    }
+    (transaction_deltas, receipt_deltas)
+}
+
+pub(crate) fn delta_amount(changes: &[BalanceChange]) -> BigDecimal {
+    changes.iter().fold(BigDecimal::zero(), |delta, change| {
+        delta + &change.delta_nonstaked_amount + &change.delta_staked_amount
+    })
+}
+
+// Validators and treasury rewards are the only way the new tokens appear
+pub(crate) fn minted_amount(changes: &[BalanceChange]) -> BigDecimal {
+    reward_amount(changes, crate::models::Cause::ValidatorsReward)
+        + reward_amount(changes, crate::models::Cause::ProtocolTreasuryReward)
+}
+
+pub(crate) fn reward_amount(changes: &[BalanceChange], cause: crate::models::Cause) -> BigDecimal {
+    let cause = cause.print();
+    changes
+        .iter()
+        .filter(|change| change.cause == cause)
+        .fold(BigDecimal::zero(), |minted, change| {
+            minted + &change.delta_nonstaked_amount + &change.delta_staked_amount
+        })
+}
+
+pub(crate) fn tokens_burnt_amount(shards: &[near_indexer_primitives::IndexerShard]) -> BigDecimal {
+    let burnt: u128 = shards
+        .iter()
+        .flat_map(|shard| {
+            let transaction_outcomes = shard
+                .chunk
+                .iter()
+                .flat_map(|chunk| &chunk.transactions)
+                .map(|transaction| &transaction.outcome.execution_outcome);
+            let receipt_outcomes = shard
+                .receipt_execution_outcomes
+                .iter()
+                .map(|outcome_with_receipt| &outcome_with_receipt.execution_outcome);
+            transaction_outcomes.chain(receipt_outcomes)
+        })
+        .map(|outcome| outcome.outcome.tokens_burnt)
+        .sum();
+    crate::models::to_decimal(burnt)
+}
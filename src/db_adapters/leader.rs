@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use near_lake_framework::near_indexer_primitives;
+use sqlx::Connection;
+
+use crate::db_adapters::storage::Storage;
+use crate::models::balance_changes::BalanceChange;
+
+// The standby asks for the lock again after this pause, the leader checks its connection as often
+const LOCK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Several replicas may run against one Postgres, only the holder of the advisory lock indexes.
+// The others wait in `acquire`, the lock is released by Postgres when the leader's session ends,
+// and the next replica continues from the last stored block.
+// The lock lives on its own connection: the pool reconnects silently, the lock would be gone
+pub(crate) struct Leader {
+    lock_id: i64,
+    lost: Arc<AtomicBool>,
+}
+
+impl Leader {
+    pub(crate) async fn acquire(database_url: &str, lock_id: i64) -> anyhow::Result<Self> {
+        let mut waiting = false;
+        let mut connection = loop {
+            // The database may be down together with the leader, the standby keeps trying
+            match try_lock(database_url, lock_id).await {
+                Ok(Some(connection)) => break connection,
+                Ok(None) if !waiting => {
+                    tracing::info!(
+                        target: crate::INDEXER,
+                        "Advisory lock {} is held by another replica, waiting as standby",
+                        lock_id
+                    );
+                    waiting = true;
+                }
+                Ok(None) => {}
+                Err(err) => tracing::warn!(
+                    target: crate::INDEXER,
+                    "Failed to take advisory lock {}: {}",
+                    lock_id,
+                    err
+                ),
+            }
+            tokio::time::sleep(LOCK_INTERVAL).await;
+        };
+        tracing::info!(
+            target: crate::INDEXER,
+            "Advisory lock {} is taken, indexing",
+            lock_id
+        );
+
+        let lost = Arc::new(AtomicBool::new(false));
+        let watched = lost.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(LOCK_INTERVAL).await;
+                if let Err(err) = sqlx::query("SELECT 1").execute(&mut connection).await {
+                    tracing::error!(
+                        target: crate::INDEXER,
+                        "Connection holding advisory lock {} is broken: {}",
+                        lock_id,
+                        err
+                    );
+                    watched.store(true, Ordering::Relaxed);
+                    return;
+                }
+            }
+        });
+        Ok(Self { lock_id, lost })
+    }
+}
+
+async fn try_lock(database_url: &str, lock_id: i64) -> anyhow::Result<Option<sqlx::PgConnection>> {
+    let mut connection = sqlx::PgConnection::connect(database_url).await?;
+    let (locked,): (bool,) = sqlx::query_as("SELECT pg_try_advisory_lock($1)")
+        .bind(lock_id)
+        .fetch_one(&mut connection)
+        .await?;
+    Ok(if locked { Some(connection) } else { None })
+}
+
+// Refuses to write as soon as the lock may belong to another replica.
+// The error stops the indexer, the process supervisor restarts it as standby
+pub(crate) struct LeaderStorage {
+    inner: Box<dyn Storage>,
+    leader: Leader,
+}
+
+impl LeaderStorage {
+    pub(crate) fn new(inner: Box<dyn Storage>, leader: Leader) -> Self {
+        Self { inner, leader }
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for LeaderStorage {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<u64> {
+        self.inner.start_after_interruption().await
+    }
+
+    async fn store_block(
+        &self,
+        shards: &[near_indexer_primitives::IndexerShard],
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        if self.leader.lost.load(Ordering::Relaxed) {
+            anyhow::bail!(
+                "Advisory lock {} is lost at block_height {}, another replica may be indexing",
+                self.leader.lock_id,
+                block_header.height
+            );
+        }
+        self.inner.store_block(shards, block_header, changes).await
+    }
+
+    async fn rollback_blocks(&self, blocks: &[crate::forks::TrackedBlock]) -> anyhow::Result<()> {
+        if self.leader.lost.load(Ordering::Relaxed) {
+            anyhow::bail!(
+                "Advisory lock {} is lost, another replica may be indexing",
+                self.leader.lock_id
+            );
+        }
+        self.inner.rollback_blocks(blocks).await
+    }
+
+    fn postgres_pool(&self) -> Option<&sqlx::Pool<sqlx::Postgres>> {
+        self.inner.postgres_pool()
+    }
+}
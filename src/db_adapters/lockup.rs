@@ -0,0 +1,87 @@
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use futures::future::try_join_all;
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::models::lockup_balances::LockupBalance;
+
+// The balance of the lockup contract belongs to the owner, but only a part of it may be withdrawn.
+// We ask the contract how much is still locked after each block where its balance changed.
+// The rows are stored in the transaction of the block, see `DerivedRows`
+pub(crate) async fn lockup_balances(
+    lockup_suffix: &str,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<Vec<LockupBalance>> {
+    let mut lockup_accounts: Vec<&str> = changes
+        .iter()
+        .map(|change| change.affected_account_id.as_str())
+        .filter(|account_id| account_id.ends_with(lockup_suffix))
+        .collect();
+    lockup_accounts.sort_unstable();
+    lockup_accounts.dedup();
+
+    let balances = try_join_all(
+        lockup_accounts
+            .into_iter()
+            .map(|account_id| get_lockup_balance(account_id, block_header, json_rpc_client)),
+    )
+    .await?;
+    Ok(balances.into_iter().flatten().collect())
+}
+
+async fn get_lockup_balance(
+    lockup_account_id: &str,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<Option<LockupBalance>> {
+    let contract = near_indexer_primitives::types::AccountId::from_str(lockup_account_id)?;
+    let call = |method_name| {
+        crate::db_adapters::balance_changes::call_view_function::<String>(
+            json_rpc_client,
+            &contract,
+            method_name,
+            serde_json::json!({}),
+            &block_header.hash,
+        )
+    };
+    // The contract may be deleted in this block, or it is not a lockup at all
+    let (owner_account_id, locked_amount, liquid_amount) = match tokio::try_join!(
+        call("get_owner_account_id"),
+        call("get_locked_amount"),
+        call("get_liquid_owners_balance"),
+    ) {
+        Ok(result) => result,
+        Err(err) => {
+            tracing::warn!(
+                target: crate::INDEXER,
+                "Failed to read the lockup {} at block_height {}: {:#}",
+                lockup_account_id,
+                block_header.height,
+                err
+            );
+            return Ok(None);
+        }
+    };
+    Ok(Some(LockupBalance {
+        block_timestamp: block_header.timestamp.into(),
+        lockup_account_id: lockup_account_id.to_string(),
+        owner_account_id,
+        locked_amount: parse_amount(&locked_amount, lockup_account_id)?,
+        liquid_amount: parse_amount(&liquid_amount, lockup_account_id)?,
+    }))
+}
+
+fn parse_amount(amount: &str, lockup_account_id: &str) -> anyhow::Result<BigDecimal> {
+    BigDecimal::from_str(amount).map_err(|err| {
+        anyhow::anyhow!(
+            "Lockup {} returned invalid amount {:?}: {}",
+            lockup_account_id,
+            amount,
+            err
+        )
+    })
+}
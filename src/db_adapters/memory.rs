@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives;
+use num_traits::Zero;
+use tokio::sync::Mutex;
+
+use crate::models::balance_changes::BalanceChange;
+
+// Keeps the rows in memory, for the tests of the delta logic on the short block ranges and the dry runs.
+// `--database memory://<path>` also checks the rows against the expectations from the TOML file,
+// the indexer stops at the first block which does not meet them:
+//
+// [[expect]]
+// block_height = 9820214
+// account_id = "alice.near"
+// cause = "RECEIPT"                       # the fields below are optional
+// direction = "INBOUND"
+// involved_account_id = "bob.near"
+// delta_nonstaked_amount = "-1000"
+// absolute_nonstaked_amount = "5000"
+// delta_staked_amount = "0"
+// absolute_staked_amount = "0"
+// index_in_chunk = 0
+// index_in_block = 0
+//
+// [[current_balance]]                     # checked after the block is stored
+// block_height = 9820214
+// account_id = "bob.near"
+// exists = false                          # optional, true by default
+// nonstaked_amount = "5000"               # optional
+//
+// Each expectation should be met by at least one row of the account in the block.
+// With `exact = true` at the top of the file, the expectations of the block are its rows in the order of
+// index_in_block, nothing is missing and nothing is added.
+// The current balances are kept like in current_balances of Postgres.
+// `--dry-run` keeps no rows, it only logs the summary of each block
+pub(crate) struct MemoryStorage {
+    expectations: Vec<Expectation>,
+    current_balance_expectations: Vec<CurrentBalanceExpectation>,
+    exact: bool,
+    dry_run: bool,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    last_block_height: u64,
+    changes: Vec<BalanceChange>,
+    // Account -> (nonstaked, staked)
+    current_balances: HashMap<String, (BigDecimal, BigDecimal)>,
+    met_expectations: usize,
+    receipt_trees: crate::db_adapters::invariant::ReceiptTrees,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Expectations {
+    #[serde(default)]
+    exact: bool,
+    #[serde(default)]
+    expect: Vec<Expectation>,
+    #[serde(default)]
+    current_balance: Vec<CurrentBalanceExpectation>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Expectation {
+    block_height: u64,
+    account_id: String,
+    cause: Option<String>,
+    direction: Option<String>,
+    involved_account_id: Option<String>,
+    delta_nonstaked_amount: Option<BigDecimal>,
+    absolute_nonstaked_amount: Option<BigDecimal>,
+    delta_staked_amount: Option<BigDecimal>,
+    absolute_staked_amount: Option<BigDecimal>,
+    index_in_chunk: Option<i32>,
+    index_in_block: Option<i32>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CurrentBalanceExpectation {
+    block_height: u64,
+    account_id: String,
+    #[serde(default = "CurrentBalanceExpectation::default_exists")]
+    exists: bool,
+    nonstaked_amount: Option<BigDecimal>,
+}
+
+impl CurrentBalanceExpectation {
+    fn default_exists() -> bool {
+        true
+    }
+
+    fn is_met_by(&self, current_balances: &HashMap<String, (BigDecimal, BigDecimal)>) -> bool {
+        match current_balances.get(&self.account_id) {
+            Some((nonstaked_amount, _)) => {
+                self.exists
+                    && self
+                        .nonstaked_amount
+                        .as_ref()
+                        .map_or(true, |expected| expected == nonstaked_amount)
+            }
+            None => !self.exists,
+        }
+    }
+}
+
+impl Expectation {
+    fn is_met_by(&self, change: &BalanceChange) -> bool {
+        fn matches<T: PartialEq>(expected: &Option<T>, actual: &T) -> bool {
+            expected
+                .as_ref()
+                .map_or(true, |expected| expected == actual)
+        }
+        change.affected_account_id == self.account_id
+            && matches(&self.cause, &change.cause)
+            && matches(&self.direction, &change.direction)
+            && self.involved_account_id.as_ref().map_or(true, |expected| {
+                change.involved_account_id.as_ref() == Some(expected)
+            })
+            && matches(&self.delta_nonstaked_amount, &change.delta_nonstaked_amount)
+            && matches(
+                &self.absolute_nonstaked_amount,
+                &change.absolute_nonstaked_amount,
+            )
+            && matches(&self.delta_staked_amount, &change.delta_staked_amount)
+            && matches(&self.absolute_staked_amount, &change.absolute_staked_amount)
+            && matches(&self.index_in_chunk, &change.index_in_chunk)
+            && matches(&self.index_in_block, &change.index_in_block)
+    }
+}
+
+impl MemoryStorage {
+    // `memory` or `memory://<expectations file>`
+    pub(crate) fn new(database_url: &str) -> anyhow::Result<Self> {
+        let expectations = match database_url.strip_prefix("memory://") {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .map_err(|err| anyhow::anyhow!("Failed to read {}: {}", path, err))?;
+                toml::from_str::<Expectations>(&content)?
+            }
+            None => Expectations {
+                exact: false,
+                expect: vec![],
+                current_balance: vec![],
+            },
+        };
+        Ok(Self {
+            expectations: expectations.expect,
+            current_balance_expectations: expectations.current_balance,
+            exact: expectations.exact,
+            dry_run: false,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    pub(crate) fn dry_run() -> Self {
+        Self {
+            expectations: vec![],
+            current_balance_expectations: vec![],
+            exact: false,
+            dry_run: true,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn check_expectations(
+        &self,
+        block_height: u64,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<usize> {
+        let expectations: Vec<&Expectation> = self
+            .expectations
+            .iter()
+            .filter(|expectation| expectation.block_height == block_height)
+            .collect();
+        if self.exact && !expectations.is_empty() {
+            let mismatch = expectations.len() != changes.len()
+                || expectations
+                    .iter()
+                    .zip(changes)
+                    .any(|(expectation, change)| !expectation.is_met_by(change));
+            if mismatch {
+                anyhow::bail!(
+                    "The rows of block_height {} are not the expected ones\nExpected:\n{:#?}\nActual:\n{:#?}",
+                    block_height,
+                    expectations,
+                    changes
+                );
+            }
+            return Ok(expectations.len());
+        }
+        let mut met = 0;
+        for expectation in expectations {
+            if !changes.iter().any(|change| expectation.is_met_by(change)) {
+                anyhow::bail!(
+                    "Expectation is not met at block_height {}: {:?}\nThe rows of the block:\n{:#?}",
+                    block_height,
+                    expectation,
+                    changes
+                        .iter()
+                        .filter(|change| change.affected_account_id == expectation.account_id)
+                        .collect::<Vec<_>>()
+                );
+            }
+            met += 1;
+        }
+        Ok(met)
+    }
+
+    fn check_current_balances(
+        &self,
+        block_height: u64,
+        current_balances: &HashMap<String, (BigDecimal, BigDecimal)>,
+    ) -> anyhow::Result<usize> {
+        let mut met = 0;
+        for expectation in self
+            .current_balance_expectations
+            .iter()
+            .filter(|expectation| expectation.block_height == block_height)
+        {
+            if !expectation.is_met_by(current_balances) {
+                anyhow::bail!(
+                    "Current balance expectation is not met at block_height {}: {:?}\nThe current balance: {:?}",
+                    block_height,
+                    expectation,
+                    current_balances.get(&expectation.account_id)
+                );
+            }
+            met += 1;
+        }
+        Ok(met)
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::db_adapters::storage::Storage for MemoryStorage {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<u64> {
+        Ok(self.state.lock().await.last_block_height)
+    }
+
+    async fn store_block(
+        &self,
+        shards: &[near_indexer_primitives::IndexerShard],
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+        // Stored already, the retry after the error
+        if block_header.height <= state.last_block_height {
+            return Ok(());
+        }
+        if self.dry_run {
+            let invariant_check = state
+                .receipt_trees
+                .check_block(shards, block_header, changes);
+            log_summary(shards, block_header, changes, &invariant_check);
+            state.receipt_trees.apply(invariant_check);
+            state.last_block_height = block_header.height;
+            return Ok(());
+        }
+        let met = self.check_expectations(block_header.height, changes)?;
+        let stored_changes = crate::db_adapters::without_dust(changes);
+        let mut current_balances = state.current_balances.clone();
+        let current_changes = if crate::db_adapters::dust_updates_current_balances() {
+            changes
+        } else {
+            &stored_changes
+        };
+        for change in current_changes.iter() {
+            current_balances.insert(
+                change.affected_account_id.clone(),
+                (
+                    change.absolute_nonstaked_amount.clone(),
+                    change.absolute_staked_amount.clone(),
+                ),
+            );
+        }
+        let met = met + self.check_current_balances(block_header.height, &current_balances)?;
+        state.met_expectations += met;
+        state.current_balances = current_balances;
+        state.changes.extend(stored_changes.iter().cloned());
+        state.last_block_height = block_header.height;
+        tracing::debug!(
+            target: crate::INDEXER,
+            "Block {}: {} rows stored in memory, {} rows in total, {} of {} expectations met",
+            block_header.height,
+            changes.len(),
+            state.changes.len(),
+            state.met_expectations,
+            self.expectations.len() + self.current_balance_expectations.len()
+        );
+        Ok(())
+    }
+
+    async fn rollback_blocks(&self, blocks: &[crate::forks::TrackedBlock]) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+        for block in blocks {
+            let block_timestamp = BigDecimal::from(block.timestamp);
+            state
+                .changes
+                .retain(|change| change.block_timestamp != block_timestamp);
+            state.last_block_height = state.last_block_height.min(block.height.saturating_sub(1));
+        }
+        // Like restore_current_balances of Postgres, the latest stored rows give them
+        let mut current_balances = HashMap::new();
+        for change in &state.changes {
+            current_balances.insert(
+                change.affected_account_id.clone(),
+                (
+                    change.absolute_nonstaked_amount.clone(),
+                    change.absolute_staked_amount.clone(),
+                ),
+            );
+        }
+        state.current_balances = current_balances;
+        state.receipt_trees.clear();
+        Ok(())
+    }
+}
+
+fn log_summary(
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+    invariant_check: &crate::db_adapters::invariant::BlockCheck,
+) {
+    let stored = crate::db_adapters::without_dust(changes);
+    let accounts = changes
+        .iter()
+        .map(|change| change.affected_account_id.as_str())
+        .collect::<std::collections::HashSet<_>>();
+    let minted = crate::db_adapters::invariant::minted_amount(changes);
+    let burnt = crate::db_adapters::invariant::tokens_burnt_amount(shards);
+    let delta = crate::db_adapters::invariant::delta_amount(changes);
+    let imbalance = invariant_check
+        .imbalance
+        .as_ref()
+        .map_or_else(BigDecimal::zero, |imbalance| {
+            imbalance.imbalance_amount.clone()
+        });
+    tracing::info!(
+        target: crate::INDEXER,
+        "Dry run, block {}: {} changes ({} would be stored) of {} accounts, minted {}, burnt {}, sum of deltas {}, imbalance {}",
+        block_header.height,
+        changes.len(),
+        stored.len(),
+        accounts.len(),
+        minted,
+        burnt,
+        delta,
+        imbalance
+    );
+    // Same as the check of Postgres storage, the filtered rows do not sum up
+    if !crate::db_adapters::account_filter::is_filtering() {
+        invariant_check.report();
+    }
+}
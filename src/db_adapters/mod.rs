@@ -1,3 +1,344 @@
+pub(crate) mod account_batch;
+pub(crate) mod account_filter;
+pub(crate) mod active_accounts;
+pub(crate) mod anomalies;
 pub(crate) mod balance_changes;
+pub(crate) mod check_account;
+pub(crate) mod clickhouse;
+pub(crate) mod disk_buffer;
+pub(crate) mod drift;
+pub(crate) mod dual_write;
+pub(crate) mod explorer_compat;
+pub(crate) mod export;
+pub(crate) mod fee_details;
+pub(crate) mod finality;
+pub(crate) mod ft_balance_changes;
+pub(crate) mod genesis;
+pub(crate) mod import;
+pub(crate) mod invariant;
+pub(crate) mod leader;
+pub(crate) mod lockup;
+pub(crate) mod memory;
+pub(crate) mod notify;
+pub(crate) mod partitions;
+pub(crate) mod pool;
+pub(crate) mod prefetch;
+pub(crate) mod reindex;
+pub(crate) mod repair;
+pub(crate) mod resharding;
+pub(crate) mod retention;
+pub(crate) mod rollback;
+pub(crate) mod schema_version;
+pub(crate) mod shard_assignment;
+#[cfg(feature = "sqlite")]
+pub(crate) mod sqlite;
+pub(crate) mod storage;
+pub(crate) mod stream_offsets;
+pub(crate) mod supply;
+pub(crate) mod timescale;
+pub(crate) mod top_accounts;
+pub(crate) mod wrap_near;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use num_traits::Zero;
+
+use crate::models::balance_changes::BalanceChange;
 
 pub(crate) const CHUNK_SIZE_FOR_BATCH_INSERT: usize = 100;
+// Postgres does not accept more bind parameters in one query
+const MAX_QUERY_PARAMETERS: usize = 65_535;
+const MIN_BATCH_SIZE: usize = 10;
+// Adaptive mode grows the batch while the queries are faster than the lower bound,
+// and shrinks it when they are slower than the upper bound
+const FAST_INSERT: std::time::Duration = std::time::Duration::from_millis(100);
+const SLOW_INSERT: std::time::Duration = std::time::Duration::from_secs(1);
+
+static INSERT_BATCH_SIZE: AtomicUsize = AtomicUsize::new(CHUNK_SIZE_FOR_BATCH_INSERT);
+static ADAPTIVE_BATCH_SIZE: AtomicBool = AtomicBool::new(false);
+static BULK_LOAD: AtomicBool = AtomicBool::new(false);
+static MIN_DELTA: once_cell::sync::OnceCell<bigdecimal::BigDecimal> =
+    once_cell::sync::OnceCell::new();
+static DUST_UPDATES_CURRENT_BALANCES: AtomicBool = AtomicBool::new(false);
+static SKIP_ZERO_DELTA: AtomicBool = AtomicBool::new(false);
+static STORE_UNKNOWN_CAUSES: AtomicBool = AtomicBool::new(false);
+static NETWORK: once_cell::sync::OnceCell<std::sync::Arc<Network>> =
+    once_cell::sync::OnceCell::new();
+
+tokio::task_local! {
+    // Each pipeline of the multi-network mode runs with its own, see src/networks.rs
+    static TASK_NETWORK: std::sync::Arc<Network>;
+}
+
+// The settings which differ between the networks indexed by one process
+pub(crate) struct Network {
+    schema: Option<String>,
+    prefix: String,
+    protocol_treasury_account: once_cell::sync::OnceCell<String>,
+    shared_balance_cache:
+        once_cell::sync::OnceCell<std::sync::Arc<dyn crate::shared_cache::SharedBalanceCache>>,
+}
+
+impl Network {
+    // The names go into the queries as they are, so they should be plain identifiers without quoting
+    pub(crate) fn new(
+        schema: Option<String>,
+        prefix: String,
+    ) -> anyhow::Result<std::sync::Arc<Self>> {
+        let is_identifier = |name: &str| {
+            name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        };
+        if let Some(schema) = &schema {
+            if !is_identifier(schema) {
+                anyhow::bail!(
+                    "--db-schema should be lowercase letters, digits and underscores, got `{}`",
+                    schema
+                );
+            }
+        }
+        if !prefix.is_empty() && !is_identifier(&prefix) {
+            anyhow::bail!(
+                "--table-prefix should be lowercase letters, digits and underscores, got `{}`",
+                prefix
+            );
+        }
+        Ok(std::sync::Arc::new(Self {
+            schema,
+            prefix,
+            protocol_treasury_account: once_cell::sync::OnceCell::new(),
+            shared_balance_cache: once_cell::sync::OnceCell::new(),
+        }))
+    }
+}
+
+pub(crate) fn configure_network(network: std::sync::Arc<Network>) -> anyhow::Result<()> {
+    NETWORK
+        .set(network)
+        .map_err(|_| anyhow::anyhow!("Network is configured twice"))
+}
+
+pub(crate) async fn in_network<F: std::future::Future>(
+    network: std::sync::Arc<Network>,
+    future: F,
+) -> F::Output {
+    TASK_NETWORK.scope(network, future).await
+}
+
+// tokio::spawn keeping the network of the caller, for the background tasks touching the tables
+pub(crate) fn spawn<F>(future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: std::future::Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match TASK_NETWORK.try_with(std::sync::Arc::clone) {
+        Ok(network) => tokio::spawn(TASK_NETWORK.scope(network, future)),
+        Err(_) => tokio::spawn(future),
+    }
+}
+
+fn with_network<T>(f: impl FnOnce(Option<&Network>) -> T) -> T {
+    match TASK_NETWORK.try_with(std::sync::Arc::clone) {
+        Ok(network) => f(Some(&network)),
+        Err(_) => f(NETWORK.get().map(std::sync::Arc::as_ref)),
+    }
+}
+
+// The treasury gets its share of the inflation in the same state change as the validators
+pub(crate) async fn configure_protocol_treasury_account(
+    account: Option<String>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<()> {
+    let account = match account {
+        Some(account) => account,
+        None => json_rpc_client
+            .call(
+                near_jsonrpc_client::methods::EXPERIMENTAL_genesis_config::RpcGenesisConfigRequest,
+            )
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed to get genesis config from RPC: {}", err))?
+            .protocol_treasury_account
+            .to_string(),
+    };
+    with_network(|network| {
+        network
+            .ok_or_else(|| anyhow::anyhow!("Network is not configured"))?
+            .protocol_treasury_account
+            .set(account)
+            .map_err(|_| anyhow::anyhow!("Protocol treasury account is configured twice"))
+    })
+}
+
+pub(crate) fn is_protocol_treasury(account_id: &str) -> bool {
+    with_network(|network| {
+        network
+            .and_then(|network| network.protocol_treasury_account.get())
+            .map_or(false, |treasury| treasury == account_id)
+    })
+}
+
+pub(crate) fn configure_shared_balance_cache(
+    shared_balance_cache: std::sync::Arc<dyn crate::shared_cache::SharedBalanceCache>,
+) -> anyhow::Result<()> {
+    with_network(|network| {
+        network
+            .ok_or_else(|| anyhow::anyhow!("Network is not configured"))?
+            .shared_balance_cache
+            .set(shared_balance_cache)
+            .map_err(|_| anyhow::anyhow!("Shared balance cache is configured twice"))
+    })
+}
+
+pub(crate) fn shared_balance_cache(
+) -> Option<std::sync::Arc<dyn crate::shared_cache::SharedBalanceCache>> {
+    with_network(|network| network.and_then(|network| network.shared_balance_cache.get().cloned()))
+}
+
+// `balance_changes` -> `balances.mainnet_balance_changes` for `--db-schema balances --table-prefix mainnet_`.
+// Every query goes through it, the indexes and the partitions are prefixed the same way
+pub(crate) fn table(name: &str) -> String {
+    with_network(|network| match network {
+        Some(Network {
+            schema: Some(schema),
+            prefix,
+            ..
+        }) => format!("{}.{}{}", schema, prefix, name),
+        Some(Network {
+            schema: None,
+            prefix,
+            ..
+        }) => format!("{}{}", prefix, name),
+        None => name.to_string(),
+    })
+}
+
+// Only the prefixed name, for the indexes and the constraints: they are always in the schema of their table
+pub(crate) fn unqualified_table(name: &str) -> String {
+    with_network(|network| match network {
+        Some(network) => format!("{}{}", network.prefix, name),
+        None => name.to_string(),
+    })
+}
+
+pub(crate) fn db_schema() -> Option<String> {
+    with_network(|network| network.and_then(|network| network.schema.clone()))
+}
+
+pub(crate) fn configure_skip_zero_delta(skip_zero_delta: bool) {
+    SKIP_ZERO_DELTA.store(skip_zero_delta, Ordering::Relaxed);
+}
+
+// The state changes with the causes this version does not know are always logged,
+// and stored with the UNKNOWN cause only if asked
+pub(crate) fn configure_store_unknown_causes(store_unknown_causes: bool) {
+    STORE_UNKNOWN_CAUSES.store(store_unknown_causes, Ordering::Relaxed);
+}
+
+pub(crate) fn store_unknown_causes() -> bool {
+    STORE_UNKNOWN_CAUSES.load(Ordering::Relaxed)
+}
+
+fn is_skipped_zero_delta(change: &BalanceChange) -> bool {
+    SKIP_ZERO_DELTA.load(Ordering::Relaxed)
+        && change.delta_nonstaked_amount.is_zero()
+        && change.delta_staked_amount.is_zero()
+}
+
+// Rows with both deltas below the threshold are not stored.
+// The balances cache is updated anyway, so the next rows have the correct deltas
+pub(crate) fn configure_min_delta(min_delta: Option<u128>, update_current_balances: bool) {
+    if let Some(min_delta) = min_delta {
+        MIN_DELTA
+            .set(bigdecimal::BigDecimal::from(
+                bigdecimal::num_bigint::BigInt::from(min_delta),
+            ))
+            .expect("Min delta is configured twice");
+    }
+    DUST_UPDATES_CURRENT_BALANCES.store(update_current_balances, Ordering::Relaxed);
+}
+
+pub(crate) fn is_dust(change: &BalanceChange) -> bool {
+    match MIN_DELTA.get() {
+        Some(min_delta) => {
+            change.delta_nonstaked_amount.abs() < *min_delta
+                && change.delta_staked_amount.abs() < *min_delta
+        }
+        None => false,
+    }
+}
+
+pub(crate) fn dust_updates_current_balances() -> bool {
+    DUST_UPDATES_CURRENT_BALANCES.load(Ordering::Relaxed)
+}
+
+// Drops the dust and zero-delta rows. Copies only when there is something to drop
+pub(crate) fn without_dust(changes: &[BalanceChange]) -> std::borrow::Cow<'_, [BalanceChange]> {
+    let zero_deltas = changes
+        .iter()
+        .filter(|change| is_skipped_zero_delta(change))
+        .count();
+    crate::metrics::ZERO_DELTA_SUPPRESSED_TOTAL.inc_by(zero_deltas as u64);
+    if zero_deltas > 0 || changes.iter().any(is_dust) {
+        std::borrow::Cow::Owned(
+            changes
+                .iter()
+                .filter(|change| !is_dust(change) && !is_skipped_zero_delta(change))
+                .cloned()
+                .collect(),
+        )
+    } else {
+        std::borrow::Cow::Borrowed(changes)
+    }
+}
+
+// Backfill writes the blocks that are not in the DB yet, so the rows can go with COPY
+pub(crate) fn configure_bulk_load(bulk_load: bool) {
+    BULK_LOAD.store(bulk_load, Ordering::Relaxed);
+}
+
+pub(crate) fn is_bulk_load() -> bool {
+    BULK_LOAD.load(Ordering::Relaxed)
+}
+
+pub(crate) fn configure_insert_batch_size(batch_size: usize, adaptive: bool) {
+    INSERT_BATCH_SIZE.store(batch_size.max(1), Ordering::Relaxed);
+    ADAPTIVE_BATCH_SIZE.store(adaptive, Ordering::Relaxed);
+}
+
+pub(crate) fn insert_batch_size(field_count: usize) -> usize {
+    INSERT_BATCH_SIZE
+        .load(Ordering::Relaxed)
+        .min(MAX_QUERY_PARAMETERS / field_count.max(1))
+}
+
+pub(crate) fn observe_insert_latency(
+    items_count: usize,
+    field_count: usize,
+    elapsed: std::time::Duration,
+) {
+    if !ADAPTIVE_BATCH_SIZE.load(Ordering::Relaxed) {
+        return;
+    }
+    let batch_size = INSERT_BATCH_SIZE.load(Ordering::Relaxed);
+    let new_batch_size = if elapsed < FAST_INSERT && items_count == insert_batch_size(field_count) {
+        // Only full batches say something about the bigger ones
+        (batch_size * 2).min(MAX_QUERY_PARAMETERS / field_count.max(1))
+    } else if elapsed > SLOW_INSERT {
+        (batch_size / 2).max(MIN_BATCH_SIZE)
+    } else {
+        batch_size
+    };
+    if new_batch_size != batch_size {
+        INSERT_BATCH_SIZE.store(new_batch_size, Ordering::Relaxed);
+        crate::metrics::INSERT_BATCH_SIZE.set(new_batch_size as i64);
+        tracing::debug!(
+            target: crate::INDEXER,
+            "Insert batch size is changed from {} to {}, last insert took {:.3?}",
+            batch_size,
+            new_batch_size,
+            elapsed
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+
+pub(crate) const CHANNEL: &str = "balance_changes";
+// Postgres rejects the payloads of 8000 bytes and longer
+const MAX_PAYLOAD_BYTES: usize = 7900;
+
+static PG_NOTIFY: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn configure_pg_notify(pg_notify: bool) {
+    PG_NOTIFY.store(pg_notify, Ordering::Relaxed);
+}
+
+// `LISTEN balance_changes` gets `{"block_height": 1, "block_hash": "...", "accounts": [...]}`.
+// NOTIFY is sent in the same transaction as the changes, so the listeners hear only about the
+// committed blocks, and Postgres delivers them in the commit order.
+// Too long account list is replaced with `"accounts": null, "truncated": true`,
+// the listener should read the changes of the block from the database.
+// The channels are shared by the schemas of the database, --table-prefix is added to the channel too
+pub(crate) async fn notify_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+) -> anyhow::Result<()> {
+    if !PG_NOTIFY.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let accounts: BTreeSet<&str> = changes
+        .iter()
+        .map(|change| change.affected_account_id.as_str())
+        .collect();
+    let mut payload = serde_json::json!({
+        "block_height": block_header.height,
+        "block_hash": block_header.hash.to_string(),
+        "accounts": accounts,
+    })
+    .to_string();
+    if payload.len() > MAX_PAYLOAD_BYTES {
+        payload = serde_json::json!({
+            "block_height": block_header.height,
+            "block_hash": block_header.hash.to_string(),
+            "accounts": null,
+            "truncated": true,
+        })
+        .to_string();
+    }
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(crate::db_adapters::unqualified_table(CHANNEL))
+        .bind(payload)
+        .execute(transaction)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use tokio::sync::Mutex;
+
+// Declarative range partitioning of balance_changes by the ranges of `span` block heights, e.g. 10M blocks.
+// The rows have no height, so the partition key is still block_timestamp: each partition covers
+// the timestamps from the first block of its range to the first block of the next one.
+// The latest partition is open, up to MAXVALUE. When the indexer reaches the next range, the open partition
+// gets the check constraint of its final bounds (validated without blocking the readers), and it is re-attached
+// with these bounds without the scan. The new open partition starts at the timestamp of the first block.
+// The first run turns the existing table into the open partition, so it keeps everything before the first boundary.
+// The boundaries are aligned to the span, so the span cannot be changed later
+pub(crate) struct Partitions {
+    span: u64,
+    open: Mutex<OpenPartition>,
+}
+
+struct OpenPartition {
+    // Without the prefix of the network
+    name: String,
+    // None for the table which was there before the partitioning, until the first block is stored
+    range: Option<u64>,
+    // None is MINVALUE
+    from_timestamp: Option<u64>,
+}
+
+impl Partitions {
+    pub(crate) async fn setup(
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        span_blocks: u64,
+    ) -> anyhow::Result<Self> {
+        let mut transaction = pool.begin().await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} \
+            (span_blocks numeric(20, 0) NOT NULL, open_partition text NOT NULL, \
+            open_range numeric(20, 0), open_from numeric(20, 0))",
+            crate::db_adapters::table("balance_changes_partitioning")
+        ))
+        .execute(&mut transaction)
+        .await?;
+        // Concurrent starts wait here
+        sqlx::query(&format!(
+            "LOCK TABLE {}",
+            crate::db_adapters::table("balance_changes_partitioning")
+        ))
+        .execute(&mut transaction)
+        .await?;
+        let existing: Option<(i64, String, Option<i64>, Option<i64>)> = sqlx::query_as(&format!(
+            "SELECT span_blocks::bigint, open_partition, open_range::bigint, open_from::bigint FROM {}",
+            crate::db_adapters::table("balance_changes_partitioning")
+        ))
+        .fetch_optional(&mut transaction)
+        .await?;
+        if let Some((existing_span, name, range, from_timestamp)) = existing {
+            if existing_span as u64 != span_blocks {
+                anyhow::bail!(
+                    "balance_changes is partitioned by {} blocks, got --partition-blocks {}",
+                    existing_span,
+                    span_blocks
+                );
+            }
+            return Ok(Self {
+                span: span_blocks,
+                open: Mutex::new(OpenPartition {
+                    name,
+                    range: range.map(|range| range as u64),
+                    from_timestamp: from_timestamp.map(|timestamp| timestamp as u64),
+                }),
+            });
+        }
+
+        tracing::info!(
+            target: crate::INDEXER,
+            "Partitioning balance_changes by {} blocks",
+            span_blocks
+        );
+        // The new name is in the same schema
+        sqlx::query(&format!(
+            "ALTER TABLE {} RENAME TO {}",
+            crate::db_adapters::table("balance_changes"),
+            crate::db_adapters::unqualified_table("balance_changes_legacy")
+        ))
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE {} (LIKE {} INCLUDING ALL) PARTITION BY RANGE (block_timestamp)",
+            crate::db_adapters::table("balance_changes"),
+            crate::db_adapters::table("balance_changes_legacy")
+        ))
+        .execute(&mut transaction)
+        .await?;
+        // block_timestamp is NOT NULL, so Postgres does not scan the table for these bounds
+        sqlx::query(&format!(
+            "ALTER TABLE {} ATTACH PARTITION {} FOR VALUES FROM (MINVALUE) TO (MAXVALUE)",
+            crate::db_adapters::table("balance_changes"),
+            crate::db_adapters::table("balance_changes_legacy")
+        ))
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query(&format!(
+            "INSERT INTO {} VALUES ($1, $2, NULL, NULL)",
+            crate::db_adapters::table("balance_changes_partitioning")
+        ))
+        .bind(span_blocks as i64)
+        .bind("balance_changes_legacy")
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(Self {
+            span: span_blocks,
+            open: Mutex::new(OpenPartition {
+                name: "balance_changes_legacy".to_string(),
+                range: None,
+                from_timestamp: None,
+            }),
+        })
+    }
+
+    // Starts the partition of the new range at its first block
+    pub(crate) async fn ensure(
+        &self,
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        block_height: u64,
+        block_timestamp: u64,
+    ) -> anyhow::Result<()> {
+        let range = block_height / self.span;
+        let mut open = self.open.lock().await;
+        match open.range {
+            Some(open_range) if range <= open_range => return Ok(()),
+            Some(_) => {}
+            // The table before the partitioning ends with the range of the first stored block
+            None => {
+                sqlx::query(&format!(
+                    "UPDATE {} SET open_range = $1",
+                    crate::db_adapters::table("balance_changes_partitioning")
+                ))
+                .bind(range as i64)
+                .execute(pool)
+                .await?;
+                open.range = Some(range);
+                return Ok(());
+            }
+        }
+
+        let closed = crate::db_adapters::table(&open.name);
+        let constraint = format!(
+            "{}_bounds",
+            crate::db_adapters::unqualified_table(&open.name)
+        );
+        let lower_bound = match open.from_timestamp {
+            Some(from_timestamp) => from_timestamp.to_string(),
+            None => "MINVALUE".to_string(),
+        };
+        tracing::info!(
+            target: crate::INDEXER,
+            "Closing partition {} at block_height {}, block_timestamp {}",
+            open.name,
+            block_height,
+            block_timestamp
+        );
+        // Validation scans the partition, but it does not block the reads.
+        // It is repeated if the indexer stops before the partition is re-attached
+        sqlx::query(&format!(
+            "ALTER TABLE {} DROP CONSTRAINT IF EXISTS {}",
+            closed, constraint
+        ))
+        .execute(pool)
+        .await?;
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD CONSTRAINT {} CHECK ({}block_timestamp < {}) NOT VALID",
+            closed,
+            constraint,
+            match open.from_timestamp {
+                Some(from_timestamp) => format!("block_timestamp >= {} AND ", from_timestamp),
+                None => String::new(),
+            },
+            block_timestamp
+        ))
+        .execute(pool)
+        .await?;
+        sqlx::query(&format!(
+            "ALTER TABLE {} VALIDATE CONSTRAINT {}",
+            closed, constraint
+        ))
+        .execute(pool)
+        .await?;
+
+        let name = format!("balance_changes_p{}", range);
+        let mut transaction = pool.begin().await?;
+        sqlx::query(&format!(
+            "ALTER TABLE {} DETACH PARTITION {}",
+            crate::db_adapters::table("balance_changes"),
+            closed
+        ))
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query(&format!(
+            "ALTER TABLE {} ATTACH PARTITION {} FOR VALUES FROM ({}) TO ({})",
+            crate::db_adapters::table("balance_changes"),
+            closed,
+            lower_bound,
+            block_timestamp
+        ))
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE {} PARTITION OF {} FOR VALUES FROM ({}) TO (MAXVALUE)",
+            crate::db_adapters::table(&name),
+            crate::db_adapters::table("balance_changes"),
+            block_timestamp
+        ))
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query(&format!(
+            "UPDATE {} SET open_partition = $1, open_range = $2, open_from = $3",
+            crate::db_adapters::table("balance_changes_partitioning")
+        ))
+        .bind(&name)
+        .bind(range as i64)
+        .bind(block_timestamp as i64)
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+
+        *open = OpenPartition {
+            name,
+            range: Some(range),
+            from_timestamp: Some(block_timestamp),
+        };
+        Ok(())
+    }
+}
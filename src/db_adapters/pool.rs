@@ -0,0 +1,73 @@
+use std::str::FromStr;
+
+// Postgres may restart or fail over, it takes some time to become available again
+pub(crate) async fn connect(
+    opts: &crate::configs::Opts,
+    database: &str,
+) -> anyhow::Result<sqlx::PgPool> {
+    let mut connect_options = sqlx::postgres::PgConnectOptions::from_str(database)?;
+    if let Some(statement_timeout) = opts.db_statement_timeout_ms {
+        connect_options =
+            connect_options.options([("statement_timeout", statement_timeout.to_string())]);
+    }
+
+    retry_on_connection_error(opts.db_retry_count, || {
+        sqlx::postgres::PgPoolOptions::new()
+            .max_connections(opts.db_max_connections)
+            .connect_timeout(std::time::Duration::from_secs(opts.db_acquire_timeout_secs))
+            .connect_with(connect_options.clone())
+    })
+    .await
+}
+
+// Retries the whole operation while the database is unavailable. Other errors are returned at once
+pub(crate) async fn retry_on_connection_error<T, E, F, Fut>(
+    retry_count: usize,
+    mut operation: F,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+    E: Into<anyhow::Error>,
+{
+    let mut interval = crate::INTERVAL;
+    let mut retry_attempt = 0usize;
+    loop {
+        retry_attempt += 1;
+        let err = match operation().await {
+            Ok(res) => return Ok(res),
+            Err(err) => err.into(),
+        };
+        if retry_attempt >= retry_count || !is_connection_error(&err) {
+            return Err(err);
+        }
+        tracing::warn!(
+            target: crate::INDEXER,
+            "Database is unavailable: {}\n Retrying in {} milliseconds...",
+            err,
+            interval.as_millis(),
+        );
+        tokio::time::sleep(interval).await;
+        if interval < crate::MAX_DELAY_TIME {
+            interval *= 2;
+        }
+    }
+}
+
+pub(crate) fn is_connection_error(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Io(_))
+        | Some(sqlx::Error::Tls(_))
+        | Some(sqlx::Error::PoolTimedOut)
+        | Some(sqlx::Error::PoolClosed) => true,
+        // Class 08 is connection exception, 57P01-57P03 are shutdown and startup of the server
+        Some(sqlx::Error::Database(db_err)) => db_err
+            .code()
+            .map(|code| code.starts_with("08") || code.starts_with("57P"))
+            .unwrap_or(false),
+        _ => err
+            .downcast_ref::<reqwest::Error>()
+            .map(|err| err.is_connect() || err.is_timeout())
+            .unwrap_or(false),
+    }
+}
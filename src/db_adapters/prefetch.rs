@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use near_lake_framework::near_indexer_primitives;
+use tokio::sync::mpsc;
+
+type PrefetchedBalance = Shared<BoxFuture<'static, Option<crate::BalanceDetails>>>;
+
+// block_hash -> account_id -> the balance at this block being queried
+static PREFETCHED: once_cell::sync::Lazy<
+    std::sync::Mutex<
+        HashMap<
+            near_indexer_primitives::CryptoHash,
+            HashMap<near_indexer_primitives::types::AccountId, PrefetchedBalance>,
+        >,
+    >,
+> = once_cell::sync::Lazy::new(Default::default);
+
+// Archival RPC is slow for the accounts which have not changed for a long time, and the blocks
+// are computed one by one. So the blocks go through this stage up to `blocks_ahead` blocks
+// before computing, and the previous balances of the accounts missing in the cache are queried
+// in background. The balance is taken by block hash, so it is correct
+// even if the previous block changes the account meanwhile
+pub(crate) fn start(
+    mut stream: mpsc::Receiver<near_indexer_primitives::StreamerMessage>,
+    blocks_ahead: usize,
+    balances_cache: crate::BalanceCache,
+    json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+) -> mpsc::Receiver<near_indexer_primitives::StreamerMessage> {
+    let (sender, receiver) = mpsc::channel(blocks_ahead);
+    tokio::spawn(async move {
+        while let Some(mut streamer_message) = stream.recv().await {
+            // The accounts of the other shards are not computed here
+            crate::db_adapters::shard_assignment::retain_assigned(&mut streamer_message.shards);
+            prefetch_block(&streamer_message, &balances_cache, &json_rpc_client).await;
+            if sender.send(streamer_message).await.is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+async fn prefetch_block(
+    streamer_message: &near_indexer_primitives::StreamerMessage,
+    balances_cache: &crate::BalanceCache,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) {
+    let block_hash = streamer_message.block.header.prev_hash;
+    let mut cold_account_ids = vec![];
+    {
+        let mut balances_cache_lock = balances_cache.lock().await;
+        for account_id in
+            crate::db_adapters::account_batch::changed_account_ids(&streamer_message.shards)
+        {
+            // The account missing now may be created before the block, computing checks it again
+            if balances_cache_lock.cache_get(account_id).is_none()
+                && !balances_cache_lock.is_missing(account_id)
+            {
+                cold_account_ids.push(account_id.clone());
+            }
+        }
+    }
+    if cold_account_ids.is_empty() {
+        return;
+    }
+
+    let mut prefetched = PREFETCHED.lock().unwrap_or_else(|err| err.into_inner());
+    let block_prefetched = prefetched.entry(block_hash).or_default();
+    for account_id in cold_account_ids {
+        let json_rpc_client = json_rpc_client.clone();
+        let query_account_id = account_id.clone();
+        let balance = async move {
+            match crate::db_adapters::balance_changes::get_account_view(
+                &json_rpc_client,
+                &query_account_id,
+                &block_hash,
+            )
+            .await
+            {
+                Ok(account_view) => Some(crate::BalanceDetails {
+                    non_staked: account_view.amount,
+                    staked: account_view.locked,
+                }),
+                Err(err) => match err.handler_error() {
+                    Some(RpcQueryError::UnknownAccount { .. }) => {
+                        Some(crate::BalanceDetails::default())
+                    }
+                    // Computing queries it again with the retries
+                    _ => None,
+                },
+            }
+        }
+        .boxed()
+        .shared();
+        // Runs even if nobody waits for it yet
+        tokio::spawn(balance.clone());
+        block_prefetched.insert(account_id, balance);
+    }
+}
+
+// None if the balance was not prefetched or the query has failed
+pub(crate) async fn take(
+    account_id: &near_indexer_primitives::types::AccountId,
+    block_hash: &near_indexer_primitives::CryptoHash,
+) -> Option<crate::BalanceDetails> {
+    let balance = PREFETCHED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_mut(block_hash)?
+        .remove(account_id)?;
+    let balance = balance.await;
+    if balance.is_some() {
+        crate::metrics::PREFETCHED_BALANCES_TOTAL.inc();
+    }
+    balance
+}
+
+// The block is computed, the rest was cached by the previous blocks
+pub(crate) fn forget(block_hash: &near_indexer_primitives::CryptoHash) {
+    PREFETCHED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .remove(block_hash);
+}
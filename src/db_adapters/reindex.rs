@@ -0,0 +1,129 @@
+use futures::{StreamExt, TryStreamExt};
+use near_lake_framework::near_indexer_primitives;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::models::balance_changes::BalanceChange;
+
+// Balance changes depend on the previous balances, so the blocks are computed strictly one by one.
+// Writing to the DB does not depend on anything, so up to `parallelism` blocks are written at once
+pub(crate) async fn reindex_range(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    mut stream: mpsc::Receiver<near_indexer_primitives::StreamerMessage>,
+    from_block_height: u64,
+    to_block_height: u64,
+    parallelism: usize,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<()> {
+    let balances_cache: crate::BalanceCache =
+        std::sync::Arc::new(Mutex::new(crate::cache::JournaledCache::with_size(100_000)));
+    let postponed_receipts: crate::PostponedReceipts =
+        std::sync::Arc::new(Mutex::new(crate::cache::JournaledCache::with_size(100_000)));
+    let receipt_transactions = crate::db_adapters::balance_changes::new_receipt_transactions();
+    let (computed_sender, computed_receiver) = mpsc::channel::<(
+        Vec<near_indexer_primitives::IndexerShard>,
+        near_indexer_primitives::views::BlockHeaderView,
+        Vec<BalanceChange>,
+    )>(parallelism);
+
+    let mut progress = Progress::new(from_block_height, to_block_height);
+    let compute = async move {
+        while let Some(streamer_message) = stream.recv().await {
+            let block_header = streamer_message.block.header;
+            if block_header.height > to_block_height {
+                break;
+            }
+            let changes = crate::db_adapters::balance_changes::collect_balance_changes(
+                &streamer_message.shards,
+                &block_header,
+                &balances_cache,
+                &postponed_receipts,
+                &receipt_transactions,
+                json_rpc_client,
+            )
+            .await?;
+            if computed_sender
+                .send((streamer_message.shards, block_header, changes))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        anyhow::Ok(())
+    };
+
+    let write = tokio_stream::wrappers::ReceiverStream::new(computed_receiver)
+        .map(|(shards, block_header, changes)| async move {
+            crate::db_adapters::repair::replace_block_changes(
+                pool,
+                &shards,
+                &block_header,
+                None,
+                &changes,
+            )
+            .await?;
+            anyhow::Ok(block_header.height)
+        })
+        .buffered(parallelism)
+        .try_for_each(|block_height| {
+            progress.update(block_height);
+            futures::future::ready(Ok(()))
+        });
+
+    tokio::try_join!(compute, write)?;
+    tracing::info!(
+        target: crate::INDEXER,
+        "Reindexed blocks {}..={} in {:.0?}",
+        from_block_height,
+        to_block_height,
+        progress.started_at.elapsed()
+    );
+    Ok(())
+}
+
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+struct Progress {
+    from_block_height: u64,
+    to_block_height: u64,
+    started_at: std::time::Instant,
+    reported_at: std::time::Instant,
+}
+
+impl Progress {
+    fn new(from_block_height: u64, to_block_height: u64) -> Self {
+        let now = std::time::Instant::now();
+        Self {
+            from_block_height,
+            to_block_height,
+            started_at: now,
+            reported_at: now,
+        }
+    }
+
+    // Prints `[=====>      ] 1500/6000 blocks, 25.0 blocks/s, ETA 180s` not more often than PROGRESS_INTERVAL
+    fn update(&mut self, block_height: u64) {
+        if self.reported_at.elapsed() < PROGRESS_INTERVAL && block_height < self.to_block_height {
+            return;
+        }
+        self.reported_at = std::time::Instant::now();
+
+        let total = self.to_block_height - self.from_block_height + 1;
+        let done = block_height - self.from_block_height + 1;
+        let speed = done as f64 / self.started_at.elapsed().as_secs_f64().max(f64::EPSILON);
+        let eta = std::time::Duration::from_secs_f64((total - done) as f64 / speed);
+        let filled = PROGRESS_BAR_WIDTH * done as usize / total as usize;
+
+        tracing::info!(
+            target: crate::INDEXER,
+            "[{}>{}] {}/{} blocks, {:.1} blocks/s, ETA {:.0?}",
+            "=".repeat(filled),
+            " ".repeat(PROGRESS_BAR_WIDTH - filled),
+            done,
+            total,
+            speed,
+            eta
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives;
+use tokio::sync::Mutex;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::models::PrintEnum;
+
+// Recomputes the block from scratch: the cache is empty, so all the previous balances come from RPC.
+// Old rows are replaced in one transaction, so the readers never see the half-repaired block.
+// See `replace_block_changes` for what is recomputed together with the rows
+pub(crate) async fn repair_block(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    streamer_message: &near_indexer_primitives::StreamerMessage,
+    account_id: Option<&str>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<()> {
+    let block_header = &streamer_message.block.header;
+    let balances_cache: crate::BalanceCache =
+        std::sync::Arc::new(Mutex::new(crate::cache::JournaledCache::with_size(100_000)));
+    let postponed_receipts: crate::PostponedReceipts =
+        std::sync::Arc::new(Mutex::new(crate::cache::JournaledCache::with_size(100_000)));
+    let receipt_transactions = crate::db_adapters::balance_changes::new_receipt_transactions();
+
+    let changes = crate::db_adapters::balance_changes::collect_balance_changes(
+        &streamer_message.shards,
+        block_header,
+        &balances_cache,
+        &postponed_receipts,
+        &receipt_transactions,
+        json_rpc_client,
+    )
+    .await?;
+
+    let (deleted, stored) = replace_block_changes(
+        pool,
+        &streamer_message.shards,
+        block_header,
+        account_id,
+        &changes,
+    )
+    .await?;
+    tracing::info!(
+        target: crate::INDEXER,
+        "Repaired block_height {}: {} rows deleted, {} rows stored",
+        block_header.height,
+        deleted,
+        stored
+    );
+    Ok(())
+}
+
+// Takes all the recomputed rows of the block, returns the numbers of the deleted and the stored rows.
+// With account_id, only its rows are replaced. The rows of the other accounts stay, so they should be
+// where the recomputed block puts them: otherwise the block was indexed differently
+// (e.g. with another --only-accounts), the indices of the account's rows would not fit, and
+// the whole block has to be repaired.
+// The rows are copied, not inserted with ON CONFLICT DO NOTHING: a conflict fails the repair
+// instead of skipping the row.
+// current_balances are recomputed in the same transaction. With the whole block, also the block summary,
+// the epoch validator rewards and the supply. The rest is left as it was written by the indexer, see `RepairArgs`
+pub(crate) async fn replace_block_changes(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    account_id: Option<&str>,
+    changes: &[BalanceChange],
+) -> anyhow::Result<(u64, usize)> {
+    let block_timestamp: BigDecimal = block_header.timestamp.into();
+    let mut transaction = pool.begin().await?;
+    let (deleted, changes) = match account_id {
+        Some(account_id) => {
+            let stored: Vec<(String, String, i32, i32, i32)> = sqlx::query_as(&format!(
+                "SELECT event_id, affected_account_id, shard_id, index_in_chunk, index_in_block FROM {} \
+                WHERE block_timestamp = $1::bigint AND affected_account_id <> $2 AND cause <> $3",
+                crate::db_adapters::table("balance_changes")
+            ))
+            .bind(&block_timestamp)
+            .bind(account_id)
+            // They are not computed from the block, see src/db_adapters/drift.rs
+            .bind(crate::models::Cause::DriftCorrection.print())
+            .fetch_all(&mut transaction)
+            .await?;
+            let recomputed: HashMap<&str, (i32, i32, i32)> = changes
+                .iter()
+                .map(|change| {
+                    (
+                        change.event_id.as_str(),
+                        (
+                            change.shard_id,
+                            change.index_in_chunk,
+                            change.index_in_block,
+                        ),
+                    )
+                })
+                .collect();
+            if let Some((_, other_account_id, ..)) =
+                stored
+                    .iter()
+                    .find(|(event_id, _, shard_id, index_in_chunk, index_in_block)| {
+                        recomputed.get(event_id.as_str())
+                            != Some(&(*shard_id, *index_in_chunk, *index_in_block))
+                    })
+            {
+                anyhow::bail!(
+                    "The stored rows of {} in block_height {} differ from the recomputed ones, \
+                    repair the whole block without --account",
+                    other_account_id,
+                    block_header.height
+                );
+            }
+            let deleted = sqlx::query(&format!(
+                "DELETE FROM {} WHERE block_timestamp = $1::bigint AND affected_account_id = $2",
+                crate::db_adapters::table("balance_changes")
+            ))
+            .bind(&block_timestamp)
+            .bind(account_id)
+            .execute(&mut transaction)
+            .await?;
+            let changes: Vec<BalanceChange> = changes
+                .iter()
+                .filter(|change| change.affected_account_id == account_id)
+                .cloned()
+                .collect();
+            (deleted, std::borrow::Cow::Owned(changes))
+        }
+        None => {
+            let deleted = sqlx::query(&format!(
+                "DELETE FROM {} WHERE block_timestamp = $1::bigint",
+                crate::db_adapters::table("balance_changes")
+            ))
+            .bind(&block_timestamp)
+            .execute(&mut transaction)
+            .await?;
+            (deleted, std::borrow::Cow::Borrowed(changes))
+        }
+    };
+    let changes: &[BalanceChange] = &changes;
+    crate::models::copy_in_transaction(&mut transaction, changes).await?;
+
+    let block_timestamp = block_header.timestamp as i64;
+    crate::db_adapters::rollback::restore_current_balances(&mut transaction, block_timestamp)
+        .await?;
+    // The repaired block does not change the account anymore, and it has no other rows
+    sqlx::query(&format!(
+        "DELETE FROM {} AS current_balances WHERE block_timestamp = $1 AND NOT EXISTS \
+            (SELECT 1 FROM {} WHERE affected_account_id = current_balances.account_id)",
+        crate::db_adapters::table("current_balances"),
+        crate::db_adapters::table("balance_changes")
+    ))
+    .bind(block_timestamp)
+    .execute(&mut transaction)
+    .await?;
+    crate::db_adapters::balance_changes::store_current_balances(
+        &mut transaction,
+        changes,
+        block_header,
+    )
+    .await?;
+
+    // The rows of one account are not enough to count the block totals
+    if account_id.is_none() {
+        for table in [
+            "block_balance_summary",
+            "epoch_validator_rewards",
+            "supply_history",
+        ] {
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE block_height = $1",
+                crate::db_adapters::table(table)
+            ))
+            .bind(block_header.height as i64)
+            .execute(&mut transaction)
+            .await?;
+        }
+        crate::models::insert_in_transaction(
+            &mut transaction,
+            &[crate::db_adapters::balance_changes::block_summary(
+                shards,
+                block_header,
+                changes,
+            )],
+        )
+        .await?;
+        crate::models::insert_in_transaction(
+            &mut transaction,
+            &crate::db_adapters::balance_changes::epoch_validator_rewards(block_header, changes),
+        )
+        .await?;
+        crate::db_adapters::supply::store_supply(&mut transaction, shards, block_header, changes)
+            .await?;
+    }
+    transaction.commit().await?;
+
+    Ok((deleted.rows_affected(), changes.len()))
+}
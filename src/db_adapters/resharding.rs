@@ -0,0 +1,79 @@
+use near_lake_framework::near_indexer_primitives::{self, views::StateChangeCauseView};
+
+use crate::models::shard_mapping::ShardMapping;
+
+pub(crate) fn is_resharding_block(shards: &[near_indexer_primitives::IndexerShard]) -> bool {
+    shards.iter().any(|shard| {
+        shard
+            .state_changes
+            .iter()
+            .any(|change| matches!(change.cause, StateChangeCauseView::Resharding))
+    })
+}
+
+// Resharding state changes just move the accounts between shards, so balances stay the same.
+// We save which old shard each new shard was split from, otherwise `shard_id` in `balance_changes`
+// is not comparable before and after the resharding
+pub(crate) async fn store_shard_mapping(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<()> {
+    let genesis_config = json_rpc_client
+        .call(near_jsonrpc_client::methods::EXPERIMENTAL_genesis_config::RpcGenesisConfigRequest)
+        .await
+        .map_err(|err| anyhow::anyhow!("Failed to get genesis config from RPC: {}", err))?;
+
+    // The layouts are known from genesis config; the one we switched to has the same number of shards
+    let simple_nightshade_shard_layout =
+        near_primitives::shard_layout::ShardLayout::get_simple_nightshade_layout();
+    let shard_layout = match [
+        &genesis_config.shard_layout,
+        &simple_nightshade_shard_layout,
+    ]
+    .into_iter()
+    .find(|layout| layout.num_shards() == shards.len() as u64)
+    {
+        Some(layout) => layout,
+        None => {
+            tracing::warn!(
+                target: crate::INDEXER,
+                "Resharding at block_height {}: no known shard layout with {} shards, shard mapping is not stored",
+                block_header.height,
+                shards.len()
+            );
+            return Ok(());
+        }
+    };
+
+    let mut mapping: Vec<ShardMapping> = vec![];
+    let mut parent_shard_id = 0;
+    while let Some(split_shard_ids) = shard_layout.get_split_shard_ids(parent_shard_id) {
+        for shard_id in split_shard_ids {
+            mapping.push(ShardMapping {
+                block_height: block_header.height.into(),
+                block_timestamp: block_header.timestamp.into(),
+                shard_layout_version: shard_layout.version() as i32,
+                shard_id: shard_id as i32,
+                parent_shard_id: parent_shard_id as i32,
+            });
+        }
+        parent_shard_id += 1;
+    }
+
+    tracing::info!(
+        target: crate::INDEXER,
+        "Resharding at block_height {}, shard layout version {}: {:?}",
+        block_header.height,
+        shard_layout.version(),
+        mapping
+            .iter()
+            .map(|item| (item.parent_shard_id, item.shard_id))
+            .collect::<Vec<_>>()
+    );
+    if mapping.is_empty() {
+        return Ok(());
+    }
+    crate::models::chunked_insert(pool, &mapping, 10).await
+}
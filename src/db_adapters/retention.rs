@@ -0,0 +1,168 @@
+const NANOS_IN_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+const DELETE_BATCH_SIZE: i64 = 10_000;
+
+// How much of balance_changes history to keep, counted back from the latest stored block.
+// If both are given, the longer history is kept.
+// current_balances and the per-block tables are not pruned, so the latest balances stay available
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Retention {
+    pub days: Option<u64>,
+    pub blocks: Option<u64>,
+}
+
+// Prunes in background. Partitions and Timescale chunks are dropped as a whole once they are
+// entirely older than the cutoff, the plain table is deleted from in small batches
+pub(crate) fn spawn_pruner(
+    retention: Retention,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    timescale: bool,
+) {
+    crate::db_adapters::spawn(async move {
+        loop {
+            match prune(&retention, &pool, timescale).await {
+                Ok(0) => {}
+                Ok(deleted) => tracing::info!(
+                    target: crate::INDEXER,
+                    "Pruned {} old balance changes",
+                    deleted
+                ),
+                Err(err) => tracing::warn!(
+                    target: crate::INDEXER,
+                    "Failed to prune old balance changes: {:#}",
+                    err
+                ),
+            }
+            tokio::time::sleep(INTERVAL).await;
+        }
+    });
+}
+
+// Returns the number of deleted rows, or the dropped partitions and chunks
+async fn prune(
+    retention: &Retention,
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    timescale: bool,
+) -> anyhow::Result<u64> {
+    let cutoff = match cutoff(retention, pool).await? {
+        Some(cutoff) => cutoff,
+        None => return Ok(0),
+    };
+    if timescale {
+        let dropped: Vec<(String,)> =
+            sqlx::query_as("SELECT drop_chunks($1::regclass, older_than => $2)::text")
+                .bind(crate::db_adapters::table("balance_changes"))
+                .bind(cutoff as i64)
+                .fetch_all(pool)
+                .await?;
+        return Ok(dropped.len() as u64);
+    }
+    if let Some(dropped) = drop_partitions(pool, cutoff).await? {
+        return Ok(dropped);
+    }
+
+    let mut deleted = 0;
+    loop {
+        let result = sqlx::query(&format!(
+            "DELETE FROM {} WHERE ctid IN \
+            (SELECT ctid FROM {} WHERE block_timestamp < $1::bigint LIMIT $2)",
+            crate::db_adapters::table("balance_changes"),
+            crate::db_adapters::table("balance_changes")
+        ))
+        .bind(bigdecimal::BigDecimal::from(cutoff))
+        .bind(DELETE_BATCH_SIZE)
+        .execute(pool)
+        .await?;
+        deleted += result.rows_affected();
+        if result.rows_affected() < DELETE_BATCH_SIZE as u64 {
+            return Ok(deleted);
+        }
+    }
+}
+
+// Rows older than the cutoff block_timestamp are pruned. None while there is nothing to prune
+async fn cutoff(
+    retention: &Retention,
+    pool: &sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<Option<u64>> {
+    let latest: Option<(i64, i64)> = sqlx::query_as(&format!(
+        "SELECT block_height::bigint, block_timestamp::bigint FROM {} \
+        ORDER BY block_height DESC LIMIT 1",
+        crate::db_adapters::table("block_balance_summary")
+    ))
+    .fetch_optional(pool)
+    .await?;
+    let (latest_height, latest_timestamp) = match latest {
+        Some((height, timestamp)) => (height as u64, timestamp as u64),
+        None => return Ok(None),
+    };
+
+    let by_days = retention
+        .days
+        .map(|days| latest_timestamp.saturating_sub(days * NANOS_IN_DAY));
+    let by_blocks = match retention.blocks {
+        Some(blocks) => {
+            let row: Option<(i64,)> = sqlx::query_as(&format!(
+                "SELECT block_timestamp::bigint FROM {} \
+                WHERE block_height >= $1 ORDER BY block_height LIMIT 1",
+                crate::db_adapters::table("block_balance_summary")
+            ))
+            .bind(bigdecimal::BigDecimal::from(
+                latest_height.saturating_sub(blocks),
+            ))
+            .fetch_optional(pool)
+            .await?;
+            row.map(|(timestamp,)| timestamp as u64)
+        }
+        None => None,
+    };
+    Ok(match (by_days, by_blocks) {
+        (Some(by_days), Some(by_blocks)) => Some(by_days.min(by_blocks)),
+        (cutoff, None) | (None, cutoff) => cutoff,
+    })
+}
+
+// None if the table is not partitioned, see src/db_adapters/partitions.rs
+async fn drop_partitions(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    cutoff: u64,
+) -> anyhow::Result<Option<u64>> {
+    let (is_partitioned,): (bool,) = sqlx::query_as("SELECT to_regclass($1) IS NOT NULL")
+        .bind(crate::db_adapters::table("balance_changes_partitioning"))
+        .fetch_one(pool)
+        .await?;
+    if !is_partitioned {
+        return Ok(None);
+    }
+    // The bounds of the partitions depend on the timestamps of the blocks, they are only in the catalog
+    let partitions: Vec<(String, String)> = sqlx::query_as(
+        "SELECT child.oid::regclass::text, pg_get_expr(child.relpartbound, child.oid) FROM pg_inherits \
+        JOIN pg_class child ON child.oid = pg_inherits.inhrelid \
+        WHERE pg_inherits.inhparent = $1::regclass",
+    )
+    .bind(crate::db_adapters::table("balance_changes"))
+    .fetch_all(pool)
+    .await?;
+
+    let mut dropped = 0;
+    for (qualified_name, bound) in partitions {
+        // FOR VALUES FROM (...) TO ('1654041600000000000'), the open partition ends with MAXVALUE
+        let until = match bound.rsplit_once("TO (").and_then(|(_, until)| {
+            until
+                .trim_end_matches(')')
+                .trim_matches('\'')
+                .parse::<u64>()
+                .ok()
+        }) {
+            Some(until) => until,
+            None => continue,
+        };
+        if until <= cutoff {
+            sqlx::query(&format!("DROP TABLE {}", qualified_name))
+                .execute(pool)
+                .await?;
+            dropped += 1;
+        }
+    }
+    Ok(Some(dropped))
+}
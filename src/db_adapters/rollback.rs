@@ -0,0 +1,109 @@
+use crate::forks::TrackedBlock;
+
+// Deletes everything stored for the blocks of the discarded fork, in one transaction.
+// current_balances of the affected accounts go back to their latest remaining rows,
+// the sketch of daily_active_accounts is rebuilt from the rows left in its day.
+// The sinks have published the discarded blocks already, they are not retracted, see src/sinks/mod.rs
+pub(crate) async fn rollback_blocks(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    blocks: &[TrackedBlock],
+) -> anyhow::Result<()> {
+    let mut transaction = pool.begin().await?;
+    for block in blocks {
+        let block_height = block.height as i64;
+        let block_timestamp = block.timestamp as i64;
+        let deleted = sqlx::query(&format!(
+            "DELETE FROM {} WHERE block_timestamp = $1::bigint",
+            crate::db_adapters::table("balance_changes")
+        ))
+        .bind(block_timestamp)
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+        for table in [
+            "block_balance_summary",
+            "epoch_validator_rewards",
+            "supply_history",
+            "balance_imbalances",
+            "fee_details",
+            "anomalies",
+            "shard_mapping",
+            "quarantined_ft_event_values",
+        ] {
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE block_height = $1 AND block_timestamp = $2",
+                crate::db_adapters::table(table)
+            ))
+            .bind(block_height)
+            .bind(block_timestamp)
+            .execute(&mut transaction)
+            .await?;
+        }
+        for table in ["ft_balance_changes", "wrap_near_events", "lockup_balances"] {
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE block_timestamp = $1",
+                crate::db_adapters::table(table)
+            ))
+            .bind(block_timestamp)
+            .execute(&mut transaction)
+            .await?;
+        }
+        crate::db_adapters::active_accounts::rebuild_in_transaction(
+            &mut transaction,
+            block.timestamp,
+        )
+        .await?;
+        restore_current_balances(&mut transaction, block_timestamp).await?;
+        // The account appeared in the discarded fork
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE block_timestamp = $1",
+            crate::db_adapters::table("current_balances")
+        ))
+        .bind(block_timestamp)
+        .execute(&mut transaction)
+        .await?;
+        crate::db_adapters::explorer_compat::rollback_in_transaction(&mut transaction, block)
+            .await?;
+        crate::db_adapters::finality::rollback_in_transaction(&mut transaction, block).await?;
+        crate::db_adapters::stream_offsets::rollback_in_transaction(&mut transaction, block)
+            .await?;
+        tracing::warn!(
+            target: crate::INDEXER,
+            "Rolled back block {} at block_height {}, {} balance changes deleted",
+            block.hash,
+            block.height,
+            deleted
+        );
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+// current_balances of the accounts last changed in the block go back to their latest rows in balance_changes
+pub(crate) async fn restore_current_balances(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block_timestamp: i64,
+) -> anyhow::Result<()> {
+    sqlx::query(&format!(
+        "UPDATE {} AS current_balances SET block_timestamp = latest.block_timestamp, \
+            nonstaked_amount = latest.absolute_nonstaked_amount, \
+            staked_amount = latest.absolute_staked_amount \
+        FROM ( \
+            SELECT DISTINCT ON (affected_account_id) affected_account_id, block_timestamp, \
+                absolute_nonstaked_amount, absolute_staked_amount \
+            FROM {} \
+            WHERE affected_account_id IN \
+                (SELECT account_id FROM {} WHERE block_timestamp = $1) \
+            ORDER BY affected_account_id, block_timestamp DESC, index_in_block DESC \
+        ) latest \
+        WHERE current_balances.account_id = latest.affected_account_id \
+            AND current_balances.block_timestamp = $1",
+        crate::db_adapters::table("current_balances"),
+        crate::db_adapters::table("balance_changes"),
+        crate::db_adapters::table("current_balances")
+    ))
+    .bind(block_timestamp)
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,314 @@
+use sqlx::Executor;
+
+// All the migrations of migrations/, by the version in their names. The schema version is the version
+// of the latest applied one, it is kept in the meta table since 20221015120000_meta.sql.
+// A new migration should be added here and set schema_version in its last statement.
+// The migrations are written without --db-schema and --table-prefix, see with_table_names
+const MIGRATIONS: [(u64, &str); 30] = [
+    (
+        20220221161526,
+        include_str!("../../migrations/20220221161526_initial.sql"),
+    ),
+    (
+        20220610120000,
+        include_str!("../../migrations/20220610120000_current_balances.sql"),
+    ),
+    (
+        20220615120000,
+        include_str!("../../migrations/20220615120000_shard_mapping.sql"),
+    ),
+    (
+        20220620120000,
+        include_str!("../../migrations/20220620120000_index_in_block.sql"),
+    ),
+    (
+        20220625120000,
+        include_str!("../../migrations/20220625120000_balance_imbalances.sql"),
+    ),
+    (
+        20220705120000,
+        include_str!("../../migrations/20220705120000_ft_balance_changes.sql"),
+    ),
+    (
+        20220710120000,
+        include_str!("../../migrations/20220710120000_wrap_near_events.sql"),
+    ),
+    (
+        20220715120000,
+        include_str!("../../migrations/20220715120000_lockup_balances.sql"),
+    ),
+    (
+        20220720120000,
+        include_str!("../../migrations/20220720120000_block_balance_summary.sql"),
+    ),
+    (
+        20220725120000,
+        include_str!("../../migrations/20220725120000_epoch_validator_rewards.sql"),
+    ),
+    (
+        20220730120000,
+        include_str!("../../migrations/20220730120000_treasury_reward.sql"),
+    ),
+    (
+        20220801120000,
+        include_str!("../../migrations/20220801120000_supply_history.sql"),
+    ),
+    (
+        20220805120000,
+        include_str!("../../migrations/20220805120000_top_accounts.sql"),
+    ),
+    (
+        20220810120000,
+        include_str!("../../migrations/20220810120000_daily_active_accounts.sql"),
+    ),
+    (
+        20220815120000,
+        include_str!("../../migrations/20220815120000_anomalies.sql"),
+    ),
+    (
+        20220820120000,
+        include_str!("../../migrations/20220820120000_balance_changes_account_order.sql"),
+    ),
+    (
+        20220825120000,
+        include_str!("../../migrations/20220825120000_fiat_value.sql"),
+    ),
+    (
+        20220830120000,
+        include_str!("../../migrations/20220830120000_explorer_account_changes.sql"),
+    ),
+    (
+        20220905120000,
+        include_str!("../../migrations/20220905120000_shard_offsets.sql"),
+    ),
+    (
+        20220910120000,
+        include_str!("../../migrations/20220910120000_finality.sql"),
+    ),
+    (
+        20220915120000,
+        include_str!("../../migrations/20220915120000_quarantined_ft_event_values.sql"),
+    ),
+    (
+        20220920120000,
+        include_str!("../../migrations/20220920120000_gas_burnt.sql"),
+    ),
+    (
+        20220925120000,
+        include_str!("../../migrations/20220925120000_fee_details.sql"),
+    ),
+    (
+        20220930120000,
+        include_str!("../../migrations/20220930120000_receipt_accounts.sql"),
+    ),
+    (
+        20221005120000,
+        include_str!("../../migrations/20221005120000_event_id.sql"),
+    ),
+    (
+        20221010120000,
+        include_str!("../../migrations/20221010120000_stream_offsets.sql"),
+    ),
+    (
+        20221015120000,
+        include_str!("../../migrations/20221015120000_meta.sql"),
+    ),
+    (
+        20221020120000,
+        include_str!("../../migrations/20221020120000_parent_transaction_hash.sql"),
+    ),
+    (
+        20221025120000,
+        include_str!("../../migrations/20221025120000_balance_changes_transaction.sql"),
+    ),
+    (
+        20221030120000,
+        include_str!("../../migrations/20221030120000_index_in_receipt.sql"),
+    ),
+];
+
+// The version the binary is written for
+pub(crate) fn binary_version() -> u64 {
+    MIGRATIONS[MIGRATIONS.len() - 1].0
+}
+
+// None if the database has no meta table: it is empty or its migrations were applied by hand
+async fn stored_version(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<Option<u64>> {
+    if !table_exists(pool, "meta").await? {
+        return Ok(None);
+    }
+    let version: Option<(String,)> = sqlx::query_as(&format!(
+        "SELECT value FROM {} WHERE key = 'schema_version'",
+        crate::db_adapters::table("meta")
+    ))
+    .fetch_optional(pool)
+    .await?;
+    match version {
+        Some((version,)) => Ok(Some(version.parse().map_err(|err| {
+            anyhow::anyhow!("Invalid schema_version `{}` in meta: {}", version, err)
+        })?)),
+        None => Ok(None),
+    }
+}
+
+// Refuses to run against the schema of another version, the mismatched columns would fail
+// the inserts in the middle of the indexing or, worse, leave the new columns empty
+pub(crate) async fn check(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    let binary_version = binary_version();
+    match stored_version(pool).await? {
+        Some(version) if version == binary_version => Ok(()),
+        Some(version) if version < binary_version => anyhow::bail!(
+            "The database schema version is {}, this binary needs {}. \
+            Stop the running indexers, back up the database and run `upgrade-schema` to apply the migrations",
+            version,
+            binary_version
+        ),
+        Some(version) => anyhow::bail!(
+            "The database schema version is {}, this binary knows only {}. \
+            Upgrade the binary to the one the schema was migrated with",
+            version,
+            binary_version
+        ),
+        None => anyhow::bail!(
+            "The database has no schema version. Run `upgrade-schema` to create the tables, \
+            or `upgrade-schema --assume-version <version of the last applied migration>` \
+            if the migrations were applied by hand"
+        ),
+    }
+}
+
+// Applies the migrations after the stored version, each in its own transaction with the new version,
+// so the interrupted upgrade continues from the failed migration. The migrations backfill their columns
+pub(crate) async fn upgrade(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    assume_version: Option<u64>,
+) -> anyhow::Result<()> {
+    let stored_version = stored_version(pool).await?;
+    let from_version = match (stored_version, assume_version) {
+        (Some(version), None) => version,
+        (Some(version), Some(_)) => anyhow::bail!(
+            "The database schema version is {}, --assume-version is only for the databases without it",
+            version
+        ),
+        (None, Some(version)) => {
+            if !MIGRATIONS.iter().any(|(known, _)| *known == version) {
+                anyhow::bail!("Unknown migration version {}, see migrations/", version);
+            }
+            version
+        }
+        (None, None) => {
+            if table_exists(pool, "balance_changes").await? {
+                anyhow::bail!(
+                    "The tables exist but have no schema version, \
+                    run with --assume-version <version of the last migration applied by hand>"
+                );
+            }
+            0
+        }
+    };
+    if from_version > binary_version() {
+        anyhow::bail!(
+            "The database schema version is {}, this binary knows only {}",
+            from_version,
+            binary_version()
+        );
+    }
+
+    if let Some(schema) = crate::db_adapters::db_schema() {
+        pool.execute(format!("CREATE SCHEMA IF NOT EXISTS {}", schema).as_str())
+            .await?;
+    }
+    let pending: Vec<&(u64, &str)> = MIGRATIONS
+        .iter()
+        .filter(|(version, _)| *version > from_version)
+        .collect();
+    if pending.is_empty() {
+        tracing::info!(
+            target: crate::INDEXER,
+            "The database schema is up to date, version {}",
+            from_version
+        );
+        return Ok(());
+    }
+    for (version, migration) in pending {
+        let started_at = std::time::Instant::now();
+        let mut transaction = pool.begin().await?;
+        // The unqualified names of the migrations go to the schema, the extensions stay visible
+        if let Some(schema) = crate::db_adapters::db_schema() {
+            transaction
+                .execute(format!("SET LOCAL search_path TO {}, public", schema).as_str())
+                .await?;
+        }
+        transaction
+            .execute(with_table_names(migration).as_str())
+            .await
+            .map_err(|err| anyhow::anyhow!("Migration {} failed: {}", version, err))?;
+        // The migrations before the meta table do not set the version themselves
+        if table_exists(&mut transaction, "meta").await? {
+            sqlx::query(&format!(
+                "INSERT INTO {} (key, value) VALUES ('schema_version', $1) \
+                ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value",
+                crate::db_adapters::table("meta")
+            ))
+            .bind(version.to_string())
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        tracing::info!(
+            target: crate::INDEXER,
+            "Migration {} is applied in {:.0?}",
+            version,
+            started_at.elapsed()
+        );
+    }
+    tracing::info!(
+        target: crate::INDEXER,
+        "The database schema is upgraded from {} to {}",
+        from_version,
+        binary_version()
+    );
+    Ok(())
+}
+
+// The names of the tables and of their indexes get --table-prefix: the indexes share the namespace
+// of the schema, they start with the name of their table
+fn with_table_names(migration: &str) -> String {
+    static TABLES: once_cell::sync::Lazy<Vec<String>> = once_cell::sync::Lazy::new(|| {
+        let create_table =
+            regex::Regex::new(r"(?i)CREATE TABLE (?:IF NOT EXISTS )?([a-z_]+)").unwrap();
+        MIGRATIONS
+            .iter()
+            .flat_map(|(_, migration)| create_table.captures_iter(migration))
+            .map(|captures| captures[1].to_string())
+            .collect()
+    });
+    static IDENTIFIER: once_cell::sync::Lazy<regex::Regex> =
+        once_cell::sync::Lazy::new(|| regex::Regex::new(r"\b[a-z_][a-z0-9_]*\b").unwrap());
+    IDENTIFIER
+        .replace_all(migration, |captures: &regex::Captures| {
+            let identifier = &captures[0];
+            let is_table_name = TABLES.iter().any(|table| {
+                identifier == table
+                    || identifier
+                        .strip_prefix(table.as_str())
+                        .map_or(false, |rest| rest.starts_with('_'))
+            });
+            match is_table_name {
+                true => crate::db_adapters::unqualified_table(identifier),
+                false => identifier.to_string(),
+            }
+        })
+        .into_owned()
+}
+
+async fn table_exists<'c, E: sqlx::Executor<'c, Database = sqlx::Postgres>>(
+    executor: E,
+    table: &str,
+) -> anyhow::Result<bool> {
+    let (exists,): (bool,) = sqlx::query_as("SELECT to_regclass($1) IS NOT NULL")
+        .bind(crate::db_adapters::table(table))
+        .fetch_one(executor)
+        .await?;
+    Ok(exists)
+}
@@ -0,0 +1,131 @@
+use std::collections::{BTreeSet, HashSet};
+
+use near_lake_framework::near_indexer_primitives;
+use near_lake_framework::near_indexer_primitives::views::StateChangeValueView;
+use num_traits::ToPrimitive;
+
+// With `--shards 0,2` the instance computes and stores the changes of these shards only,
+// so several instances may fill one database in parallel.
+// Each instance keeps its own offsets in `shard_offsets`.
+// The rows of the involved accounts need the balances of the accounts from the other shards,
+// which change without this instance seeing it, so they are not kept in the balances cache.
+// The block-level aggregates (summary, epoch rewards, supply, invariant, active accounts)
+// need all the shards, they are not stored
+static ASSIGNED_SHARDS: once_cell::sync::OnceCell<
+    BTreeSet<near_indexer_primitives::types::ShardId>,
+> = once_cell::sync::OnceCell::new();
+
+// Leaves room for the rows of the other shards in `index_in_block`
+const INDEX_IN_BLOCK_PER_SHARD: i32 = 1_000_000;
+
+pub(crate) fn configure_shards(shards: Option<Vec<near_indexer_primitives::types::ShardId>>) {
+    if let Some(shards) = shards {
+        ASSIGNED_SHARDS
+            .set(shards.into_iter().collect())
+            .expect("Shards are configured twice");
+    }
+}
+
+pub(crate) fn is_sharded() -> bool {
+    ASSIGNED_SHARDS.get().is_some()
+}
+
+pub(crate) fn retain_assigned(shards: &mut Vec<near_indexer_primitives::IndexerShard>) {
+    if let Some(assigned) = ASSIGNED_SHARDS.get() {
+        shards.retain(|shard| assigned.contains(&shard.shard_id));
+    }
+}
+
+// Keeps in the cache only the accounts changed in the assigned shards of the block.
+// Without the shard layout we cannot tell the unchanged accounts of the assigned shards from
+// the accounts of the other shards, so they are queried from RPC again too
+pub(crate) async fn forget_foreign_accounts(
+    shards: &[near_indexer_primitives::IndexerShard],
+    changes: &[crate::models::balance_changes::BalanceChange],
+    balances_cache: &crate::BalanceCache,
+) {
+    if !is_sharded() {
+        return;
+    }
+    let changed: HashSet<&near_indexer_primitives::types::AccountId> = shards
+        .iter()
+        .flat_map(|shard| &shard.state_changes)
+        .filter_map(|state_change| match &state_change.value {
+            StateChangeValueView::AccountUpdate { account_id, .. }
+            | StateChangeValueView::AccountDeletion { account_id } => Some(account_id),
+            _ => None,
+        })
+        .collect();
+    let mut balances_cache_lock = balances_cache.lock().await;
+    for change in changes {
+        if let Ok(account_id) = change
+            .affected_account_id
+            .parse::<near_indexer_primitives::types::AccountId>()
+        {
+            if !changed.contains(&account_id) {
+                balances_cache_lock.cache_remove(&account_id);
+            }
+        }
+    }
+}
+
+// The instances do not know how many rows the other shards have,
+// the rows keep the order of the block but get the gaps between the shards
+pub(crate) fn index_in_block(shard_id: i32, index_in_chunk: i32, index_in_instance: usize) -> i32 {
+    if is_sharded() {
+        shard_id * INDEX_IN_BLOCK_PER_SHARD + index_in_chunk
+    } else {
+        index_in_instance as i32
+    }
+}
+
+pub(crate) async fn store_offsets(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+) -> anyhow::Result<()> {
+    let assigned = match ASSIGNED_SHARDS.get() {
+        Some(assigned) => assigned,
+        None => return Ok(()),
+    };
+    for shard_id in assigned {
+        sqlx::query(&format!(
+            "INSERT INTO {} VALUES ($1, $2) \
+            ON CONFLICT (shard_id) DO UPDATE SET block_height = EXCLUDED.block_height",
+            crate::db_adapters::table("shard_offsets")
+        ))
+        .bind(*shard_id as i32)
+        .bind(bigdecimal::BigDecimal::from(block_header.height))
+        .execute(&mut *transaction)
+        .await?;
+    }
+    Ok(())
+}
+
+// The instance continues from the shard which is behind, the stores are idempotent.
+// 0 if some shard has not been stored yet
+pub(crate) async fn start_after_interruption(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<Option<u64>> {
+    let assigned = match ASSIGNED_SHARDS.get() {
+        Some(assigned) => assigned,
+        None => return Ok(None),
+    };
+    let shard_ids: Vec<i32> = assigned.iter().map(|shard_id| *shard_id as i32).collect();
+    let offsets: Vec<(bigdecimal::BigDecimal,)> = sqlx::query_as(&format!(
+        "SELECT block_height FROM {} WHERE shard_id = ANY($1)",
+        crate::db_adapters::table("shard_offsets")
+    ))
+    .bind(&shard_ids)
+    .fetch_all(pool)
+    .await?;
+    if offsets.len() < shard_ids.len() {
+        return Ok(Some(0));
+    }
+    Ok(Some(
+        offsets
+            .iter()
+            .filter_map(|(block_height,)| block_height.to_u64())
+            .min()
+            .unwrap_or(0),
+    ))
+}
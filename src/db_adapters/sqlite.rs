@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use near_lake_framework::near_indexer_primitives;
+use sqlx::Executor;
+
+use crate::models::balance_changes::BalanceChange;
+
+// The columns added after the initial schema and their migrations
+const MIGRATIONS: [(&str, &str); 5] = [
+    (
+        "gas_burnt",
+        include_str!("../../migrations_sqlite/20220920120000_gas_burnt.sql"),
+    ),
+    (
+        "predecessor_account_id",
+        include_str!("../../migrations_sqlite/20220930120000_receipt_accounts.sql"),
+    ),
+    (
+        "event_id",
+        include_str!("../../migrations_sqlite/20221005120000_event_id.sql"),
+    ),
+    (
+        "parent_transaction_hash",
+        include_str!("../../migrations_sqlite/20221020120000_parent_transaction_hash.sql"),
+    ),
+    (
+        "index_in_receipt",
+        include_str!("../../migrations_sqlite/20221030120000_index_in_receipt.sql"),
+    ),
+];
+
+// Local development storage: `sqlite://balances.db` is created with the schema if it does not exist.
+// Only balance_changes, current_balances and the stored blocks are kept, the Postgres-only features
+// (subcommands, FT, lockups, top accounts and so on) do not work with it.
+// Built with `--features sqlite`
+pub(crate) struct SqliteStorage {
+    pool: sqlx::Pool<sqlx::Sqlite>,
+}
+
+impl SqliteStorage {
+    pub(crate) async fn connect(database_url: &str) -> anyhow::Result<Self> {
+        let options =
+            sqlx::sqlite::SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect_with(options)
+            .await?;
+        pool.execute(include_str!(
+            "../../migrations_sqlite/20220901120000_initial.sql"
+        ))
+        .await?;
+        // SQLite has no `ADD COLUMN IF NOT EXISTS`, the migration is applied if its column is missing
+        for (column, migration) in MIGRATIONS {
+            let (has_column,): (bool,) = sqlx::query_as(
+                "SELECT count(*) > 0 FROM pragma_table_info('balance_changes') WHERE name = $1",
+            )
+            .bind(column)
+            .fetch_one(&pool)
+            .await?;
+            if !has_column {
+                pool.execute(migration).await?;
+            }
+        }
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::db_adapters::storage::Storage for SqliteStorage {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<u64> {
+        let (height,): (Option<i64>,) = sqlx::query_as("SELECT max(block_height) FROM blocks")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(height.unwrap_or_default() as u64)
+    }
+
+    async fn store_block(
+        &self,
+        _shards: &[near_indexer_primitives::IndexerShard],
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let changes = crate::db_adapters::without_dust(changes);
+        let block_timestamp = block_header.timestamp as i64;
+        let mut transaction = self.pool.begin().await?;
+        for change in changes.iter() {
+            sqlx::query(
+                "INSERT INTO balance_changes VALUES \
+                ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22) \
+                ON CONFLICT DO NOTHING",
+            )
+            .bind(block_timestamp)
+            .bind(&change.receipt_id)
+            .bind(&change.transaction_hash)
+            .bind(&change.affected_account_id)
+            .bind(&change.involved_account_id)
+            .bind(&change.direction)
+            .bind(&change.cause)
+            .bind(&change.status)
+            .bind(change.delta_nonstaked_amount.to_string())
+            .bind(change.absolute_nonstaked_amount.to_string())
+            .bind(change.delta_staked_amount.to_string())
+            .bind(change.absolute_staked_amount.to_string())
+            .bind(change.shard_id)
+            .bind(change.index_in_chunk)
+            .bind(change.index_in_block)
+            .bind(change.fiat_value_usd.as_ref().map(ToString::to_string))
+            .bind(change.gas_burnt.as_ref().map(ToString::to_string))
+            .bind(&change.predecessor_account_id)
+            .bind(&change.receiver_account_id)
+            .bind(&change.event_id)
+            .bind(&change.parent_transaction_hash)
+            .bind(change.index_in_receipt)
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        // The latest row of the account in the block has its balance after the block
+        let mut latest_changes: HashMap<&str, &BalanceChange> = HashMap::new();
+        for change in changes.iter() {
+            latest_changes.insert(&change.affected_account_id, change);
+        }
+        for (account_id, change) in latest_changes {
+            sqlx::query(
+                "INSERT INTO current_balances VALUES ($1, $2, $3, $4) \
+                ON CONFLICT (account_id) DO UPDATE SET block_timestamp = excluded.block_timestamp, \
+                    nonstaked_amount = excluded.nonstaked_amount, staked_amount = excluded.staked_amount \
+                WHERE excluded.block_timestamp >= current_balances.block_timestamp",
+            )
+            .bind(account_id)
+            .bind(block_timestamp)
+            .bind(change.absolute_nonstaked_amount.to_string())
+            .bind(change.absolute_staked_amount.to_string())
+            .execute(&mut transaction)
+            .await?;
+        }
+
+        sqlx::query("INSERT INTO blocks VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(block_header.height as i64)
+            .bind(block_timestamp)
+            .execute(&mut transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+}
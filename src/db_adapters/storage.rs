@@ -0,0 +1,338 @@
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+
+// Where the computed balance changes go. Selected by the scheme of `--database`
+#[async_trait::async_trait]
+pub(crate) trait Storage: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Height of the block to continue from, 0 if nothing is stored yet
+    async fn start_after_interruption(&self) -> anyhow::Result<u64>;
+
+    // Should be idempotent, the block may be stored again after the failure
+    async fn store_block(
+        &self,
+        shards: &[near_indexer_primitives::IndexerShard],
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()>;
+
+    // Deletes the blocks of the discarded fork, the latest first
+    async fn rollback_blocks(&self, blocks: &[crate::forks::TrackedBlock]) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "Rollback of {} blocks is not supported for {}",
+            blocks.len(),
+            self.name()
+        )
+    }
+
+    // Subcommands work only with Postgres
+    fn postgres_pool(&self) -> Option<&sqlx::Pool<sqlx::Postgres>> {
+        None
+    }
+}
+
+pub(crate) async fn connect(
+    opts: &crate::configs::Opts,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<Box<dyn Storage>> {
+    // Nothing is written anywhere, the database is not even touched
+    if opts.dry_run {
+        return Ok(Box::new(
+            crate::db_adapters::memory::MemoryStorage::dry_run(),
+        ));
+    }
+    // Before the setup of the tables, the standby should not touch them
+    let leader = match opts.ha_lock_id {
+        Some(lock_id) if opts.database.starts_with("postgres") => {
+            Some(crate::db_adapters::leader::Leader::acquire(&opts.database, lock_id).await?)
+        }
+        Some(_) => anyhow::bail!("--ha-lock-id requires Postgres database"),
+        None => None,
+    };
+    let primary = connect_database(opts, &opts.database, json_rpc_client).await?;
+    let storage = match &opts.secondary_database {
+        Some(database) => Box::new(crate::db_adapters::dual_write::DualStorage::new(
+            primary,
+            connect_database(opts, database, json_rpc_client).await?,
+            opts.secondary_queue_size,
+        )),
+        None => primary,
+    };
+    match leader {
+        Some(leader) => Ok(Box::new(crate::db_adapters::leader::LeaderStorage::new(
+            storage, leader,
+        ))),
+        None => Ok(storage),
+    }
+}
+
+async fn connect_database(
+    opts: &crate::configs::Opts,
+    database: &str,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<Box<dyn Storage>> {
+    if database == "none" {
+        return Ok(Box::new(NoStorage));
+    }
+    if database == "memory" || database.starts_with("memory://") {
+        return Ok(Box::new(crate::db_adapters::memory::MemoryStorage::new(
+            database,
+        )?));
+    }
+    match database.split_once("://") {
+        Some(("clickhouse", _)) => Ok(Box::new(
+            crate::db_adapters::clickhouse::ClickHouseStorage::new(database)?,
+        )),
+        #[cfg(feature = "sqlite")]
+        Some(("sqlite", _)) => Ok(Box::new(
+            crate::db_adapters::sqlite::SqliteStorage::connect(database).await?,
+        )),
+        #[cfg(not(feature = "sqlite"))]
+        Some(("sqlite", _)) => {
+            anyhow::bail!("SQLite storage needs the build with --features sqlite")
+        }
+        Some(("postgres", _)) | Some(("postgresql", _)) => {
+            let pool = crate::db_adapters::pool::connect(opts, database).await?;
+            if !opts.skip_schema_check {
+                crate::db_adapters::schema_version::check(&pool).await?;
+            }
+            if opts.timescale {
+                if opts.partition_blocks.is_some() {
+                    anyhow::bail!("Timescale partitions the table itself, --partition-blocks does not work with --timescale");
+                }
+                crate::db_adapters::timescale::setup(
+                    &pool,
+                    opts.timescale_chunk_days,
+                    opts.timescale_compress_after_days,
+                )
+                .await?;
+            }
+            // The table may be converted by the previous run with --timescale
+            if opts.bulk_load && crate::db_adapters::timescale::is_hypertable(&pool).await? {
+                anyhow::bail!("--bulk-load writes numeric block_timestamp, it does not work with the Timescale hypertable");
+            }
+            let top_accounts = match opts.top_accounts {
+                Some(size) => {
+                    let top_accounts = std::sync::Arc::new(
+                        crate::db_adapters::top_accounts::TopAccounts::load(&pool, size).await?,
+                    );
+                    crate::db_adapters::top_accounts::spawn_publisher(
+                        top_accounts.clone(),
+                        pool.clone(),
+                        std::time::Duration::from_secs(opts.top_accounts_interval_secs),
+                    );
+                    Some(top_accounts)
+                }
+                None => None,
+            };
+            let anomaly_detector = if opts.detect_anomalies {
+                Some(
+                    crate::db_adapters::anomalies::AnomalyDetector::load(
+                        &pool,
+                        opts.anomaly_bucket_secs,
+                        opts.anomaly_window,
+                        opts.anomaly_z_score,
+                        opts.anomaly_min_outflow_near,
+                    )
+                    .await?,
+                )
+            } else {
+                None
+            };
+            let partitions = match opts.partition_blocks {
+                Some(blocks) => {
+                    Some(crate::db_adapters::partitions::Partitions::setup(&pool, blocks).await?)
+                }
+                None => None,
+            };
+            // After the partitioning, the pruner drops the partitions
+            if opts.retention_days.is_some() || opts.retention_blocks.is_some() {
+                crate::db_adapters::retention::spawn_pruner(
+                    crate::db_adapters::retention::Retention {
+                        days: opts.retention_days,
+                        blocks: opts.retention_blocks,
+                    },
+                    pool.clone(),
+                    opts.timescale,
+                );
+            }
+            Ok(Box::new(PostgresStorage {
+                partitions,
+                pool,
+                lockup_suffix: opts.lockup_suffix.clone(),
+                top_accounts,
+                active_accounts: crate::db_adapters::active_accounts::ActiveAccounts::new(),
+                anomaly_detector,
+                receipt_trees: Default::default(),
+                json_rpc_client: json_rpc_client.clone(),
+            }))
+        }
+        _ => anyhow::bail!(
+            "Unknown database `{}`, expected `postgres://...`, `clickhouse://...`, `memory` or `none`",
+            database
+        ),
+    }
+}
+
+pub(crate) struct PostgresStorage {
+    pool: sqlx::Pool<sqlx::Postgres>,
+    partitions: Option<crate::db_adapters::partitions::Partitions>,
+    lockup_suffix: Option<String>,
+    top_accounts: Option<std::sync::Arc<crate::db_adapters::top_accounts::TopAccounts>>,
+    active_accounts: crate::db_adapters::active_accounts::ActiveAccounts,
+    anomaly_detector: Option<crate::db_adapters::anomalies::AnomalyDetector>,
+    receipt_trees: std::sync::Mutex<crate::db_adapters::invariant::ReceiptTrees>,
+    // Resharding and lockups need RPC
+    json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+}
+
+#[async_trait::async_trait]
+impl Storage for PostgresStorage {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<u64> {
+        if let Some(height) =
+            crate::db_adapters::shard_assignment::start_after_interruption(&self.pool).await?
+        {
+            return Ok(height);
+        }
+        match crate::db_adapters::stream_offsets::start_after_interruption(&self.pool).await? {
+            Some(height) => Ok(height),
+            None => crate::models::start_after_interruption(&self.pool).await,
+        }
+    }
+
+    async fn store_block(
+        &self,
+        shards: &[near_indexer_primitives::IndexerShard],
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        if let Some(partitions) = &self.partitions {
+            partitions
+                .ensure(&self.pool, block_header.height, block_header.timestamp)
+                .await?;
+        }
+        // The filtered changes miss the other accounts, the other shards are stored by the other instances
+        let invariant_check = if !crate::db_adapters::account_filter::is_filtering()
+            && !crate::db_adapters::shard_assignment::is_sharded()
+        {
+            Some(
+                self.receipt_trees
+                    .lock()
+                    .unwrap()
+                    .check_block(shards, block_header, changes),
+            )
+        } else {
+            None
+        };
+        let lockup_balances = match &self.lockup_suffix {
+            Some(lockup_suffix) => {
+                crate::db_adapters::lockup::lockup_balances(
+                    lockup_suffix,
+                    block_header,
+                    changes,
+                    &self.json_rpc_client,
+                )
+                .await?
+            }
+            None => vec![],
+        };
+        // The daily counters are rewritten by each instance
+        let active_accounts = if !crate::db_adapters::shard_assignment::is_sharded() {
+            self.active_accounts
+                .prepare(&self.pool, block_header, changes)
+                .await?
+        } else {
+            None
+        };
+        let anomalies = match &self.anomaly_detector {
+            Some(anomaly_detector) => Some(anomaly_detector.prepare(block_header, changes).await),
+            None => None,
+        };
+        let mut derived_rows = crate::db_adapters::balance_changes::DerivedRows {
+            imbalances: invariant_check
+                .iter()
+                .filter_map(|check| check.imbalance.clone())
+                .collect(),
+            lockup_balances,
+            active_accounts,
+            anomalies,
+        };
+        let stored = crate::db_adapters::balance_changes::store_balance_changes(
+            &self.pool,
+            shards,
+            block_header,
+            changes,
+            &derived_rows,
+            &self.json_rpc_client,
+        )
+        .await?;
+        // The in-memory state follows the committed block, even if it was committed by the previous attempt
+        if let Some(active_accounts) = derived_rows.active_accounts.take() {
+            self.active_accounts.apply(active_accounts).await;
+        }
+        if let (Some(anomaly_detector), Some(anomalies)) =
+            (&self.anomaly_detector, derived_rows.anomalies.take())
+        {
+            anomaly_detector.apply(anomalies).await;
+        }
+        // The rest was stored with the block, or the block is repeated after the failure of the rest
+        if !stored {
+            return Ok(());
+        }
+        if let Some(invariant_check) = invariant_check {
+            invariant_check.report();
+            self.receipt_trees.lock().unwrap().apply(invariant_check);
+        }
+        if let Some(top_accounts) = &self.top_accounts {
+            top_accounts.update(block_header, changes).await;
+        }
+        Ok(())
+    }
+
+    async fn rollback_blocks(&self, blocks: &[crate::forks::TrackedBlock]) -> anyhow::Result<()> {
+        crate::db_adapters::rollback::rollback_blocks(&self.pool, blocks).await?;
+        self.receipt_trees.lock().unwrap().clear();
+        self.active_accounts.forget().await;
+        if let Some(anomaly_detector) = &self.anomaly_detector {
+            anomaly_detector.reseed(&self.pool).await?;
+        }
+        Ok(())
+    }
+
+    fn postgres_pool(&self) -> Option<&sqlx::Pool<sqlx::Postgres>> {
+        Some(&self.pool)
+    }
+}
+
+// Only the sinks get the balance changes
+pub(crate) struct NoStorage;
+
+#[async_trait::async_trait]
+impl Storage for NoStorage {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<u64> {
+        Ok(0)
+    }
+
+    async fn store_block(
+        &self,
+        _shards: &[near_indexer_primitives::IndexerShard],
+        _block_header: &near_indexer_primitives::views::BlockHeaderView,
+        _changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn rollback_blocks(&self, _blocks: &[crate::forks::TrackedBlock]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use near_lake_framework::near_indexer_primitives;
+use num_traits::ToPrimitive;
+
+// NEAR Lake and the local lake directory number the blocks the same way, so they share the offset
+const LAKE_SOURCE: &str = "lake";
+
+// Exactly-once storing of the blocks: the offset is moved in the transaction with the rows,
+// so after the crash the block is either stored with its offset or not stored at all.
+// The block at or below the offset is stored already, the transaction is dropped.
+// The sharded instances keep their own offsets in `shard_offsets`
+pub(crate) async fn commit_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+) -> anyhow::Result<bool> {
+    advance_in_transaction(transaction, block_header.height).await
+}
+
+// false if the offset is at or after the block already
+pub(crate) async fn advance_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block_height: u64,
+) -> anyhow::Result<bool> {
+    let moved = sqlx::query(&format!(
+        "INSERT INTO {} AS stream_offsets VALUES ($1, $2) \
+        ON CONFLICT (source) DO UPDATE SET block_height = EXCLUDED.block_height \
+        WHERE stream_offsets.block_height < EXCLUDED.block_height",
+        crate::db_adapters::table("stream_offsets")
+    ))
+    .bind(LAKE_SOURCE)
+    .bind(bigdecimal::BigDecimal::from(block_height))
+    .execute(&mut *transaction)
+    .await
+    .map_err(|err| anyhow::anyhow!("Failed to commit the stream offset: {}", err))?
+    .rows_affected();
+    Ok(moved > 0)
+}
+
+// The blocks of the discarded fork are rolled back from the latest one,
+// so the offset ends right before the earliest of them. The offsets of the sinks go back too
+pub(crate) async fn rollback_in_transaction(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    block: &crate::forks::TrackedBlock,
+) -> anyhow::Result<()> {
+    sqlx::query(&format!(
+        "UPDATE {} SET block_height = $1 - 1 WHERE block_height >= $1",
+        crate::db_adapters::table("stream_offsets")
+    ))
+    .bind(bigdecimal::BigDecimal::from(block.height))
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+// The block after the committed one, None if nothing is committed yet
+pub(crate) async fn start_after_interruption(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+) -> anyhow::Result<Option<u64>> {
+    Ok(read(pool, LAKE_SOURCE)
+        .await?
+        .map(|block_height| block_height + 1))
+}
+
+// The last block published to the sink, None if the sink has not published anything yet
+pub(crate) async fn published(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    sink: &str,
+) -> anyhow::Result<Option<u64>> {
+    read(pool, &sink_source(sink)).await
+}
+
+// Moved after the sink has published the block, never goes back except for the rollback
+pub(crate) async fn advance_published(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    sink: &str,
+    block_height: u64,
+) -> anyhow::Result<()> {
+    sqlx::query(&format!(
+        "INSERT INTO {} AS stream_offsets VALUES ($1, $2) \
+        ON CONFLICT (source) DO UPDATE SET block_height = EXCLUDED.block_height \
+        WHERE stream_offsets.block_height < EXCLUDED.block_height",
+        crate::db_adapters::table("stream_offsets")
+    ))
+    .bind(sink_source(sink))
+    .bind(bigdecimal::BigDecimal::from(block_height))
+    .execute(pool)
+    .await
+    .map_err(|err| anyhow::anyhow!("Failed to commit the offset of {} sink: {}", sink, err))?;
+    Ok(())
+}
+
+fn sink_source(sink: &str) -> String {
+    format!("sink:{}", sink)
+}
+
+async fn read(pool: &sqlx::Pool<sqlx::Postgres>, source: &str) -> anyhow::Result<Option<u64>> {
+    let offset: Option<(bigdecimal::BigDecimal,)> = sqlx::query_as(&format!(
+        "SELECT block_height FROM {} WHERE source = $1",
+        crate::db_adapters::table("stream_offsets")
+    ))
+    .bind(source)
+    .fetch_optional(pool)
+    .await?;
+    Ok(offset.and_then(|(block_height,)| block_height.to_u64()))
+}
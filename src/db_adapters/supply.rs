@@ -0,0 +1,65 @@
+use bigdecimal::BigDecimal;
+use sqlx::Row;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::models::supply_history::SupplyHistory;
+use near_lake_framework::near_indexer_primitives;
+
+// The header has the total supply after the block, it is stored as is.
+// It moves only by minted - burnt, so the value continued from the previous block cross-checks our minted amount.
+// The header subtracts the tokens burnt by the previous chunks, they come in the chunk headers of this block
+pub(crate) async fn store_supply(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    shards: &[near_indexer_primitives::IndexerShard],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+) -> anyhow::Result<()> {
+    let minted = crate::db_adapters::invariant::minted_amount(changes);
+    let burnt = crate::db_adapters::invariant::tokens_burnt_amount(shards);
+    let block_height: BigDecimal = block_header.height.into();
+    let total_supply = crate::models::to_decimal(block_header.total_supply);
+
+    let prev_total_supply: Option<BigDecimal> = match block_header.prev_height {
+        Some(prev_height) => sqlx::query(&format!(
+            "SELECT total_supply FROM {} WHERE block_height = $1",
+            crate::db_adapters::table("supply_history")
+        ))
+        .bind(BigDecimal::from(prev_height))
+        .fetch_optional(&mut *transaction)
+        .await?
+        .map(|row| row.get(0)),
+        None => None,
+    };
+    if let Some(prev_total_supply) = prev_total_supply {
+        let chunks_burnt: u128 = shards
+            .iter()
+            .filter_map(|shard| shard.chunk.as_ref())
+            .map(|chunk| chunk.header.balance_burnt)
+            .sum();
+        let derived_total_supply =
+            prev_total_supply + &minted - crate::models::to_decimal(chunks_burnt);
+        if derived_total_supply != total_supply {
+            tracing::warn!(
+                target: crate::INDEXER,
+                "Total supply at block_height {} is {}, but minted {} and burnt {} give {}",
+                block_header.height,
+                total_supply,
+                minted,
+                chunks_burnt,
+                derived_total_supply
+            );
+        }
+    }
+
+    crate::models::insert_in_transaction(
+        transaction,
+        &[SupplyHistory {
+            block_height,
+            block_timestamp: block_header.timestamp.into(),
+            minted_amount: minted,
+            burnt_amount: burnt,
+            total_supply,
+        }],
+    )
+    .await
+}
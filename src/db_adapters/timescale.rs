@@ -0,0 +1,88 @@
+const NANOS_IN_DAY: i64 = 24 * 60 * 60 * 1_000_000_000;
+
+// Turns balance_changes into the Timescale hypertable with the compression policy, once.
+// Timescale needs the integer time column, so block_timestamp becomes bigint: the nanoseconds fit it
+// until 2262. On the big existing table it rewrites the data and locks the table for that time.
+// The rows of one block share the timestamp, so the insert batches never cross the chunks
+pub(crate) async fn setup(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    chunk_days: i64,
+    compress_after_days: i64,
+) -> anyhow::Result<()> {
+    sqlx::query("CREATE EXTENSION IF NOT EXISTS timescaledb")
+        .execute(pool)
+        .await?;
+    if is_hypertable(pool).await? {
+        return Ok(());
+    }
+    tracing::info!(
+        target: crate::INDEXER,
+        "Converting balance_changes to the hypertable, it may take long on the big table"
+    );
+
+    let table = crate::db_adapters::table("balance_changes");
+    let now_function = crate::db_adapters::table("balance_changes_now");
+    let mut transaction = pool.begin().await?;
+    sqlx::query(&format!(
+        "ALTER TABLE {} ALTER COLUMN block_timestamp TYPE bigint",
+        table
+    ))
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query(
+        "SELECT create_hypertable($1::regclass, 'block_timestamp', \
+        chunk_time_interval => $2, migrate_data => true)",
+    )
+    .bind(&table)
+    .bind(chunk_days * NANOS_IN_DAY)
+    .execute(&mut transaction)
+    .await?;
+    // The compression policy needs "now" in the units of the time column
+    sqlx::query(&format!(
+        "CREATE OR REPLACE FUNCTION {}() RETURNS bigint \
+        LANGUAGE SQL STABLE AS $$ SELECT coalesce(max(block_timestamp), 0) FROM {} $$",
+        now_function, table
+    ))
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query("SELECT set_integer_now_func($1::regclass, $2::regproc)")
+        .bind(&table)
+        .bind(&now_function)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query(&format!(
+        "ALTER TABLE {} SET (timescaledb.compress, \
+        timescaledb.compress_segmentby = 'affected_account_id', \
+        timescaledb.compress_orderby = 'block_timestamp, shard_id, index_in_chunk')",
+        table
+    ))
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query("SELECT add_compression_policy($1::regclass, compress_after => $2)")
+        .bind(&table)
+        .bind(compress_after_days * NANOS_IN_DAY)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+// False without the extension
+pub(crate) async fn is_hypertable(pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<bool> {
+    let (has_timescale,): (bool,) =
+        sqlx::query_as("SELECT to_regclass('timescaledb_information.hypertables') IS NOT NULL")
+            .fetch_one(pool)
+            .await?;
+    if !has_timescale {
+        return Ok(false);
+    }
+    let (is_hypertable,): (bool,) = sqlx::query_as(
+        "SELECT EXISTS (SELECT 1 FROM timescaledb_information.hypertables \
+        WHERE hypertable_schema = coalesce($1, current_schema()) AND hypertable_name = $2)",
+    )
+    .bind(crate::db_adapters::db_schema())
+    .bind(crate::db_adapters::unqualified_table("balance_changes"))
+    .fetch_one(pool)
+    .await?;
+    Ok(is_hypertable)
+}
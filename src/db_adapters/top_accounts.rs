@@ -0,0 +1,223 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives;
+use num_traits::Zero;
+use sqlx::Row;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::models::top_accounts::{TopAccount, TopAccountRank};
+
+// Rich list by the total (nonstaked + staked) balance.
+// We keep more candidates than we publish: the account which falls out of the candidates is forgotten
+// until its balance changes again, so the top is exact while less than `size` accounts fall below it
+pub(crate) struct TopAccounts {
+    size: usize,
+    capacity: usize,
+    state: tokio::sync::Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    balances: HashMap<String, BigDecimal>,
+    ranked: BTreeSet<(BigDecimal, String)>,
+    block_height: u64,
+    block_timestamp: u64,
+    // What is in `top_accounts` now
+    published_ranks: HashMap<String, (i32, BigDecimal)>,
+}
+
+impl TopAccounts {
+    // The candidates are seeded from current_balances, it's a full scan done once at the start
+    pub(crate) async fn load(
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        size: usize,
+    ) -> anyhow::Result<Self> {
+        let capacity = size * 2;
+        let mut state = State::default();
+        let rows = sqlx::query(&format!(
+            "SELECT account_id, nonstaked_amount + staked_amount AS total_amount \
+            FROM {} ORDER BY total_amount DESC LIMIT $1",
+            crate::db_adapters::table("current_balances")
+        ))
+        .bind(capacity as i64)
+        .fetch_all(pool)
+        .await?;
+        for row in rows {
+            state.set_balance(row.get(0), row.get(1), capacity);
+        }
+        for row in sqlx::query(&format!(
+            "SELECT account_id, rank, total_amount FROM {}",
+            crate::db_adapters::table("top_accounts")
+        ))
+        .fetch_all(pool)
+        .await?
+        {
+            state
+                .published_ranks
+                .insert(row.get(0), (row.get(1), row.get(2)));
+        }
+        tracing::info!(
+            target: crate::INDEXER,
+            "Top accounts are seeded with {} candidates",
+            state.ranked.len()
+        );
+        Ok(Self {
+            size,
+            capacity,
+            state: tokio::sync::Mutex::new(state),
+        })
+    }
+
+    // Called after the block is stored. Absolute balances make it safe to apply the block twice
+    pub(crate) async fn update(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) {
+        let mut state = self.state.lock().await;
+        for change in changes {
+            state.set_balance(
+                change.affected_account_id.clone(),
+                &change.absolute_nonstaked_amount + &change.absolute_staked_amount,
+                self.capacity,
+            );
+        }
+        state.block_height = block_header.height;
+        state.block_timestamp = block_header.timestamp;
+    }
+
+    // Rewrites `top_accounts` and appends the changed ranks to the history
+    pub(crate) async fn publish(&self, pool: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+        // The indexing waits for the lock, so we do not hold it while writing
+        let state = self.state.lock().await;
+        if state.block_height == 0 {
+            return Ok(());
+        }
+        let block_height: BigDecimal = state.block_height.into();
+        let block_timestamp: BigDecimal = state.block_timestamp.into();
+        let top: HashMap<String, (i32, BigDecimal)> = state
+            .ranked
+            .iter()
+            .rev()
+            .take(self.size)
+            .enumerate()
+            .map(|(i, (total_amount, account_id))| {
+                (account_id.clone(), (i as i32 + 1, total_amount.clone()))
+            })
+            .collect();
+
+        let mut history: Vec<TopAccountRank> = top
+            .iter()
+            .filter(|(account_id, (rank, _))| {
+                state.published_ranks.get(*account_id).map(|(rank, _)| rank) != Some(rank)
+            })
+            .map(|(account_id, (rank, total_amount))| TopAccountRank {
+                block_height: block_height.clone(),
+                block_timestamp: block_timestamp.clone(),
+                account_id: account_id.clone(),
+                rank: Some(*rank),
+                total_amount: Some(total_amount.clone()),
+            })
+            .collect();
+        history.extend(
+            state
+                .published_ranks
+                .keys()
+                .filter(|account_id| !top.contains_key(*account_id))
+                .map(|account_id| TopAccountRank {
+                    block_height: block_height.clone(),
+                    block_timestamp: block_timestamp.clone(),
+                    account_id: account_id.clone(),
+                    rank: None,
+                    total_amount: state.balances.get(account_id).cloned(),
+                }),
+        );
+        let published_height = state.block_height;
+        drop(state);
+        if history.is_empty() {
+            return Ok(());
+        }
+
+        let rows: Vec<TopAccount> = top
+            .iter()
+            .map(|(account_id, (rank, total_amount))| TopAccount {
+                account_id: account_id.clone(),
+                rank: *rank,
+                total_amount: total_amount.clone(),
+                block_height: block_height.clone(),
+            })
+            .collect();
+        let mut transaction = pool.begin().await?;
+        sqlx::query(&format!(
+            "DELETE FROM {}",
+            crate::db_adapters::table("top_accounts")
+        ))
+        .execute(&mut transaction)
+        .await?;
+        crate::models::insert_in_transaction(&mut transaction, &rows).await?;
+        crate::models::insert_in_transaction(&mut transaction, &history).await?;
+        transaction.commit().await?;
+
+        tracing::debug!(
+            target: crate::INDEXER,
+            "Top accounts are published at block_height {}, {} ranks changed",
+            published_height,
+            history.len()
+        );
+        // Only this job changes the published ranks
+        self.state.lock().await.published_ranks = top;
+        Ok(())
+    }
+}
+
+impl State {
+    fn set_balance(&mut self, account_id: String, total_amount: BigDecimal, capacity: usize) {
+        match self.balances.get(&account_id) {
+            Some(prev_amount) => {
+                self.ranked
+                    .remove(&(prev_amount.clone(), account_id.clone()));
+            }
+            None => {
+                let is_candidate = self.ranked.len() < capacity
+                    || self
+                        .ranked
+                        .iter()
+                        .next()
+                        .map_or(true, |(min_amount, _)| total_amount > *min_amount);
+                if !is_candidate || total_amount.is_zero() {
+                    return;
+                }
+            }
+        }
+        self.ranked
+            .insert((total_amount.clone(), account_id.clone()));
+        self.balances.insert(account_id, total_amount);
+        while self.ranked.len() > capacity {
+            if let Some(smallest) = self.ranked.iter().next().cloned() {
+                self.ranked.remove(&smallest);
+                self.balances.remove(&smallest.1);
+            }
+        }
+    }
+}
+
+// The job runs separately from the indexing, so a slow rewrite of the table does not delay the blocks
+pub(crate) fn spawn_publisher(
+    top_accounts: std::sync::Arc<TopAccounts>,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    interval: std::time::Duration,
+) {
+    crate::db_adapters::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(err) = top_accounts.publish(&pool).await {
+                tracing::warn!(
+                    target: crate::INDEXER,
+                    "Failed to publish top accounts: {:#}",
+                    err
+                );
+            }
+        }
+    });
+}
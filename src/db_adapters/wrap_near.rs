@@ -0,0 +1,146 @@
+use cached::Cached;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives::{
+    self,
+    views::{ActionView, ExecutionStatusView, ReceiptEnumView},
+};
+
+use crate::models::wrap_near_events::WrapNearEvent;
+
+// Wallets want to show "wrapped 5 NEAR" as one event instead of two unrelated changes.
+// The receipt executing near_deposit/near_withdraw on the wrap contract is the correlation id
+pub(crate) struct WrapNearTracker {
+    contract_account_id: String,
+    // near_deposit receipt -> transaction that attached the NEAR to it
+    pending_deposits: tokio::sync::Mutex<
+        cached::SizedCache<
+            near_indexer_primitives::CryptoHash,
+            near_indexer_primitives::CryptoHash,
+        >,
+    >,
+}
+
+#[derive(serde::Deserialize)]
+struct WithdrawArgs {
+    amount: String,
+}
+
+impl WrapNearTracker {
+    pub(crate) fn new(contract_account_id: String) -> Self {
+        Self {
+            contract_account_id,
+            pending_deposits: tokio::sync::Mutex::new(cached::SizedCache::with_size(10_000)),
+        }
+    }
+
+    // Should be called for each block in order: the deposit transaction
+    // is met before its receipt is executed
+    pub(crate) async fn collect_events(
+        &self,
+        streamer_message: &near_indexer_primitives::StreamerMessage,
+    ) -> anyhow::Result<Vec<WrapNearEvent>> {
+        let block_header = &streamer_message.block.header;
+        let mut pending_deposits = self.pending_deposits.lock().await;
+        for transaction in streamer_message
+            .shards
+            .iter()
+            .filter_map(|shard| shard.chunk.as_ref())
+            .flat_map(|chunk| &chunk.transactions)
+        {
+            if transaction.transaction.receiver_id.as_str() != self.contract_account_id
+                || find_function_call(&transaction.transaction.actions, "near_deposit").is_none()
+            {
+                continue;
+            }
+            if let Some(receipt_id) = transaction
+                .outcome
+                .execution_outcome
+                .outcome
+                .receipt_ids
+                .first()
+            {
+                pending_deposits.cache_set(*receipt_id, transaction.transaction.hash);
+            }
+        }
+
+        let mut result = vec![];
+        for outcome_with_receipt in streamer_message
+            .shards
+            .iter()
+            .flat_map(|shard| &shard.receipt_execution_outcomes)
+        {
+            let receipt = &outcome_with_receipt.receipt;
+            let outcome = &outcome_with_receipt.execution_outcome.outcome;
+            if receipt.receiver_id.as_str() != self.contract_account_id
+                || matches!(
+                    outcome.status,
+                    ExecutionStatusView::Failure(_) | ExecutionStatusView::Unknown
+                )
+            {
+                continue;
+            }
+            let actions = match &receipt.receipt {
+                ReceiptEnumView::Action { actions, .. } => actions,
+                ReceiptEnumView::Data { .. } => continue,
+            };
+
+            let event = if let Some((_, deposit)) = find_function_call(actions, "near_deposit") {
+                WrapNearEvent {
+                    correlation_id: receipt.receipt_id.to_string(),
+                    block_timestamp: block_header.timestamp.into(),
+                    kind: "WRAP".to_string(),
+                    account_id: receipt.predecessor_id.to_string(),
+                    amount: crate::models::to_decimal(deposit),
+                    // None if NEAR is attached by a contract or if we started after the transaction
+                    near_transaction_hash: pending_deposits
+                        .cache_remove(&receipt.receipt_id)
+                        .map(|hash| hash.to_string()),
+                    near_receipt_id: None,
+                    ft_receipt_id: receipt.receipt_id.to_string(),
+                }
+            } else if let Some((args, _)) = find_function_call(actions, "near_withdraw") {
+                // The contract rejects the malformed arguments, nothing is withdrawn
+                let amount = match serde_json::from_slice::<WithdrawArgs>(args)
+                    .ok()
+                    .and_then(|args| BigDecimal::from_str(&args.amount).ok())
+                {
+                    Some(amount) => amount,
+                    None => continue,
+                };
+                WrapNearEvent {
+                    correlation_id: receipt.receipt_id.to_string(),
+                    block_timestamp: block_header.timestamp.into(),
+                    kind: "UNWRAP".to_string(),
+                    account_id: receipt.predecessor_id.to_string(),
+                    amount,
+                    near_transaction_hash: None,
+                    // NEAR is sent back with the Transfer receipt
+                    near_receipt_id: outcome.receipt_ids.first().map(|id| id.to_string()),
+                    ft_receipt_id: receipt.receipt_id.to_string(),
+                }
+            } else {
+                continue;
+            };
+            result.push(event);
+        }
+        Ok(result)
+    }
+}
+
+// Returns args and deposit of the first call of the method
+fn find_function_call<'a>(
+    actions: &'a [ActionView],
+    method: &str,
+) -> Option<(&'a [u8], near_indexer_primitives::types::Balance)> {
+    actions.iter().find_map(|action| match action {
+        ActionView::FunctionCall {
+            method_name,
+            args,
+            deposit,
+            ..
+        } if method_name == method => Some((args.as_slice(), *deposit)),
+        _ => None,
+    })
+}
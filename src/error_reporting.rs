@@ -0,0 +1,198 @@
+static REPORTER: once_cell::sync::OnceCell<Reporter> = once_cell::sync::OnceCell::new();
+
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Sends the failures the operators should know about before the process dies:
+// balance drift and broken invariants, failed reconciliation, RPC retries exhausted and panics.
+// `--sentry-dsn` uses the Sentry store API, `--error-webhook-url` gets the JSON
+// `{"kind", "level", "message", "tags", "timestamp"}`. Delivery is best effort
+struct Reporter {
+    client: reqwest::Client,
+    targets: Vec<Target>,
+}
+
+enum Target {
+    Sentry { store_url: String, auth: String },
+    Webhook(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Level {
+    Warning,
+    Error,
+    Fatal,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Warning => "warning",
+            Level::Error => "error",
+            Level::Fatal => "fatal",
+        }
+    }
+}
+
+pub(crate) fn configure_error_reporting(
+    sentry_dsn: Option<&str>,
+    error_webhook_url: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut targets = vec![];
+    if let Some(dsn) = sentry_dsn {
+        targets.push(sentry_target(dsn)?);
+    }
+    if let Some(url) = error_webhook_url {
+        targets.push(Target::Webhook(url.to_string()));
+    }
+    if targets.is_empty() {
+        return Ok(());
+    }
+    REPORTER
+        .set(Reporter {
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            targets,
+        })
+        .map_err(|_| anyhow::anyhow!("Error reporting is configured twice"))?;
+    install_panic_hook();
+    Ok(())
+}
+
+// https://<public key>@<host>/<project id>
+fn sentry_target(dsn: &str) -> anyhow::Result<Target> {
+    let url =
+        reqwest::Url::parse(dsn).map_err(|err| anyhow::anyhow!("Invalid Sentry DSN: {}", err))?;
+    let project_id = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|project_id| !project_id.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Project id is missing in the Sentry DSN"))?;
+    if url.username().is_empty() {
+        anyhow::bail!("Public key is missing in the Sentry DSN");
+    }
+    let auth = format!(
+        "Sentry sentry_version=7, sentry_client=indexer-balances/{}, sentry_key={}",
+        env!("CARGO_PKG_VERSION"),
+        url.username()
+    );
+    let path_prefix = url
+        .path()
+        .strip_suffix(project_id)
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    let mut store_url = url.clone();
+    store_url.set_path(&format!("{}/api/{}/store/", path_prefix, project_id));
+    store_url
+        .set_username("")
+        .map_err(|_| anyhow::anyhow!("Invalid Sentry DSN"))?;
+    store_url
+        .set_password(None)
+        .map_err(|_| anyhow::anyhow!("Invalid Sentry DSN"))?;
+    Ok(Target::Sentry {
+        store_url: store_url.to_string(),
+        auth,
+    })
+}
+
+pub(crate) async fn report(
+    level: Level,
+    kind: &'static str,
+    message: String,
+    tags: Vec<(&'static str, String)>,
+) {
+    let reporter = match REPORTER.get() {
+        Some(reporter) => reporter,
+        None => return,
+    };
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let tags: serde_json::Map<String, serde_json::Value> = tags
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.into()))
+        .collect();
+    for target in &reporter.targets {
+        let result = match target {
+            Target::Sentry { store_url, auth } => {
+                let event = serde_json::json!({
+                    "event_id": format!("{:032x}", rand::random::<u128>()),
+                    "timestamp": timestamp,
+                    "platform": "other",
+                    "level": level.as_str(),
+                    "logger": kind,
+                    "message": message,
+                    "tags": tags,
+                });
+                reporter
+                    .client
+                    .post(store_url)
+                    .header("X-Sentry-Auth", auth)
+                    .json(&event)
+                    .send()
+                    .await
+            }
+            Target::Webhook(url) => {
+                let event = serde_json::json!({
+                    "kind": kind,
+                    "level": level.as_str(),
+                    "message": message,
+                    "tags": tags,
+                    "timestamp": timestamp,
+                });
+                reporter.client.post(url).json(&event).send().await
+            }
+        };
+        match result.and_then(|response| response.error_for_status()) {
+            Ok(_) => {}
+            Err(err) => tracing::warn!(
+                target: crate::INDEXER,
+                "Failed to report {} error: {}",
+                kind,
+                err
+            ),
+        }
+    }
+}
+
+// For the places which should not wait for the delivery
+pub(crate) fn spawn_report(
+    level: Level,
+    kind: &'static str,
+    message: String,
+    tags: Vec<(&'static str, String)>,
+) {
+    if REPORTER.get().is_some() {
+        tokio::spawn(report(level, kind, message, tags));
+    }
+}
+
+// The panicking thread may be the runtime one, so the report is sent from its own thread and runtime
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info.to_string();
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_default();
+        let thread = std::thread::current()
+            .name()
+            .unwrap_or("unnamed")
+            .to_string();
+        let sent = std::thread::spawn(move || {
+            if let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                runtime.block_on(report(
+                    Level::Fatal,
+                    "panic",
+                    message,
+                    vec![("location", location), ("thread", thread)],
+                ));
+            }
+        });
+        let _ = sent.join();
+        default_hook(info);
+    }));
+}
@@ -0,0 +1,60 @@
+use std::collections::VecDeque;
+
+use near_lake_framework::near_indexer_primitives;
+
+// Forks on NEAR are a few blocks deep at most
+const TRACKED_BLOCKS: usize = 100;
+
+#[derive(Debug, Clone)]
+pub(crate) struct TrackedBlock {
+    pub height: u64,
+    pub hash: near_indexer_primitives::CryptoHash,
+    pub timestamp: u64,
+}
+
+// Remembers the hashes of the last computed blocks.
+// NEAR Lake has only the final blocks, but the optimistic source may switch to another fork:
+// then the next block does not point to the last one with prev_hash
+pub(crate) struct ForkTracker {
+    blocks: VecDeque<TrackedBlock>,
+}
+
+impl ForkTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            blocks: VecDeque::with_capacity(TRACKED_BLOCKS),
+        }
+    }
+
+    // Returns the blocks of the discarded fork, the latest first
+    pub(crate) fn track(
+        &mut self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+    ) -> anyhow::Result<Vec<TrackedBlock>> {
+        let mut orphaned = vec![];
+        while let Some(last) = self.blocks.back() {
+            if last.hash == block_header.prev_hash {
+                break;
+            }
+            orphaned.extend(self.blocks.pop_back());
+        }
+        if self.blocks.is_empty() && !orphaned.is_empty() {
+            anyhow::bail!(
+                "Block {} at block_height {} does not continue any of the last {} blocks, prev_hash {}",
+                block_header.hash,
+                block_header.height,
+                orphaned.len(),
+                block_header.prev_hash
+            );
+        }
+        if self.blocks.len() == TRACKED_BLOCKS {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(TrackedBlock {
+            height: block_header.height,
+            hash: block_header.hash,
+            timestamp: block_header.timestamp,
+        });
+        Ok(orphaned)
+    }
+}
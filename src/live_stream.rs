@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+
+// Blocks a slow subscriber may fall behind before it misses the changes
+const CHANNEL_CAPACITY: usize = 1024;
+const KEEP_ALIVE: std::time::Duration = std::time::Duration::from_secs(15);
+
+// Server-sent events with the balance changes right after the block is committed:
+// `GET /stream?accounts=alice.near,bob.near&causes=TRANSACTION,RECEIPT`, both filters are optional.
+// Each change is `data: <json>` with `id: <block_height>:<index_in_block>`.
+// The subscriber which falls behind gets `event: lagged` with the number of the missed blocks,
+// it should catch up from the database.
+// The blocks stored from the disk buffer after the database outage are not streamed
+#[derive(Clone)]
+pub(crate) struct LiveStream {
+    sender: tokio::sync::broadcast::Sender<std::sync::Arc<StreamBlock>>,
+}
+
+// Serialized once, shared by all the subscribers
+pub(crate) struct StreamBlock {
+    changes: Vec<StreamChange>,
+}
+
+struct StreamChange {
+    account_id: String,
+    cause: String,
+    event: String,
+}
+
+struct Subscription {
+    accounts: Option<HashSet<String>>,
+    causes: Option<HashSet<String>>,
+}
+
+impl LiveStream {
+    pub(crate) fn start(port: u16) -> Self {
+        let (sender, _) = tokio::sync::broadcast::channel(CHANNEL_CAPACITY);
+        let stream = Self { sender };
+        tokio::spawn(stream.clone().serve(port));
+        stream
+    }
+
+    pub(crate) fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        // Nobody listens
+        if self.sender.receiver_count() == 0 || changes.is_empty() {
+            return Ok(());
+        }
+        let mut block = StreamBlock { changes: vec![] };
+        for change in changes {
+            block.changes.push(StreamChange {
+                account_id: change.affected_account_id.clone(),
+                cause: change.cause.clone(),
+                event: format!(
+                    "id: {}:{}\ndata: {}\n\n",
+                    block_header.height,
+                    change.index_in_block,
+                    serde_json::to_string(&crate::sinks::BalanceChangeEvent::new(
+                        block_header,
+                        change
+                    ))?
+                ),
+            });
+        }
+        // Fails only when the last subscriber has just gone
+        let _ = self.sender.send(std::sync::Arc::new(block));
+        Ok(())
+    }
+
+    async fn serve(self, port: u16) -> anyhow::Result<()> {
+        let address = std::net::SocketAddr::from(([0, 0, 0, 0], port));
+        tracing::info!(
+            target: crate::INDEXER,
+            "Starting live stream on http://{}/stream",
+            address
+        );
+        let make_service = make_service_fn(move |_| {
+            let stream = self.clone();
+            async move {
+                Ok::<_, anyhow::Error>(service_fn(move |request| {
+                    let stream = stream.clone();
+                    async move { stream.handle(request) }
+                }))
+            }
+        });
+        hyper::Server::bind(&address).serve(make_service).await?;
+        Ok(())
+    }
+
+    fn handle(&self, request: Request<Body>) -> anyhow::Result<Response<Body>> {
+        if request.method() != Method::GET || request.uri().path() != "/stream" {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("Not found"))?);
+        }
+        let subscription = Subscription::from_query(request.uri().query());
+        let (sender, body) = Body::channel();
+        tokio::spawn(forward(self.sender.subscribe(), sender, subscription));
+        Ok(Response::builder()
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .body(body)?)
+    }
+}
+
+impl Subscription {
+    fn from_query(query: Option<&str>) -> Self {
+        let mut subscription = Self {
+            accounts: None,
+            causes: None,
+        };
+        let url = match reqwest::Url::parse(&format!("http://localhost/?{}", query.unwrap_or(""))) {
+            Ok(url) => url,
+            Err(_) => return subscription,
+        };
+        for (key, value) in url.query_pairs() {
+            let values = Some(value.split(',').map(str::to_string).collect());
+            match key.as_ref() {
+                "accounts" => subscription.accounts = values,
+                "causes" => subscription.causes = values,
+                _ => {}
+            }
+        }
+        subscription
+    }
+
+    fn matches(&self, change: &StreamChange) -> bool {
+        self.accounts
+            .as_ref()
+            .map_or(true, |accounts| accounts.contains(&change.account_id))
+            && self
+                .causes
+                .as_ref()
+                .map_or(true, |causes| causes.contains(&change.cause))
+    }
+}
+
+// Runs until the client disconnects
+async fn forward(
+    mut receiver: tokio::sync::broadcast::Receiver<std::sync::Arc<StreamBlock>>,
+    mut sender: hyper::body::Sender,
+    subscription: Subscription,
+) {
+    loop {
+        let chunk = match tokio::time::timeout(KEEP_ALIVE, receiver.recv()).await {
+            Ok(Ok(block)) => block
+                .changes
+                .iter()
+                .filter(|change| subscription.matches(change))
+                .map(|change| change.event.as_str())
+                .collect::<String>(),
+            Ok(Err(tokio::sync::broadcast::error::RecvError::Lagged(missed))) => {
+                format!("event: lagged\ndata: {}\n\n", missed)
+            }
+            Ok(Err(tokio::sync::broadcast::error::RecvError::Closed)) => return,
+            // Proxies close the idle connections
+            Err(_) => ":\n\n".to_string(),
+        };
+        if !chunk.is_empty() && sender.send_data(chunk.into()).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use futures::StreamExt;
+use near_lake_framework::near_indexer_primitives;
+use tokio::sync::mpsc;
+
+// Reads the blocks from the local copy of NEAR Lake bucket.
+// The directory layout is the same as in S3: `<root>/<block_height:012>/block.json`,
+// `<root>/<block_height:012>/shard_<shard_id>.json`
+// The stream finishes when all the blocks from the directory are sent
+pub(crate) fn streamer(
+    root: PathBuf,
+    start_block_height: u64,
+    blocks_preload_pool_size: usize,
+) -> (
+    tokio::task::JoinHandle<anyhow::Result<()>>,
+    mpsc::Receiver<near_indexer_primitives::StreamerMessage>,
+) {
+    let (sender, receiver) = mpsc::channel(blocks_preload_pool_size);
+    (
+        tokio::spawn(start(
+            sender,
+            root,
+            start_block_height,
+            blocks_preload_pool_size,
+        )),
+        receiver,
+    )
+}
+
+async fn start(
+    streamer_message_sink: mpsc::Sender<near_indexer_primitives::StreamerMessage>,
+    root: PathBuf,
+    start_block_height: u64,
+    blocks_preload_pool_size: usize,
+) -> anyhow::Result<()> {
+    let block_heights = list_blocks(&root, start_block_height).await?;
+    tracing::info!(
+        target: crate::INDEXER,
+        "Found {} blocks in {} starting from {}",
+        block_heights.len(),
+        root.display(),
+        start_block_height
+    );
+
+    // Up to `blocks_preload_pool_size` blocks are read at the same time, `buffered` keeps their order
+    let mut streamer_messages = futures::stream::iter(block_heights)
+        .map(|block_height| read_streamer_message(&root, block_height))
+        .buffered(blocks_preload_pool_size);
+    while let Some(streamer_message) = streamer_messages.next().await {
+        if streamer_message_sink.send(streamer_message?).await.is_err() {
+            // The receiver is dropped, nobody waits for the blocks anymore
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn list_blocks(root: &Path, start_block_height: u64) -> anyhow::Result<Vec<u64>> {
+    let mut block_heights = vec![];
+    let mut entries = tokio::fs::read_dir(root).await.map_err(|err| {
+        anyhow::anyhow!("Failed to read lake directory {}: {}", root.display(), err)
+    })?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        if let Some(block_height) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| u64::from_str(name).ok())
+        {
+            if block_height >= start_block_height {
+                block_heights.push(block_height);
+            }
+        }
+    }
+    block_heights.sort_unstable();
+    Ok(block_heights)
+}
+
+async fn read_streamer_message(
+    root: &Path,
+    block_height: u64,
+) -> anyhow::Result<near_indexer_primitives::StreamerMessage> {
+    let block_dir = root.join(format!("{:0>12}", block_height));
+    let block: near_indexer_primitives::views::BlockView =
+        read_json(&block_dir.join("block.json")).await?;
+
+    let mut shards = Vec::with_capacity(block.chunks.len());
+    for shard_id in 0..block.chunks.len() {
+        shards.push(read_json(&block_dir.join(format!("shard_{}.json", shard_id))).await?);
+    }
+
+    Ok(near_indexer_primitives::StreamerMessage { block, shards })
+}
+
+async fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|err| anyhow::anyhow!("Failed to read {}: {}", path.display(), err))?;
+    serde_json::from_slice(&bytes)
+        .map_err(|err| anyhow::anyhow!("Failed to parse {}: {}", path.display(), err))
+}
@@ -1,15 +1,34 @@
 // // TODO cleanup imports in all the files in the end
-use cached::SizedCache;
 use clap::Parser;
-use futures::StreamExt;
 
 use near_lake_framework::near_indexer_primitives;
 use tokio::sync::Mutex;
 use tracing_subscriber::EnvFilter;
 
+mod admin;
+mod api;
+mod cache;
+mod chain_head;
+mod config_file;
 mod configs;
 mod db_adapters;
+mod error_reporting;
+mod forks;
+mod live_stream;
+mod local_lake;
+mod metrics;
+mod mock_rpc;
 mod models;
+#[cfg(feature = "nearcore-state")]
+mod nearcore_state;
+mod networks;
+mod prices;
+mod reload;
+mod rpc_cassette;
+mod shared_cache;
+mod sinks;
+mod throttle;
+mod verification;
 
 // TODO naming
 pub(crate) const INDEXER: &str = "indexer";
@@ -17,6 +36,8 @@ pub(crate) const INDEXER: &str = "indexer";
 const INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
 const MAX_DELAY_TIME: std::time::Duration = std::time::Duration::from_secs(120);
 const RETRY_COUNT: usize = 10;
+// RPC requests are retried inside, the block is computed again only after the other errors
+const BLOCK_RETRY_COUNT: usize = 3;
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct BalanceDetails {
@@ -30,59 +51,311 @@ pub struct AccountWithBalance {
     pub balance: BalanceDetails,
 }
 
-pub type BalanceCache =
-    std::sync::Arc<Mutex<SizedCache<near_indexer_primitives::types::AccountId, BalanceDetails>>>;
+pub type BalanceCache = std::sync::Arc<
+    Mutex<cache::JournaledCache<near_indexer_primitives::types::AccountId, BalanceDetails>>,
+>;
+
+// Postponed receipt id -> block height where it was postponed
+pub type PostponedReceipts =
+    std::sync::Arc<Mutex<cache::JournaledCache<near_indexer_primitives::CryptoHash, u64>>>;
+
+// Receipt id -> hash of the transaction it descends from. The same receipt always gets the same
+// transaction, so the cache is neither rolled back nor cleared on forks
+pub type ReceiptTransactions = std::sync::Arc<
+    Mutex<
+        cache::JournaledCache<
+            near_indexer_primitives::CryptoHash,
+            near_indexer_primitives::CryptoHash,
+        >,
+    >,
+>;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
 
-    let opts = crate::configs::Opts::parse();
-    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL")?).await?;
+    // The reconciler of the optimistic blocks needs the options in background
+    let configs = config_file::load_args()?;
+    let mut networks: Vec<(Option<String>, std::sync::Arc<configs::Opts>)> = configs
+        .iter()
+        .map(|config_args| {
+            (
+                config_args.network.clone(),
+                std::sync::Arc::new(crate::configs::Opts::parse_from(&config_args.args)),
+            )
+        })
+        .collect();
+    // The shared options are the same in all the networks
+    let opts = networks[0].1.clone();
+    if let Some(configs::SubCommand::PrintConfig) = opts.command {
+        return config_file::print_config(&configs);
+    }
+    init_tracing();
+    db_adapters::configure_insert_batch_size(opts.insert_batch_size, opts.adaptive_batch_size);
+    db_adapters::configure_bulk_load(opts.bulk_load);
+    db_adapters::configure_min_delta(opts.min_delta_yocto, opts.dust_updates_current_balances);
+    db_adapters::configure_skip_zero_delta(opts.skip_zero_delta);
+    db_adapters::configure_store_unknown_causes(opts.store_unknown_causes);
+    db_adapters::balance_changes::configure_receipt_cache_size(opts.receipt_cache_size);
+    db_adapters::balance_changes::configure_split_actions(opts.split_actions);
+    db_adapters::balance_changes::configure_skipped_state_changes_log(
+        opts.log_skipped_state_changes_every,
+    );
+    db_adapters::notify::configure_pg_notify(opts.pg_notify);
+    db_adapters::explorer_compat::configure_compat_schema(opts.compat_schema);
+    db_adapters::fee_details::configure_fee_details(opts.fee_details);
+    db_adapters::shard_assignment::configure_shards(opts.shards.clone());
+    db_adapters::finality::configure_finality(opts.finality);
+    db_adapters::account_batch::configure_account_batching(opts.batch_account_queries);
+    db_adapters::account_filter::configure_account_filter(
+        db_adapters::account_filter::AccountFilter::from_opts(&opts)?,
+    );
+
+    error_reporting::configure_error_reporting(
+        opts.sentry_dsn.as_deref(),
+        opts.error_webhook_url.as_deref(),
+    )?;
+    throttle::configure_throttles(opts.max_blocks_per_second, opts.max_db_writes_per_second)?;
+    rpc_cassette::configure_rpc_cassette(opts.rpc_record.clone(), opts.rpc_replay.clone())?;
+    #[cfg(feature = "nearcore-state")]
+    if let Some(path) = &opts.nearcore_state {
+        nearcore_state::configure_nearcore_state(path)?;
+    }
+    #[cfg(not(feature = "nearcore-state"))]
+    if opts.nearcore_state.is_some() {
+        anyhow::bail!("--nearcore-state needs the build with --features nearcore-state");
+    }
+    if networks.len() > 1 {
+        return networks::run(
+            networks
+                .into_iter()
+                .map(|(name, opts)| (name.unwrap_or_default(), opts))
+                .collect(),
+        )
+        .await;
+    }
+    let (_, opts) = networks.remove(0);
+    db_adapters::configure_network(db_adapters::Network::new(
+        opts.db_schema.clone(),
+        opts.table_prefix.clone(),
+    )?)?;
+    run_network(opts).await
+}
+
+// Everything after the process-wide settings, once per network in the multi-network mode
+async fn run_network(opts: std::sync::Arc<configs::Opts>) -> anyhow::Result<()> {
+    let rpc_url = match &opts.mock_rpc {
+        Some(path) => mock_rpc::start(path).await?,
+        None => opts.rpc_url()?,
+    };
+    let json_rpc_client = near_jsonrpc_client::JsonRpcClient::connect(&rpc_url);
+    db_adapters::configure_protocol_treasury_account(
+        opts.protocol_treasury_account.clone(),
+        &json_rpc_client,
+    )
+    .await?;
+    // Before the storage, it refuses to connect to the outdated schema
+    if let Some(configs::SubCommand::UpgradeSchema(args)) = &opts.command {
+        if !opts.database.starts_with("postgres") {
+            anyhow::bail!("upgrade-schema works only with Postgres, the other storages create their tables themselves");
+        }
+        let pool = db_adapters::pool::connect(&opts, &opts.database).await?;
+        return db_adapters::schema_version::upgrade(&pool, args.assume_version).await;
+    }
+    let storage = db_adapters::storage::connect(&opts, &json_rpc_client).await?;
+    if opts.compat_schema.is_some() && storage.postgres_pool().is_none() {
+        anyhow::bail!("--compat-schema is not supported for {}", storage.name());
+    }
+    if opts.heal_drift
+        && (storage.postgres_pool().is_none() || opts.verify_every_n_blocks.is_none())
+    {
+        anyhow::bail!("--heal-drift needs Postgres and --verify-every-n-blocks");
+    }
+    if opts.fee_details && storage.postgres_pool().is_none() {
+        anyhow::bail!("--fee-details is not supported for {}", storage.name());
+    }
+    if opts.finality == configs::Finality::Optimistic {
+        if storage.postgres_pool().is_none() {
+            anyhow::bail!(
+                "--finality optimistic is not supported for {}",
+                storage.name()
+            );
+        }
+        if !matches!(opts.source, configs::Source::LakeLocal(_)) {
+            anyhow::bail!("--finality optimistic needs --source lake-local:<path>, NEAR Lake has only the final blocks");
+        }
+    }
+    if opts.shards.is_some() {
+        if storage.postgres_pool().is_none() {
+            anyhow::bail!("--shards is not supported for {}", storage.name());
+        }
+        // They need the whole block
+        if opts.index_ft || opts.top_accounts.is_some() || opts.detect_anomalies {
+            anyhow::bail!(
+                "--shards is not supported with --index-ft, --top-accounts or --detect-anomalies"
+            );
+        }
+    }
+    let sinks = std::sync::Arc::new(if opts.dry_run {
+        vec![]
+    } else {
+        sinks::connect(&opts, storage.postgres_pool()).await?
+    });
+    reload::spawn_sighup_handler(opts.clone(), sinks.clone())?;
     // TODO Error: while executing migrations: error returned from database: 1128 (HY000): Function 'near_indexer.GET_LOCK' is not defined
     // sqlx::migrate!().run(&pool).await?;
 
-    let start_block_height = match opts.start_block_height {
-        Some(x) => x,
-        None => models::start_after_interruption(&pool).await?,
-    };
-    let config = near_lake_framework::LakeConfigBuilder::default()
-        .s3_bucket_name(opts.s3_bucket_name)
-        .s3_region_name(opts.s3_region_name)
-        .start_block_height(start_block_height)
-        .blocks_preload_pool_size(1000)
-        .build()?;
-    init_tracing();
+    if let Some(command) = &opts.command {
+        let pool = storage.postgres_pool().ok_or_else(|| {
+            anyhow::anyhow!("Subcommands are not supported for {}", storage.name())
+        })?;
+        return run_subcommand(command, &opts, pool, &json_rpc_client).await;
+    }
 
-    let (lake_handle, stream) = near_lake_framework::streamer(config);
+    // The blocks before `stored_from` are stored already, they are streamed again only for the sinks
+    let (start_block_height, stored_from) = match opts.start_block_height {
+        Some(x) => (x, x),
+        None if opts.dry_run => anyhow::bail!("--dry-run requires --start-block-height"),
+        None => match storage.start_after_interruption().await? {
+            0 => {
+                let genesis_block_height = opts.genesis_block_height()?;
+                (genesis_block_height, genesis_block_height)
+            }
+            x => match sinks::start_after_interruption(&sinks).await? {
+                Some(replay_from) if replay_from < x => {
+                    tracing::info!(
+                        target: INDEXER,
+                        "Publishing blocks {}..{} again to the sinks which missed them",
+                        replay_from,
+                        x
+                    );
+                    (replay_from, x)
+                }
+                _ => (x, x),
+            },
+        },
+    };
 
-    // We want to prevent unnecessary RPC queries to find previous balance
-    let balances_cache: BalanceCache =
-        std::sync::Arc::new(Mutex::new(SizedCache::with_size(100_000)));
+    if let Some(url) = &opts.shared_balance_cache {
+        // The balances of the discarded blocks would stay in the cache
+        if opts.finality == configs::Finality::Optimistic {
+            anyhow::bail!("--shared-balance-cache works only with --finality final");
+        }
+        db_adapters::configure_shared_balance_cache(std::sync::Arc::new(
+            shared_cache::RedisBalanceCache::new(
+                url,
+                &opts.shared_balance_cache_prefix,
+                opts.chain_id,
+            )?,
+        ))?;
+    }
 
-    let json_rpc_client = near_jsonrpc_client::JsonRpcClient::connect(&opts.near_archival_rpc_url);
+    let ft_indexer = match (opts.index_ft, storage.postgres_pool()) {
+        (false, _) => None,
+        (true, Some(pool)) => Some(db_adapters::ft_balance_changes::FtIndexer::new(
+            pool.clone(),
+            opts.wrap_near_contract()?,
+            json_rpc_client.clone(),
+        )),
+        (true, None) => anyhow::bail!("--index-ft is not supported for {}", storage.name()),
+    };
 
-    let mut handlers = tokio_stream::wrappers::ReceiverStream::new(stream)
-        .map(|streamer_message| {
-            handle_streamer_message(streamer_message, &pool, &balances_cache, &json_rpc_client)
-        })
-        .buffer_unordered(1usize);
+    let prices = match &opts.price_provider {
+        Some(kind) => Some(prices::Prices::new(kind).await?),
+        None => None,
+    };
 
-    let mut time_now = std::time::Instant::now();
-    while let Some(handle_message) = handlers.next().await {
-        match handle_message {
-            Ok(block_height) => {
-                let elapsed = time_now.elapsed();
-                tracing::trace!(
-                    "Elapsed time spent on block {}: {:.3?}",
-                    block_height, elapsed
-                );
-                time_now = std::time::Instant::now();
-            }
-            Err(e) => {
-                return Err(anyhow::anyhow!(e));
-            }
+    // The networks of one process share the servers
+    static SERVERS: std::sync::Once = std::sync::Once::new();
+    SERVERS.call_once(|| {
+        if let Some(port) = opts.metrics_port {
+            tokio::spawn(metrics::init_server(port));
+        }
+        if let Some(port) = opts.admin_port {
+            tokio::spawn(admin::init_server(port));
         }
+    });
+    // The recorded or replayed RPC has no chain head
+    if (opts.metrics_port.is_some() || opts.alert_lag_blocks.is_some())
+        && !rpc_cassette::is_enabled()
+        && !networks::is_multi_network()
+    {
+        chain_head::spawn_lag_monitor(
+            json_rpc_client.clone(),
+            std::time::Duration::from_secs(opts.chain_head_interval_secs),
+            opts.alert_lag_blocks,
+        );
+    }
+    let live_stream = opts.stream_port.map(live_stream::LiveStream::start);
+
+    // We want to prevent unnecessary RPC queries to find previous balance
+    let balances_cache: BalanceCache = std::sync::Arc::new(Mutex::new(
+        cache::JournaledCache::with_size(100_000)
+            .with_missing_ttl(100_000, opts.missing_account_ttl_secs),
+    ));
+    let verifier = opts.verify_every_n_blocks.map(|every_n_blocks| {
+        let healing = match (opts.heal_drift, storage.postgres_pool()) {
+            (true, Some(pool)) => Some(verification::Healing {
+                pool: pool.clone(),
+                balances_cache: balances_cache.clone(),
+            }),
+            _ => None,
+        };
+        let (_, sender) = verification::start(json_rpc_client.clone(), healing);
+        (every_n_blocks, opts.verify_sample_size, sender)
+    });
+    let postponed_receipts: PostponedReceipts =
+        std::sync::Arc::new(Mutex::new(cache::JournaledCache::with_size(100_000)));
+
+    let (lake_handle, stream) = start_streamer(&opts, start_block_height)?;
+    let stream = match opts.prefetch_blocks {
+        0 => stream,
+        blocks_ahead => db_adapters::prefetch::start(
+            stream,
+            blocks_ahead,
+            balances_cache.clone(),
+            json_rpc_client.clone(),
+        ),
+    };
+    if let (configs::Finality::Optimistic, Some(pool)) = (opts.finality, storage.postgres_pool()) {
+        db_adapters::finality::spawn_reconciler(
+            opts.clone(),
+            pool.clone(),
+            json_rpc_client.clone(),
+            balances_cache.clone(),
+            postponed_receipts.clone(),
+        );
+    }
+
+    // fetch (lake) -> compute deltas -> insert.
+    // Computing needs the balances from the previous block, so each stage handles the blocks in order.
+    // Slow DB does not stop computing until the queue between them is full
+    let (computed_sender, computed_receiver) =
+        tokio::sync::mpsc::channel::<ComputedBlock>(opts.insert_queue_size);
+    let (_, stopped) = tokio::try_join!(
+        compute_stage(
+            stream,
+            computed_sender,
+            &balances_cache,
+            &postponed_receipts,
+            ft_indexer.as_ref(),
+            prices.as_ref(),
+            &json_rpc_client,
+        ),
+        insert_stage(
+            computed_receiver,
+            storage.as_ref(),
+            &sinks,
+            live_stream.as_ref(),
+            &opts,
+            &verifier,
+            stored_from
+        ),
+    )?;
+    // Lake Framework streams forever
+    if stopped {
+        lake_handle.abort();
+        return Ok(());
     }
 
     // propagate errors from the Lake Framework
@@ -93,22 +366,341 @@ async fn main() -> anyhow::Result<()> {
     }
 }
 
-async fn handle_streamer_message(
-    streamer_message: near_indexer_primitives::StreamerMessage,
+async fn run_subcommand(
+    command: &configs::SubCommand,
+    opts: &configs::Opts,
     pool: &sqlx::Pool<sqlx::Postgres>,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<()> {
+    match command {
+        configs::SubCommand::ImportGenesis(args) => {
+            db_adapters::genesis::import_genesis(pool, &args.genesis_file, json_rpc_client).await
+        }
+        configs::SubCommand::Repair(args) => {
+            let (lake_handle, mut stream) = start_streamer(opts, args.height)?;
+            let streamer_message = stream.recv().await.ok_or_else(|| {
+                anyhow::anyhow!("Block {} is not found in the source", args.height)
+            })?;
+            drop(stream);
+            lake_handle.abort();
+            if streamer_message.block.header.height != args.height {
+                anyhow::bail!(
+                    "Block {} is not found in the source, the next block is {}",
+                    args.height,
+                    streamer_message.block.header.height
+                );
+            }
+            db_adapters::repair::repair_block(
+                pool,
+                &streamer_message,
+                args.account.as_deref(),
+                json_rpc_client,
+            )
+            .await
+        }
+        configs::SubCommand::Reindex(args) => {
+            if args.from > args.to {
+                anyhow::bail!("--from should not be greater than --to");
+            }
+            let (lake_handle, stream) = start_streamer(opts, args.from)?;
+            let result = db_adapters::reindex::reindex_range(
+                pool,
+                stream,
+                args.from,
+                args.to,
+                args.parallelism,
+                json_rpc_client,
+            )
+            .await;
+            lake_handle.abort();
+            result
+        }
+        configs::SubCommand::Serve(args) => api::serve(pool, json_rpc_client, args.port).await,
+        configs::SubCommand::BalanceAt(args) => {
+            api::print_balance_at(pool, json_rpc_client, &args.account, args.timestamp).await
+        }
+        configs::SubCommand::CheckAccount(args) => {
+            db_adapters::check_account::check_account(
+                pool,
+                json_rpc_client,
+                &args.account,
+                args.heal,
+            )
+            .await
+        }
+        configs::SubCommand::Import(args) => db_adapters::import::import(pool, opts, args).await,
+        configs::SubCommand::Export(args) => {
+            if args.from > args.to {
+                anyhow::bail!("--from should not be later than --to");
+            }
+            db_adapters::export::export_statement(
+                pool,
+                args,
+                &opts.explorer_url(args.explorer_url.as_deref())?,
+            )
+            .await
+        }
+        configs::SubCommand::PrintConfig => unreachable!("The config is printed before connecting"),
+        configs::SubCommand::UpgradeSchema(_) => {
+            unreachable!("The schema is upgraded before connecting")
+        }
+    }
+}
+
+fn start_streamer(
+    opts: &configs::Opts,
+    start_block_height: u64,
+) -> anyhow::Result<(
+    tokio::task::JoinHandle<anyhow::Result<()>>,
+    tokio::sync::mpsc::Receiver<near_indexer_primitives::StreamerMessage>,
+)> {
+    Ok(match &opts.source {
+        configs::Source::LakeS3 => {
+            near_lake_framework::streamer(opts.to_lake_config(start_block_height)?)
+        }
+        configs::Source::LakeLocal(path) => {
+            local_lake::streamer(path.clone(), start_block_height, opts.preload_window)
+        }
+    })
+}
+
+// Streamer message with the balance changes computed for it,
+// and the blocks of the discarded fork to roll back before storing it
+type ComputedBlock = (
+    near_indexer_primitives::StreamerMessage,
+    Vec<models::balance_changes::BalanceChange>,
+    Vec<forks::TrackedBlock>,
+);
+
+async fn compute_stage(
+    mut stream: tokio::sync::mpsc::Receiver<near_indexer_primitives::StreamerMessage>,
+    computed_sender: tokio::sync::mpsc::Sender<ComputedBlock>,
     balances_cache: &BalanceCache,
+    postponed_receipts: &PostponedReceipts,
+    ft_indexer: Option<&db_adapters::ft_balance_changes::FtIndexer>,
+    prices: Option<&prices::Prices>,
     json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
-) -> anyhow::Result<u64> {
-    db_adapters::balance_changes::store_balance_changes(
-        pool,
-        &streamer_message.shards,
-        &streamer_message.block.header,
-        balances_cache,
-        json_rpc_client,
-    )
-    .await?;
+) -> anyhow::Result<()> {
+    let mut fork_tracker = forks::ForkTracker::new();
+    let receipt_transactions = db_adapters::balance_changes::new_receipt_transactions();
+    while let Some(mut streamer_message) = stream.recv().await {
+        admin::wait_while_paused().await;
+        throttle::BLOCKS.acquire().await;
+        db_adapters::shard_assignment::retain_assigned(&mut streamer_message.shards);
+        let orphaned = fork_tracker.track(&streamer_message.block.header)?;
+        if !orphaned.is_empty() {
+            tracing::warn!(
+                target: INDEXER,
+                "Block {} at block_height {} switches to another fork, {} blocks are discarded",
+                streamer_message.block.header.hash,
+                streamer_message.block.header.height,
+                orphaned.len()
+            );
+            metrics::ROLLED_BACK_BLOCKS_TOTAL.inc_by(orphaned.len() as u64);
+            // The balances after the discarded blocks are queried from RPC again
+            balances_cache.lock().await.cache_clear();
+            postponed_receipts.lock().await.cache_clear();
+        }
+        // FT balances are tracked in their own cache, the rows go straight to the database
+        if let Some(ft_indexer) = ft_indexer {
+            ft_indexer.process_block(&streamer_message).await?;
+        }
+        let mut changes = collect_block_changes(
+            &streamer_message,
+            balances_cache,
+            postponed_receipts,
+            &receipt_transactions,
+            json_rpc_client,
+        )
+        .await?;
+        db_adapters::prefetch::forget(&streamer_message.block.header.prev_hash);
+        shared_cache::publish_block(&streamer_message).await;
+        // Before the insert stage, so the sinks get the fiat values too
+        if let Some(prices) = prices {
+            prices
+                .enrich(&streamer_message.block.header, &mut changes)
+                .await;
+        }
+        if computed_sender
+            .send((streamer_message, changes, orphaned))
+            .await
+            .is_err()
+        {
+            // Insert stage has failed, the error is returned from there
+            break;
+        }
+        metrics::INSERT_QUEUE_DEPTH
+            .set((computed_sender.max_capacity() - computed_sender.capacity()) as i64);
+    }
+    Ok(())
+}
+
+// The caches are restored after the failed attempt, the retry starts from the balances before the block
+async fn collect_block_changes(
+    streamer_message: &near_indexer_primitives::StreamerMessage,
+    balances_cache: &BalanceCache,
+    postponed_receipts: &PostponedReceipts,
+    receipt_transactions: &ReceiptTransactions,
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+) -> anyhow::Result<Vec<models::balance_changes::BalanceChange>> {
+    let block_header = &streamer_message.block.header;
+    let mut retry_attempt = 0usize;
+    loop {
+        retry_attempt += 1;
+        balances_cache.lock().await.begin();
+        postponed_receipts.lock().await.begin();
+        let result = db_adapters::balance_changes::collect_balance_changes(
+            &streamer_message.shards,
+            block_header,
+            balances_cache,
+            postponed_receipts,
+            receipt_transactions,
+            json_rpc_client,
+        )
+        .await;
+        let err = match result {
+            Ok(changes) => {
+                balances_cache.lock().await.commit();
+                postponed_receipts.lock().await.commit();
+                return Ok(changes);
+            }
+            Err(err) => err,
+        };
+        let restored = balances_cache.lock().await.rollback();
+        postponed_receipts.lock().await.rollback();
+        if retry_attempt >= BLOCK_RETRY_COUNT {
+            return Err(err);
+        }
+        tracing::warn!(
+            target: INDEXER,
+            "Failed to compute block_height {}, {} cached balances are restored, retrying: {:#}",
+            block_header.height,
+            restored,
+            err
+        );
+    }
+}
+
+async fn insert_stage(
+    mut computed_receiver: tokio::sync::mpsc::Receiver<ComputedBlock>,
+    storage: &dyn db_adapters::storage::Storage,
+    sinks: &[Box<dyn sinks::Sink>],
+    live_stream: Option<&live_stream::LiveStream>,
+    opts: &configs::Opts,
+    verifier: &Option<(
+        u64,
+        usize,
+        tokio::sync::mpsc::Sender<verification::BlockSample>,
+    )>,
+    stored_from: u64,
+) -> anyhow::Result<bool> {
+    let mut disk_buffer = match &opts.disk_buffer_path {
+        Some(path) => Some(
+            db_adapters::disk_buffer::DiskBuffer::open(
+                path.clone(),
+                opts.disk_buffer_max_mb * 1024 * 1024,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let mut time_now = std::time::Instant::now();
+    while let Some((streamer_message, changes, orphaned)) = computed_receiver.recv().await {
+        admin::wait_while_paused().await;
+        let block_header = &streamer_message.block.header;
+        if !orphaned.is_empty() {
+            // The discarded blocks may wait in the buffer
+            if let Some(buffer) = disk_buffer.as_mut() {
+                buffer.drain(storage, sinks).await?;
+                if !buffer.is_empty() {
+                    anyhow::bail!(
+                        "Failed to store the buffered blocks before the rollback of {} blocks",
+                        orphaned.len()
+                    );
+                }
+            }
+            db_adapters::pool::retry_on_connection_error(opts.db_retry_count, || {
+                storage.rollback_blocks(&orphaned)
+            })
+            .await?;
+            sinks::rollback(sinks, &orphaned).await?;
+        }
+        metrics::observe_balance_changes(&changes);
+
+        // The blocks go to the database in order, so the new block waits behind the buffered ones
+        let behind_buffer = match disk_buffer.as_mut().filter(|buffer| !buffer.is_empty()) {
+            Some(buffer) => {
+                buffer.drain(storage, sinks).await?;
+                !buffer.is_empty()
+            }
+            None => false,
+        };
+        if let Some(buffer) = disk_buffer.as_mut().filter(|_| behind_buffer) {
+            buffer.push(&streamer_message, &changes).await?;
+        } else {
+            // Storing the block is idempotent, so we can repeat it after reconnect
+            let stored = db_adapters::pool::retry_on_connection_error(opts.db_retry_count, || {
+                storage.store_block(&streamer_message.shards, block_header, &changes)
+            })
+            .await;
+            match (stored, disk_buffer.as_mut()) {
+                (Ok(()), _) if block_header.height < stored_from => {
+                    sinks::replay(sinks, block_header, &changes).await?;
+                }
+                (Ok(()), _) => {
+                    // The sinks never get the block which may be lost in the storage
+                    sinks::publish(sinks, block_header, &changes).await?;
+                    if let Some(live_stream) = live_stream {
+                        live_stream.publish(block_header, &changes)?;
+                    }
+                }
+                (Err(err), Some(buffer)) if db_adapters::pool::is_connection_error(&err) => {
+                    buffer.push(&streamer_message, &changes).await?;
+                }
+                (Err(err), _) => return Err(err),
+            }
+        }
+        metrics::INDEXED_HEIGHT.set(block_header.height as i64);
 
-    Ok(streamer_message.block.header.height)
+        if let Some((every_n_blocks, sample_size, sender)) = verifier {
+            if block_header.height % every_n_blocks == 0 {
+                let sample = verification::BlockSample::new(block_header, &changes, *sample_size);
+                if sender.try_send(sample).is_err() {
+                    tracing::debug!(
+                        target: INDEXER,
+                        "Verifier is busy, skipping block_height {}",
+                        block_header.height
+                    );
+                }
+            }
+        }
+
+        let elapsed = time_now.elapsed();
+        tracing::trace!(
+            "Elapsed time spent on block {}: {:.3?}",
+            block_header.height,
+            elapsed
+        );
+        time_now = std::time::Instant::now();
+
+        if opts.stop_block_height.map_or(false, |stop_block_height| {
+            block_header.height >= stop_block_height
+        }) {
+            tracing::info!(
+                target: INDEXER,
+                "Stopping after block_height {}",
+                block_header.height
+            );
+            // The rest stays in the file for the next run
+            if let Some(buffer) = disk_buffer.as_mut() {
+                buffer.drain(storage, sinks).await?;
+            }
+            sinks::flush(sinks).await?;
+            return Ok(true);
+        }
+    }
+    sinks::flush(sinks).await?;
+    Ok(false)
 }
 
 fn init_tracing() {
@@ -0,0 +1,179 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use prometheus::{CounterVec, Encoder, Histogram, IntCounter, IntCounterVec, IntGauge};
+
+lazy_static::lazy_static! {
+    pub(crate) static ref DRIFT_DETECTED_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "drift_detected_total",
+        "Number of sampled accounts where the computed balance differs from RPC"
+    )
+    .unwrap();
+    pub(crate) static ref DRIFT_CORRECTIONS_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "drift_corrections_total",
+        "Number of DRIFT_CORRECTION rows stored to anchor the history to RPC"
+    )
+    .unwrap();
+    pub(crate) static ref INSERT_QUEUE_DEPTH: IntGauge = prometheus::register_int_gauge!(
+        "insert_queue_depth",
+        "Number of computed blocks waiting to be stored to the database"
+    )
+    .unwrap();
+    pub(crate) static ref INSERT_BATCH_SIZE: IntGauge = prometheus::register_int_gauge!(
+        "insert_batch_size",
+        "Number of rows in one INSERT query"
+    )
+    .unwrap();
+    pub(crate) static ref DISK_BUFFER_BLOCKS: IntGauge = prometheus::register_int_gauge!(
+        "disk_buffer_blocks",
+        "Number of blocks waiting in the disk buffer for the database"
+    )
+    .unwrap();
+    pub(crate) static ref DISK_BUFFER_BYTES: IntGauge = prometheus::register_int_gauge!(
+        "disk_buffer_bytes",
+        "Size of the disk buffer file"
+    )
+    .unwrap();
+    pub(crate) static ref ZERO_DELTA_SUPPRESSED_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "zero_delta_suppressed_total",
+        "Number of rows with both deltas equal to zero that were not stored"
+    )
+    .unwrap();
+    pub(crate) static ref RECEIPT_TRANSACTION_MISSES_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "receipt_transaction_misses_total",
+        "Number of receipt rows stored without parent_transaction_hash, the receipt was not in the cache"
+    )
+    .unwrap();
+    pub(crate) static ref BLOCK_ACTIVE_ACCOUNTS: IntGauge = prometheus::register_int_gauge!(
+        "block_active_accounts",
+        "Number of distinct accounts with balance changes in the latest stored block"
+    )
+    .unwrap();
+    pub(crate) static ref DAILY_ACTIVE_ACCOUNTS: IntGauge = prometheus::register_int_gauge!(
+        "daily_active_accounts",
+        "Approximate number of distinct accounts with balance changes during the current day (UTC)"
+    )
+    .unwrap();
+    pub(crate) static ref SECONDARY_INDEXED_HEIGHT: IntGauge = prometheus::register_int_gauge!(
+        "secondary_indexed_height",
+        "Height of the latest block stored to the secondary database"
+    )
+    .unwrap();
+    pub(crate) static ref SECONDARY_LAG_BLOCKS: IntGauge = prometheus::register_int_gauge!(
+        "secondary_lag_blocks",
+        "How many blocks the secondary database is behind the primary one"
+    )
+    .unwrap();
+    pub(crate) static ref SECONDARY_STORE_ERRORS_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "secondary_store_errors_total",
+        "Number of failed attempts to store a block to the secondary database"
+    )
+    .unwrap();
+    pub(crate) static ref ORPHANED_OPTIMISTIC_BLOCKS_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "orphaned_optimistic_blocks_total",
+        "Number of optimistic blocks which turned out not to be final"
+    )
+    .unwrap();
+    pub(crate) static ref ROLLED_BACK_BLOCKS_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "rolled_back_blocks_total",
+        "Number of stored blocks deleted because the next block switched to another fork"
+    )
+    .unwrap();
+    pub(crate) static ref PREFETCHED_BALANCES_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "prefetched_balances_total",
+        "Number of previous balances taken from the queries made ahead of computing"
+    )
+    .unwrap();
+    pub(crate) static ref QUARANTINED_FT_EVENT_VALUES_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "quarantined_ft_event_values_total",
+        "Number of malformed account ids from the NEP-141 event logs stored to quarantined_ft_event_values"
+    )
+    .unwrap();
+    pub(crate) static ref UNKNOWN_STATE_CHANGE_CAUSES_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "unknown_state_change_causes_total",
+        "Number of balance changing state changes with the cause unknown to this version"
+    )
+    .unwrap();
+    pub(crate) static ref PAUSED: IntGauge = prometheus::register_int_gauge!(
+        "paused",
+        "1 while indexing is paused with the admin endpoint"
+    )
+    .unwrap();
+    pub(crate) static ref THROTTLED_SECONDS_TOTAL: CounterVec = prometheus::register_counter_vec!(
+        "throttled_seconds_total",
+        "Time spent waiting for --max-blocks-per-second and --max-db-writes-per-second",
+        &["throttle"]
+    )
+    .unwrap();
+    pub(crate) static ref MISSING_ACCOUNT_HITS_TOTAL: IntCounter = prometheus::register_int_counter!(
+        "missing_account_hits_total",
+        "Number of RPC queries not sent for the accounts known not to exist, see --missing-account-ttl-secs"
+    )
+    .unwrap();
+    pub(crate) static ref SHARED_BALANCE_CACHE_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "shared_balance_cache_total",
+        "Number of lookups in the shared balance cache by result: hit, miss or error",
+        &["result"]
+    )
+    .unwrap();
+    pub(crate) static ref SKIPPED_STATE_CHANGES_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "skipped_state_changes_total",
+        "Number of state changes not turned into balance changes by value kind and cause",
+        &["value", "cause"]
+    )
+    .unwrap();
+    pub(crate) static ref BALANCE_CHANGES_TOTAL: IntCounterVec = prometheus::register_int_counter_vec!(
+        "balance_changes_total",
+        "Number of computed balance changes by cause and direction",
+        &["cause", "direction"]
+    )
+    .unwrap();
+    pub(crate) static ref BLOCK_BALANCE_CHANGES: Histogram = prometheus::register_histogram!(
+        "block_balance_changes",
+        "Number of computed balance changes in one block",
+        prometheus::exponential_buckets(1.0, 2.0, 14).unwrap()
+    )
+    .unwrap();
+    pub(crate) static ref CHAIN_HEAD_HEIGHT: IntGauge = prometheus::register_int_gauge!(
+        "chain_head_height",
+        "Height of the latest final block according to RPC"
+    )
+    .unwrap();
+    pub(crate) static ref LAG_BLOCKS: IntGauge = prometheus::register_int_gauge!(
+        "lag_blocks",
+        "How many blocks the database is behind the chain head"
+    )
+    .unwrap();
+    pub(crate) static ref INDEXED_HEIGHT: IntGauge = prometheus::register_int_gauge!(
+        "indexed_height",
+        "Height of the latest block stored to the database"
+    )
+    .unwrap();
+}
+
+// The cause mix is stable on the normal blocks, the sudden change of it
+// usually means the protocol upgrade broke the computation
+pub(crate) fn observe_balance_changes(changes: &[crate::models::balance_changes::BalanceChange]) {
+    for change in changes {
+        BALANCE_CHANGES_TOTAL
+            .with_label_values(&[&change.cause, &change.direction])
+            .inc();
+    }
+    BLOCK_BALANCE_CHANGES.observe(changes.len() as f64);
+}
+
+async fn serve_metrics(_request: Request<Body>) -> anyhow::Result<Response<Body>> {
+    let mut buffer = vec![];
+    prometheus::TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(Response::new(Body::from(buffer)))
+}
+
+// Exposes all the registered metrics at `http://0.0.0.0:<port>/metrics`
+pub(crate) async fn init_server(port: u16) -> anyhow::Result<()> {
+    let address = std::net::SocketAddr::from(([0, 0, 0, 0], port));
+    tracing::info!(target: crate::INDEXER, "Starting metrics server on {}", address);
+
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, anyhow::Error>(service_fn(serve_metrics)) });
+    Server::bind(&address).serve(make_service).await?;
+    Ok(())
+}
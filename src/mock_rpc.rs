@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response};
+use near_lake_framework::near_indexer_primitives;
+
+// ViewAccount response recorded from the archival RPC, `account` is None for UNKNOWN_ACCOUNT
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct RecordedAccountView {
+    pub account_id: near_indexer_primitives::types::AccountId,
+    pub block_hash: near_indexer_primitives::CryptoHash,
+    #[serde(default)]
+    pub block_height: u64,
+    pub account: Option<near_indexer_primitives::views::AccountView>,
+}
+
+type Recordings = HashMap<
+    (
+        near_indexer_primitives::types::AccountId,
+        near_indexer_primitives::CryptoHash,
+    ),
+    RecordedAccountView,
+>;
+
+// JSON RPC on the local port which answers `view_account` by block hash from the recorded responses,
+// so the problematic blocks (like 22633808) are computed with exactly the balances seen at triage time.
+// Everything that is not recorded fails, the indexer should not silently go to the real RPC
+pub(crate) async fn start(path: &std::path::Path) -> anyhow::Result<String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("Failed to read {}: {}", path.display(), err))?;
+    let recordings: Vec<RecordedAccountView> = serde_json::from_str(&content)?;
+    let recordings: std::sync::Arc<Recordings> = std::sync::Arc::new(
+        recordings
+            .into_iter()
+            .map(|recording| {
+                (
+                    (recording.account_id.clone(), recording.block_hash),
+                    recording,
+                )
+            })
+            .collect(),
+    );
+
+    let make_service = make_service_fn(move |_| {
+        let recordings = recordings.clone();
+        async move {
+            Ok::<_, anyhow::Error>(service_fn(move |request| {
+                let recordings = recordings.clone();
+                async move { handle(&recordings, request).await }
+            }))
+        }
+    });
+    let server = hyper::Server::try_bind(&std::net::SocketAddr::from(([127, 0, 0, 1], 0)))?
+        .serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tracing::info!(
+        target: crate::INDEXER,
+        "Serving the recorded RPC responses from {} on {}",
+        path.display(),
+        url
+    );
+    tokio::spawn(server);
+    Ok(url)
+}
+
+async fn handle(recordings: &Recordings, request: Request<Body>) -> anyhow::Result<Response<Body>> {
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+    let request: serde_json::Value = serde_json::from_slice(&body)?;
+    let (key, value) = match view_account_key(&request) {
+        Some(key) => match recordings.get(&key) {
+            Some(recording) => respond(recording),
+            None => internal_error(format!(
+                "No recorded response for account {}, block_hash {}",
+                key.0, key.1
+            )),
+        },
+        None => internal_error(format!("Only view_account is recorded, got {}", request)),
+    };
+    let mut response = serde_json::json!({ "jsonrpc": "2.0", "id": request["id"] });
+    response[key] = value;
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(response.to_string()))?)
+}
+
+fn view_account_key(
+    request: &serde_json::Value,
+) -> Option<(
+    near_indexer_primitives::types::AccountId,
+    near_indexer_primitives::CryptoHash,
+)> {
+    let params = &request["params"];
+    if request["method"] != "query" || params["request_type"] != "view_account" {
+        return None;
+    }
+    Some((
+        params["account_id"].as_str()?.parse().ok()?,
+        params["block_id"].as_str()?.parse().ok()?,
+    ))
+}
+
+fn respond(recording: &RecordedAccountView) -> (&'static str, serde_json::Value) {
+    match &recording.account {
+        Some(account) => {
+            let mut result = serde_json::to_value(account).unwrap_or_default();
+            result["block_height"] = recording.block_height.into();
+            result["block_hash"] = recording.block_hash.to_string().into();
+            ("result", result)
+        }
+        None => (
+            "error",
+            serde_json::json!({
+                "name": "HANDLER_ERROR",
+                "cause": {
+                    "name": "UNKNOWN_ACCOUNT",
+                    "info": {
+                        "requested_account_id": recording.account_id,
+                        "block_height": recording.block_height,
+                        "block_hash": recording.block_hash,
+                    },
+                },
+                "code": -32000,
+                "message": "Server error",
+                "data": format!("account {} does not exist while viewing", recording.account_id),
+            }),
+        ),
+    }
+}
+
+fn internal_error(message: String) -> (&'static str, serde_json::Value) {
+    (
+        "error",
+        serde_json::json!({
+            "name": "INTERNAL_ERROR",
+            "cause": {
+                "name": "INTERNAL_ERROR",
+                "info": { "error_message": message },
+            },
+            "code": -32000,
+            "message": "Server error",
+            "data": message,
+        }),
+    )
+}
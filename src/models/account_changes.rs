@@ -0,0 +1,58 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+// Row of account_changes in the near-indexer-for-explorer layout, `id` is generated by the database
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct AccountChange {
+    pub affected_account_id: String,
+    pub changed_in_block_timestamp: BigDecimal,
+    pub changed_in_block_hash: String,
+    pub caused_by_transaction_hash: Option<String>,
+    pub caused_by_receipt_id: Option<String>,
+    pub update_reason: String,
+    pub affected_account_nonstaked_balance: BigDecimal,
+    pub affected_account_staked_balance: BigDecimal,
+    pub affected_account_storage_usage: BigDecimal,
+    pub index_in_block: i32,
+}
+
+impl crate::models::SqlxMethods for AccountChange {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.affected_account_id);
+        args.add(&self.changed_in_block_timestamp);
+        args.add(&self.changed_in_block_hash);
+        args.add(&self.caused_by_transaction_hash);
+        args.add(&self.caused_by_receipt_id);
+        args.add(&self.update_reason);
+        args.add(&self.affected_account_nonstaked_balance);
+        args.add(&self.affected_account_staked_balance);
+        args.add(&self.affected_account_storage_usage);
+        args.add(&self.index_in_block);
+    }
+
+    // update_reason is the enum, the text parameter needs the explicit cast
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!("INSERT INTO {} (affected_account_id, changed_in_block_timestamp, \
+            changed_in_block_hash, caused_by_transaction_hash, caused_by_receipt_id, update_reason, \
+            affected_account_nonstaked_balance, affected_account_staked_balance, \
+            affected_account_storage_usage, index_in_block) \
+            SELECT affected_account_id, changed_in_block_timestamp, changed_in_block_hash, \
+            caused_by_transaction_hash, caused_by_receipt_id, \
+            update_reason::state_change_reason_kind, affected_account_nonstaked_balance, \
+            affected_account_staked_balance, affected_account_storage_usage, index_in_block \
+            FROM (VALUES ",
+            crate::db_adapters::table("account_changes")
+        )
+            + &crate::models::create_placeholders_chain(count, AccountChange::field_count())?
+            + ") AS changes (affected_account_id, changed_in_block_timestamp, \
+            changed_in_block_hash, caused_by_transaction_hash, caused_by_receipt_id, update_reason, \
+            affected_account_nonstaked_balance, affected_account_staked_balance, \
+            affected_account_storage_usage, index_in_block)")
+    }
+
+    fn name() -> String {
+        "account_changes".to_string()
+    }
+}
@@ -0,0 +1,39 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct Anomaly {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub account_id: String,
+    pub outflow_amount: BigDecimal,
+    pub trailing_mean: f64,
+    pub trailing_stddev: f64,
+    pub z_score: f64,
+}
+
+impl crate::models::SqlxMethods for Anomaly {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.account_id);
+        args.add(&self.outflow_amount);
+        args.add(self.trailing_mean);
+        args.add(self.trailing_stddev);
+        args.add(self.z_score);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("anomalies")
+        ) + &crate::models::create_placeholders_chain(count, Anomaly::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "anomalies".to_string()
+    }
+}
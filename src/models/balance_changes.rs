@@ -1,9 +1,12 @@
+use std::collections::HashMap;
+
 use bigdecimal::BigDecimal;
+use sha2::Digest;
 use sqlx::Arguments;
 
 use crate::models::FieldCount;
 
-#[derive(Debug, sqlx::FromRow, FieldCount)]
+#[derive(Debug, Clone, sqlx::FromRow, FieldCount, serde::Serialize, serde::Deserialize)]
 pub struct BalanceChange {
     pub block_timestamp: BigDecimal,
     pub receipt_id: Option<String>,
@@ -20,8 +23,90 @@ pub struct BalanceChange {
     pub absolute_staked_amount: BigDecimal,
     pub shard_id: i32,
     pub index_in_chunk: i32,
+    pub index_in_block: i32,
+    // NEAR/USD value of both deltas at the block time, see src/prices/mod.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fiat_value_usd: Option<BigDecimal>,
+    // Of the transaction or receipt, the rows without the execution outcome (e.g. rewards) have None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_burnt: Option<BigDecimal>,
+    // Of the receipt, for the rows caused by it. The call chain is predecessor -> receiver,
+    // affected/involved are the same accounts ordered by whose balance the row is about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub predecessor_account_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receiver_account_id: Option<String>,
+    // See set_event_id
+    #[serde(default)]
+    pub event_id: String,
+    // Of the receipt rows, the transaction the receipt descends from. The transaction rows have
+    // the receipt it is converted to in receipt_id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_transaction_hash: Option<String>,
+    // With --split-actions, the index of the action in the receipt for the rows of one action.
+    // None for the merged rows, see split_by_actions in src/db_adapters/balance_changes.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_in_receipt: Option<i32>,
+}
+
+impl BalanceChange {
+    // Hex sha256 of `block_hash:cause_id:cause:affected_account_id:position`. cause_id is the
+    // transaction_hash of the transaction rows, the receipt_id of the receipt rows and empty for the
+    // rest (rewards, genesis, corrections); position counts the previous rows of the block with
+    // the same cause_id, cause and account, e.g. the split rows of one receipt.
+    // Nothing positional is in it: the block indexed again with another --only-accounts, with
+    // the changed order of the shards or of the rows gets the same ids. It is the primary key
+    // of balance_changes, the exports and the streams dedupe and reference the changes by it
+    pub fn set_event_id(&mut self, block_hash: &str, position: usize) {
+        let cause_id = self
+            .transaction_hash
+            .as_deref()
+            .or(self.receipt_id.as_deref())
+            .unwrap_or_default();
+        let key = format!(
+            "{}:{}:{}:{}:{}",
+            block_hash, cause_id, self.cause, self.affected_account_id, position
+        );
+        self.event_id = hex::encode(sha2::Sha256::digest(key.as_bytes()));
+    }
+}
+
+// The changes of one block in the order of index_in_block
+pub fn set_event_ids(changes: &mut [BalanceChange], block_hash: &str) {
+    let mut positions: HashMap<(Option<String>, String, String), usize> = HashMap::new();
+    for change in changes {
+        let position = positions
+            .entry((
+                change
+                    .transaction_hash
+                    .clone()
+                    .or_else(|| change.receipt_id.clone()),
+                change.cause.clone(),
+                change.affected_account_id.clone(),
+            ))
+            .or_default();
+        change.set_event_id(block_hash, *position);
+        *position += 1;
+    }
 }
 
+// block_timestamp is bigint in the Timescale hypertable, see src/db_adapters/timescale.rs.
+// The filters should cast the parameter to bigint, otherwise the column is cast and the index is not used
+pub(crate) const SELECT_COLUMNS: &str = "block_timestamp::numeric AS block_timestamp, receipt_id, \
+    transaction_hash, affected_account_id, involved_account_id, direction, cause, status, \
+    delta_nonstaked_amount, absolute_nonstaked_amount, delta_staked_amount, absolute_staked_amount, \
+    shard_id, index_in_chunk, index_in_block, fiat_value_usd, gas_burnt, \
+    predecessor_account_id, receiver_account_id, event_id, parent_transaction_hash, \
+    index_in_receipt";
+
+// In the order of the fields. The inserts name them: finality is not a field
+// and it stands before gas_burnt in the table migrated from scratch
+const INSERT_COLUMNS: &str = "block_timestamp, receipt_id, transaction_hash, affected_account_id, \
+    involved_account_id, direction, cause, status, delta_nonstaked_amount, absolute_nonstaked_amount, \
+    delta_staked_amount, absolute_staked_amount, shard_id, index_in_chunk, index_in_block, \
+    fiat_value_usd, gas_burnt, predecessor_account_id, receiver_account_id, event_id, \
+    parent_transaction_hash, index_in_receipt";
+
 impl crate::models::SqlxMethods for BalanceChange {
     fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
         args.add(&self.block_timestamp);
@@ -38,11 +123,22 @@ impl crate::models::SqlxMethods for BalanceChange {
         args.add(&self.absolute_staked_amount);
         args.add(&self.shard_id);
         args.add(&self.index_in_chunk);
+        args.add(&self.index_in_block);
+        args.add(&self.fiat_value_usd);
+        args.add(&self.gas_burnt);
+        args.add(&self.predecessor_account_id);
+        args.add(&self.receiver_account_id);
+        args.add(&self.event_id);
+        args.add(&self.parent_transaction_hash);
+        args.add(&self.index_in_receipt);
     }
 
     fn insert_query(count: usize) -> anyhow::Result<String> {
-        Ok("INSERT INTO balance_changes VALUES ".to_owned()
-            + &crate::models::create_placeholders_chain(count, BalanceChange::field_count())?
+        Ok(format!(
+            "INSERT INTO {} ({}) VALUES ",
+            crate::db_adapters::table("balance_changes"),
+            INSERT_COLUMNS
+        ) + &crate::models::create_placeholders_chain(count, BalanceChange::field_count())?
             + " ON CONFLICT DO NOTHING")
     }
 
@@ -50,3 +146,39 @@ impl crate::models::SqlxMethods for BalanceChange {
         "balance_changes".to_string()
     }
 }
+
+impl crate::models::copy::CopyMethods for BalanceChange {
+    fn copy_statement() -> String {
+        format!(
+            "COPY {} ({}) FROM STDIN (FORMAT binary)",
+            crate::db_adapters::table("balance_changes"),
+            INSERT_COLUMNS
+        )
+    }
+
+    fn write_copy_row(&self, row: &mut crate::models::copy::CopyRow) -> anyhow::Result<()> {
+        row.add_numeric(&self.block_timestamp)?;
+        row.add_optional_text(&self.receipt_id);
+        row.add_optional_text(&self.transaction_hash);
+        row.add_text(&self.affected_account_id);
+        row.add_optional_text(&self.involved_account_id);
+        row.add_text(&self.direction);
+        row.add_text(&self.cause);
+        row.add_text(&self.status);
+        row.add_numeric(&self.delta_nonstaked_amount)?;
+        row.add_numeric(&self.absolute_nonstaked_amount)?;
+        row.add_numeric(&self.delta_staked_amount)?;
+        row.add_numeric(&self.absolute_staked_amount)?;
+        row.add_i32(self.shard_id);
+        row.add_i32(self.index_in_chunk);
+        row.add_i32(self.index_in_block);
+        row.add_optional_numeric(&self.fiat_value_usd)?;
+        row.add_optional_numeric(&self.gas_burnt)?;
+        row.add_optional_text(&self.predecessor_account_id);
+        row.add_optional_text(&self.receiver_account_id);
+        row.add_text(&self.event_id);
+        row.add_optional_text(&self.parent_transaction_hash);
+        row.add_optional_i32(self.index_in_receipt);
+        Ok(())
+    }
+}
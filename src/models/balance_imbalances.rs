@@ -0,0 +1,37 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, Clone, sqlx::FromRow, FieldCount)]
+pub struct BalanceImbalance {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub minted_amount: BigDecimal,
+    pub burnt_amount: BigDecimal,
+    pub delta_amount: BigDecimal,
+    pub imbalance_amount: BigDecimal,
+}
+
+impl crate::models::SqlxMethods for BalanceImbalance {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.minted_amount);
+        args.add(&self.burnt_amount);
+        args.add(&self.delta_amount);
+        args.add(&self.imbalance_amount);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("balance_imbalances")
+        ) + &crate::models::create_placeholders_chain(count, BalanceImbalance::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "balance_imbalances".to_string()
+    }
+}
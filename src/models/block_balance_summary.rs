@@ -0,0 +1,41 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct BlockBalanceSummary {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub tokens_burnt_amount: BigDecimal,
+    pub validators_reward_amount: BigDecimal,
+    pub delta_staked_amount: BigDecimal,
+    pub affected_accounts_count: i32,
+    pub treasury_reward_amount: BigDecimal,
+}
+
+impl crate::models::SqlxMethods for BlockBalanceSummary {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.tokens_burnt_amount);
+        args.add(&self.validators_reward_amount);
+        args.add(&self.delta_staked_amount);
+        args.add(&self.affected_accounts_count);
+        args.add(&self.treasury_reward_amount);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("block_balance_summary")
+        ) + &crate::models::create_placeholders_chain(
+            count,
+            BlockBalanceSummary::field_count(),
+        )? + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "block_balance_summary".to_string()
+    }
+}
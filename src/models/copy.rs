@@ -0,0 +1,84 @@
+use bigdecimal::BigDecimal;
+
+// https://www.postgresql.org/docs/current/sql-copy.html#id-1.9.3.55.9.4
+const COPY_SIGNATURE: &[u8] = b"PGCOPY\n\xff\r\n\0";
+
+pub trait CopyMethods {
+    fn copy_statement() -> String;
+
+    fn write_copy_row(&self, row: &mut CopyRow) -> anyhow::Result<()>;
+}
+
+// Rows in the binary format of `COPY ... FROM STDIN (FORMAT binary)`
+pub struct CopyRow {
+    buffer: Vec<u8>,
+}
+
+impl CopyRow {
+    pub fn header() -> Self {
+        let mut buffer = COPY_SIGNATURE.to_vec();
+        // flags and header extension length
+        buffer.extend_from_slice(&0i32.to_be_bytes());
+        buffer.extend_from_slice(&0i32.to_be_bytes());
+        Self { buffer }
+    }
+
+    pub fn start_row(&mut self, fields_count: usize) {
+        self.buffer
+            .extend_from_slice(&(fields_count as i16).to_be_bytes());
+    }
+
+    pub fn add_text(&mut self, value: &str) {
+        self.add_bytes(value.as_bytes());
+    }
+
+    pub fn add_optional_text(&mut self, value: &Option<String>) {
+        match value {
+            Some(value) => self.add_text(value),
+            None => self.buffer.extend_from_slice(&(-1i32).to_be_bytes()),
+        }
+    }
+
+    pub fn add_optional_numeric(&mut self, value: &Option<BigDecimal>) -> anyhow::Result<()> {
+        match value {
+            Some(value) => self.add_numeric(value)?,
+            None => self.buffer.extend_from_slice(&(-1i32).to_be_bytes()),
+        }
+        Ok(())
+    }
+
+    pub fn add_i32(&mut self, value: i32) {
+        self.add_bytes(&value.to_be_bytes());
+    }
+
+    pub fn add_optional_i32(&mut self, value: Option<i32>) {
+        match value {
+            Some(value) => self.add_i32(value),
+            None => self.buffer.extend_from_slice(&(-1i32).to_be_bytes()),
+        }
+    }
+
+    pub fn add_numeric(&mut self, value: &BigDecimal) -> anyhow::Result<()> {
+        let encoded = encode_numeric(value);
+        self.add_bytes(&encoded);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buffer.extend_from_slice(&(-1i16).to_be_bytes());
+        self.buffer
+    }
+
+    fn add_bytes(&mut self, value: &[u8]) {
+        self.buffer
+            .extend_from_slice(&(value.len() as i32).to_be_bytes());
+        self.buffer.extend_from_slice(value);
+    }
+}
+
+// The same binary encoding as sqlx uses for the query parameters
+fn encode_numeric(value: &BigDecimal) -> Vec<u8> {
+    let mut buffer = sqlx::postgres::PgArgumentBuffer::default();
+    let _ = sqlx::Encode::<sqlx::Postgres>::encode_by_ref(value, &mut buffer);
+    buffer.to_vec()
+}
@@ -0,0 +1,38 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct CurrentBalance {
+    pub account_id: String,
+    pub block_timestamp: BigDecimal,
+    pub nonstaked_amount: BigDecimal,
+    pub staked_amount: BigDecimal,
+}
+
+impl crate::models::SqlxMethods for CurrentBalance {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.account_id);
+        args.add(&self.block_timestamp);
+        args.add(&self.nonstaked_amount);
+        args.add(&self.staked_amount);
+    }
+
+    // Items in one query should have unique account_id
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} AS current_balances VALUES ",
+            crate::db_adapters::table("current_balances")
+        ) + &crate::models::create_placeholders_chain(count, CurrentBalance::field_count())?
+            + " ON CONFLICT (account_id) DO UPDATE SET \
+                block_timestamp = EXCLUDED.block_timestamp, \
+                nonstaked_amount = EXCLUDED.nonstaked_amount, \
+                staked_amount = EXCLUDED.staked_amount \
+            WHERE current_balances.block_timestamp <= EXCLUDED.block_timestamp")
+    }
+
+    fn name() -> String {
+        "current_balances".to_string()
+    }
+}
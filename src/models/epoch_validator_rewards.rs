@@ -0,0 +1,39 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct EpochValidatorReward {
+    pub epoch_id: String,
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub validator_id: String,
+    pub reward_amount: BigDecimal,
+    pub staked_amount: BigDecimal,
+}
+
+impl crate::models::SqlxMethods for EpochValidatorReward {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.epoch_id);
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.validator_id);
+        args.add(&self.reward_amount);
+        args.add(&self.staked_amount);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("epoch_validator_rewards")
+        ) + &crate::models::create_placeholders_chain(
+            count,
+            EpochValidatorReward::field_count(),
+        )? + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "epoch_validator_rewards".to_string()
+    }
+}
@@ -0,0 +1,47 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct FeeDetails {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub shard_id: i32,
+    pub transaction_hash: Option<String>,
+    pub receipt_id: Option<String>,
+    pub executor_account_id: String,
+    pub gas_price: BigDecimal,
+    pub gas_burnt: BigDecimal,
+    pub tokens_burnt: BigDecimal,
+    pub contract_reward_amount: BigDecimal,
+    pub refund_amount: BigDecimal,
+}
+
+impl crate::models::SqlxMethods for FeeDetails {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(self.shard_id);
+        args.add(&self.transaction_hash);
+        args.add(&self.receipt_id);
+        args.add(&self.executor_account_id);
+        args.add(&self.gas_price);
+        args.add(&self.gas_burnt);
+        args.add(&self.tokens_burnt);
+        args.add(&self.contract_reward_amount);
+        args.add(&self.refund_amount);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("fee_details")
+        ) + &crate::models::create_placeholders_chain(count, FeeDetails::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "fee_details".to_string()
+    }
+}
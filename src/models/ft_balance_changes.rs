@@ -0,0 +1,43 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct FtBalanceChange {
+    pub block_timestamp: BigDecimal,
+    pub receipt_id: String,
+    pub contract_account_id: String,
+    pub affected_account_id: String,
+    pub involved_account_id: Option<String>,
+    pub cause: String,
+    pub delta_amount: BigDecimal,
+    pub absolute_amount: Option<BigDecimal>,
+    pub index_in_receipt: i32,
+}
+
+impl crate::models::SqlxMethods for FtBalanceChange {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_timestamp);
+        args.add(&self.receipt_id);
+        args.add(&self.contract_account_id);
+        args.add(&self.affected_account_id);
+        args.add(&self.involved_account_id);
+        args.add(&self.cause);
+        args.add(&self.delta_amount);
+        args.add(&self.absolute_amount);
+        args.add(&self.index_in_receipt);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("ft_balance_changes")
+        ) + &crate::models::create_placeholders_chain(count, FtBalanceChange::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "ft_balance_changes".to_string()
+    }
+}
@@ -0,0 +1,35 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct LockupBalance {
+    pub block_timestamp: BigDecimal,
+    pub lockup_account_id: String,
+    pub owner_account_id: String,
+    pub locked_amount: BigDecimal,
+    pub liquid_amount: BigDecimal,
+}
+
+impl crate::models::SqlxMethods for LockupBalance {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_timestamp);
+        args.add(&self.lockup_account_id);
+        args.add(&self.owner_account_id);
+        args.add(&self.locked_amount);
+        args.add(&self.liquid_amount);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("lockup_balances")
+        ) + &crate::models::create_placeholders_chain(count, LockupBalance::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "lockup_balances".to_string()
+    }
+}
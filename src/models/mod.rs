@@ -6,8 +6,23 @@ use num_traits::{ToPrimitive, Zero};
 use sqlx::{Arguments, Row};
 
 pub(crate) use indexer_balances::FieldCount;
+pub(crate) mod account_changes;
+pub(crate) mod anomalies;
 pub(crate) mod balance_changes;
+pub(crate) mod balance_imbalances;
+pub(crate) mod block_balance_summary;
+pub(crate) mod copy;
+pub(crate) mod current_balances;
+pub(crate) mod epoch_validator_rewards;
+pub(crate) mod fee_details;
+pub(crate) mod ft_balance_changes;
+pub(crate) mod lockup_balances;
+pub(crate) mod quarantined_ft_event_values;
 mod serializers;
+pub(crate) mod shard_mapping;
+pub(crate) mod supply_history;
+pub(crate) mod top_accounts;
+pub(crate) mod wrap_near_events;
 
 pub trait FieldCount {
     /// Get the number of fields on a struct.
@@ -22,18 +37,18 @@ pub trait SqlxMethods {
     fn name() -> String;
 }
 
-pub async fn chunked_insert<T: SqlxMethods + std::fmt::Debug>(
+pub async fn chunked_insert<T: SqlxMethods + FieldCount + std::fmt::Debug>(
     pool: &sqlx::Pool<sqlx::Postgres>,
     items: &[T],
     retry_count: usize,
 ) -> anyhow::Result<()> {
     let futures = items
-        .chunks(crate::db_adapters::CHUNK_SIZE_FOR_BATCH_INSERT)
+        .chunks(crate::db_adapters::insert_batch_size(T::field_count()))
         .map(|items_part| insert_retry_or_panic(pool, items_part, retry_count));
     try_join_all(futures).await.map(|_| ())
 }
 
-async fn insert_retry_or_panic<T: SqlxMethods + std::fmt::Debug>(
+async fn insert_retry_or_panic<T: SqlxMethods + FieldCount + std::fmt::Debug>(
     pool: &sqlx::Pool<sqlx::Postgres>,
     items: &[T],
     retry_count: usize,
@@ -56,17 +71,26 @@ async fn insert_retry_or_panic<T: SqlxMethods + std::fmt::Debug>(
             item.add_to_args(&mut args);
         }
 
+        crate::throttle::DB_WRITES.acquire().await;
+        let started_at = std::time::Instant::now();
         match sqlx::query_with(&query, args).execute(pool).await {
-            Ok(_) => break,
+            Ok(_) => {
+                crate::db_adapters::observe_insert_latency(
+                    items.len(),
+                    T::field_count(),
+                    started_at.elapsed(),
+                );
+                break;
+            }
             Err(async_error) => {
                 tracing::error!(
-                         target: crate::INDEXER,
-                         "Error occurred during {}:\n{} were not stored. \n{:#?} \n Retrying in {} milliseconds...",
-                         async_error,
-                         &T::name(),
-                         &items,
-                         interval.as_millis(),
-                     );
+                    target: crate::INDEXER,
+                    "Error occurred during {}:\n{} were not stored. \n{:#?} \n Retrying in {} milliseconds...",
+                    async_error,
+                    &T::name(),
+                    &items,
+                    interval.as_millis(),
+                );
                 tokio::time::sleep(interval).await;
                 if interval < crate::MAX_DELAY_TIME {
                     interval *= 2;
@@ -77,6 +101,53 @@ async fn insert_retry_or_panic<T: SqlxMethods + std::fmt::Debug>(
     Ok(())
 }
 
+// All the items go in one transaction, so the caller decides when to commit
+pub async fn insert_in_transaction<T: SqlxMethods + FieldCount + std::fmt::Debug>(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    items: &[T],
+) -> anyhow::Result<()> {
+    for items_part in items.chunks(crate::db_adapters::insert_batch_size(T::field_count())) {
+        let mut args = sqlx::postgres::PgArguments::default();
+        for item in items_part {
+            item.add_to_args(&mut args);
+        }
+        crate::throttle::DB_WRITES.acquire().await;
+        let started_at = std::time::Instant::now();
+        sqlx::query_with(&T::insert_query(items_part.len())?, args)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed to insert {}: {}", T::name(), err))?;
+        crate::db_adapters::observe_insert_latency(
+            items_part.len(),
+            T::field_count(),
+            started_at.elapsed(),
+        );
+    }
+    Ok(())
+}
+
+// COPY is much faster than INSERT for the big amount of rows, but it fails on the duplicates.
+// Use it when the rows for the block are not stored yet
+pub async fn copy_in_transaction<T: copy::CopyMethods + FieldCount>(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    items: &[T],
+) -> anyhow::Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    let mut rows = copy::CopyRow::header();
+    for item in items {
+        rows.start_row(T::field_count());
+        item.write_copy_row(&mut rows)?;
+    }
+
+    crate::throttle::DB_WRITES.acquire().await;
+    let mut copy_in = transaction.copy_in_raw(&T::copy_statement()).await?;
+    copy_in.send(rows.finish()).await?;
+    copy_in.finish().await?;
+    Ok(())
+}
+
 pub async fn select_retry_or_panic(
     pool: &sqlx::Pool<sqlx::Postgres>,
     query: &str,
@@ -105,12 +176,12 @@ pub async fn select_retry_or_panic(
             Err(async_error) => {
                 // todo we print here select with non-filled placeholders. It would be better to get the final select statement here
                 tracing::error!(
-                         target: crate::INDEXER,
-                         "Error occurred during {}:\nFailed SELECT:\n{}\n Retrying in {} milliseconds...",
-                         async_error,
-                    query,
-                         interval.as_millis(),
-                     );
+                     target: crate::INDEXER,
+                     "Error occurred during {}:\nFailed SELECT:\n{}\n Retrying in {} milliseconds...",
+                     async_error,
+                query,
+                     interval.as_millis(),
+                 );
                 tokio::time::sleep(interval).await;
                 if interval < crate::MAX_DELAY_TIME {
                     interval *= 2;
@@ -176,6 +247,12 @@ fn create_placeholder(start_num: &mut usize, mut fields_count: usize) -> anyhow:
     Ok(item)
 }
 
+// Balances are u128, the deltas are i128.
+// Goes through BigInt without the string formatting, and can't fail
+pub(crate) fn to_decimal(value: impl Into<num_bigint::BigInt>) -> BigDecimal {
+    BigDecimal::new(value.into(), 0)
+}
+
 pub(crate) trait PrintEnum {
     fn print(&self) -> &str;
 }
@@ -206,19 +283,51 @@ impl PrintEnum for Direction {
 }
 
 pub(crate) enum Cause {
+    InitialState,
     ValidatorsReward,
+    ProtocolTreasuryReward,
     Transaction,
+    MetaTransaction,
     Receipt,
     ContractReward,
+    // The receipt with the transfer which has created the implicit account
+    ImplicitAccountCreated,
+    // The state change cause this version does not know
+    Unknown,
+    // Anchors the history to RPC after the drift, see src/db_adapters/drift.rs
+    DriftCorrection,
 }
 
 impl PrintEnum for Cause {
     fn print(&self) -> &str {
         match self {
+            Cause::InitialState => "INITIAL_STATE",
             Cause::ValidatorsReward => "VALIDATORS_REWARD",
+            Cause::ProtocolTreasuryReward => "PROTOCOL_TREASURY_REWARD",
             Cause::Transaction => "TRANSACTION",
+            Cause::MetaTransaction => "META_TRANSACTION",
             Cause::Receipt => "RECEIPT",
             Cause::ContractReward => "CONTRACT_REWARD",
+            Cause::ImplicitAccountCreated => "IMPLICIT_ACCOUNT_CREATED",
+            Cause::Unknown => "UNKNOWN",
+            Cause::DriftCorrection => "DRIFT_CORRECTION",
+        }
+    }
+}
+
+// NEP-141 events, https://nomicon.io/Standards/Tokens/FungibleToken/Event
+pub(crate) enum FtCause {
+    Transfer,
+    Mint,
+    Burn,
+}
+
+impl PrintEnum for FtCause {
+    fn print(&self) -> &str {
+        match self {
+            FtCause::Transfer => "FT_TRANSFER",
+            FtCause::Mint => "FT_MINT",
+            FtCause::Burn => "FT_BURN",
         }
     }
 }
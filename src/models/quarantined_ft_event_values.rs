@@ -0,0 +1,43 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct QuarantinedFtEventValue {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub shard_id: i32,
+    pub receipt_id: String,
+    pub contract_account_id: String,
+    pub field: String,
+    pub value: String,
+    pub error: String,
+}
+
+impl crate::models::SqlxMethods for QuarantinedFtEventValue {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(self.shard_id);
+        args.add(&self.receipt_id);
+        args.add(&self.contract_account_id);
+        args.add(&self.field);
+        args.add(&self.value);
+        args.add(&self.error);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("quarantined_ft_event_values")
+        ) + &crate::models::create_placeholders_chain(
+            count,
+            QuarantinedFtEventValue::field_count(),
+        )? + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "quarantined_ft_event_values".to_string()
+    }
+}
@@ -0,0 +1,35 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct ShardMapping {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub shard_layout_version: i32,
+    pub shard_id: i32,
+    pub parent_shard_id: i32,
+}
+
+impl crate::models::SqlxMethods for ShardMapping {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.shard_layout_version);
+        args.add(&self.shard_id);
+        args.add(&self.parent_shard_id);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("shard_mapping")
+        ) + &crate::models::create_placeholders_chain(count, ShardMapping::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "shard_mapping".to_string()
+    }
+}
@@ -0,0 +1,35 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct SupplyHistory {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub minted_amount: BigDecimal,
+    pub burnt_amount: BigDecimal,
+    pub total_supply: BigDecimal,
+}
+
+impl crate::models::SqlxMethods for SupplyHistory {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.minted_amount);
+        args.add(&self.burnt_amount);
+        args.add(&self.total_supply);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("supply_history")
+        ) + &crate::models::create_placeholders_chain(count, SupplyHistory::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "supply_history".to_string()
+    }
+}
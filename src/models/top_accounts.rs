@@ -0,0 +1,64 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct TopAccount {
+    pub account_id: String,
+    pub rank: i32,
+    pub total_amount: BigDecimal,
+    pub block_height: BigDecimal,
+}
+
+impl crate::models::SqlxMethods for TopAccount {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.account_id);
+        args.add(&self.rank);
+        args.add(&self.total_amount);
+        args.add(&self.block_height);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("top_accounts")
+        ) + &crate::models::create_placeholders_chain(count, TopAccount::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "top_accounts".to_string()
+    }
+}
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct TopAccountRank {
+    pub block_height: BigDecimal,
+    pub block_timestamp: BigDecimal,
+    pub account_id: String,
+    pub rank: Option<i32>,
+    pub total_amount: Option<BigDecimal>,
+}
+
+impl crate::models::SqlxMethods for TopAccountRank {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.block_height);
+        args.add(&self.block_timestamp);
+        args.add(&self.account_id);
+        args.add(&self.rank);
+        args.add(&self.total_amount);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("top_accounts_history")
+        ) + &crate::models::create_placeholders_chain(count, TopAccountRank::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "top_accounts_history".to_string()
+    }
+}
@@ -0,0 +1,41 @@
+use bigdecimal::BigDecimal;
+use sqlx::Arguments;
+
+use crate::models::FieldCount;
+
+#[derive(Debug, sqlx::FromRow, FieldCount)]
+pub struct WrapNearEvent {
+    pub correlation_id: String,
+    pub block_timestamp: BigDecimal,
+    pub kind: String,
+    pub account_id: String,
+    pub amount: BigDecimal,
+    pub near_transaction_hash: Option<String>,
+    pub near_receipt_id: Option<String>,
+    pub ft_receipt_id: String,
+}
+
+impl crate::models::SqlxMethods for WrapNearEvent {
+    fn add_to_args(&self, args: &mut sqlx::postgres::PgArguments) {
+        args.add(&self.correlation_id);
+        args.add(&self.block_timestamp);
+        args.add(&self.kind);
+        args.add(&self.account_id);
+        args.add(&self.amount);
+        args.add(&self.near_transaction_hash);
+        args.add(&self.near_receipt_id);
+        args.add(&self.ft_receipt_id);
+    }
+
+    fn insert_query(count: usize) -> anyhow::Result<String> {
+        Ok(format!(
+            "INSERT INTO {} VALUES ",
+            crate::db_adapters::table("wrap_near_events")
+        ) + &crate::models::create_placeholders_chain(count, WrapNearEvent::field_count())?
+            + " ON CONFLICT DO NOTHING")
+    }
+
+    fn name() -> String {
+        "wrap_near_events".to_string()
+    }
+}
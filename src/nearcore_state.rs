@@ -0,0 +1,379 @@
+use near_jsonrpc_client::errors::{JsonRpcError, JsonRpcServerError};
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use near_lake_framework::near_indexer_primitives;
+use near_primitives::borsh::BorshDeserialize;
+use near_primitives::hash::CryptoHash;
+
+static STATE: once_cell::sync::OnceCell<NearcoreState> = once_cell::sync::OnceCell::new();
+
+// Column families of nearcore's RocksDB, named after DBCol
+const STATE_COLUMN: &str = "State";
+const CHUNK_EXTRA_COLUMN: &str = "ChunkExtra";
+const BLOCK_HEADER_COLUMN: &str = "BlockHeader";
+
+// `--nearcore-state <path>` reads the balances straight from the RocksDB of the archival node
+// on the same host, ViewAccount RPC is not called. The database is opened read-only,
+// so the blocks after the moment of opening are not there, they still go to RPC
+struct NearcoreState {
+    db: rocksdb::DB,
+    // The layouts this version of near-primitives knows, the account goes to the shard
+    // which has the chunk in the block
+    shard_layouts: Vec<near_primitives::shard_layout::ShardLayout>,
+}
+
+pub(crate) fn configure_nearcore_state(path: &std::path::Path) -> anyhow::Result<()> {
+    let db = rocksdb::DB::open_cf_for_read_only(
+        &rocksdb::Options::default(),
+        path,
+        [STATE_COLUMN, CHUNK_EXTRA_COLUMN, BLOCK_HEADER_COLUMN],
+        false,
+    )
+    .map_err(|err| {
+        anyhow::anyhow!(
+            "Failed to open nearcore RocksDB {}: {}",
+            path.display(),
+            err
+        )
+    })?;
+    let state = NearcoreState {
+        db,
+        shard_layouts: vec![
+            near_primitives::shard_layout::ShardLayout::get_simple_nightshade_layout(),
+            near_primitives::shard_layout::ShardLayout::v0_single_shard(),
+        ],
+    };
+    STATE
+        .set(state)
+        .map_err(|_| anyhow::anyhow!("nearcore state is configured twice"))
+}
+
+pub(crate) fn is_enabled() -> bool {
+    STATE.get().is_some()
+}
+
+// Same result as ViewAccount RPC, None if the block is not in the local database
+#[allow(clippy::result_large_err)]
+pub(crate) fn view_account(
+    account_id: &near_indexer_primitives::types::AccountId,
+    block_hash: &CryptoHash,
+) -> Option<Result<near_indexer_primitives::views::AccountView, JsonRpcError<RpcQueryError>>> {
+    let state = STATE.get()?;
+    match state.account(account_id, block_hash) {
+        Ok(None) => None,
+        Ok(Some((_, Some(account)))) => Some(Ok((&account).into())),
+        Ok(Some((block_height, None))) => Some(Err(JsonRpcError::ServerError(
+            JsonRpcServerError::HandlerError(RpcQueryError::UnknownAccount {
+                requested_account_id: account_id.clone(),
+                block_height,
+                block_hash: *block_hash,
+            }),
+        ))),
+        Err(err) => Some(Err(JsonRpcError::ServerError(
+            JsonRpcServerError::InternalError {
+                info: Some(format!(
+                    "Failed to read account {} at block {} from nearcore RocksDB: {:#}",
+                    account_id, block_hash, err
+                )),
+            },
+        ))),
+    }
+}
+
+impl NearcoreState {
+    fn get(&self, column: &str, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let column_family = self
+            .db
+            .cf_handle(column)
+            .ok_or_else(|| anyhow::anyhow!("Column family {} is missing", column))?;
+        Ok(self.db.get_cf(column_family, key)?)
+    }
+
+    // The block height and the account after the block, None if the block is not there
+    fn account(
+        &self,
+        account_id: &near_indexer_primitives::types::AccountId,
+        block_hash: &CryptoHash,
+    ) -> anyhow::Result<Option<(u64, Option<near_primitives::account::Account>)>> {
+        let block_header = match self.get(BLOCK_HEADER_COLUMN, &block_hash.0)? {
+            Some(bytes) => near_primitives::block_header::BlockHeader::try_from_slice(&bytes)?,
+            None => return Ok(None),
+        };
+        for shard_layout in &self.shard_layouts {
+            let shard_uid =
+                near_primitives::shard_layout::account_id_to_shard_uid(account_id, shard_layout);
+            // The state after the chunks of the block are applied, that is what ViewAccount returns
+            let chunk_extra = match self.get(
+                CHUNK_EXTRA_COLUMN,
+                &[&block_hash.0[..], &shard_uid.to_bytes()].concat(),
+            )? {
+                Some(bytes) => {
+                    near_primitives::types::chunk_extra::ChunkExtra::try_from_slice(&bytes)?
+                }
+                None => continue,
+            };
+            let key = near_primitives::trie_key::TrieKey::Account {
+                account_id: account_id.clone(),
+            }
+            .to_vec();
+            let account = lookup(
+                |hash| {
+                    self.get(STATE_COLUMN, &[&shard_uid.to_bytes()[..], &hash.0].concat())
+                        .map(|value| value.and_then(strip_refcount))
+                },
+                chunk_extra.state_root(),
+                &key,
+            )?
+            .map(|bytes| near_primitives::account::Account::try_from_slice(&bytes))
+            .transpose()?;
+            return Ok(Some((block_header.height(), account)));
+        }
+        Ok(None)
+    }
+}
+
+// State column is reference counted, the count goes after the value as i64.
+// The values with the count down to 0 are garbage collected
+fn strip_refcount(mut value: Vec<u8>) -> Option<Vec<u8>> {
+    let split_at = value.len().checked_sub(8)?;
+    let refcount = i64::from_le_bytes(value[split_at..].try_into().ok()?);
+    value.truncate(split_at);
+    Some(value).filter(|_| refcount > 0)
+}
+
+// RawTrieNodeWithSize of near-store without the memory usage going after the node
+#[derive(Debug, PartialEq)]
+enum Node {
+    Leaf {
+        key: Vec<u8>,
+        value: CryptoHash,
+    },
+    Branch {
+        children: Box<[Option<CryptoHash>; 16]>,
+        value: Option<CryptoHash>,
+    },
+    Extension {
+        key: Vec<u8>,
+        child: CryptoHash,
+    },
+}
+
+fn decode_node(mut bytes: &[u8]) -> anyhow::Result<Node> {
+    let reader = &mut bytes;
+    Ok(match u8::deserialize(reader)? {
+        0 => Node::Leaf {
+            key: Vec::<u8>::deserialize(reader)?,
+            value: near_primitives::state::ValueRef::deserialize(reader)?.hash,
+        },
+        1 => Node::Branch {
+            children: decode_children(reader)?,
+            value: None,
+        },
+        2 => {
+            let value = near_primitives::state::ValueRef::deserialize(reader)?.hash;
+            Node::Branch {
+                children: decode_children(reader)?,
+                value: Some(value),
+            }
+        }
+        3 => Node::Extension {
+            key: Vec::<u8>::deserialize(reader)?,
+            child: CryptoHash::deserialize(reader)?,
+        },
+        tag => anyhow::bail!("Unknown trie node tag {}", tag),
+    })
+}
+
+// The bitmap of the present children, then their hashes in order
+fn decode_children(reader: &mut &[u8]) -> anyhow::Result<Box<[Option<CryptoHash>; 16]>> {
+    let bitmap = u16::deserialize(reader)?;
+    let mut children = Box::new([None; 16]);
+    for (index, child) in children.iter_mut().enumerate() {
+        if bitmap & (1 << index) != 0 {
+            *child = Some(CryptoHash::deserialize(reader)?);
+        }
+    }
+    Ok(children)
+}
+
+// The first byte of the encoded path has 0x10 for the odd number of nibbles, then the first nibble
+// is in its lower half, and 0x20 for the leaf
+fn decode_nibbles(encoded: &[u8]) -> Vec<u8> {
+    let mut nibbles = vec![];
+    if let Some(first) = encoded.first().filter(|first| *first & 0x10 != 0) {
+        nibbles.push(first & 0x0f);
+    }
+    for byte in encoded.iter().skip(1) {
+        nibbles.extend([byte >> 4, byte & 0x0f]);
+    }
+    nibbles
+}
+
+// Walks from the state root by the nibbles of the key, `get_state` reads the nodes and the values by hash
+fn lookup(
+    get_state: impl Fn(&CryptoHash) -> anyhow::Result<Option<Vec<u8>>>,
+    state_root: &CryptoHash,
+    key: &[u8],
+) -> anyhow::Result<Option<Vec<u8>>> {
+    // The empty trie
+    if *state_root == CryptoHash::default() {
+        return Ok(None);
+    }
+    let nibbles: Vec<u8> = key
+        .iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect();
+    let mut path = &nibbles[..];
+    let mut hash = *state_root;
+    let value = loop {
+        let node =
+            get_state(&hash)?.ok_or_else(|| anyhow::anyhow!("Trie node {} is missing", hash))?;
+        match decode_node(&node)? {
+            Node::Leaf { key, value } => {
+                break Some(value).filter(|_| decode_nibbles(&key) == path);
+            }
+            Node::Extension { key, child } => {
+                let key = decode_nibbles(&key);
+                match path.strip_prefix(&key[..]) {
+                    Some(rest) => {
+                        path = rest;
+                        hash = child;
+                    }
+                    None => break None,
+                }
+            }
+            Node::Branch { children, value } => match path.split_first() {
+                None => break value,
+                Some((nibble, rest)) => match children[*nibble as usize] {
+                    Some(child) => {
+                        path = rest;
+                        hash = child;
+                    }
+                    None => break None,
+                },
+            },
+        }
+    };
+    match value {
+        Some(value) => {
+            Ok(Some(get_state(&value)?.ok_or_else(|| {
+                anyhow::anyhow!("Trie value {} is missing", value)
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use near_primitives::borsh::BorshSerialize;
+
+    use super::*;
+
+    fn encode_nibbles(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+        let mut first = if is_leaf { 0x20 } else { 0 };
+        let rest = if nibbles.len() % 2 == 1 {
+            first |= 0x10 | nibbles[0];
+            &nibbles[1..]
+        } else {
+            nibbles
+        };
+        let mut encoded = vec![first];
+        encoded.extend(rest.chunks(2).map(|pair| pair[0] << 4 | pair[1]));
+        encoded
+    }
+
+    fn value_ref(value: &[u8]) -> near_primitives::state::ValueRef {
+        near_primitives::state::ValueRef {
+            length: value.len() as u32,
+            hash: near_primitives::hash::hash(value),
+        }
+    }
+
+    // Stores the node with the memory usage after it, as nearcore does
+    fn insert(state: &mut HashMap<CryptoHash, Vec<u8>>, mut node: Vec<u8>) -> CryptoHash {
+        node.extend(100u64.to_le_bytes());
+        let hash = near_primitives::hash::hash(&node);
+        state.insert(hash, node);
+        hash
+    }
+
+    // root: extension [0] -> branch { 1: leaf [2, 3] = "a", 4: leaf [] = "b" }, value "c"
+    fn trie() -> (HashMap<CryptoHash, Vec<u8>>, CryptoHash) {
+        let mut state = HashMap::new();
+        for value in [&b"a"[..], b"b", b"c"] {
+            state.insert(near_primitives::hash::hash(value), value.to_vec());
+        }
+        let mut leaf_a = vec![0];
+        leaf_a.extend(encode_nibbles(&[2, 3], true).try_to_vec().unwrap());
+        leaf_a.extend(value_ref(b"a").try_to_vec().unwrap());
+        let leaf_a = insert(&mut state, leaf_a);
+        let mut leaf_b = vec![0];
+        leaf_b.extend(encode_nibbles(&[], true).try_to_vec().unwrap());
+        leaf_b.extend(value_ref(b"b").try_to_vec().unwrap());
+        let leaf_b = insert(&mut state, leaf_b);
+        let mut branch = vec![2];
+        branch.extend(value_ref(b"c").try_to_vec().unwrap());
+        branch.extend((1u16 << 1 | 1 << 4).to_le_bytes());
+        branch.extend(leaf_a.0);
+        branch.extend(leaf_b.0);
+        let branch = insert(&mut state, branch);
+        let mut extension = vec![3];
+        extension.extend(encode_nibbles(&[0], false).try_to_vec().unwrap());
+        extension.extend(branch.0);
+        let root = insert(&mut state, extension);
+        (state, root)
+    }
+
+    #[test]
+    fn decodes_nibbles() {
+        assert_eq!(decode_nibbles(&encode_nibbles(&[1, 2, 3], true)), [1, 2, 3]);
+        assert_eq!(decode_nibbles(&encode_nibbles(&[1, 2], false)), [1, 2]);
+        assert_eq!(decode_nibbles(&encode_nibbles(&[], true)), [] as [u8; 0]);
+    }
+
+    #[test]
+    fn finds_values_by_key() {
+        let (state, root) = trie();
+        let get_state = |hash: &CryptoHash| Ok(state.get(hash).cloned());
+        // Nibbles 0 1 2 3
+        assert_eq!(
+            lookup(get_state, &root, &[0x01, 0x23]).unwrap(),
+            Some(b"a".to_vec())
+        );
+        // Nibbles 0 4, the leaf has the empty rest of the path
+        assert_eq!(
+            lookup(get_state, &root, &[0x04]).unwrap(),
+            Some(b"b".to_vec())
+        );
+        // The value of the branch itself is at nibble 0, the keys have the even number of nibbles
+        assert_eq!(lookup(get_state, &root, &[0x01, 0x24]).unwrap(), None);
+        assert_eq!(lookup(get_state, &root, &[0x02]).unwrap(), None);
+        assert_eq!(lookup(get_state, &root, &[0x14]).unwrap(), None);
+        assert_eq!(lookup(get_state, &root, &[]).unwrap(), None);
+        assert_eq!(
+            lookup(get_state, &CryptoHash::default(), &[0x04]).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn fails_on_missing_nodes() {
+        let (mut state, root) = trie();
+        state.remove(&near_primitives::hash::hash(b"a"));
+        let get_state = |hash: &CryptoHash| Ok(state.get(hash).cloned());
+        assert!(lookup(get_state, &root, &[0x01, 0x23]).is_err());
+    }
+
+    #[test]
+    fn strips_refcount() {
+        let mut value = b"node".to_vec();
+        value.extend(2i64.to_le_bytes());
+        assert_eq!(strip_refcount(value), Some(b"node".to_vec()));
+        let mut value = b"node".to_vec();
+        value.extend(0i64.to_le_bytes());
+        assert_eq!(strip_refcount(value), None);
+        assert_eq!(strip_refcount(vec![1, 2]), None);
+    }
+}
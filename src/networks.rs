@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+static MULTI_NETWORK: AtomicBool = AtomicBool::new(false);
+
+// Several chains indexed by one process, e.g. mainnet and testnet for the staging:
+//
+//     database = "postgres://..."
+//     [networks.mainnet]
+//     table-prefix = "mainnet_"
+//     [networks.testnet]
+//     chain-id = "testnet"
+//     table-prefix = "testnet_"
+//
+// Each network has its own source, RPC, caches, tables and sinks, see NETWORK_OPTIONS in
+// src/config_file.rs. The rest is shared: the throttles, the admin pause, the account filter,
+// the metrics and their server. The height metrics are of the last stored block of any network,
+// so the lag monitor is off. The subcommands work with one network chosen by --network
+pub(crate) async fn run(networks: Vec<(String, Arc<crate::configs::Opts>)>) -> anyhow::Result<()> {
+    for (name, opts) in &networks {
+        if opts.command.is_some() {
+            anyhow::bail!(
+                "Subcommands work with one network, choose it with --network, e.g. --network {}",
+                name
+            );
+        }
+        if opts.stream_port.is_some() || opts.alert_lag_blocks.is_some() {
+            anyhow::bail!(
+                "--stream-port and --alert-lag-blocks are not supported with several networks"
+            );
+        }
+    }
+    ensure_distinct(&networks, "database, db-schema and table-prefix", |opts| {
+        Some((
+            opts.database.clone(),
+            opts.db_schema.clone(),
+            opts.table_prefix.clone(),
+        ))
+    })?;
+    ensure_distinct(&networks, "disk-buffer-path", |opts| {
+        opts.disk_buffer_path.clone()
+    })?;
+    ensure_distinct(&networks, "parquet-output", |opts| {
+        opts.parquet_output.clone()
+    })?;
+    ensure_distinct(&networks, "archive-output", |opts| {
+        opts.archive_output.clone()
+    })?;
+    ensure_distinct(&networks, "ha-lock-id", |opts| opts.ha_lock_id)?;
+    MULTI_NETWORK.store(true, Ordering::Relaxed);
+
+    let mut pipelines = vec![];
+    for (name, opts) in networks {
+        let network =
+            crate::db_adapters::Network::new(opts.db_schema.clone(), opts.table_prefix.clone())?;
+        tracing::info!(
+            target: crate::INDEXER,
+            "Starting network {}, chain {:?}",
+            name,
+            opts.chain_id
+        );
+        pipelines.push(crate::db_adapters::in_network(network, async move {
+            crate::run_network(opts)
+                .await
+                .map_err(|err| err.context(format!("Network {} has failed", name)))
+        }));
+    }
+    // One failed network stops the process, the others continue from their offsets after the restart
+    futures::future::try_join_all(pipelines).await?;
+    Ok(())
+}
+
+pub(crate) fn is_multi_network() -> bool {
+    MULTI_NETWORK.load(Ordering::Relaxed)
+}
+
+// The networks writing to the same place would overwrite each other
+fn ensure_distinct<T: PartialEq>(
+    networks: &[(String, Arc<crate::configs::Opts>)],
+    option: &str,
+    get: impl Fn(&crate::configs::Opts) -> Option<T>,
+) -> anyhow::Result<()> {
+    for (i, (name, opts)) in networks.iter().enumerate() {
+        let value = match get(opts) {
+            Some(value) => value,
+            None => continue,
+        };
+        if let Some((other, _)) = networks[i + 1..]
+            .iter()
+            .find(|(_, other)| get(other).as_ref() == Some(&value))
+        {
+            anyhow::bail!("Networks {} and {} have the same {}", name, other, option);
+        }
+    }
+    Ok(())
+}
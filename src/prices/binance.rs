@@ -0,0 +1,59 @@
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+
+const KLINES_URL: &str = "https://api.binance.com/api/v3/klines";
+const MINUTE_MS: u64 = 60_000;
+
+// Close prices of NEARUSDT one-minute candles. USDT is taken as USD
+pub(crate) struct Binance {
+    client: reqwest::Client,
+}
+
+impl Binance {
+    pub(crate) fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::prices::PriceProvider for Binance {
+    fn name(&self) -> &'static str {
+        "binance"
+    }
+
+    async fn fetch_prices(&self, from_ms: u64) -> anyhow::Result<Vec<(u64, BigDecimal)>> {
+        // [open time, open, high, low, close, ...]
+        let klines: Vec<Vec<serde_json::Value>> = self
+            .client
+            .get(KLINES_URL)
+            .query(&[
+                ("symbol", "NEARUSDT".to_string()),
+                ("interval", "1m".to_string()),
+                ("startTime", from_ms.to_string()),
+                ("limit", "1000".to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        klines
+            .iter()
+            .map(|kline| {
+                let open_time = kline.first().and_then(|value| value.as_u64());
+                let close = kline.get(4).and_then(|value| value.as_str());
+                match (open_time, close) {
+                    (Some(open_time), Some(close)) => Ok((open_time, BigDecimal::from_str(close)?)),
+                    _ => anyhow::bail!("Unexpected kline {:?}", kline),
+                }
+            })
+            .collect()
+    }
+
+    fn max_gap_ms(&self) -> u64 {
+        MINUTE_MS
+    }
+}
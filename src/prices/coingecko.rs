@@ -0,0 +1,61 @@
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+
+const RANGE_URL: &str = "https://api.coingecko.com/api/v3/coins/near/market_chart/range";
+// CoinGecko gives 5-minute points for the ranges shorter than a day
+const RANGE_SECS: u64 = 23 * 60 * 60;
+const MAX_GAP_MS: u64 = 15 * 60 * 1000;
+
+pub(crate) struct Coingecko {
+    client: reqwest::Client,
+}
+
+#[derive(serde::Deserialize)]
+struct MarketChart {
+    // [unix milliseconds, price]
+    prices: Vec<(u64, f64)>,
+}
+
+impl Coingecko {
+    pub(crate) fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::prices::PriceProvider for Coingecko {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    async fn fetch_prices(&self, from_ms: u64) -> anyhow::Result<Vec<(u64, BigDecimal)>> {
+        let from = from_ms / 1000;
+        let chart: MarketChart = self
+            .client
+            .get(RANGE_URL)
+            .query(&[
+                ("vs_currency", "usd".to_string()),
+                ("from", from.to_string()),
+                ("to", (from + RANGE_SECS).to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        chart
+            .prices
+            .iter()
+            .map(|(timestamp_ms, price)| {
+                Ok((*timestamp_ms, BigDecimal::from_str(&price.to_string())?))
+            })
+            .collect()
+    }
+
+    fn max_gap_ms(&self) -> u64 {
+        MAX_GAP_MS
+    }
+}
@@ -0,0 +1,59 @@
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+
+// Daily prices are the coarsest we expect in the file
+const MAX_GAP_MS: u64 = 2 * 24 * 60 * 60 * 1000;
+
+// CSV with `<unix seconds>,<price in USD>` lines, sorted or not. The header line is optional
+pub(crate) struct PriceFile {
+    points: Vec<(u64, BigDecimal)>,
+}
+
+impl PriceFile {
+    pub(crate) async fn load(path: &std::path::Path) -> anyhow::Result<Self> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let mut points = vec![];
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(',').and_then(|(timestamp, price)| {
+                Some((
+                    timestamp.trim().parse::<u64>().ok()? * 1000,
+                    BigDecimal::from_str(price.trim()).ok()?,
+                ))
+            });
+            match parsed {
+                Some(point) => points.push(point),
+                None if i == 0 => {}
+                None => anyhow::bail!("Invalid line {} of {}: `{}`", i + 1, path.display(), line),
+            }
+        }
+        if points.is_empty() {
+            anyhow::bail!("No prices in {}", path.display());
+        }
+        Ok(Self { points })
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::prices::PriceProvider for PriceFile {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn fetch_prices(&self, from_ms: u64) -> anyhow::Result<Vec<(u64, BigDecimal)>> {
+        Ok(self
+            .points
+            .iter()
+            .filter(|(timestamp_ms, _)| *timestamp_ms >= from_ms)
+            .cloned()
+            .collect())
+    }
+
+    fn max_gap_ms(&self) -> u64 {
+        MAX_GAP_MS
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives;
+use num_traits::Signed;
+use tokio::sync::Mutex;
+
+use crate::models::balance_changes::BalanceChange;
+
+pub(crate) mod binance;
+pub(crate) mod coingecko;
+pub(crate) mod file;
+
+// Do not ask the provider again for the moment it had no price for
+const REFETCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+const FIAT_SCALE: i64 = 6;
+
+// Source of the historical NEAR/USD prices
+#[async_trait::async_trait]
+pub(crate) trait PriceProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Price points (unix milliseconds, price) from `from_ms` on, as many as the provider gives at once
+    async fn fetch_prices(&self, from_ms: u64) -> anyhow::Result<Vec<(u64, BigDecimal)>>;
+
+    // The price point is used for the moments up to this long after it
+    fn max_gap_ms(&self) -> u64;
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ProviderKind {
+    Coingecko,
+    Binance,
+    File(std::path::PathBuf),
+}
+
+impl FromStr for ProviderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "coingecko" => Ok(Self::Coingecko),
+            None if s == "binance" => Ok(Self::Binance),
+            Some(("file", path)) if !path.is_empty() => Ok(Self::File(path.into())),
+            _ => Err(format!(
+                "Unknown price provider `{}`, expected `coingecko`, `binance` or `file:<path>`",
+                s
+            )),
+        }
+    }
+}
+
+// Fills `fiat_value_usd` of the changes with the price at the block time.
+// The blocks come in order, so only the points around the current block are kept.
+// The price is best effort: if the provider fails or has no price, the value stays NULL
+pub(crate) struct Prices {
+    provider: Box<dyn PriceProvider>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    points: BTreeMap<u64, BigDecimal>,
+    last_miss: Option<std::time::Instant>,
+}
+
+impl Prices {
+    pub(crate) async fn new(kind: &ProviderKind) -> anyhow::Result<Self> {
+        let provider: Box<dyn PriceProvider> = match kind {
+            ProviderKind::Coingecko => Box::new(coingecko::Coingecko::new()),
+            ProviderKind::Binance => Box::new(binance::Binance::new()),
+            ProviderKind::File(path) => Box::new(file::PriceFile::load(path).await?),
+        };
+        tracing::info!(
+            target: crate::INDEXER,
+            "Fiat values are computed with the {} prices",
+            provider.name()
+        );
+        Ok(Self {
+            provider,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    pub(crate) async fn enrich(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &mut [BalanceChange],
+    ) {
+        if changes.is_empty() {
+            return;
+        }
+        let price = match self
+            .price_at(block_header.timestamp_nanosec / 1_000_000)
+            .await
+        {
+            Some(price) => price,
+            None => return,
+        };
+        let near_per_yocto = BigDecimal::new(1.into(), 24);
+        for change in changes {
+            let delta = &change.delta_nonstaked_amount + &change.delta_staked_amount;
+            change.fiat_value_usd = Some(round(delta * &near_per_yocto * &price));
+        }
+    }
+
+    async fn price_at(&self, timestamp_ms: u64) -> Option<BigDecimal> {
+        let max_gap_ms = self.provider.max_gap_ms();
+        let mut state = self.state.lock().await;
+        if let Some(price) = state.price_at(timestamp_ms, max_gap_ms) {
+            return Some(price);
+        }
+        if state
+            .last_miss
+            .map_or(false, |last_miss| last_miss.elapsed() < REFETCH_INTERVAL)
+        {
+            return None;
+        }
+        match self
+            .provider
+            .fetch_prices(timestamp_ms.saturating_sub(max_gap_ms))
+            .await
+        {
+            Ok(points) => state.points.extend(points),
+            Err(err) => tracing::warn!(
+                target: crate::INDEXER,
+                "Failed to fetch the prices from {}: {:#}",
+                self.provider.name(),
+                err
+            ),
+        }
+        // The older points are not needed anymore
+        state.points = state
+            .points
+            .split_off(&timestamp_ms.saturating_sub(max_gap_ms));
+        let price = state.price_at(timestamp_ms, max_gap_ms);
+        if price.is_none() {
+            tracing::warn!(
+                target: crate::INDEXER,
+                "No NEAR price for {} ms from {}",
+                timestamp_ms,
+                self.provider.name()
+            );
+            state.last_miss = Some(std::time::Instant::now());
+        }
+        price
+    }
+}
+
+impl State {
+    // The latest point at or before the moment
+    fn price_at(&self, timestamp_ms: u64, max_gap_ms: u64) -> Option<BigDecimal> {
+        self.points
+            .range(..=timestamp_ms)
+            .next_back()
+            .filter(|(point_ms, _)| timestamp_ms - **point_ms <= max_gap_ms)
+            .map(|(_, price)| price.clone())
+    }
+}
+
+// Half away from zero. BigDecimal::round panics on the numbers longer than i128
+fn round(value: BigDecimal) -> BigDecimal {
+    let half = BigDecimal::new(5.into(), FIAT_SCALE + 1);
+    if value.is_negative() {
+        (value - half).with_scale(FIAT_SCALE)
+    } else {
+        (value + half).with_scale(FIAT_SCALE)
+    }
+}
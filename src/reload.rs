@@ -0,0 +1,25 @@
+// SIGHUP re-reads the files referenced by the options: the account filter (--accounts-file),
+// --watch-accounts-file, --webhooks-config and --alerts-config, so they change without losing
+// the warm caches. The options themselves and the RPC endpoint need the restart:
+// the RPC client is cloned into every stage
+pub(crate) fn spawn_sighup_handler(
+    opts: std::sync::Arc<crate::configs::Opts>,
+    sinks: std::sync::Arc<Vec<Box<dyn crate::sinks::Sink>>>,
+) -> anyhow::Result<()> {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            tracing::info!(target: crate::INDEXER, "SIGHUP received, reloading the configs");
+            match crate::db_adapters::account_filter::reload_account_filter(&opts) {
+                Ok(()) => tracing::info!(target: crate::INDEXER, "Reloaded account filter"),
+                Err(err) => tracing::error!(
+                    target: crate::INDEXER,
+                    "Failed to reload account filter, keeping the old one: {:#}",
+                    err
+                ),
+            }
+            crate::sinks::reload(&sinks);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use near_jsonrpc_client::errors::{JsonRpcError, JsonRpcServerError};
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use near_lake_framework::near_indexer_primitives;
+
+use crate::mock_rpc::RecordedAccountView;
+
+static CASSETTE: once_cell::sync::OnceCell<Cassette> = once_cell::sync::OnceCell::new();
+
+// `--rpc-record=dir` appends each ViewAccount response to `dir/<block_hash>.jsonl`,
+// `--rpc-replay=dir` answers from there without the network, so the failing block is captured once
+// and then recomputed offline as many times as needed.
+// Replay fails on the response which was not recorded
+enum Cassette {
+    Record(std::path::PathBuf, std::sync::Mutex<()>),
+    Replay(
+        std::path::PathBuf,
+        std::sync::Mutex<HashMap<near_indexer_primitives::CryptoHash, Vec<RecordedAccountView>>>,
+    ),
+}
+
+pub(crate) fn configure_rpc_cassette(
+    record: Option<std::path::PathBuf>,
+    replay: Option<std::path::PathBuf>,
+) -> anyhow::Result<()> {
+    let cassette = match (record, replay) {
+        (None, None) => return Ok(()),
+        (Some(_), Some(_)) => anyhow::bail!("--rpc-record and --rpc-replay are exclusive"),
+        (Some(dir), None) => {
+            std::fs::create_dir_all(&dir)
+                .map_err(|err| anyhow::anyhow!("Failed to create {}: {}", dir.display(), err))?;
+            Cassette::Record(dir, std::sync::Mutex::new(()))
+        }
+        (None, Some(dir)) => Cassette::Replay(dir, std::sync::Mutex::new(HashMap::new())),
+    };
+    CASSETTE
+        .set(cassette)
+        .map_err(|_| anyhow::anyhow!("RPC cassette is configured twice"))
+}
+
+// Only ViewAccount goes to the cassette, the other methods should be avoided
+pub(crate) fn is_enabled() -> bool {
+    CASSETTE.get().is_some()
+}
+
+pub(crate) fn is_replaying() -> bool {
+    matches!(CASSETTE.get(), Some(Cassette::Replay(..)))
+}
+
+fn file_path(
+    dir: &std::path::Path,
+    block_hash: &near_indexer_primitives::CryptoHash,
+) -> std::path::PathBuf {
+    dir.join(format!("{}.jsonl", block_hash))
+}
+
+// Only the successful responses and UNKNOWN_ACCOUNT say something about the balance
+pub(crate) fn record(
+    account_id: &near_indexer_primitives::types::AccountId,
+    block_hash: &near_indexer_primitives::CryptoHash,
+    block_height: u64,
+    result: &Result<near_indexer_primitives::views::AccountView, JsonRpcError<RpcQueryError>>,
+) {
+    let (dir, lock) = match CASSETTE.get() {
+        Some(Cassette::Record(dir, lock)) => (dir, lock),
+        _ => return,
+    };
+    let (account, block_height) = match result {
+        Ok(account) => (Some(account.clone()), block_height),
+        Err(err) => match err.handler_error() {
+            Some(RpcQueryError::UnknownAccount { block_height, .. }) => (None, *block_height),
+            _ => return,
+        },
+    };
+    let recording = RecordedAccountView {
+        account_id: account_id.clone(),
+        block_hash: *block_hash,
+        block_height,
+        account,
+    };
+    let path = file_path(dir, block_hash);
+    // The verifier queries RPC concurrently with the indexing
+    let _guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+    let written = serde_json::to_string(&recording)
+        .map_err(anyhow::Error::from)
+        .and_then(|line| {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?;
+            writeln!(file, "{}", line)?;
+            Ok(())
+        });
+    if let Err(err) = written {
+        tracing::warn!(
+            target: crate::INDEXER,
+            "Failed to record RPC response to {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
+// Same error as the RPC client returns, the caller handles both the same way
+#[allow(clippy::result_large_err)]
+pub(crate) fn replay(
+    account_id: &near_indexer_primitives::types::AccountId,
+    block_hash: &near_indexer_primitives::CryptoHash,
+) -> Result<near_indexer_primitives::views::AccountView, JsonRpcError<RpcQueryError>> {
+    let (dir, recordings) = match CASSETTE.get() {
+        Some(Cassette::Replay(dir, recordings)) => (dir, recordings),
+        _ => unreachable!("RPC cassette is not in the replay mode"),
+    };
+    let internal_error = |message: String| {
+        JsonRpcError::ServerError(JsonRpcServerError::InternalError {
+            info: Some(message),
+        })
+    };
+    let mut recordings = recordings.lock().unwrap_or_else(|err| err.into_inner());
+    if !recordings.contains_key(block_hash) {
+        let path = file_path(dir, block_hash);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|err| internal_error(format!("Failed to read {}: {}", path.display(), err)))?;
+        let block_recordings = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<RecordedAccountView>, _>>()
+            .map_err(|err| {
+                internal_error(format!("Failed to parse {}: {}", path.display(), err))
+            })?;
+        recordings.insert(*block_hash, block_recordings);
+    }
+    let recording = recordings[block_hash]
+        .iter()
+        .find(|recording| &recording.account_id == account_id)
+        .ok_or_else(|| {
+            internal_error(format!(
+                "No recorded response for account {}, block_hash {}",
+                account_id, block_hash
+            ))
+        })?;
+    match &recording.account {
+        Some(account) => Ok(account.clone()),
+        None => Err(JsonRpcError::ServerError(JsonRpcServerError::HandlerError(
+            RpcQueryError::UnknownAccount {
+                requested_account_id: account_id.clone(),
+                block_height: recording.block_height,
+                block_hash: *block_hash,
+            },
+        ))),
+    }
+}
@@ -0,0 +1,368 @@
+use near_lake_framework::near_indexer_primitives;
+use near_lake_framework::near_indexer_primitives::types::{AccountId, ShardId};
+
+// The idle accounts expire, they are queried from RPC again
+const ENTRY_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+// The previous balances shared by the indexer instances, e.g. the ones of the shard-split deployment
+// (--shards): each instance publishes the balances changed in its shards after every block,
+// the others take them instead of querying the archival RPC. It is the second level after
+// the balances cache of the instance, see get_balance in src/db_adapters/balance_changes.rs
+#[async_trait::async_trait]
+pub(crate) trait SharedBalanceCache: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // The balance after the block before `block_height`. None if it is unknown,
+    // or the instance of its shard has not published that block yet
+    async fn get(
+        &self,
+        account_id: &AccountId,
+        block_height: u64,
+    ) -> anyhow::Result<Option<crate::BalanceDetails>>;
+
+    // The balances after the block of the accounts changed in it, all the shards are published
+    async fn publish(
+        &self,
+        block_height: u64,
+        shard_ids: &[ShardId],
+        balances: &[(AccountId, ShardId, crate::BalanceDetails)],
+    ) -> anyhow::Result<()>;
+}
+
+// The state changes of the block have the exact balances of the accounts of the shard,
+// including the ones not tracked by the account filter. The failure is not fatal,
+// the other instances do not use the shard until it is published again
+pub(crate) async fn publish_block(streamer_message: &near_indexer_primitives::StreamerMessage) {
+    let shared_balance_cache = match crate::db_adapters::shared_balance_cache() {
+        Some(shared_balance_cache) => shared_balance_cache,
+        None => return,
+    };
+    let mut balances: Vec<(AccountId, ShardId, crate::BalanceDetails)> = vec![];
+    for shard in &streamer_message.shards {
+        for state_change in &shard.state_changes {
+            let (account_id, balance) = match &state_change.value {
+                near_indexer_primitives::views::StateChangeValueView::AccountUpdate {
+                    account_id,
+                    account,
+                } => (
+                    account_id,
+                    crate::BalanceDetails {
+                        non_staked: account.amount,
+                        staked: account.locked,
+                    },
+                ),
+                near_indexer_primitives::views::StateChangeValueView::AccountDeletion {
+                    account_id,
+                } => (account_id, crate::BalanceDetails::default()),
+                _ => continue,
+            };
+            // The last change of the account in the block is the balance after it
+            match balances.iter_mut().find(|(id, _, _)| id == account_id) {
+                Some(entry) => entry.2 = balance,
+                None => balances.push((account_id.clone(), shard.shard_id, balance)),
+            }
+        }
+    }
+    let shard_ids: Vec<ShardId> = streamer_message
+        .shards
+        .iter()
+        .map(|shard| shard.shard_id)
+        .collect();
+    let block_height = streamer_message.block.header.height;
+    if let Err(err) = shared_balance_cache
+        .publish(block_height, &shard_ids, &balances)
+        .await
+    {
+        tracing::warn!(
+            target: crate::INDEXER,
+            "Failed to publish block_height {} to the shared balance cache {}: {:#}",
+            block_height,
+            shared_balance_cache.name(),
+            err
+        );
+    }
+}
+
+// Keys, with the prefix of the chain:
+//   balance:<account_id> = <block_height>:<shard_id>:<non_staked>:<staked>, the balance after the block
+//   progress:<shard_id> = <since>:<head>, the shard is published for all the blocks from since to head
+// The entry is valid for the later block if its shard has no gaps since it and is published up to
+// the previous block: there were no changes of the account in between, or they would be in the entry.
+// The restart or the failed publish starts the new since, the older entries may miss the changes.
+// The queries share one multiplexed connection, so the concurrent gets do not wait for each other
+pub(crate) struct RedisBalanceCache {
+    client: redis::Client,
+    // Reconnects by itself after the failure
+    connection: tokio::sync::OnceCell<redis::aio::ConnectionManager>,
+    prefix: String,
+    since: std::sync::Mutex<Option<u64>>,
+}
+
+impl RedisBalanceCache {
+    pub(crate) fn new(
+        url: &str,
+        prefix: &str,
+        chain_id: crate::configs::ChainId,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            connection: tokio::sync::OnceCell::new(),
+            prefix: format!("{}{:?}:", prefix, chain_id).to_lowercase(),
+            since: std::sync::Mutex::new(None),
+        })
+    }
+
+    async fn connection(&self) -> anyhow::Result<redis::aio::ConnectionManager> {
+        Ok(self
+            .connection
+            .get_or_try_init(|| self.client.get_tokio_connection_manager())
+            .await?
+            .clone())
+    }
+
+    async fn get_entry(
+        connection: &mut impl redis::aio::ConnectionLike,
+        balance_key: &str,
+        progress_key: impl Fn(&str) -> String,
+        block_height: u64,
+    ) -> anyhow::Result<Option<crate::BalanceDetails>> {
+        let entry: String = match redis::cmd("GET")
+            .arg(balance_key)
+            .query_async(connection)
+            .await?
+        {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let invalid = || anyhow::anyhow!("Invalid shared balance `{}` = `{}`", balance_key, entry);
+        let fields: Vec<&str> = entry.split(':').collect();
+        let (height, shard_id, non_staked, staked) = match fields.as_slice() {
+            [height, shard_id, non_staked, staked] => (
+                height.parse::<u64>().map_err(|_| invalid())?,
+                *shard_id,
+                non_staked.parse::<u128>().map_err(|_| invalid())?,
+                staked.parse::<u128>().map_err(|_| invalid())?,
+            ),
+            _ => return Err(invalid()),
+        };
+        if height >= block_height {
+            return Ok(None);
+        }
+
+        let progress_key = progress_key(shard_id);
+        let progress: String = match redis::cmd("GET")
+            .arg(&progress_key)
+            .query_async(connection)
+            .await?
+        {
+            Some(progress) => progress,
+            None => return Ok(None),
+        };
+        let (since, head) = progress
+            .split_once(':')
+            .and_then(|(since, head)| Some((since.parse::<u64>().ok()?, head.parse::<u64>().ok()?)))
+            .ok_or_else(|| {
+                anyhow::anyhow!("Invalid shard progress `{}` = `{}`", progress_key, progress)
+            })?;
+        // The heights may skip, so the previous block may be published while the check fails.
+        // The balance is queried from RPC then
+        if since <= height && head + 1 >= block_height {
+            Ok(Some(crate::BalanceDetails { non_staked, staked }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SharedBalanceCache for RedisBalanceCache {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn get(
+        &self,
+        account_id: &AccountId,
+        block_height: u64,
+    ) -> anyhow::Result<Option<crate::BalanceDetails>> {
+        Self::get_entry(
+            &mut self.connection().await?,
+            &format!("{}balance:{}", self.prefix, account_id),
+            |shard_id| format!("{}progress:{}", self.prefix, shard_id),
+            block_height,
+        )
+        .await
+    }
+
+    async fn publish(
+        &self,
+        block_height: u64,
+        shard_ids: &[ShardId],
+        balances: &[(AccountId, ShardId, crate::BalanceDetails)],
+    ) -> anyhow::Result<()> {
+        let since = *self
+            .since
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get_or_insert(block_height);
+        let mut entries: Vec<(String, String)> = balances
+            .iter()
+            .map(|(account_id, shard_id, balance)| {
+                (
+                    format!("{}balance:{}", self.prefix, account_id),
+                    format!(
+                        "{}:{}:{}:{}",
+                        block_height, shard_id, balance.non_staked, balance.staked
+                    ),
+                )
+            })
+            .collect();
+        // After the balances, Redis applies the commands of one connection in order.
+        // The blocks are published one by one, the pipeline of the block is replied before the next one
+        let progress_start = entries.len();
+        entries.extend(shard_ids.iter().map(|shard_id| {
+            (
+                format!("{}progress:{}", self.prefix, shard_id),
+                format!("{}:{}", since, block_height),
+            )
+        }));
+        let mut pipeline = redis::pipe();
+        for (index, (key, value)) in entries.iter().enumerate() {
+            let command = pipeline.cmd("SET").arg(key).arg(value);
+            if index < progress_start {
+                command.arg("EX").arg(ENTRY_TTL_SECS);
+            }
+            command.ignore();
+        }
+
+        let result = async {
+            pipeline
+                .query_async::<_, ()>(&mut self.connection().await?)
+                .await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if result.is_err() {
+            // Some balances of the block may be lost, the entries before the next since are not used
+            *self.since.lock().unwrap_or_else(|err| err.into_inner()) = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+    // Answers GET of the given keys, nil for the other ones
+    async fn fake_redis(entries: &[(&str, &str)]) -> String {
+        let entries: std::collections::HashMap<String, String> = entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tokio::io::BufReader::new(stream);
+            let mut line = String::new();
+            while stream.read_line(&mut line).await.unwrap() > 0 {
+                let mut args = vec![];
+                for _ in 0..line.trim_end()[1..].parse::<usize>().unwrap() {
+                    line.clear();
+                    stream.read_line(&mut line).await.unwrap();
+                    let mut arg = vec![0; line.trim_end()[1..].parse::<usize>().unwrap() + 2];
+                    stream.read_exact(&mut arg).await.unwrap();
+                    arg.truncate(arg.len() - 2);
+                    args.push(String::from_utf8(arg).unwrap());
+                }
+                let reply = match entries.get(&args[1]) {
+                    Some(value) => format!("${}\r\n{}\r\n", value.len(), value),
+                    None => "$-1\r\n".to_string(),
+                };
+                stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                line.clear();
+            }
+        });
+        url
+    }
+
+    async fn get_entry(
+        entries: &[(&str, &str)],
+        block_height: u64,
+    ) -> anyhow::Result<Option<(u128, u128)>> {
+        let mut connection = redis::Client::open(fake_redis(entries).await)
+            .unwrap()
+            .get_multiplexed_tokio_connection()
+            .await
+            .unwrap();
+        let balance = super::RedisBalanceCache::get_entry(
+            &mut connection,
+            "balance:alice.near",
+            |shard_id| format!("progress:{}", shard_id),
+            block_height,
+        )
+        .await?;
+        Ok(balance.map(|balance| (balance.non_staked, balance.staked)))
+    }
+
+    #[tokio::test]
+    async fn takes_entry_of_published_shard() {
+        let entries = [
+            ("balance:alice.near", "100:2:5:7"),
+            ("progress:2", "90:110"),
+        ];
+        assert_eq!(get_entry(&entries, 101).await.unwrap(), Some((5, 7)));
+        assert_eq!(get_entry(&entries, 111).await.unwrap(), Some((5, 7)));
+    }
+
+    #[tokio::test]
+    async fn skips_entry_of_shard_not_published_up_to_previous_block() {
+        let entries = [
+            ("balance:alice.near", "100:2:5:7"),
+            ("progress:2", "90:110"),
+        ];
+        assert_eq!(get_entry(&entries, 112).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn skips_entry_published_before_since() {
+        let entries = [
+            ("balance:alice.near", "100:2:5:7"),
+            ("progress:2", "101:110"),
+        ];
+        assert_eq!(get_entry(&entries, 105).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn skips_entry_of_same_or_later_block() {
+        let entries = [
+            ("balance:alice.near", "100:2:5:7"),
+            ("progress:2", "90:110"),
+        ];
+        assert_eq!(get_entry(&entries, 100).await.unwrap(), None);
+        assert_eq!(get_entry(&entries, 99).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn skips_unknown_account_and_shard() {
+        assert_eq!(
+            get_entry(&[("progress:2", "90:110")], 101).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            get_entry(&[("balance:alice.near", "100:2:5:7")], 101)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_entry() {
+        let entries = [("balance:alice.near", "100:2:5"), ("progress:2", "90:110")];
+        assert!(get_entry(&entries, 101).await.is_err());
+        let entries = [("balance:alice.near", "100:2:5:7"), ("progress:2", "90")];
+        assert!(get_entry(&entries, 101).await.is_err());
+    }
+}
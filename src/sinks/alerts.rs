@@ -0,0 +1,210 @@
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::sinks::BalanceChangeEvent;
+
+const RETRY_COUNT: usize = 3;
+const YOCTO_PER_NEAR: &str = "1000000000000000000000000";
+
+// Fires the alerts about the matching balance changes, the rules are taken from the config file:
+//
+// [[alert]]
+// name = "whale-transfer"
+// min_delta_near = "100000"             # optional, compared with the liquid and the staked deltas,
+//                                       # or min_delta_yocto as in the webhooks config
+// causes = ["TRANSACTION", "RECEIPT"]   # optional, all causes by default
+// accounts = ["treasury.near"]          # optional, all accounts by default
+// slack_webhook_url = "https://hooks.slack.com/services/..."  # optional
+// pagerduty_routing_key = "..."         # optional, Events API v2
+// webhook_url = "https://example.com/alerts"                 # optional, gets the JSON of the change
+//
+// Alerts are best effort: the failed delivery is logged and does not stop the indexing
+pub(crate) struct AlertsSink {
+    client: reqwest::Client,
+    config_path: std::path::PathBuf,
+    rules: std::sync::RwLock<std::sync::Arc<Vec<AlertRule>>>,
+    yocto_per_near: BigDecimal,
+}
+
+#[derive(serde::Deserialize)]
+struct AlertsConfig {
+    alert: Vec<AlertRule>,
+}
+
+#[derive(serde::Deserialize)]
+struct AlertRule {
+    name: String,
+    min_delta_near: Option<BigDecimal>,
+    slack_webhook_url: Option<String>,
+    pagerduty_routing_key: Option<String>,
+    webhook_url: Option<String>,
+    #[serde(flatten)]
+    filter: crate::sinks::ChangeFilter,
+}
+
+#[derive(serde::Serialize)]
+struct WebhookAlert<'a> {
+    alert: &'a str,
+    #[serde(flatten)]
+    event: BalanceChangeEvent<'a>,
+}
+
+impl AlertsSink {
+    pub(crate) fn new(config_path: &std::path::Path) -> anyhow::Result<Self> {
+        let yocto_per_near = BigDecimal::from_str(YOCTO_PER_NEAR)?;
+        let rules = read_rules(config_path, &yocto_per_near)?;
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()?,
+            config_path: config_path.to_path_buf(),
+            rules: std::sync::RwLock::new(std::sync::Arc::new(rules)),
+            yocto_per_near,
+        })
+    }
+
+    async fn fire(
+        &self,
+        rule: &AlertRule,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        change: &BalanceChange,
+    ) -> anyhow::Result<()> {
+        let summary = self.summary(rule, block_header, change);
+        if let Some(url) = &rule.slack_webhook_url {
+            self.post(url, &serde_json::json!({ "text": summary }))
+                .await?;
+        }
+        if let Some(routing_key) = &rule.pagerduty_routing_key {
+            self.post(
+                "https://events.pagerduty.com/v2/enqueue",
+                &serde_json::json!({
+                    "routing_key": routing_key,
+                    "event_action": "trigger",
+                    // The same change fires once even if the block is processed again
+                    "dedup_key": format!("{}:{}:{}", rule.name, block_header.hash, change.index_in_block),
+                    "payload": {
+                        "summary": summary,
+                        "source": "indexer-balances",
+                        "severity": "warning",
+                        "custom_details": BalanceChangeEvent::new(block_header, change),
+                    },
+                }),
+            )
+            .await?;
+        }
+        if let Some(url) = &rule.webhook_url {
+            self.post(
+                url,
+                &WebhookAlert {
+                    alert: &rule.name,
+                    event: BalanceChangeEvent::new(block_header, change),
+                },
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    fn summary(
+        &self,
+        rule: &AlertRule,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        change: &BalanceChange,
+    ) -> String {
+        let near = |yocto: &BigDecimal| (yocto / &self.yocto_per_near).with_scale(2);
+        format!(
+            "[{}] {}: liquid {} NEAR, staked {} NEAR ({}) at block {}{}",
+            rule.name,
+            change.affected_account_id,
+            near(&change.delta_nonstaked_amount),
+            near(&change.delta_staked_amount),
+            change.cause,
+            block_header.height,
+            match (&change.transaction_hash, &change.receipt_id) {
+                (Some(transaction_hash), _) => format!(", transaction {}", transaction_hash),
+                (None, Some(receipt_id)) => format!(", receipt {}", receipt_id),
+                (None, None) => String::new(),
+            }
+        )
+    }
+
+    async fn post<T: serde::Serialize>(&self, url: &str, body: &T) -> anyhow::Result<()> {
+        let mut attempt = 0;
+        loop {
+            let result = match self.client.post(url).json(body).send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => Err(anyhow::anyhow!("{} returned {}", url, response.status())),
+                Err(err) => Err(err.into()),
+            };
+            if attempt == RETRY_COUNT {
+                return result;
+            }
+            attempt += 1;
+            tokio::time::sleep(crate::INTERVAL * 2u32.pow(attempt as u32)).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::Sink for AlertsSink {
+    fn name(&self) -> &'static str {
+        "alerts"
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let rules = self
+            .rules
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        for rule in rules.iter() {
+            for change in changes.iter().filter(|change| rule.filter.matches(change)) {
+                if let Err(err) = self.fire(rule, block_header, change).await {
+                    tracing::error!(
+                        target: crate::INDEXER,
+                        "Failed to fire alert {} for {} at block {}: {:#}",
+                        rule.name,
+                        change.affected_account_id,
+                        block_header.height,
+                        err
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn reload(&self) -> anyhow::Result<()> {
+        let rules = read_rules(&self.config_path, &self.yocto_per_near)?;
+        *self.rules.write().unwrap_or_else(|err| err.into_inner()) = std::sync::Arc::new(rules);
+        Ok(())
+    }
+}
+
+fn read_rules(
+    config_path: &std::path::Path,
+    yocto_per_near: &BigDecimal,
+) -> anyhow::Result<Vec<AlertRule>> {
+    let content = std::fs::read_to_string(config_path)
+        .map_err(|err| anyhow::anyhow!("Failed to read {}: {}", config_path.display(), err))?;
+    let mut config: AlertsConfig = toml::from_str(&content)?;
+    for rule in &mut config.alert {
+        if rule.slack_webhook_url.is_none()
+            && rule.pagerduty_routing_key.is_none()
+            && rule.webhook_url.is_none()
+        {
+            anyhow::bail!("Alert {} has nowhere to send the alerts", rule.name);
+        }
+        if let Some(min_delta_near) = &rule.min_delta_near {
+            rule.filter.min_delta_yocto = Some(min_delta_near * yocto_per_near);
+        }
+    }
+    Ok(config.alert)
+}
@@ -0,0 +1,149 @@
+use near_lake_framework::near_indexer_primitives;
+use tokio::sync::Mutex;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::sinks::object_store::ObjectStore;
+use crate::sinks::BalanceChangeEvent;
+
+pub(crate) const FILE_EXTENSION: &str = ".jsonl.gz";
+
+// Archives the balance changes to gzipped JSONL files partitioned by date:
+// `<output>/date=2022-07-01/<first block height>-<last block height>.jsonl.gz`.
+// The lines are the events of the other sinks, see BalanceChangeEvent, in the order of the blocks.
+// The file covers all the blocks of its range, including the ones without changes,
+// so `import` can rebuild the database from the files.
+// Like the Parquet files, the blocks not written before the crash should be replayed with --start-block-height
+pub(crate) struct ArchiveSink {
+    destination: ObjectStore,
+    blocks_per_file: u64,
+    pending: Mutex<PendingFile>,
+}
+
+#[derive(Default)]
+struct PendingFile {
+    date: Option<chrono::NaiveDate>,
+    first_block_height: u64,
+    last_block_height: u64,
+    blocks: u64,
+    rows: u64,
+    lines: Vec<u8>,
+}
+
+// A line of the archive file
+#[derive(Debug, serde::Deserialize)]
+struct ArchivedChange {
+    block_height: u64,
+    #[serde(flatten)]
+    change: BalanceChange,
+}
+
+impl ArchiveSink {
+    pub(crate) async fn new(
+        opts: &crate::configs::Opts,
+        output: &str,
+        blocks_per_file: u64,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            destination: ObjectStore::new(opts, output).await?,
+            blocks_per_file: blocks_per_file.max(1),
+            pending: Mutex::new(PendingFile::default()),
+        })
+    }
+
+    async fn write(&self, pending: PendingFile) -> anyhow::Result<()> {
+        let date = match pending.date {
+            Some(date) => date,
+            None => return Ok(()),
+        };
+        let name = format!(
+            "date={}/{}-{}{}",
+            date.format("%Y-%m-%d"),
+            pending.first_block_height,
+            pending.last_block_height,
+            FILE_EXTENSION
+        );
+        let content = crate::sinks::gzip::compress(&pending.lines)?;
+        let compressed_size = content.len();
+        self.destination.write(&name, content).await?;
+        tracing::debug!(
+            target: crate::INDEXER,
+            "Archive file {} is written, {} rows, {} bytes compressed to {}",
+            name,
+            pending.rows,
+            pending.lines.len(),
+            compressed_size
+        );
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::Sink for ArchiveSink {
+    fn name(&self) -> &'static str {
+        "archive"
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let date = chrono::DateTime::from_timestamp(
+            (block_header.timestamp_nanosec / 1_000_000_000) as i64,
+            0,
+        )
+        .ok_or_else(|| anyhow::anyhow!("Invalid timestamp of block {}", block_header.height))?
+        .date_naive();
+
+        let mut pending = self.pending.lock().await;
+        // One file never crosses the partition
+        if pending.date.is_some() && pending.date != Some(date) {
+            let full = std::mem::take(&mut *pending);
+            self.write(full).await?;
+        }
+        if pending.date.is_none() {
+            pending.date = Some(date);
+            pending.first_block_height = block_header.height;
+        }
+        pending.last_block_height = block_header.height;
+        pending.blocks += 1;
+        for change in changes {
+            serde_json::to_writer(
+                &mut pending.lines,
+                &BalanceChangeEvent::new(block_header, change),
+            )?;
+            pending.lines.push(b'\n');
+            pending.rows += 1;
+        }
+
+        if pending.blocks >= self.blocks_per_file {
+            let full = std::mem::take(&mut *pending);
+            self.write(full).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().await);
+        self.write(pending).await
+    }
+}
+
+// The rows of the archive file with their block heights, in the order of the file
+pub(crate) fn decode(content: &[u8]) -> anyhow::Result<Vec<(u64, BalanceChange)>> {
+    let content = crate::sinks::gzip::decompress(content)?;
+    let mut rows = vec![];
+    for (i, line) in content.split(|byte| *byte == b'\n').enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let archived: ArchivedChange = serde_json::from_slice(line)
+            .map_err(|err| anyhow::anyhow!("Invalid line {}: {}", i + 1, err))?;
+        // It is derived from the block hash, which is not in the file
+        if archived.change.event_id.is_empty() {
+            anyhow::bail!("Line {} has no event_id", i + 1);
+        }
+        rows.push((archived.block_height, archived.change));
+    }
+    Ok(rows)
+}
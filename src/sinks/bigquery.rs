@@ -0,0 +1,376 @@
+use near_lake_framework::near_indexer_primitives;
+use prost::Message;
+use tokio::sync::Mutex;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::sinks::bigquery_write::{AppendRowsRequest, ProtoData, ProtoRows, ProtoSchema, COLUMNS};
+
+const BIGQUERY_URL: &str = "https://bigquery.googleapis.com/bigquery/v2";
+const STORAGE_URL: &str = "https://bigquerystorage.googleapis.com";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/bigquery";
+// AppendRows request is limited by 10 MB
+const MAX_REQUEST_BYTES: usize = 9 * 1024 * 1024;
+// The token is refreshed a bit earlier than it expires
+const TOKEN_MARGIN: std::time::Duration = std::time::Duration::from_secs(60);
+
+// Streams the balance changes to BigQuery through the default stream of the Storage Write API.
+// The table `<project>.<dataset>.<table>` is created partitioned by day of block_time
+// and clustered by affected_account_id; the columns missing in the existing table are added.
+// The default stream is at-least-once, the rows of the repeated block are deduped by event_id
+pub(crate) struct BigQuerySink {
+    channel: tonic::transport::Channel,
+    write_stream: String,
+    auth: Auth,
+}
+
+impl BigQuerySink {
+    pub(crate) async fn new(
+        table: &str,
+        credentials: Option<&std::path::Path>,
+    ) -> anyhow::Result<Self> {
+        let (project_id, dataset_id, table_id) = match table.split('.').collect::<Vec<_>>()[..] {
+            [project_id, dataset_id, table_id] => (project_id, dataset_id, table_id),
+            _ => anyhow::bail!(
+                "Invalid BigQuery table `{}`, expected `<project>.<dataset>.<table>`",
+                table
+            ),
+        };
+        let auth = Auth::new(credentials)?;
+        ensure_table(&auth, project_id, dataset_id, table_id).await?;
+
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_only()
+            .enable_http2()
+            .build();
+        let channel = tonic::transport::Endpoint::from_static(STORAGE_URL)
+            .connect_with_connector(connector)
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed to connect to {}: {}", STORAGE_URL, err))?;
+        Ok(Self {
+            channel,
+            write_stream: format!(
+                "projects/{}/datasets/{}/tables/{}/streams/_default",
+                project_id, dataset_id, table_id
+            ),
+            auth,
+        })
+    }
+
+    fn request(&self, serialized_rows: Vec<Vec<u8>>) -> AppendRowsRequest {
+        AppendRowsRequest {
+            write_stream: self.write_stream.clone(),
+            proto_rows: Some(ProtoData {
+                writer_schema: Some(ProtoSchema {
+                    proto_descriptor: Some(crate::sinks::bigquery_write::descriptor()),
+                }),
+                rows: Some(ProtoRows { serialized_rows }),
+            }),
+            trace_id: format!("indexer-balances/{}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::Sink for BigQuerySink {
+    fn name(&self) -> &'static str {
+        "bigquery"
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let access_token = self.auth.access_token().await?;
+        let mut serialized_rows = vec![];
+        let mut request_bytes = 0;
+        for change in changes {
+            let row = crate::sinks::bigquery_write::encode_row(block_header, change);
+            if request_bytes + row.len() > MAX_REQUEST_BYTES && !serialized_rows.is_empty() {
+                let request = self.request(std::mem::take(&mut serialized_rows));
+                crate::sinks::bigquery_write::append_rows(
+                    self.channel.clone(),
+                    &access_token,
+                    request,
+                )
+                .await?;
+                request_bytes = 0;
+            }
+            request_bytes += row.len();
+            serialized_rows.push(row);
+        }
+        let request = self.request(serialized_rows);
+        tracing::debug!(
+            target: crate::INDEXER,
+            "Appending {} rows of block {} to BigQuery, {} bytes",
+            changes.len(),
+            block_header.height,
+            request.encoded_len()
+        );
+        crate::sinks::bigquery_write::append_rows(self.channel.clone(), &access_token, request)
+            .await
+    }
+}
+
+// Creates the table or adds the missing columns to it. The existing columns are not changed
+async fn ensure_table(
+    auth: &Auth,
+    project_id: &str,
+    dataset_id: &str,
+    table_id: &str,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let access_token = auth.access_token().await?;
+    let table_url = format!(
+        "{}/projects/{}/datasets/{}/tables/{}",
+        BIGQUERY_URL, project_id, dataset_id, table_id
+    );
+    let response = client
+        .get(&table_url)
+        .bearer_auth(&access_token)
+        .send()
+        .await?;
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        let fields: Vec<serde_json::Value> = COLUMNS
+            .iter()
+            .map(|(name, column_type, required)| field(name, column_type, *required))
+            .collect();
+        let table = serde_json::json!({
+            "tableReference": {
+                "projectId": project_id,
+                "datasetId": dataset_id,
+                "tableId": table_id,
+            },
+            "schema": { "fields": fields },
+            "timePartitioning": { "type": "DAY", "field": "block_time" },
+            "clustering": { "fields": ["affected_account_id"] },
+        });
+        let response = client
+            .post(format!(
+                "{}/projects/{}/datasets/{}/tables",
+                BIGQUERY_URL, project_id, dataset_id
+            ))
+            .bearer_auth(&access_token)
+            .json(&table)
+            .send()
+            .await?;
+        check_response(response, "create the BigQuery table").await?;
+        tracing::info!(
+            target: crate::INDEXER,
+            "Created BigQuery table {}.{}.{}",
+            project_id,
+            dataset_id,
+            table_id
+        );
+        return Ok(());
+    }
+
+    let table: serde_json::Value = check_response(response, "get the BigQuery table")
+        .await?
+        .json()
+        .await?;
+    if table.get("timePartitioning").is_none() {
+        tracing::warn!(
+            target: crate::INDEXER,
+            "BigQuery table {}.{}.{} is not partitioned, the queries scan the whole table",
+            project_id,
+            dataset_id,
+            table_id
+        );
+    }
+    let mut fields: Vec<serde_json::Value> = table["schema"]["fields"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let existing: std::collections::HashSet<String> = fields
+        .iter()
+        .filter_map(|field| field["name"].as_str().map(str::to_string))
+        .collect();
+    let missing: Vec<&str> = COLUMNS
+        .iter()
+        .filter(|(name, _, _)| !existing.contains(*name))
+        .map(|(name, column_type, _)| {
+            // The added columns can be only NULLABLE
+            fields.push(field(name, column_type, false));
+            *name
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let response = client
+        .patch(&table_url)
+        .bearer_auth(&access_token)
+        .json(&serde_json::json!({ "schema": { "fields": fields } }))
+        .send()
+        .await?;
+    check_response(response, "add the columns to the BigQuery table").await?;
+    tracing::info!(
+        target: crate::INDEXER,
+        "Added columns {} to BigQuery table {}.{}.{}",
+        missing.join(", "),
+        project_id,
+        dataset_id,
+        table_id
+    );
+    Ok(())
+}
+
+fn field(
+    name: &str,
+    column_type: &crate::sinks::bigquery_write::ColumnType,
+    required: bool,
+) -> serde_json::Value {
+    serde_json::json!({
+        "name": name,
+        "type": column_type.bigquery_type(),
+        "mode": if required { "REQUIRED" } else { "NULLABLE" },
+    })
+}
+
+async fn check_response(
+    response: reqwest::Response,
+    action: &str,
+) -> anyhow::Result<reqwest::Response> {
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!(
+            "Failed to {}, BigQuery returned {}: {}",
+            action,
+            status,
+            response.text().await?
+        );
+    }
+    Ok(response)
+}
+
+// OAuth 2.0 access token from the service account key or, without it, from the GCE metadata server
+struct Auth {
+    client: reqwest::Client,
+    service_account: Option<ServiceAccount>,
+    token: Mutex<Option<(String, std::time::Instant)>>,
+}
+
+struct ServiceAccount {
+    client_email: String,
+    token_uri: String,
+    key_pair: ring::signature::RsaKeyPair,
+}
+
+#[derive(serde::Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl Auth {
+    fn new(credentials: Option<&std::path::Path>) -> anyhow::Result<Self> {
+        let service_account = match credentials {
+            Some(path) => {
+                let key: ServiceAccountKey = serde_json::from_str(&std::fs::read_to_string(path)?)
+                    .map_err(|err| {
+                        anyhow::anyhow!("Invalid service account key {}: {}", path.display(), err)
+                    })?;
+                // PKCS#8 in PEM
+                let der: String = key
+                    .private_key
+                    .lines()
+                    .filter(|line| !line.starts_with("-----"))
+                    .collect();
+                let key_pair = ring::signature::RsaKeyPair::from_pkcs8(&base64::decode(der)?)
+                    .map_err(|err| {
+                        anyhow::anyhow!("Invalid private key in {}: {}", path.display(), err)
+                    })?;
+                Some(ServiceAccount {
+                    client_email: key.client_email,
+                    token_uri: key.token_uri,
+                    key_pair,
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            client: reqwest::Client::new(),
+            service_account,
+            token: Mutex::new(None),
+        })
+    }
+
+    async fn access_token(&self) -> anyhow::Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some((access_token, expires_at)) = token.as_ref() {
+            if std::time::Instant::now() + TOKEN_MARGIN < *expires_at {
+                return Ok(access_token.clone());
+            }
+        }
+        let request = match &self.service_account {
+            Some(service_account) => self.client.post(&service_account.token_uri).form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", &service_account.jwt()?),
+            ]),
+            None => self
+                .client
+                .get(METADATA_TOKEN_URL)
+                .header("Metadata-Flavor", "Google"),
+        };
+        let response: TokenResponse = check_response(request.send().await?, "get the access token")
+            .await?
+            .json()
+            .await?;
+        *token = Some((
+            response.access_token.clone(),
+            std::time::Instant::now() + std::time::Duration::from_secs(response.expires_in),
+        ));
+        Ok(response.access_token)
+    }
+}
+
+impl ServiceAccount {
+    // https://developers.google.com/identity/protocols/oauth2/service-account#authorizingrequests
+    fn jwt(&self) -> anyhow::Result<String> {
+        let issued_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let header = serde_json::json!({ "alg": "RS256", "typ": "JWT" });
+        let claims = serde_json::json!({
+            "iss": self.client_email,
+            "scope": SCOPE,
+            "aud": self.token_uri,
+            "iat": issued_at,
+            "exp": issued_at + 3600,
+        });
+        let message = format!(
+            "{}.{}",
+            base64::encode_config(header.to_string(), base64::URL_SAFE_NO_PAD),
+            base64::encode_config(claims.to_string(), base64::URL_SAFE_NO_PAD)
+        );
+        let mut signature = vec![0; self.key_pair.public_modulus_len()];
+        self.key_pair
+            .sign(
+                &ring::signature::RSA_PKCS1_SHA256,
+                &ring::rand::SystemRandom::new(),
+                message.as_bytes(),
+                &mut signature,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to sign the JWT for {}", self.client_email))?;
+        Ok(format!(
+            "{}.{}",
+            message,
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        ))
+    }
+}
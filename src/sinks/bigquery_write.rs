@@ -0,0 +1,286 @@
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+
+use crate::models::balance_changes::BalanceChange;
+
+// The messages of google.cloud.bigquery.storage.v1 the sink needs, written by hand:
+// the oneof fields are the optional ones with the same tags, the rest is skipped
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct AppendRowsRequest {
+    #[prost(string, tag = "1")]
+    pub write_stream: String,
+    #[prost(message, optional, tag = "4")]
+    pub proto_rows: Option<ProtoData>,
+    #[prost(string, tag = "6")]
+    pub trace_id: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct ProtoData {
+    #[prost(message, optional, tag = "1")]
+    pub writer_schema: Option<ProtoSchema>,
+    #[prost(message, optional, tag = "2")]
+    pub rows: Option<ProtoRows>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct ProtoSchema {
+    #[prost(message, optional, tag = "1")]
+    pub proto_descriptor: Option<prost_types::DescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct ProtoRows {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub serialized_rows: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct AppendRowsResponse {
+    #[prost(message, optional, tag = "1")]
+    pub append_result: Option<AppendResult>,
+    #[prost(message, optional, tag = "2")]
+    pub error: Option<Status>,
+    #[prost(message, repeated, tag = "4")]
+    pub row_errors: Vec<RowError>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct AppendResult {}
+
+// google.rpc.Status without the details
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct Status {
+    #[prost(int32, tag = "1")]
+    pub code: i32,
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(super) struct RowError {
+    #[prost(int64, tag = "1")]
+    pub index: i64,
+    #[prost(string, tag = "3")]
+    pub message: String,
+}
+
+#[derive(Clone, Copy)]
+pub(super) enum ColumnType {
+    String,
+    Int64,
+    // Microseconds since the epoch
+    Timestamp,
+    // The amounts in yoctoNEAR do not fit NUMERIC, they are sent as strings
+    BigNumeric,
+}
+
+impl ColumnType {
+    pub(super) fn bigquery_type(&self) -> &'static str {
+        match self {
+            ColumnType::String => "STRING",
+            ColumnType::Int64 => "INT64",
+            ColumnType::Timestamp => "TIMESTAMP",
+            ColumnType::BigNumeric => "BIGNUMERIC",
+        }
+    }
+
+    fn proto_type(&self) -> Type {
+        match self {
+            ColumnType::String | ColumnType::BigNumeric => Type::String,
+            ColumnType::Int64 | ColumnType::Timestamp => Type::Int64,
+        }
+    }
+}
+
+// (name, type, required). The proto field number is the position + 1, see Row.
+// The new columns go to the end, the sink adds them to the existing table
+pub(super) const COLUMNS: &[(&str, ColumnType, bool)] = &[
+    ("event_id", ColumnType::String, true),
+    ("block_height", ColumnType::Int64, true),
+    ("block_hash", ColumnType::String, true),
+    ("block_timestamp", ColumnType::Int64, true),
+    ("block_time", ColumnType::Timestamp, true),
+    ("receipt_id", ColumnType::String, false),
+    ("transaction_hash", ColumnType::String, false),
+    ("affected_account_id", ColumnType::String, true),
+    ("involved_account_id", ColumnType::String, false),
+    ("direction", ColumnType::String, true),
+    ("cause", ColumnType::String, true),
+    ("status", ColumnType::String, true),
+    ("delta_nonstaked_amount", ColumnType::BigNumeric, true),
+    ("absolute_nonstaked_amount", ColumnType::BigNumeric, true),
+    ("delta_staked_amount", ColumnType::BigNumeric, true),
+    ("absolute_staked_amount", ColumnType::BigNumeric, true),
+    ("shard_id", ColumnType::Int64, true),
+    ("index_in_chunk", ColumnType::Int64, true),
+    ("index_in_block", ColumnType::Int64, true),
+    ("fiat_value_usd", ColumnType::BigNumeric, false),
+    ("gas_burnt", ColumnType::Int64, false),
+    ("predecessor_account_id", ColumnType::String, false),
+    ("receiver_account_id", ColumnType::String, false),
+];
+
+// The descriptor is read as proto2: the missing field is NULL, so all the fields are optional
+// and the zeros are sent explicitly
+#[derive(Clone, PartialEq, Message)]
+struct Row {
+    #[prost(string, optional, tag = "1")]
+    event_id: Option<String>,
+    #[prost(int64, optional, tag = "2")]
+    block_height: Option<i64>,
+    #[prost(string, optional, tag = "3")]
+    block_hash: Option<String>,
+    #[prost(int64, optional, tag = "4")]
+    block_timestamp: Option<i64>,
+    #[prost(int64, optional, tag = "5")]
+    block_time: Option<i64>,
+    #[prost(string, optional, tag = "6")]
+    receipt_id: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    transaction_hash: Option<String>,
+    #[prost(string, optional, tag = "8")]
+    affected_account_id: Option<String>,
+    #[prost(string, optional, tag = "9")]
+    involved_account_id: Option<String>,
+    #[prost(string, optional, tag = "10")]
+    direction: Option<String>,
+    #[prost(string, optional, tag = "11")]
+    cause: Option<String>,
+    #[prost(string, optional, tag = "12")]
+    status: Option<String>,
+    #[prost(string, optional, tag = "13")]
+    delta_nonstaked_amount: Option<String>,
+    #[prost(string, optional, tag = "14")]
+    absolute_nonstaked_amount: Option<String>,
+    #[prost(string, optional, tag = "15")]
+    delta_staked_amount: Option<String>,
+    #[prost(string, optional, tag = "16")]
+    absolute_staked_amount: Option<String>,
+    #[prost(int64, optional, tag = "17")]
+    shard_id: Option<i64>,
+    #[prost(int64, optional, tag = "18")]
+    index_in_chunk: Option<i64>,
+    #[prost(int64, optional, tag = "19")]
+    index_in_block: Option<i64>,
+    #[prost(string, optional, tag = "20")]
+    fiat_value_usd: Option<String>,
+    #[prost(int64, optional, tag = "21")]
+    gas_burnt: Option<i64>,
+    #[prost(string, optional, tag = "22")]
+    predecessor_account_id: Option<String>,
+    #[prost(string, optional, tag = "23")]
+    receiver_account_id: Option<String>,
+}
+
+pub(super) fn descriptor() -> prost_types::DescriptorProto {
+    prost_types::DescriptorProto {
+        name: Some("BalanceChange".to_string()),
+        field: COLUMNS
+            .iter()
+            .enumerate()
+            .map(|(i, (name, column_type, _))| {
+                let mut field = prost_types::FieldDescriptorProto {
+                    name: Some(name.to_string()),
+                    number: Some(i as i32 + 1),
+                    ..Default::default()
+                };
+                field.set_label(Label::Optional);
+                field.set_type(column_type.proto_type());
+                field
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+pub(super) fn encode_row(
+    block_header: &near_lake_framework::near_indexer_primitives::views::BlockHeaderView,
+    change: &BalanceChange,
+) -> Vec<u8> {
+    use bigdecimal::ToPrimitive;
+
+    Row {
+        event_id: Some(change.event_id.clone()),
+        block_height: Some(block_header.height as i64),
+        block_hash: Some(block_header.hash.to_string()),
+        block_timestamp: Some(block_header.timestamp as i64),
+        block_time: Some((block_header.timestamp / 1000) as i64),
+        receipt_id: change.receipt_id.clone(),
+        transaction_hash: change.transaction_hash.clone(),
+        affected_account_id: Some(change.affected_account_id.clone()),
+        involved_account_id: change.involved_account_id.clone(),
+        direction: Some(change.direction.clone()),
+        cause: Some(change.cause.clone()),
+        status: Some(change.status.clone()),
+        delta_nonstaked_amount: Some(change.delta_nonstaked_amount.to_string()),
+        absolute_nonstaked_amount: Some(change.absolute_nonstaked_amount.to_string()),
+        delta_staked_amount: Some(change.delta_staked_amount.to_string()),
+        absolute_staked_amount: Some(change.absolute_staked_amount.to_string()),
+        shard_id: Some(change.shard_id as i64),
+        index_in_chunk: Some(change.index_in_chunk as i64),
+        index_in_block: Some(change.index_in_block as i64),
+        fiat_value_usd: change
+            .fiat_value_usd
+            .as_ref()
+            .map(|value| value.to_string()),
+        gas_burnt: change.gas_burnt.as_ref().and_then(|gas| gas.to_i64()),
+        predecessor_account_id: change.predecessor_account_id.clone(),
+        receiver_account_id: change.receiver_account_id.clone(),
+    }
+    .encode_to_vec()
+}
+
+// AppendRows is bidirectional streaming, the sink sends one request per stream and waits for its response
+pub(super) async fn append_rows(
+    channel: tonic::transport::Channel,
+    access_token: &str,
+    request: AppendRowsRequest,
+) -> anyhow::Result<()> {
+    let mut grpc = tonic::client::Grpc::new(channel);
+    grpc.ready()
+        .await
+        .map_err(|err| anyhow::anyhow!("BigQuery Storage Write API is not ready: {}", err))?;
+    let write_stream = request.write_stream.clone();
+    let mut request = tonic::Request::new(tokio_stream::once(request));
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {}", access_token).parse()?);
+    // The routing header is required for the streaming calls
+    request.metadata_mut().insert(
+        "x-goog-request-params",
+        format!("write_stream={}", write_stream.replace('/', "%2F")).parse()?,
+    );
+    let path = tonic::codegen::http::uri::PathAndQuery::from_static(
+        "/google.cloud.bigquery.storage.v1.BigQueryWrite/AppendRows",
+    );
+    let codec = tonic::codec::ProstCodec::<AppendRowsRequest, AppendRowsResponse>::default();
+    let mut responses = grpc
+        .streaming(request, path, codec)
+        .await
+        .map_err(|status| anyhow::anyhow!("BigQuery rejected AppendRows: {}", status))?
+        .into_inner();
+    let response = responses
+        .message()
+        .await
+        .map_err(|status| anyhow::anyhow!("BigQuery rejected AppendRows: {}", status))?
+        .ok_or_else(|| anyhow::anyhow!("BigQuery closed AppendRows without the response"))?;
+    if let Some(row_error) = response.row_errors.first() {
+        anyhow::bail!(
+            "BigQuery rejected row {}: {}",
+            row_error.index,
+            row_error.message
+        );
+    }
+    if let Some(error) = response.error {
+        anyhow::bail!(
+            "BigQuery failed to append rows, code {}: {}",
+            error.code,
+            error.message
+        );
+    }
+    if response.append_result.is_none() {
+        anyhow::bail!("BigQuery returned AppendRows response without the result");
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use std::io::{Read, Write};
+
+// gzip (RFC 1952) of the archive files, the files recompressed by other tools are read too
+pub(crate) fn compress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+// Reads all the members of the file, `cat a.gz b.gz` is a valid archive file
+pub(crate) fn decompress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut output = vec![];
+    flate2::read::MultiGzDecoder::new(data)
+        .read_to_end(&mut output)
+        .map_err(|err| anyhow::anyhow!("Invalid gzip data: {}", err))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn reads_concatenated_members() {
+        let mut data = super::compress(b"{\"block_height\":1}\n").unwrap();
+        data.extend(super::compress(b"{\"block_height\":2}\n").unwrap());
+        assert_eq!(
+            super::decompress(&data).unwrap(),
+            b"{\"block_height\":1}\n{\"block_height\":2}\n"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_data() {
+        assert!(super::decompress(b"{\"block_height\":1}\n").is_err());
+    }
+}
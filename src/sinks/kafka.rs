@@ -0,0 +1,95 @@
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::sinks::BalanceChangeEvent;
+
+// Publishes to Kafka via Confluent REST Proxy (API v2), one request per block.
+// The blocks stored before the restart are published again from the offset of the sink,
+// see `sinks::Replayed`. The records are keyed by event_id, so the block published again
+// replaces its own records in the compacted topic instead of duplicating them
+pub(crate) struct KafkaSink {
+    client: reqwest::Client,
+    url: reqwest::Url,
+}
+
+#[derive(serde::Serialize)]
+struct ProduceRequest<'a> {
+    records: Vec<ProduceRecord<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct ProduceRecord<'a> {
+    key: &'a str,
+    value: BalanceChangeEvent<'a>,
+}
+
+#[derive(serde::Deserialize)]
+struct ProduceResponse {
+    offsets: Vec<ProduceOffset>,
+}
+
+#[derive(serde::Deserialize)]
+struct ProduceOffset {
+    error: Option<String>,
+}
+
+impl KafkaSink {
+    pub(crate) fn new(rest_url: &str, topic: &str) -> anyhow::Result<Self> {
+        let url = reqwest::Url::parse(&format!(
+            "{}/topics/{}",
+            rest_url.trim_end_matches('/'),
+            topic
+        ))?;
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::Sink for KafkaSink {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let request = ProduceRequest {
+            records: changes
+                .iter()
+                .map(|change| ProduceRecord {
+                    key: &change.event_id,
+                    value: BalanceChangeEvent::new(block_header, change),
+                })
+                .collect(),
+        };
+        let response = self
+            .client
+            .post(self.url.clone())
+            .header("Content-Type", "application/vnd.kafka.json.v2+json")
+            .header("Accept", "application/vnd.kafka.v2+json")
+            .json(&request)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!(
+                "Kafka REST Proxy returned {}: {}",
+                status,
+                response.text().await?
+            );
+        }
+        let response: ProduceResponse = response.json().await?;
+        if let Some(error) = response.offsets.into_iter().find_map(|offset| offset.error) {
+            anyhow::bail!("Kafka rejected the record: {}", error);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,403 @@
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+
+pub(crate) mod alerts;
+pub(crate) mod archive;
+#[cfg(feature = "bigquery")]
+pub(crate) mod bigquery;
+#[cfg(feature = "bigquery")]
+mod bigquery_write;
+pub(crate) mod gzip;
+pub(crate) mod kafka;
+pub(crate) mod nats;
+pub(crate) mod object_store;
+pub(crate) mod parquet;
+pub(crate) mod redis;
+pub(crate) mod webhooks;
+
+// Balance change as it is sent to the message brokers
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct BalanceChangeEvent<'a> {
+    pub block_height: u64,
+    pub block_hash: String,
+    #[serde(flatten)]
+    pub change: &'a BalanceChange,
+}
+
+impl<'a> BalanceChangeEvent<'a> {
+    pub(crate) fn new(
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        change: &'a BalanceChange,
+    ) -> Self {
+        Self {
+            block_height: block_header.height,
+            block_hash: block_header.hash.to_string(),
+            change,
+        }
+    }
+}
+
+// Which changes the webhook or the alert is about. All the changes by default
+#[derive(Debug, Default, serde::Deserialize)]
+pub(crate) struct ChangeFilter {
+    pub accounts: Option<std::collections::HashSet<String>>,
+    pub causes: Option<Vec<String>>,
+    // Compared with the liquid and the staked deltas
+    pub min_delta_yocto: Option<bigdecimal::BigDecimal>,
+}
+
+impl ChangeFilter {
+    pub(crate) fn matches(&self, change: &BalanceChange) -> bool {
+        if let Some(accounts) = &self.accounts {
+            if !accounts.contains(&change.affected_account_id) {
+                return false;
+            }
+        }
+        if let Some(causes) = &self.causes {
+            if !causes.contains(&change.cause) {
+                return false;
+            }
+        }
+        if let Some(min_delta) = &self.min_delta_yocto {
+            if change.delta_nonstaked_amount.abs() < *min_delta
+                && change.delta_staked_amount.abs() < *min_delta
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Additional outputs for the computed balance changes.
+// They get every block in order, after the block is stored.
+// With Postgres, the sinks which can take the duplicates keep their offsets, see `Replayed`.
+// With the optimistic source, they get only the final blocks, see `FinalOnly`
+#[async_trait::async_trait]
+pub(crate) trait Sink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()>;
+
+    // The block to publish from after the restart, None if the sink does not keep the offset
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    // Gets the blocks stored before the restart which it missed
+    fn keeps_offset(&self) -> bool {
+        false
+    }
+
+    // The blocks of the discarded fork, the latest first
+    async fn rollback(&self, _blocks: &[crate::forks::TrackedBlock]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // Called when the stream is over
+    async fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // Re-reads the config files on SIGHUP. The old settings stay if the new ones are broken
+    fn reload(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+// At-least-once delivery: the offset of the sink is moved after the block is published,
+// so the blocks stored before the crash but not published are published again after the restart.
+// The subscribers deduplicate them by event_id
+pub(crate) struct Replayed {
+    sink: Box<dyn Sink>,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    // The blocks up to it were published before the restart
+    published: tokio::sync::Mutex<Option<u64>>,
+}
+
+impl Replayed {
+    fn wrap(sink: Box<dyn Sink>, pool: Option<&sqlx::Pool<sqlx::Postgres>>) -> Box<dyn Sink> {
+        match pool {
+            Some(pool) => Box::new(Self {
+                sink,
+                pool: pool.clone(),
+                published: tokio::sync::Mutex::new(None),
+            }),
+            // Nowhere to keep the offset, the blocks are published only once
+            None => sink,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Replayed {
+    fn name(&self) -> &'static str {
+        self.sink.name()
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        let published =
+            crate::db_adapters::stream_offsets::published(&self.pool, self.sink.name()).await?;
+        *self.published.lock().await = published;
+        Ok(published.map(|block_height| block_height + 1))
+    }
+
+    fn keeps_offset(&self) -> bool {
+        true
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let mut published = self.published.lock().await;
+        if published.map_or(false, |block_height| block_header.height <= block_height) {
+            return Ok(());
+        }
+        self.sink.publish(block_header, changes).await?;
+        crate::db_adapters::stream_offsets::advance_published(
+            &self.pool,
+            self.sink.name(),
+            block_header.height,
+        )
+        .await?;
+        // The replay is over, the blocks of the new fork may come at the same heights after the rollback
+        *published = None;
+        Ok(())
+    }
+
+    async fn rollback(&self, blocks: &[crate::forks::TrackedBlock]) -> anyhow::Result<()> {
+        self.sink.rollback(blocks).await
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        self.sink.flush().await
+    }
+
+    fn reload(&self) -> anyhow::Result<()> {
+        self.sink.reload()
+    }
+}
+
+// The optimistic block may be discarded, and the published messages can't be taken back.
+// The blocks wait here until the later block says they are final, the discarded ones are dropped
+pub(crate) struct FinalOnly {
+    sink: Box<dyn Sink>,
+    pending: tokio::sync::Mutex<
+        std::collections::VecDeque<(
+            near_indexer_primitives::views::BlockHeaderView,
+            Vec<BalanceChange>,
+        )>,
+    >,
+}
+
+impl FinalOnly {
+    fn wrap(sink: Box<dyn Sink>, finality: crate::configs::Finality) -> Box<dyn Sink> {
+        match finality {
+            crate::configs::Finality::Final => sink,
+            crate::configs::Finality::Optimistic => Box::new(Self {
+                sink,
+                pending: tokio::sync::Mutex::new(std::collections::VecDeque::new()),
+            }),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for FinalOnly {
+    fn name(&self) -> &'static str {
+        self.sink.name()
+    }
+
+    async fn start_after_interruption(&self) -> anyhow::Result<Option<u64>> {
+        self.sink.start_after_interruption().await
+    }
+
+    fn keeps_offset(&self) -> bool {
+        self.sink.keeps_offset()
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let mut pending = self.pending.lock().await;
+        pending.push_back((block_header.clone(), changes.to_vec()));
+        let final_blocks = match pending
+            .iter()
+            .position(|(header, _)| header.hash == block_header.last_final_block)
+        {
+            Some(position) => position + 1,
+            None => return Ok(()),
+        };
+        for (header, changes) in pending.drain(..final_blocks) {
+            self.sink.publish(&header, &changes).await?;
+        }
+        Ok(())
+    }
+
+    async fn rollback(&self, blocks: &[crate::forks::TrackedBlock]) -> anyhow::Result<()> {
+        self.pending
+            .lock()
+            .await
+            .retain(|(header, _)| blocks.iter().all(|block| block.hash != header.hash));
+        self.sink.rollback(blocks).await
+    }
+
+    // The blocks which are not final yet are not published, with the offset they are replayed after the restart
+    async fn flush(&self) -> anyhow::Result<()> {
+        self.sink.flush().await
+    }
+
+    fn reload(&self) -> anyhow::Result<()> {
+        self.sink.reload()
+    }
+}
+
+pub(crate) async fn connect(
+    opts: &crate::configs::Opts,
+    pool: Option<&sqlx::Pool<sqlx::Postgres>>,
+) -> anyhow::Result<Vec<Box<dyn Sink>>> {
+    let mut sinks: Vec<Box<dyn Sink>> = vec![];
+    if let Some(output) = &opts.parquet_output {
+        sinks.push(Box::new(
+            parquet::ParquetSink::new(opts, output, opts.parquet_blocks_per_file).await?,
+        ));
+    }
+    if let Some(output) = &opts.archive_output {
+        sinks.push(Box::new(
+            archive::ArchiveSink::new(opts, output, opts.archive_blocks_per_file).await?,
+        ));
+    }
+    if let Some(rest_url) = &opts.kafka_rest_url {
+        sinks.push(Replayed::wrap(
+            Box::new(kafka::KafkaSink::new(rest_url, &opts.kafka_topic)?),
+            pool,
+        ));
+    }
+    if let Some(url) = &opts.nats_url {
+        sinks.push(Replayed::wrap(
+            Box::new(nats::NatsSink::new(url, &opts.nats_subject).await?),
+            pool,
+        ));
+    }
+    if let Some(url) = &opts.redis_url {
+        let watch_accounts_file = opts
+            .watch_accounts_file
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("--watch-accounts-file is required for --redis-url"))?;
+        sinks.push(Box::new(redis::RedisSink::new(
+            url,
+            &opts.redis_channel,
+            watch_accounts_file,
+        )?));
+    }
+    if let Some(config_path) = &opts.webhooks_config {
+        sinks.push(Box::new(webhooks::WebhooksSink::new(config_path)?));
+    }
+    if let Some(config_path) = &opts.alerts_config {
+        sinks.push(Box::new(alerts::AlertsSink::new(config_path)?));
+    }
+    #[cfg(feature = "bigquery")]
+    if let Some(table) = &opts.bigquery_table {
+        sinks.push(Box::new(
+            bigquery::BigQuerySink::new(table, opts.bigquery_credentials.as_deref()).await?,
+        ));
+    }
+    #[cfg(not(feature = "bigquery"))]
+    if opts.bigquery_table.is_some() {
+        anyhow::bail!("BigQuery sink needs the build with --features bigquery");
+    }
+    Ok(sinks
+        .into_iter()
+        .map(|sink| FinalOnly::wrap(sink, opts.finality))
+        .collect())
+}
+
+pub(crate) async fn publish(
+    sinks: &[Box<dyn Sink>],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+) -> anyhow::Result<()> {
+    for sink in sinks {
+        sink.publish(block_header, changes).await.map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to publish block {} to {}: {:#}",
+                block_header.height,
+                sink.name(),
+                err
+            )
+        })?;
+    }
+    Ok(())
+}
+
+// The earliest block which one of the sinks has not published before the restart
+pub(crate) async fn start_after_interruption(
+    sinks: &[Box<dyn Sink>],
+) -> anyhow::Result<Option<u64>> {
+    let mut start = None;
+    for sink in sinks {
+        if let Some(block_height) = sink.start_after_interruption().await? {
+            start = Some(start.map_or(block_height, |start: u64| start.min(block_height)));
+        }
+    }
+    Ok(start)
+}
+
+// The block stored before the restart goes only to the sinks which may have missed it
+pub(crate) async fn replay(
+    sinks: &[Box<dyn Sink>],
+    block_header: &near_indexer_primitives::views::BlockHeaderView,
+    changes: &[BalanceChange],
+) -> anyhow::Result<()> {
+    for sink in sinks.iter().filter(|sink| sink.keeps_offset()) {
+        sink.publish(block_header, changes).await.map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to publish block {} again to {}: {:#}",
+                block_header.height,
+                sink.name(),
+                err
+            )
+        })?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn rollback(
+    sinks: &[Box<dyn Sink>],
+    blocks: &[crate::forks::TrackedBlock],
+) -> anyhow::Result<()> {
+    for sink in sinks {
+        sink.rollback(blocks).await?;
+    }
+    Ok(())
+}
+
+pub(crate) fn reload(sinks: &[Box<dyn Sink>]) {
+    for sink in sinks {
+        match sink.reload() {
+            Ok(()) => tracing::info!(target: crate::INDEXER, "Reloaded {} sink", sink.name()),
+            Err(err) => tracing::error!(
+                target: crate::INDEXER,
+                "Failed to reload {} sink, keeping the old settings: {:#}",
+                sink.name(),
+                err
+            ),
+        }
+    }
+}
+
+pub(crate) async fn flush(sinks: &[Box<dyn Sink>]) -> anyhow::Result<()> {
+    for sink in sinks {
+        sink.flush().await?;
+    }
+    Ok(())
+}
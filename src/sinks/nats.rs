@@ -0,0 +1,122 @@
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::sinks::BalanceChangeEvent;
+
+const ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+const RETRY_COUNT: usize = 5;
+
+// Publishes to NATS JetStream with at-least-once delivery.
+// Every message waits for the JetStream ack, the block is published again after the failure.
+// Nats-Msg-Id header lets JetStream drop the duplicates within its deduplication window.
+// The block is published only after it is committed to the storage, so the subscribers never get
+// the changes which are not in the database. The blocks stored before the restart are published
+// again from the offset of the sink, see `sinks::Replayed`.
+// The subject is built from the template, `{shard}` and `{account_prefix}` are substituted
+pub(crate) struct NatsSink {
+    jetstream: async_nats::jetstream::Context,
+    subject_template: String,
+}
+
+impl NatsSink {
+    pub(crate) async fn new(url: &str, subject_template: &str) -> anyhow::Result<Self> {
+        if !url.starts_with("nats://") {
+            anyhow::bail!("Expected `nats://host:port`, got {}", url);
+        }
+        // The client reconnects by itself, the messages published meanwhile wait for the ack
+        let client = async_nats::ConnectOptions::new()
+            .name("indexer-balances")
+            .connect(url)
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed to connect to NATS: {}", err))?;
+        let mut jetstream = async_nats::jetstream::new(client);
+        jetstream.set_timeout(ACK_TIMEOUT);
+        Ok(Self {
+            jetstream,
+            subject_template: subject_template.to_string(),
+        })
+    }
+
+    fn subject(&self, change: &BalanceChange) -> String {
+        // Dots separate the tokens of the subject, so they can't be a part of the account prefix.
+        // All the Ethereum-implicit accounts would get `0x`
+        let account_id = &change.affected_account_id;
+        let account_id = if crate::db_adapters::account_filter::is_eth_implicit(account_id) {
+            &account_id[2..]
+        } else {
+            account_id
+        };
+        let account_prefix: String = account_id
+            .chars()
+            .take(2)
+            .map(|c| if c == '.' { '_' } else { c })
+            .collect();
+        self.subject_template
+            .replace("{shard}", &change.shard_id.to_string())
+            .replace("{account_prefix}", &account_prefix)
+    }
+
+    async fn publish_once(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        // All the messages of the block are sent before waiting for the first ack
+        let mut acks = Vec::with_capacity(changes.len());
+        for change in changes {
+            let payload = serde_json::to_vec(&BalanceChangeEvent::new(block_header, change))?;
+            let mut headers = async_nats::HeaderMap::new();
+            headers.insert(
+                async_nats::header::NATS_MESSAGE_ID,
+                change.event_id.as_str(),
+            );
+            let ack = self
+                .jetstream
+                .publish_with_headers(self.subject(change), headers, payload.into())
+                .await
+                .map_err(|err| anyhow::anyhow!("Failed to publish to JetStream: {}", err))?;
+            acks.push(ack);
+        }
+        for ack in acks {
+            ack.await
+                .map_err(|err| anyhow::anyhow!("JetStream did not ack the message: {}", err))?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::Sink for NatsSink {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let mut attempt = 0;
+        loop {
+            match self.publish_once(block_header, changes).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < RETRY_COUNT => {
+                    attempt += 1;
+                    tracing::warn!(
+                        target: crate::INDEXER,
+                        "Failed to publish block {} to NATS, retrying ({}/{}): {:#}",
+                        block_header.height,
+                        attempt,
+                        RETRY_COUNT,
+                        err
+                    );
+                    tokio::time::sleep(crate::INTERVAL * attempt as u32).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// Where the file sinks put their files: local directory, `s3://bucket/prefix` or `gs://bucket/prefix`.
+// The names are relative paths like `date=2022-07-01/100-199.parquet`
+pub(crate) enum ObjectStore {
+    Local(std::path::PathBuf),
+    // GCS goes through its S3-compatible API
+    S3 {
+        client: aws_sdk_s3::Client,
+        bucket: String,
+        prefix: String,
+    },
+}
+
+impl ObjectStore {
+    pub(crate) async fn new(opts: &crate::configs::Opts, location: &str) -> anyhow::Result<Self> {
+        let (client, path) = if let Some(path) = location.strip_prefix("s3://") {
+            (opts.s3_client().await?, path)
+        } else if let Some(path) = location.strip_prefix("gs://") {
+            (opts.gcs_client().await?, path)
+        } else {
+            return Ok(Self::Local(std::path::PathBuf::from(location)));
+        };
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        Ok(Self::S3 {
+            client,
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn key(prefix: &str, name: &str) -> String {
+        match prefix {
+            "" => name.to_string(),
+            prefix => format!("{}/{}", prefix, name),
+        }
+    }
+
+    pub(crate) async fn write(&self, name: &str, content: Vec<u8>) -> anyhow::Result<()> {
+        match self {
+            Self::Local(path) => {
+                let path = path.join(name);
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                // Readers should not see the partially written file
+                let mut tmp_path = path.clone().into_os_string();
+                tmp_path.push(".tmp");
+                tokio::fs::write(&tmp_path, content).await?;
+                tokio::fs::rename(&tmp_path, &path).await?;
+            }
+            Self::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                client
+                    .put_object()
+                    .bucket(bucket)
+                    .key(Self::key(prefix, name))
+                    .body(aws_sdk_s3::types::ByteStream::from(content))
+                    .send()
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn read(&self, name: &str) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Local(path) => Ok(tokio::fs::read(path.join(name)).await?),
+            Self::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                let key = Self::key(prefix, name);
+                let object = client
+                    .get_object()
+                    .bucket(bucket)
+                    .key(&key)
+                    .send()
+                    .await
+                    .map_err(|err| anyhow::anyhow!("Failed to get {}: {}", key, err))?;
+                Ok(object.body.collect().await?.into_bytes().to_vec())
+            }
+        }
+    }
+
+    // All the names under the location, in no particular order
+    pub(crate) async fn list(&self) -> anyhow::Result<Vec<String>> {
+        match self {
+            Self::Local(path) => {
+                let mut names = vec![];
+                let mut directories = vec![path.clone()];
+                while let Some(directory) = directories.pop() {
+                    let mut entries = tokio::fs::read_dir(&directory).await.map_err(|err| {
+                        anyhow::anyhow!("Failed to read {}: {}", directory.display(), err)
+                    })?;
+                    while let Some(entry) = entries.next_entry().await? {
+                        if entry.file_type().await?.is_dir() {
+                            directories.push(entry.path());
+                        } else if let Ok(name) = entry.path().strip_prefix(path) {
+                            names.push(name.to_string_lossy().to_string());
+                        }
+                    }
+                }
+                Ok(names)
+            }
+            Self::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                let mut names = vec![];
+                let mut continuation_token = None;
+                loop {
+                    let response = client
+                        .list_objects_v2()
+                        .bucket(bucket)
+                        .prefix(Self::key(prefix, ""))
+                        .set_continuation_token(continuation_token)
+                        .send()
+                        .await?;
+                    names.extend(
+                        response
+                            .contents()
+                            .unwrap_or_default()
+                            .iter()
+                            .filter_map(|object| object.key())
+                            .map(|key| {
+                                key.strip_prefix(&Self::key(prefix, ""))
+                                    .unwrap_or(key)
+                                    .to_string()
+                            }),
+                    );
+                    continuation_token = response.next_continuation_token().map(str::to_string);
+                    if continuation_token.is_none() {
+                        return Ok(names);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// `date=2022-07-01/100-199.parquet` -> (100, 199)
+pub(crate) fn block_range(name: &str, extension: &str) -> Option<(u64, u64)> {
+    let file_name = name.rsplit('/').next()?.strip_suffix(extension)?;
+    let (first, last) = file_name.split_once('-')?;
+    Some((first.parse().ok()?, last.parse().ok()?))
+}
@@ -0,0 +1,460 @@
+use bigdecimal::ToPrimitive;
+use near_lake_framework::near_indexer_primitives;
+use tokio::sync::Mutex;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::sinks::object_store::ObjectStore;
+use parquet::data_type::{
+    ByteArray, ByteArrayType, DataType, FixedLenByteArray, FixedLenByteArrayType, Int32Type,
+    Int64Type,
+};
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::record::Field;
+use parquet::schema::parser::parse_message_type;
+
+pub(crate) const FILE_EXTENSION: &str = ".parquet";
+
+// Writes the balance changes to Parquet files partitioned by date:
+// `<output>/date=2022-07-01/<first block height>-<last block height>.parquet`.
+// The blocks are collected in memory until the file is written, so the blocks
+// not written before the crash should be replayed with --start-block-height
+pub(crate) struct ParquetSink {
+    destination: ObjectStore,
+    blocks_per_file: u64,
+    pending: Mutex<PendingFile>,
+}
+
+#[derive(Default)]
+struct PendingFile {
+    date: Option<chrono::NaiveDate>,
+    first_block_height: u64,
+    last_block_height: u64,
+    blocks: u64,
+    rows: Vec<(u64, BalanceChange)>,
+}
+
+impl ParquetSink {
+    pub(crate) async fn new(
+        opts: &crate::configs::Opts,
+        output: &str,
+        blocks_per_file: u64,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            destination: ObjectStore::new(opts, output).await?,
+            blocks_per_file: blocks_per_file.max(1),
+            pending: Mutex::new(PendingFile::default()),
+        })
+    }
+
+    async fn write(&self, pending: PendingFile) -> anyhow::Result<()> {
+        let date = match pending.date {
+            Some(date) => date,
+            None => return Ok(()),
+        };
+        let name = format!(
+            "date={}/{}-{}{}",
+            date.format("%Y-%m-%d"),
+            pending.first_block_height,
+            pending.last_block_height,
+            FILE_EXTENSION
+        );
+        let rows_count = pending.rows.len();
+        let content = encode(&pending.rows)?;
+        self.destination.write(&name, content).await?;
+        tracing::debug!(
+            target: crate::INDEXER,
+            "Parquet file {} is written, {} rows",
+            name,
+            rows_count
+        );
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::Sink for ParquetSink {
+    fn name(&self) -> &'static str {
+        "parquet"
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let date = chrono::DateTime::from_timestamp(
+            (block_header.timestamp_nanosec / 1_000_000_000) as i64,
+            0,
+        )
+        .ok_or_else(|| anyhow::anyhow!("Invalid timestamp of block {}", block_header.height))?
+        .date_naive();
+
+        let mut pending = self.pending.lock().await;
+        // One file never crosses the partition
+        if pending.date.is_some() && pending.date != Some(date) {
+            let full = std::mem::take(&mut *pending);
+            self.write(full).await?;
+        }
+        if pending.date.is_none() {
+            pending.date = Some(date);
+            pending.first_block_height = block_header.height;
+        }
+        pending.last_block_height = block_header.height;
+        pending.blocks += 1;
+        pending.rows.extend(
+            changes
+                .iter()
+                .map(|change| (block_header.height, change.clone())),
+        );
+
+        if pending.blocks >= self.blocks_per_file {
+            let full = std::mem::take(&mut *pending);
+            self.write(full).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().await);
+        self.write(pending).await
+    }
+}
+
+// DECIMAL(38, 0) fits the yoctoNEAR amounts, it is FIXED_LEN_BYTE_ARRAY of 16 big-endian bytes
+const SCHEMA: &str = "
+message balance_changes {
+    REQUIRED INT64 block_height;
+    REQUIRED INT64 block_timestamp;
+    OPTIONAL BYTE_ARRAY receipt_id (UTF8);
+    OPTIONAL BYTE_ARRAY transaction_hash (UTF8);
+    REQUIRED BYTE_ARRAY affected_account_id (UTF8);
+    OPTIONAL BYTE_ARRAY involved_account_id (UTF8);
+    REQUIRED BYTE_ARRAY direction (UTF8);
+    REQUIRED BYTE_ARRAY cause (UTF8);
+    REQUIRED BYTE_ARRAY status (UTF8);
+    REQUIRED FIXED_LEN_BYTE_ARRAY (16) delta_nonstaked_amount (DECIMAL(38, 0));
+    REQUIRED FIXED_LEN_BYTE_ARRAY (16) absolute_nonstaked_amount (DECIMAL(38, 0));
+    REQUIRED FIXED_LEN_BYTE_ARRAY (16) delta_staked_amount (DECIMAL(38, 0));
+    REQUIRED FIXED_LEN_BYTE_ARRAY (16) absolute_staked_amount (DECIMAL(38, 0));
+    REQUIRED INT32 shard_id;
+    REQUIRED INT32 index_in_chunk;
+    REQUIRED INT32 index_in_block;
+    REQUIRED BYTE_ARRAY event_id (UTF8);
+}
+";
+
+// One row group, the files are small enough
+fn encode(rows: &[(u64, BalanceChange)]) -> anyhow::Result<Vec<u8>> {
+    let to_decimal = |value: &bigdecimal::BigDecimal| -> anyhow::Result<FixedLenByteArray> {
+        let value = value
+            .with_scale(0)
+            .as_bigint_and_exponent()
+            .0
+            .to_i128()
+            .ok_or_else(|| anyhow::anyhow!("{} does not fit into DECIMAL(38, 0)", value))?;
+        Ok(ByteArray::from(value.to_be_bytes().to_vec()).into())
+    };
+    let decimals = |get: fn(&BalanceChange) -> &bigdecimal::BigDecimal| {
+        rows.iter()
+            .map(|(_, change)| to_decimal(get(change)))
+            .collect::<anyhow::Result<Vec<FixedLenByteArray>>>()
+    };
+    let utf8 = |get: fn(&BalanceChange) -> &str| -> Vec<ByteArray> {
+        rows.iter()
+            .map(|(_, change)| ByteArray::from(get(change)))
+            .collect()
+    };
+    // The values without the nulls and the definition levels
+    let optional_utf8 = |get: fn(&BalanceChange) -> Option<&str>| -> (Vec<ByteArray>, Vec<i16>) {
+        let values = rows
+            .iter()
+            .filter_map(|(_, change)| get(change).map(ByteArray::from))
+            .collect();
+        let levels = rows
+            .iter()
+            .map(|(_, change)| get(change).is_some() as i16)
+            .collect();
+        (values, levels)
+    };
+    let block_timestamps = rows
+        .iter()
+        .map(|(_, change)| {
+            change.block_timestamp.to_i64().ok_or_else(|| {
+                anyhow::anyhow!("Invalid block_timestamp {}", change.block_timestamp)
+            })
+        })
+        .collect::<anyhow::Result<Vec<i64>>>()?;
+
+    let schema = std::sync::Arc::new(parse_message_type(SCHEMA)?);
+    let properties = std::sync::Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(vec![], schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+    write_column::<Int64Type>(
+        &mut row_group,
+        &rows
+            .iter()
+            .map(|(height, _)| *height as i64)
+            .collect::<Vec<_>>(),
+        None,
+    )?;
+    write_column::<Int64Type>(&mut row_group, &block_timestamps, None)?;
+    let optional_strings: [fn(&BalanceChange) -> Option<&str>; 2] = [
+        |change| change.receipt_id.as_deref(),
+        |change| change.transaction_hash.as_deref(),
+    ];
+    for get in optional_strings {
+        let (values, levels) = optional_utf8(get);
+        write_column::<ByteArrayType>(&mut row_group, &values, Some(&levels))?;
+    }
+    write_column::<ByteArrayType>(
+        &mut row_group,
+        &utf8(|change| &change.affected_account_id),
+        None,
+    )?;
+    let (values, levels) = optional_utf8(|change| change.involved_account_id.as_deref());
+    write_column::<ByteArrayType>(&mut row_group, &values, Some(&levels))?;
+    let strings: [fn(&BalanceChange) -> &str; 3] = [
+        |change| &change.direction,
+        |change| &change.cause,
+        |change| &change.status,
+    ];
+    for get in strings {
+        write_column::<ByteArrayType>(&mut row_group, &utf8(get), None)?;
+    }
+    let amounts: [fn(&BalanceChange) -> &bigdecimal::BigDecimal; 4] = [
+        |change| &change.delta_nonstaked_amount,
+        |change| &change.absolute_nonstaked_amount,
+        |change| &change.delta_staked_amount,
+        |change| &change.absolute_staked_amount,
+    ];
+    for get in amounts {
+        write_column::<FixedLenByteArrayType>(&mut row_group, &decimals(get)?, None)?;
+    }
+    let indices: [fn(&BalanceChange) -> i32; 3] = [
+        |change| change.shard_id,
+        |change| change.index_in_chunk,
+        |change| change.index_in_block,
+    ];
+    for get in indices {
+        write_column::<Int32Type>(
+            &mut row_group,
+            &rows
+                .iter()
+                .map(|(_, change)| get(change))
+                .collect::<Vec<_>>(),
+            None,
+        )?;
+    }
+    write_column::<ByteArrayType>(&mut row_group, &utf8(|change| &change.event_id), None)?;
+    row_group.close()?;
+    Ok(writer.into_inner()?)
+}
+
+// The columns are written in the order of SCHEMA
+fn write_column<T: DataType>(
+    row_group: &mut SerializedRowGroupWriter<'_, Vec<u8>>,
+    values: &[T::T],
+    definition_levels: Option<&[i16]>,
+) -> anyhow::Result<()> {
+    let mut column = row_group
+        .next_column()?
+        .ok_or_else(|| anyhow::anyhow!("More columns are written than SCHEMA has"))?;
+    column
+        .typed::<T>()
+        .write_batch(values, definition_levels, None)?;
+    column.close()?;
+    Ok(())
+}
+
+// The rows of the file written by encode. The columns not exported
+// (fiat_value_usd, gas_burnt, predecessor, receiver, parent_transaction_hash and index_in_receipt) are None
+pub(crate) fn decode(content: &[u8]) -> anyhow::Result<Vec<(u64, BalanceChange)>> {
+    let reader = SerializedFileReader::new(bytes::Bytes::copy_from_slice(content))?;
+    let names: Vec<String> = reader
+        .metadata()
+        .file_metadata()
+        .schema_descr()
+        .columns()
+        .iter()
+        .map(|column| column.name().to_string())
+        .collect();
+    let index = |name: &str| {
+        names
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| anyhow::anyhow!("Column {} is missing", name))
+    };
+    let columns = Columns {
+        block_height: index("block_height")?,
+        block_timestamp: index("block_timestamp")?,
+        receipt_id: index("receipt_id")?,
+        transaction_hash: index("transaction_hash")?,
+        affected_account_id: index("affected_account_id")?,
+        involved_account_id: index("involved_account_id")?,
+        direction: index("direction")?,
+        cause: index("cause")?,
+        status: index("status")?,
+        delta_nonstaked_amount: index("delta_nonstaked_amount")?,
+        absolute_nonstaked_amount: index("absolute_nonstaked_amount")?,
+        delta_staked_amount: index("delta_staked_amount")?,
+        absolute_staked_amount: index("absolute_staked_amount")?,
+        shard_id: index("shard_id")?,
+        index_in_chunk: index("index_in_chunk")?,
+        index_in_block: index("index_in_block")?,
+        event_id: index("event_id")?,
+    };
+
+    let mut rows = Vec::with_capacity(reader.metadata().file_metadata().num_rows() as usize);
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        let fields: Vec<&Field> = row.get_column_iter().map(|(_, field)| field).collect();
+        let field = |index: usize| -> &Field { fields[index] };
+        let change = BalanceChange {
+            block_timestamp: long(field(columns.block_timestamp))?.into(),
+            receipt_id: optional_string(field(columns.receipt_id))?,
+            transaction_hash: optional_string(field(columns.transaction_hash))?,
+            affected_account_id: string(field(columns.affected_account_id))?,
+            involved_account_id: optional_string(field(columns.involved_account_id))?,
+            direction: string(field(columns.direction))?,
+            cause: string(field(columns.cause))?,
+            status: string(field(columns.status))?,
+            delta_nonstaked_amount: decimal(field(columns.delta_nonstaked_amount))?,
+            absolute_nonstaked_amount: decimal(field(columns.absolute_nonstaked_amount))?,
+            delta_staked_amount: decimal(field(columns.delta_staked_amount))?,
+            absolute_staked_amount: decimal(field(columns.absolute_staked_amount))?,
+            shard_id: int(field(columns.shard_id))?,
+            index_in_chunk: int(field(columns.index_in_chunk))?,
+            index_in_block: int(field(columns.index_in_block))?,
+            fiat_value_usd: None,
+            gas_burnt: None,
+            predecessor_account_id: None,
+            receiver_account_id: None,
+            event_id: string(field(columns.event_id))?,
+            parent_transaction_hash: None,
+            index_in_receipt: None,
+        };
+        rows.push((long(field(columns.block_height))? as u64, change));
+    }
+    Ok(rows)
+}
+
+// The positions of the columns in the file
+struct Columns {
+    block_height: usize,
+    block_timestamp: usize,
+    receipt_id: usize,
+    transaction_hash: usize,
+    affected_account_id: usize,
+    involved_account_id: usize,
+    direction: usize,
+    cause: usize,
+    status: usize,
+    delta_nonstaked_amount: usize,
+    absolute_nonstaked_amount: usize,
+    delta_staked_amount: usize,
+    absolute_staked_amount: usize,
+    shard_id: usize,
+    index_in_chunk: usize,
+    index_in_block: usize,
+    event_id: usize,
+}
+
+fn int(field: &Field) -> anyhow::Result<i32> {
+    match field {
+        Field::Int(value) => Ok(*value),
+        _ => anyhow::bail!("Expected INT32, got {:?}", field),
+    }
+}
+
+fn long(field: &Field) -> anyhow::Result<i64> {
+    match field {
+        Field::Long(value) => Ok(*value),
+        _ => anyhow::bail!("Expected INT64, got {:?}", field),
+    }
+}
+
+fn string(field: &Field) -> anyhow::Result<String> {
+    match field {
+        Field::Str(value) => Ok(value.clone()),
+        _ => anyhow::bail!("Expected UTF8, got {:?}", field),
+    }
+}
+
+fn optional_string(field: &Field) -> anyhow::Result<Option<String>> {
+    match field {
+        Field::Null => Ok(None),
+        _ => string(field).map(Some),
+    }
+}
+
+fn decimal(field: &Field) -> anyhow::Result<bigdecimal::BigDecimal> {
+    match field {
+        Field::Decimal(value) if value.scale() == 0 => {
+            let value = i128::from_be_bytes(
+                value
+                    .data()
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("Expected DECIMAL(38, 0), got {:?}", value))?,
+            );
+            Ok(bigdecimal::BigDecimal::new(value.into(), 0))
+        }
+        _ => anyhow::bail!("Expected DECIMAL(38, 0), got {:?}", field),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use crate::models::balance_changes::BalanceChange;
+
+    fn change(affected_account_id: &str, involved_account_id: Option<&str>) -> BalanceChange {
+        BalanceChange {
+            block_timestamp: BigDecimal::from(1_656_633_600_000_000_000u64),
+            receipt_id: Some("receipt".to_string()),
+            transaction_hash: None,
+            affected_account_id: affected_account_id.to_string(),
+            involved_account_id: involved_account_id.map(ToString::to_string),
+            direction: "INBOUND".to_string(),
+            cause: "RECEIPT".to_string(),
+            status: "SUCCESS".to_string(),
+            delta_nonstaked_amount: BigDecimal::from(-1),
+            absolute_nonstaked_amount: BigDecimal::from_str(&i128::MAX.to_string()).unwrap(),
+            delta_staked_amount: BigDecimal::from(0),
+            absolute_staked_amount: BigDecimal::from(5),
+            shard_id: 3,
+            index_in_chunk: 1,
+            index_in_block: 7,
+            fiat_value_usd: None,
+            gas_burnt: None,
+            predecessor_account_id: None,
+            receiver_account_id: None,
+            event_id: format!("{}-event", affected_account_id),
+            parent_transaction_hash: None,
+            index_in_receipt: None,
+        }
+    }
+
+    #[test]
+    fn decodes_the_encoded_rows() {
+        let rows = vec![
+            (100, change("alice.near", None)),
+            (101, change("bob.near", Some("alice.near"))),
+        ];
+        let decoded = super::decode(&super::encode(&rows).unwrap()).unwrap();
+        // BalanceChange has no PartialEq
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", rows));
+    }
+
+    #[test]
+    fn rejects_too_big_amounts() {
+        let mut change = change("alice.near", None);
+        change.absolute_nonstaked_amount =
+            BigDecimal::from_str(&i128::MAX.to_string()).unwrap() + BigDecimal::from(1);
+        assert!(super::encode(&[(100, change)]).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+
+// Publishes a short message to the Redis channel when the balance of the watched account changes.
+// Pub/sub has no delivery guarantees, the subscribers should reconcile from the database after reconnect
+pub(crate) struct RedisSink {
+    client: redis::Client,
+    // Reconnects by itself after the failure
+    connection: tokio::sync::OnceCell<redis::aio::ConnectionManager>,
+    channel: String,
+    watch_accounts_file: std::path::PathBuf,
+    watched_accounts: std::sync::RwLock<std::sync::Arc<std::collections::HashSet<String>>>,
+}
+
+#[derive(serde::Serialize)]
+struct Notification<'a> {
+    account_id: &'a str,
+    block_height: u64,
+    block_timestamp: &'a bigdecimal::BigDecimal,
+    cause: &'a str,
+    transaction_hash: Option<&'a str>,
+    receipt_id: Option<&'a str>,
+    delta_nonstaked: &'a bigdecimal::BigDecimal,
+    nonstaked: &'a bigdecimal::BigDecimal,
+    delta_staked: &'a bigdecimal::BigDecimal,
+    staked: &'a bigdecimal::BigDecimal,
+}
+
+impl RedisSink {
+    pub(crate) fn new(
+        url: &str,
+        channel: &str,
+        watch_accounts_file: &std::path::Path,
+    ) -> anyhow::Result<Self> {
+        let watched_accounts = crate::configs::read_accounts_file(watch_accounts_file)?;
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            connection: tokio::sync::OnceCell::new(),
+            channel: channel.to_string(),
+            watch_accounts_file: watch_accounts_file.to_path_buf(),
+            watched_accounts: std::sync::RwLock::new(std::sync::Arc::new(watched_accounts)),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::Sink for RedisSink {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let watched_accounts = self
+            .watched_accounts
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        let mut messages = vec![];
+        for change in changes
+            .iter()
+            .filter(|change| watched_accounts.contains(&change.affected_account_id))
+        {
+            messages.push(serde_json::to_string(&Notification {
+                account_id: &change.affected_account_id,
+                block_height: block_header.height,
+                block_timestamp: &change.block_timestamp,
+                cause: &change.cause,
+                transaction_hash: change.transaction_hash.as_deref(),
+                receipt_id: change.receipt_id.as_deref(),
+                delta_nonstaked: &change.delta_nonstaked_amount,
+                nonstaked: &change.absolute_nonstaked_amount,
+                delta_staked: &change.delta_staked_amount,
+                staked: &change.absolute_staked_amount,
+            })?);
+        }
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        let mut connection = self
+            .connection
+            .get_or_try_init(|| self.client.get_tokio_connection_manager())
+            .await?
+            .clone();
+        let mut pipeline = redis::pipe();
+        for message in &messages {
+            pipeline
+                .cmd("PUBLISH")
+                .arg(&self.channel)
+                .arg(message)
+                .ignore();
+        }
+        pipeline.query_async::<_, ()>(&mut connection).await?;
+        Ok(())
+    }
+
+    fn reload(&self) -> anyhow::Result<()> {
+        let watched_accounts = crate::configs::read_accounts_file(&self.watch_accounts_file)?;
+        *self
+            .watched_accounts
+            .write()
+            .unwrap_or_else(|err| err.into_inner()) = std::sync::Arc::new(watched_accounts);
+        Ok(())
+    }
+}
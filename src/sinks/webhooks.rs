@@ -0,0 +1,140 @@
+use hmac::Mac;
+use near_lake_framework::near_indexer_primitives;
+
+use crate::models::balance_changes::BalanceChange;
+use crate::sinks::BalanceChangeEvent;
+
+const RETRY_COUNT: usize = 5;
+
+// POSTs the matching balance changes of the stored block to the webhooks from the config file.
+// The rules are the same as for the alerts, see `ChangeFilter`:
+//
+// [[webhook]]
+// url = "https://example.com/deposits"
+// secret = "..."                       # signs the body with HMAC-SHA256, optional
+// accounts = ["deposit.exchange.near"] # optional, all accounts by default
+// min_delta_yocto = "1000000000000000000000000" # optional, compared with the liquid and the staked deltas
+// causes = ["TRANSACTION", "RECEIPT"]  # optional, all causes by default
+pub(crate) struct WebhooksSink {
+    client: reqwest::Client,
+    config_path: std::path::PathBuf,
+    webhooks: std::sync::RwLock<std::sync::Arc<Vec<Webhook>>>,
+}
+
+#[derive(serde::Deserialize)]
+struct WebhooksConfig {
+    webhook: Vec<Webhook>,
+}
+
+#[derive(serde::Deserialize)]
+struct Webhook {
+    url: String,
+    secret: Option<String>,
+    #[serde(flatten)]
+    filter: crate::sinks::ChangeFilter,
+}
+
+#[derive(serde::Serialize)]
+struct Payload<'a> {
+    block_height: u64,
+    block_hash: String,
+    changes: Vec<BalanceChangeEvent<'a>>,
+}
+
+impl WebhooksSink {
+    pub(crate) fn new(config_path: &std::path::Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()?,
+            config_path: config_path.to_path_buf(),
+            webhooks: std::sync::RwLock::new(std::sync::Arc::new(read_webhooks(config_path)?)),
+        })
+    }
+
+    async fn send(&self, webhook: &Webhook, body: Vec<u8>) -> anyhow::Result<()> {
+        let mut request = self
+            .client
+            .post(&webhook.url)
+            .header("Content-Type", "application/json");
+        if let Some(secret) = &webhook.secret {
+            let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())?;
+            mac.update(&body);
+            request = request.header(
+                "X-Signature-256",
+                format!("sha256={}", hex::encode(mac.finalize().into_bytes())),
+            );
+        }
+        let response = request.body(body).send().await?;
+        if !response.status().is_success() {
+            anyhow::bail!("{} returned {}", webhook.url, response.status());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::sinks::Sink for WebhooksSink {
+    fn name(&self) -> &'static str {
+        "webhooks"
+    }
+
+    async fn publish(
+        &self,
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+    ) -> anyhow::Result<()> {
+        let webhooks = self
+            .webhooks
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        for webhook in webhooks.iter() {
+            let matched: Vec<BalanceChangeEvent> = changes
+                .iter()
+                .filter(|change| webhook.filter.matches(change))
+                .map(|change| BalanceChangeEvent::new(block_header, change))
+                .collect();
+            if matched.is_empty() {
+                continue;
+            }
+            let body = serde_json::to_vec(&Payload {
+                block_height: block_header.height,
+                block_hash: block_header.hash.to_string(),
+                changes: matched,
+            })?;
+
+            let mut attempt = 0;
+            while let Err(err) = self.send(webhook, body.clone()).await {
+                if attempt == RETRY_COUNT {
+                    return Err(err);
+                }
+                attempt += 1;
+                tracing::warn!(
+                    target: crate::INDEXER,
+                    "Webhook failed for block {}, retrying ({}/{}): {:#}",
+                    block_header.height,
+                    attempt,
+                    RETRY_COUNT,
+                    err
+                );
+                tokio::time::sleep(crate::INTERVAL * 2u32.pow(attempt as u32)).await;
+            }
+        }
+        Ok(())
+    }
+
+    fn reload(&self) -> anyhow::Result<()> {
+        let webhooks = read_webhooks(&self.config_path)?;
+        *self.webhooks.write().unwrap_or_else(|err| err.into_inner()) =
+            std::sync::Arc::new(webhooks);
+        Ok(())
+    }
+}
+
+fn read_webhooks(config_path: &std::path::Path) -> anyhow::Result<Vec<Webhook>> {
+    let content = std::fs::read_to_string(config_path)
+        .map_err(|err| anyhow::anyhow!("Failed to read {}: {}", config_path.display(), err))?;
+    let config: WebhooksConfig = toml::from_str(&content)?;
+    Ok(config.webhook)
+}
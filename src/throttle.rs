@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+pub(crate) static BLOCKS: once_cell::sync::Lazy<Throttle> =
+    once_cell::sync::Lazy::new(|| Throttle::new("blocks"));
+pub(crate) static DB_WRITES: once_cell::sync::Lazy<Throttle> =
+    once_cell::sync::Lazy::new(|| Throttle::new("db_writes"));
+
+// Paces the backfill which shares the database with the production API.
+// The permits are spread evenly, there are no bursts after the idle time
+pub(crate) struct Throttle {
+    name: &'static str,
+    interval: once_cell::sync::OnceCell<Duration>,
+    next: tokio::sync::Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            interval: once_cell::sync::OnceCell::new(),
+            next: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    fn configure(&self, per_second: Option<f64>) -> anyhow::Result<()> {
+        let per_second = match per_second {
+            Some(per_second) if per_second > 0.0 => per_second,
+            Some(per_second) => anyhow::bail!(
+                "The limit for {} should be positive, got {}",
+                self.name,
+                per_second
+            ),
+            None => return Ok(()),
+        };
+        self.interval
+            .set(Duration::from_secs_f64(1.0 / per_second))
+            .map_err(|_| anyhow::anyhow!("Throttle for {} is configured twice", self.name))
+    }
+
+    pub(crate) async fn acquire(&self) {
+        let interval = match self.interval.get() {
+            Some(interval) => *interval,
+            None => return,
+        };
+        // The waiters queue up on the lock, each one takes the next slot
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        let slot = match *next {
+            Some(slot) if slot > now => {
+                tokio::time::sleep_until(slot).await;
+                crate::metrics::THROTTLED_SECONDS_TOTAL
+                    .with_label_values(&[self.name])
+                    .inc_by((slot - now).as_secs_f64());
+                slot
+            }
+            _ => now,
+        };
+        *next = Some(slot + interval);
+    }
+}
+
+pub(crate) fn configure_throttles(
+    max_blocks_per_second: Option<f64>,
+    max_db_writes_per_second: Option<f64>,
+) -> anyhow::Result<()> {
+    BLOCKS.configure(max_blocks_per_second)?;
+    DB_WRITES.configure(max_db_writes_per_second)
+}
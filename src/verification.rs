@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use near_lake_framework::near_indexer_primitives;
+use rand::seq::IteratorRandom;
+use tokio::sync::mpsc;
+
+use crate::models::balance_changes::BalanceChange;
+
+// Accounts with the absolute balances we computed for the end of the block
+#[derive(Debug)]
+pub(crate) struct BlockSample {
+    pub block_height: u64,
+    pub block_hash: near_indexer_primitives::CryptoHash,
+    pub block_timestamp: u64,
+    pub balances: Vec<(String, BigDecimal, BigDecimal)>,
+}
+
+impl BlockSample {
+    // The latest row of each account in the block has its final balance
+    pub(crate) fn new(
+        block_header: &near_indexer_primitives::views::BlockHeaderView,
+        changes: &[BalanceChange],
+        sample_size: usize,
+    ) -> Self {
+        let mut latest: HashMap<&str, &BalanceChange> = HashMap::new();
+        for change in changes {
+            latest.insert(&change.affected_account_id, change);
+        }
+        let balances = latest
+            .into_values()
+            .choose_multiple(&mut rand::thread_rng(), sample_size)
+            .into_iter()
+            .map(|change| {
+                (
+                    change.affected_account_id.clone(),
+                    change.absolute_nonstaked_amount.clone(),
+                    change.absolute_staked_amount.clone(),
+                )
+            })
+            .collect();
+        Self {
+            block_height: block_header.height,
+            block_hash: block_header.hash,
+            block_timestamp: block_header.timestamp,
+            balances,
+        }
+    }
+}
+
+// With --heal-drift the drift is corrected, see heal
+pub(crate) struct Healing {
+    pub pool: sqlx::Pool<sqlx::Postgres>,
+    pub balances_cache: crate::BalanceCache,
+}
+
+// Compares the sampled balances with RPC in background, so it does not slow down the indexing.
+// The samples are dropped if the verifier can't keep up
+pub(crate) fn start(
+    json_rpc_client: near_jsonrpc_client::JsonRpcClient,
+    healing: Option<Healing>,
+) -> (tokio::task::JoinHandle<()>, mpsc::Sender<BlockSample>) {
+    let (sender, mut receiver) = mpsc::channel::<BlockSample>(10);
+    let handle = crate::db_adapters::spawn(async move {
+        while let Some(sample) = receiver.recv().await {
+            if let Err(err) = verify_sample(&json_rpc_client, healing.as_ref(), &sample).await {
+                tracing::warn!(
+                    target: crate::INDEXER,
+                    "Failed to verify balances at block_height {}: {}",
+                    sample.block_height,
+                    err
+                );
+            }
+        }
+    });
+    (handle, sender)
+}
+
+async fn verify_sample(
+    json_rpc_client: &near_jsonrpc_client::JsonRpcClient,
+    healing: Option<&Healing>,
+    sample: &BlockSample,
+) -> anyhow::Result<()> {
+    for (account_id, non_staked, staked) in &sample.balances {
+        let account_id = near_indexer_primitives::types::AccountId::from_str(account_id)?;
+        let (rpc_non_staked, rpc_staked) =
+            match crate::db_adapters::balance_changes::get_account_view(
+                json_rpc_client,
+                &account_id,
+                &sample.block_hash,
+            )
+            .await
+            {
+                Ok(account_view) => (account_view.amount, account_view.locked),
+                Err(err) => match err.handler_error() {
+                    Some(RpcQueryError::UnknownAccount { .. }) => (0, 0),
+                    _ => return Err(err.into()),
+                },
+            };
+        let rpc_non_staked = crate::models::to_decimal(rpc_non_staked);
+        let rpc_staked = crate::models::to_decimal(rpc_staked);
+
+        if &rpc_non_staked != non_staked || &rpc_staked != staked {
+            crate::metrics::DRIFT_DETECTED_TOTAL.inc();
+            crate::error_reporting::spawn_report(
+                crate::error_reporting::Level::Warning,
+                "balance_drift",
+                format!(
+                    "Balance drift for account {}: computed {} / {}, RPC {} / {}",
+                    account_id, non_staked, staked, rpc_non_staked, rpc_staked
+                ),
+                vec![
+                    ("account_id", account_id.to_string()),
+                    ("block_height", sample.block_height.to_string()),
+                ],
+            );
+            tracing::warn!(
+                target: crate::INDEXER,
+                "Balance drift for account {} at block_height {}: computed {} / {}, RPC {} / {}",
+                account_id,
+                sample.block_height,
+                non_staked,
+                staked,
+                rpc_non_staked,
+                rpc_staked
+            );
+            if let Some(healing) = healing {
+                heal(
+                    healing,
+                    &account_id,
+                    sample,
+                    (non_staked, staked),
+                    (&rpc_non_staked, &rpc_staked),
+                )
+                .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The cache keeps computing the next deltas from our balance, so it gets the RPC one too.
+// The computing is ahead of the verification: if the account has changed meanwhile,
+// the next rows are already computed from the wrong balance, and the correction would
+// count the difference twice. Such accounts are left for `check-account --heal`
+async fn heal(
+    healing: &Healing,
+    account_id: &near_indexer_primitives::types::AccountId,
+    sample: &BlockSample,
+    computed: (&BigDecimal, &BigDecimal),
+    rpc: (&BigDecimal, &BigDecimal),
+) -> anyhow::Result<()> {
+    let mut balances_cache_lock = healing.balances_cache.lock().await;
+    if let Some(cached) = balances_cache_lock.cache_get(account_id) {
+        if &crate::models::to_decimal(cached.non_staked) != computed.0
+            || &crate::models::to_decimal(cached.staked) != computed.1
+        {
+            tracing::warn!(
+                target: crate::INDEXER,
+                "Account {} has changed after block_height {}, the drift is not corrected, run check-account --heal",
+                account_id,
+                sample.block_height
+            );
+            return Ok(());
+        }
+        let rpc_balance = crate::BalanceDetails {
+            non_staked: rpc.0.to_string().parse()?,
+            staked: rpc.1.to_string().parse()?,
+        };
+        balances_cache_lock.cache_set(account_id.clone(), rpc_balance);
+    }
+    // The lock is held, so the account is not computed until the correction is stored
+    crate::db_adapters::drift::insert_correction(
+        &healing.pool,
+        account_id.as_str(),
+        &sample.block_hash,
+        sample.block_timestamp,
+        computed,
+        rpc,
+    )
+    .await?;
+    Ok(())
+}
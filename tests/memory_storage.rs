@@ -0,0 +1,128 @@
+// Runs the indexer on the hand-written blocks from tests/fixtures/transfers/lake with the in-memory storage.
+// The previous balances are served by --mock-rpc from rpc.json, the rows are checked by the expectations
+// of src/db_adapters/memory.rs, so the indexer fails on the first block with the wrong deltas or balances
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn fixture(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(path)
+}
+
+fn run_indexer(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_indexer-balances"))
+        .arg("--source")
+        .arg(format!(
+            "lake-local:{}",
+            fixture("transfers/lake").display()
+        ))
+        .arg("--mock-rpc")
+        .arg(fixture("transfers/rpc.json"))
+        .args(["--protocol-treasury-account", "treasury.near"])
+        .args(args)
+        .env("RUST_LOG", "indexer=info")
+        .env_remove("DATABASE_URL")
+        .env_remove("INDEXER_CONFIG")
+        .output()
+        .expect("Failed to run the indexer");
+    println!("{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn assert_stopped_at(output: &Output, block_height: u64) {
+    assert!(output.status.success(), "The indexer has failed");
+    let stopping = format!("Stopping after block_height {}", block_height);
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(&stopping),
+        "The indexer has not reached block_height {}",
+        block_height
+    );
+}
+
+#[test]
+fn computes_deltas_and_absolute_balances() {
+    let output = run_indexer(&[
+        "--database",
+        &format!(
+            "memory://{}",
+            fixture("transfers/expectations.toml").display()
+        ),
+        "--start-block-height",
+        "100",
+        "--stop-block-height",
+        "101",
+    ]);
+    assert_stopped_at(&output, 101);
+}
+
+#[test]
+fn fails_on_wrong_balance() {
+    let expectations = std::env::temp_dir().join("indexer_balances_wrong_balance.toml");
+    std::fs::write(
+        &expectations,
+        r#"
+[[expect]]
+block_height = 100
+account_id = "bob.near"
+cause = "RECEIPT"
+absolute_nonstaked_amount = "5000000000000000000000000"
+"#,
+    )
+    .unwrap();
+    let output = run_indexer(&[
+        "--database",
+        &format!("memory://{}", expectations.display()),
+        "--start-block-height",
+        "100",
+        "--stop-block-height",
+        "101",
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Expectation is not met at block_height 100"));
+}
+
+// The blocks are read concurrently, the balances of each block depend on the previous ones
+#[test]
+fn keeps_block_order_with_preload_window() {
+    for preload_window in ["1", "2", "5"] {
+        let output = run_indexer(&[
+            "--database",
+            &format!("memory://{}", fixture("transfers/exact.toml").display()),
+            "--start-block-height",
+            "100",
+            "--stop-block-height",
+            "104",
+            "--preload-window",
+            preload_window,
+        ]);
+        assert_stopped_at(&output, 104);
+    }
+}
+
+// The rows of the other accounts are dropped before the indices are counted
+#[test]
+fn filters_accounts() {
+    let output = run_indexer(&[
+        "--database",
+        &format!(
+            "memory://{}",
+            fixture("transfers/only_alice.toml").display()
+        ),
+        "--start-block-height",
+        "100",
+        "--stop-block-height",
+        "104",
+        "--only-accounts",
+        "alice.near",
+    ]);
+    assert_stopped_at(&output, 104);
+}
+
+#[test]
+fn rejects_zero_preload_window() {
+    let output = run_indexer(&["--database", "memory", "--preload-window", "0"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--preload-window"));
+}
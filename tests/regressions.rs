@@ -0,0 +1,62 @@
+// Replays the blocks with the previous balances from the cassette recorded by --rpc-record,
+// without the network. The expectations are exact: every row of the block, in the order of index_in_block
+use std::path::PathBuf;
+use std::process::Output;
+
+fn fixture(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(path)
+}
+
+fn replay(name: &str, start_block_height: u64, stop_block_height: u64, args: &[&str]) -> Output {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_indexer-balances"))
+        .arg("--source")
+        .arg(format!(
+            "lake-local:{}",
+            fixture(name).join("lake").display()
+        ))
+        .arg("--rpc-replay")
+        .arg(fixture(name).join("cassette"))
+        .arg("--database")
+        .arg(format!(
+            "memory://{}",
+            fixture(name).join("exact.toml").display()
+        ))
+        .args(["--protocol-treasury-account", "near"])
+        .args(["--start-block-height", &start_block_height.to_string()])
+        .args(["--stop-block-height", &stop_block_height.to_string()])
+        .args(args)
+        .env("RUST_LOG", "indexer=info")
+        .env_remove("DATABASE_URL")
+        .env_remove("INDEXER_CONFIG")
+        .output()
+        .expect("Failed to run the indexer");
+    println!("{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn assert_replayed(output: &Output, stop_block_height: u64) {
+    assert!(output.status.success(), "The indexer has failed");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(&format!(
+            "Stopping after block_height {}",
+            stop_block_height
+        )),
+        "The indexer has not reached block_height {}",
+        stop_block_height
+    );
+}
+
+#[test]
+fn transfers() {
+    let output = replay("transfers", 100, 104, &[]);
+    assert_replayed(&output, 104);
+}
+
+// carol.near does not exist before block 101, the batch loads it as the missing account
+#[test]
+fn transfers_with_batched_queries() {
+    let output = replay("transfers", 100, 104, &["--batch-account-queries"]);
+    assert_replayed(&output, 104);
+}